@@ -0,0 +1,59 @@
+import { getAuthCookie } from "./auth";
+import { cardText, createCard } from "./cards";
+
+const sanitizationCardText = (marker) =>
+  cardText(
+    `Sanitization test ${marker}`,
+    "Stored card html is rendered by every client that displays the card, so anything executable has to be removed before it is written."
+  );
+
+describe("Card Sanitization Tests", () => {
+  let authCookie = null;
+  test("Script elements are stripped from created cards", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await createCard(
+      authCookie,
+      `<p>${sanitizationCardText(Date.now())}</p><script>alert(document.cookie)</script><scr<script>x</script>ipt>alert(1)</script>`
+    );
+    expect(json).toHaveProperty("card_metadata");
+    expect(json.card_metadata.card_html).not.toMatch(/<script/i);
+  });
+
+  test("Event handlers and javascript urls are stripped from created cards", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await createCard(
+      authCookie,
+      `<p onclick="alert(1)">${sanitizationCardText(Date.now())}</p><img src="x" onerror="alert(1)"><a href="javascript:alert(1)">link</a>`
+    );
+    expect(json).toHaveProperty("card_metadata");
+    const card_html = json.card_metadata.card_html;
+    expect(card_html).not.toMatch(/onerror|onclick/i);
+    expect(card_html).not.toMatch(/<img/i);
+    expect(card_html).not.toMatch(/javascript:/i);
+    expect(card_html).toContain("<a>link</a>");
+  });
+
+  test("Entity encoded javascript urls and styles are stripped from created cards", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await createCard(
+      authCookie,
+      `<p style="width: &#101;xpression(alert(1))">${sanitizationCardText(Date.now())}</p>` +
+        `<a href="&#106;avascript:alert(1)">first</a>` +
+        `<a href="javascript&colon;alert(1)">second</a>` +
+        `<a href="&#x6A;ava&#x73;cript&#58;alert(1)">third</a>` +
+        `<a href="https://example.com/?a=1&amp;b=2">fourth</a>`
+    );
+    expect(json).toHaveProperty("card_metadata");
+    const card_html = json.card_metadata.card_html;
+    expect(card_html).not.toMatch(/style=/i);
+    expect(card_html).toContain("<a>first</a>");
+    expect(card_html).toContain("<a>second</a>");
+    expect(card_html).toContain("<a>third</a>");
+    expect(card_html).toContain(
+      '<a href="https://example.com/?a=1&amp;b=2">fourth</a>'
+    );
+  });
+});
@@ -0,0 +1,75 @@
+import fetch from "node-fetch";
+import { getAuthCookie } from "./auth";
+
+const api_endpoint = process.env.API_ENDPOINT || "http://localhost:8090/api";
+
+const evidence = (marker) =>
+  `Evidence ${marker}. ` +
+  "Cards are split at every heading and only keep the text that follows the link under it. ".repeat(
+    6
+  );
+
+const previewSplit = async (authCookie, text) => {
+  const response = await fetch(`${api_endpoint}/card/split-preview`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Cookie: authCookie,
+    },
+    credentials: "include",
+    body: JSON.stringify({ text }),
+  });
+  return response.json();
+};
+
+describe("Card Split Tests", () => {
+  let authCookie = null;
+  test("Each heading with a link starts a card, including the last one", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await previewSplit(
+      authCookie,
+      [
+        "# First tag",
+        "https://example.com/first",
+        evidence("one"),
+        "## Second tag",
+        "https://example.com/second",
+        evidence("two"),
+      ].join("\n")
+    );
+    expect(json).toHaveLength(2);
+    expect(json[0].link).toBe("https://example.com/first");
+    expect(json[1].link).toBe("https://example.com/second");
+    expect(json[1].card_html).toContain("Evidence two");
+    expect(json[0].rejection_reason).toBeNull();
+  });
+
+  test("Text before a heading or link is not part of any card", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await previewSplit(
+      authCookie,
+      [
+        evidence("preamble"),
+        "# Tag without a link",
+        evidence("unlinked"),
+      ].join("\n")
+    );
+    expect(json).toHaveLength(0);
+  });
+
+  test("Short cards are reported as rejected with their word count", async () => {
+    authCookie = await getAuthCookie();
+
+    const json = await previewSplit(
+      authCookie,
+      ["# Short tag", "https://example.com/short", "Too short."].join("\n")
+    );
+    expect(json).toHaveLength(1);
+    expect(json[0].word_count).toBe(2);
+    expect(json[0].rejection_reason).toBe(
+      "Card content must be at least 70 words long"
+    );
+  });
+});
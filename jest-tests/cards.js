@@ -0,0 +1,19 @@
+import fetch from "node-fetch";
+
+const api_endpoint = process.env.API_ENDPOINT || "http://localhost:8090/api";
+
+// Cards must be at least 70 words, the marker keeps each one from colliding with the last run
+export const cardText = (marker, sentence) => `${marker}. ` + `${sentence} `.repeat(4);
+
+export const createCard = async (authCookie, card_html) => {
+  const response = await fetch(`${api_endpoint}/card`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Cookie: authCookie,
+    },
+    credentials: "include",
+    body: JSON.stringify({ card_html, private: true }),
+  });
+  return response.json();
+};
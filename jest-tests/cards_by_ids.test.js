@@ -0,0 +1,62 @@
+import fetch from "node-fetch";
+import { randomUUID } from "crypto";
+import { getAuthCookie } from "./auth";
+import { cardText, createCard } from "./cards";
+
+const api_endpoint = process.env.API_ENDPOINT || "http://localhost:8090/api";
+
+const orderedCardText = (marker) =>
+  cardText(
+    `Citation order test ${marker}`,
+    "Citation lists number cards in the order the client asked for them, so the server has to hand them back in exactly that order."
+  );
+
+const createOrderedCard = async (authCookie, marker) => {
+  const json = await createCard(
+    authCookie,
+    `<p>${orderedCardText(marker)}</p>`
+  );
+  return json.card_metadata.id;
+};
+
+describe("Cards By Ids Tests", () => {
+  test("Cards come back in the requested order and missing ids are left out", async () => {
+    const authCookie = await getAuthCookie();
+
+    const firstCardId = await createOrderedCard(
+      authCookie,
+      `${Date.now()} first`
+    );
+    const secondCardId = await createOrderedCard(
+      authCookie,
+      `${Date.now()} second`
+    );
+    const thirdCardId = await createOrderedCard(
+      authCookie,
+      `${Date.now()} third`
+    );
+
+    const response = await fetch(`${api_endpoint}/card/by-ids`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+        Cookie: authCookie,
+      },
+      credentials: "include",
+      body: JSON.stringify([
+        thirdCardId,
+        randomUUID(),
+        firstCardId,
+        secondCardId,
+      ]),
+    });
+
+    expect(response.status).toBe(200);
+    const cards = await response.json();
+    expect(cards.map((card) => card.id)).toEqual([
+      thirdCardId,
+      firstCardId,
+      secondCardId,
+    ]);
+  }, 40000);
+});
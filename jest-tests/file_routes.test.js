@@ -4,6 +4,64 @@ import fetch from "node-fetch";
 import { getAuthCookie } from "./auth";
 
 const api_endpoint = process.env.API_ENDPOINT || "http://localhost:8090/api";
+const qdrant_endpoint =
+  process.env.QDRANT_REST_ENDPOINT || "http://localhost:6333";
+
+const uploadFirstDocxFile = async (authCookie) => {
+  const directoryPath = "./demo-files/";
+  const files = readdirSync(directoryPath);
+  const docxFile = files.find((file) => file.endsWith(".docx"));
+  const filePath = `${directoryPath}/${docxFile}`;
+
+  const createFileResponse = await fetch(`${api_endpoint}/file`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Cookie: authCookie,
+    },
+    credentials: "include",
+    body: JSON.stringify({
+      base64_docx_file: readFileSync(filePath, "base64url"),
+      file_name: docxFile,
+      file_mime_type: mime.getType(filePath),
+      private: false,
+    }),
+  });
+
+  expect(createFileResponse.status).toBe(200);
+
+  return createFileResponse.json();
+};
+
+const deleteFile = async (authCookie, fileId) => {
+  const deleteFileResponse = await fetch(`${api_endpoint}/file/${fileId}`, {
+    method: "DELETE",
+    headers: {
+      Cookie: authCookie,
+    },
+    credentials: "include",
+  });
+
+  expect(deleteFileResponse.status).toBe(204);
+};
+
+const getQdrantPoints = async (pointIds) => {
+  const pointsResponse = await fetch(
+    `${qdrant_endpoint}/collections/debate_cards/points`,
+    {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ ids: pointIds }),
+    }
+  );
+
+  expect(pointsResponse.ok).toBe(true);
+
+  const pointsData = await pointsResponse.json();
+  return pointsData.result;
+};
 
 describe("File Upload and Download Test", () => {
   let authCookie = null;
@@ -99,5 +157,43 @@ describe("File Upload and Download Test", () => {
       file.endsWith("(downloaded).docx")
     );
     expect(deletedFile).toBeUndefined();
+
+    // Remove the upload so its cards don't collide with later uploads
+    await deleteFile(authCookie, createdFileId);
   }, 40000);
 });
+
+describe("File Deletion Test", () => {
+  test("Deleting a file removes its cards' points from the vector store", async () => {
+    const authCookie = await getAuthCookie();
+
+    const createData = await uploadFirstDocxFile(authCookie);
+    expect(createData.created_cards.length).toBeGreaterThan(0);
+
+    const bookmarksResponse = await fetch(
+      `${api_endpoint}/card_collection/${createData.collection_id}/1`,
+      {
+        method: "GET",
+        headers: {
+          Cookie: authCookie,
+        },
+        credentials: "include",
+      }
+    );
+    expect(bookmarksResponse.status).toBe(200);
+
+    const bookmarksData = await bookmarksResponse.json();
+    const pointIds = bookmarksData.bookmarks.map(
+      (bookmark) => bookmark.metadata[0].qdrant_point_id
+    );
+    expect(pointIds.length).toBeGreaterThan(0);
+
+    const pointsBeforeDelete = await getQdrantPoints(pointIds);
+    expect(pointsBeforeDelete.length).toBe(pointIds.length);
+
+    await deleteFile(authCookie, createData.file_metadata.id);
+
+    const pointsAfterDelete = await getQdrantPoints(pointIds);
+    expect(pointsAfterDelete).toHaveLength(0);
+  }, 60000);
+});
@@ -0,0 +1,34 @@
+import fetch from "node-fetch";
+
+const api_endpoint = process.env.API_ENDPOINT || "http://localhost:8090/api";
+
+const searchFullText = (content) =>
+  fetch(`${api_endpoint}/card/fulltextsearch/1`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ content }),
+  });
+
+// Assumes the server runs with LOG_SEARCH_QUERY_USERS unset and the default
+// TRENDING_SEARCH_MIN_USERS of 5
+describe("Trending Searches Tests", () => {
+  test("Anonymously logged queries still trend", async () => {
+    const query = `trending test ${Date.now()}`;
+
+    for (let i = 0; i < 5; i++) {
+      const response = await searchFullText(query);
+      expect(response.status).toBe(200);
+    }
+
+    const response = await fetch(
+      `${api_endpoint}/search/trending?days=1&limit=100`
+    );
+
+    expect(response.status).toBe(200);
+    const trending = await response.json();
+    expect(trending.length).toBeGreaterThan(0);
+    expect(trending).toContainEqual({ query, count: 5 });
+  }, 40000);
+});
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE search_queries (
+    id UUID PRIMARY KEY,
+    query TEXT NOT NULL,
+    search_type TEXT NOT NULL,
+    user_id UUID REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_queries_created_at_idx ON search_queries (created_at);
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON search_queries
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at();
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS update_tsvector_trigger ON card_metadata;
+
+CREATE OR REPLACE FUNCTION update_tsvector() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.card_metadata_tsvector := to_tsvector(NEW.content);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER update_tsvector_trigger
+BEFORE INSERT ON card_metadata
+FOR EACH ROW
+EXECUTE FUNCTION update_tsvector();
+
+DROP INDEX IF EXISTS idx_card_metadata_language;
+
+ALTER TABLE card_metadata DROP COLUMN IF EXISTS language;
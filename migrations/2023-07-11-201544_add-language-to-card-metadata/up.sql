@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE card_metadata ADD COLUMN language TEXT NOT NULL DEFAULT 'english';
+
+CREATE INDEX idx_card_metadata_language ON card_metadata (language);
+
+DROP TRIGGER IF EXISTS update_tsvector_trigger ON card_metadata;
+
+CREATE OR REPLACE FUNCTION update_tsvector() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.card_metadata_tsvector := to_tsvector(NEW.language::regconfig, NEW.content);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER update_tsvector_trigger
+BEFORE INSERT OR UPDATE OF content, language ON card_metadata
+FOR EACH ROW
+EXECUTE FUNCTION update_tsvector();
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE message_versions (
+    id UUID PRIMARY KEY,
+    message_id UUID NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    topic_id UUID NOT NULL REFERENCES topics (id) ON DELETE CASCADE,
+    sort_order INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    role VARCHAR(10) NOT NULL,
+    message_created_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX message_versions_topic_id_sort_order_idx ON message_versions (topic_id, sort_order);
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON message_versions
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at();
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE card_idempotency_keys (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    idempotency_key TEXT NOT NULL,
+    card_metadata_id UUID NOT NULL REFERENCES card_metadata (id) ON DELETE CASCADE,
+    duplicate BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    CONSTRAINT uq_user_idempotency_key UNIQUE (user_id, idempotency_key)
+);
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON card_idempotency_keys
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at();
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS update_updated_at ON card_metadata;
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON card_metadata
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at();
+
+DROP INDEX IF EXISTS idx_card_metadata_view_count;
+
+ALTER TABLE card_metadata DROP COLUMN IF EXISTS view_count;
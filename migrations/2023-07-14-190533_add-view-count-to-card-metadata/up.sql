@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE card_metadata ADD COLUMN view_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_card_metadata_view_count ON card_metadata (view_count);
+
+-- view count flushes shouldn't count as edits to the card
+DROP TRIGGER IF EXISTS update_updated_at ON card_metadata;
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON card_metadata
+FOR EACH ROW
+WHEN (OLD.view_count = NEW.view_count)
+EXECUTE FUNCTION update_updated_at();
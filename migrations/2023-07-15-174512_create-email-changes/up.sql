@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE email_changes (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    new_email VARCHAR(100) NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER update_updated_at
+BEFORE UPDATE ON email_changes
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at();
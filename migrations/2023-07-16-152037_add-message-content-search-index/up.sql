@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Messages are searched with the same expression so the index is used without a stored column
+CREATE INDEX idx_messages_content_tsvector ON messages USING GIN(to_tsvector('english', content));
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE invitations
+DROP CONSTRAINT IF EXISTS invitations_token_key,
+DROP COLUMN IF EXISTS token,
+DROP COLUMN IF EXISTS used;
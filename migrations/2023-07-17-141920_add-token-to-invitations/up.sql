@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE invitations
+ADD COLUMN token TEXT,
+ADD COLUMN used BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE invitations
+SET token = replace(gen_random_uuid()::text || gen_random_uuid()::text, '-', '');
+
+ALTER TABLE invitations
+ALTER COLUMN token SET NOT NULL,
+ADD CONSTRAINT invitations_token_key UNIQUE (token);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS idx_card_metadata_source_author;
+DROP INDEX IF EXISTS idx_card_metadata_source_date;
+
+ALTER TABLE card_metadata
+DROP COLUMN IF EXISTS source_author,
+DROP COLUMN IF EXISTS source_title,
+DROP COLUMN IF EXISTS source_date,
+DROP COLUMN IF EXISTS source_url;
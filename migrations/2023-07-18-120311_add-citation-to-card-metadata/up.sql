@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE card_metadata
+ADD COLUMN source_author TEXT,
+ADD COLUMN source_title TEXT,
+ADD COLUMN source_date DATE,
+ADD COLUMN source_url TEXT;
+
+CREATE INDEX idx_card_metadata_source_author ON card_metadata (source_author);
+CREATE INDEX idx_card_metadata_source_date ON card_metadata (source_date);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS idx_card_metadata_unapproved;
+
+ALTER TABLE card_metadata
+DROP COLUMN IF EXISTS approved;
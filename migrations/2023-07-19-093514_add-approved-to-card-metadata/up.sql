@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE card_metadata
+ADD COLUMN approved BOOLEAN NOT NULL DEFAULT true;
+
+CREATE INDEX idx_card_metadata_unapproved ON card_metadata (created_at) WHERE NOT approved;
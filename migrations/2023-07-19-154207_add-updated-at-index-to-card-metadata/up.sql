@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE INDEX idx_card_metadata_updated_at_id ON card_metadata (updated_at, id);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Usernames that only differ by case or surrounding whitespace keep the oldest account's name,
+-- the others get their id appended so the new index can be created
+UPDATE users
+SET username = btrim(users.username) || '-' || left(users.id::text, 8)
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY lower(btrim(username)) ORDER BY created_at, id) AS username_rank
+    FROM users
+    WHERE username IS NOT NULL
+) AS ranked_users
+WHERE users.id = ranked_users.id AND ranked_users.username_rank > 1;
+
+UPDATE users SET username = btrim(username) WHERE username <> btrim(username);
+
+CREATE UNIQUE INDEX idx_users_username_lower ON users (lower(username));
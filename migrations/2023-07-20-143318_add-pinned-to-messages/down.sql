@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS idx_messages_topic_id_pinned;
+
+ALTER TABLE messages DROP COLUMN IF EXISTS pinned;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE messages ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX idx_messages_topic_id_pinned ON messages (topic_id) WHERE pinned;
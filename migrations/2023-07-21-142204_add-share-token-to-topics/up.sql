@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE topics ADD COLUMN share_token TEXT UNIQUE;
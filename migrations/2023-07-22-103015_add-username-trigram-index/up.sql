@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE INDEX idx_users_username_lower_trgm ON users USING gin (lower(username) gin_trgm_ops);
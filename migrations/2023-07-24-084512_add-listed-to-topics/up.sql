@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE topics ADD COLUMN listed BOOLEAN NOT NULL DEFAULT false;
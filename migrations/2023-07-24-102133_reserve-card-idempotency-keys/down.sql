@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM card_idempotency_keys WHERE card_metadata_id IS NULL;
+ALTER TABLE card_idempotency_keys ALTER COLUMN card_metadata_id SET NOT NULL;
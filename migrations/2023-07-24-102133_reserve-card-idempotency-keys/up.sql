@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE card_idempotency_keys ALTER COLUMN card_metadata_id DROP NOT NULL;
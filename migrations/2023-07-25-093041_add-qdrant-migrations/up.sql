@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE qdrant_migrations (
+    name TEXT PRIMARY KEY,
+    completed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
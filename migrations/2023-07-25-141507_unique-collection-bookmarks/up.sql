@@ -0,0 +1,8 @@
+-- Your SQL goes here
+DELETE FROM card_collection_bookmarks a
+USING card_collection_bookmarks b
+WHERE a.collection_id = b.collection_id
+    AND a.card_metadata_id = b.card_metadata_id
+    AND (a.created_at, a.id) > (b.created_at, b.id);
+
+CREATE UNIQUE INDEX idx_card_collection_bookmarks_collection_card ON card_collection_bookmarks (collection_id, card_metadata_id);
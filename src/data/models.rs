@@ -1,5 +1,6 @@
 #![allow(clippy::extra_unused_lifetimes)]
 
+use actix_web::web;
 use diesel::{expression::ValidGrouping, r2d2::ConnectionManager, PgConnection};
 
 use openai_dive::v1::resources::chat_completion::{ChatMessage, Role};
@@ -10,6 +11,11 @@ use super::schema::*;
 // type alias to use in multiple places
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
+// Pool for read only queries. It connects to READ_DATABASE_URL when that is set and shares the
+// primary pool otherwise, so it can be handed to any operator taking a Pool.
+#[derive(Clone)]
+pub struct ReadPool(pub web::Data<Pool>);
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, ValidGrouping)]
 #[diesel(table_name = users)]
 pub struct User {
@@ -47,6 +53,18 @@ pub struct Invitation {
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
     pub referral_tokens: Option<String>,
+    pub token: String,
+    pub used: bool,
+}
+
+// Links sent by email carry this instead of a row id so they can't be guessed
+pub fn generate_secure_token() -> String {
+    let mut token_bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut token_bytes).expect("Failed to generate secure token");
+    token_bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
 // any type that implements Into<String> can be used to create Invitation
@@ -62,6 +80,8 @@ where
             created_at: chrono::Local::now().naive_local(),
             updated_at: chrono::Local::now().naive_local(),
             referral_tokens: None,
+            token: generate_secure_token(),
+            used: false,
         }
     }
 }
@@ -92,6 +112,63 @@ where
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, ValidGrouping)]
+#[diesel(table_name = email_changes)]
+pub struct EmailChange {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub new_email: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl EmailChange {
+    pub fn from_details<S: Into<String>>(user_id: uuid::Uuid, new_email: S) -> Self {
+        EmailChange {
+            id: uuid::Uuid::new_v4(),
+            user_id,
+            new_email: new_email.into(),
+            expires_at: chrono::Local::now().naive_local() + chrono::Duration::minutes(30),
+            created_at: chrono::Local::now().naive_local(),
+            updated_at: chrono::Local::now().naive_local(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, ValidGrouping, Clone)]
+#[diesel(table_name = user_sessions)]
+pub struct UserSession {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl UserSession {
+    pub fn from_details(user_id: uuid::Uuid, user_agent: Option<String>) -> Self {
+        UserSession {
+            id: uuid::Uuid::new_v4(),
+            user_id,
+            user_agent,
+            created_at: chrono::Local::now().naive_local(),
+            last_seen_at: chrono::Local::now().naive_local(),
+            revoked_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSessionDTO {
+    pub id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen_at: chrono::NaiveDateTime,
+    pub current: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, ValidGrouping)]
 #[diesel(table_name = topics)]
 pub struct Topic {
@@ -103,6 +180,8 @@ pub struct Topic {
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
     pub normal_chat: bool,
+    pub share_token: Option<String>,
+    pub listed: bool,
 }
 
 impl Topic {
@@ -120,10 +199,41 @@ impl Topic {
             created_at: chrono::Local::now().naive_local(),
             updated_at: chrono::Local::now().naive_local(),
             normal_chat: normal_chat.unwrap_or(false),
+            share_token: None,
+            listed: false,
         }
     }
 }
 
+// What anyone holding a share link sees, without ids or the owner
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedTopicDTO {
+    pub share_token: String,
+    pub resolution: String,
+    pub normal_chat: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl SharedTopicDTO {
+    pub fn from_topic(topic: Topic) -> Option<Self> {
+        Some(SharedTopicDTO {
+            share_token: topic.share_token?,
+            resolution: topic.resolution,
+            normal_chat: topic.normal_chat,
+            created_at: topic.created_at,
+            updated_at: topic.updated_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedMessageDTO {
+    pub role: String,
+    pub content: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Clone)]
 #[diesel(table_name = messages)]
 pub struct Message {
@@ -137,6 +247,8 @@ pub struct Message {
     pub completion_tokens: Option<i32>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub pinned: bool,
+    pub finish_reason: Option<String>,
 }
 
 impl From<Message> for ChatMessage {
@@ -175,6 +287,38 @@ impl Message {
             completion_tokens,
             created_at: chrono::Local::now().naive_local(),
             updated_at: chrono::Local::now().naive_local(),
+            pinned: false,
+            finish_reason: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[diesel(table_name = message_versions)]
+pub struct MessageVersion {
+    pub id: uuid::Uuid,
+    pub message_id: uuid::Uuid,
+    pub topic_id: uuid::Uuid,
+    pub sort_order: i32,
+    pub content: String,
+    pub role: String,
+    pub message_created_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl From<Message> for MessageVersion {
+    fn from(message: Message) -> Self {
+        MessageVersion {
+            id: uuid::Uuid::new_v4(),
+            message_id: message.id,
+            topic_id: message.topic_id,
+            sort_order: message.sort_order,
+            content: message.content,
+            role: message.role,
+            message_created_at: message.created_at,
+            created_at: chrono::Local::now().naive_local(),
+            updated_at: chrono::Local::now().naive_local(),
         }
     }
 }
@@ -231,6 +375,140 @@ impl UserPlan {
         }
     }
 }
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Plan {
+    Free,
+    Silver,
+    Gold,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlanFeatures {
+    pub max_topics: Option<i64>,
+    pub max_cards: Option<i64>,
+    pub max_files: Option<i64>,
+    pub max_file_storage_bytes: Option<i64>,
+    pub max_messages_per_topic: Option<i64>,
+    pub completions_per_minute: u64,
+    pub models: Vec<String>,
+}
+
+impl Plan {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "free" => Some(Plan::Free),
+            "silver" => Some(Plan::Silver),
+            "gold" => Some(Plan::Gold),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Plan::Free => "free",
+            Plan::Silver => "silver",
+            Plan::Gold => "gold",
+        }
+    }
+
+    pub fn from_stripe_price_id(price_id: &str) -> Option<Self> {
+        let silver_plan_id =
+            std::env::var("STRIPE_SILVER_PLAN_ID").expect("STRIPE_SILVER_PLAN_ID must be set");
+        let gold_plan_id =
+            std::env::var("STRIPE_GOLD_PLAN_ID").expect("STRIPE_GOLD_PLAN_ID must be set");
+
+        if price_id == silver_plan_id {
+            Some(Plan::Silver)
+        } else if price_id == gold_plan_id {
+            Some(Plan::Gold)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_checkout_amount(amount: i64) -> Option<Self> {
+        match amount {
+            999 => Some(Plan::Silver),
+            4999 => Some(Plan::Gold),
+            _ => None,
+        }
+    }
+
+    // None means the plan has no limit on that resource
+    pub fn features(&self) -> PlanFeatures {
+        let (max_topics, max_cards, completions_per_minute, models) = match self {
+            Plan::Free => (Some(20), Some(200), 10, vec!["gpt-3.5-turbo"]),
+            Plan::Silver => (Some(200), Some(2000), 30, vec!["gpt-3.5-turbo"]),
+            Plan::Gold => (None, None, 60, vec!["gpt-3.5-turbo", "gpt-4"]),
+        };
+        let (max_files, max_file_storage_bytes) = match self {
+            Plan::Free => (Some(10), Some(50 * 1024 * 1024)),
+            Plan::Silver => (Some(100), Some(1024 * 1024 * 1024)),
+            Plan::Gold => (None, None),
+        };
+        // long topics send ever larger contexts to OpenAI, forking starts a fresh one
+        let max_messages_per_topic = match self {
+            Plan::Free => Some(50),
+            Plan::Silver => Some(200),
+            Plan::Gold => Some(1000),
+        };
+
+        PlanFeatures {
+            max_topics,
+            max_cards,
+            max_files,
+            max_file_storage_bytes,
+            max_messages_per_topic,
+            completions_per_minute,
+            models: models.into_iter().map(|model| model.to_string()).collect(),
+        }
+    }
+}
+
+// Users without a subscription are on the free plan and have no UserPlan row
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPlanDTO {
+    pub plan: Plan,
+    pub features: PlanFeatures,
+    pub subscription: Option<UserPlan>,
+}
+
+impl From<Option<UserPlan>> for UserPlanDTO {
+    fn from(subscription: Option<UserPlan>) -> Self {
+        let plan = subscription
+            .as_ref()
+            .and_then(|user_plan| Plan::from_name(&user_plan.plan))
+            .unwrap_or(Plan::Free);
+
+        UserPlanDTO {
+            plan,
+            features: plan.features(),
+            subscription,
+        }
+    }
+}
+
+// What the frontend needs to gate features, status is None for users on the free plan
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserEntitlementsDTO {
+    pub plan: Plan,
+    pub status: Option<String>,
+    pub features: PlanFeatures,
+}
+
+impl From<UserPlanDTO> for UserEntitlementsDTO {
+    fn from(user_plan: UserPlanDTO) -> Self {
+        UserEntitlementsDTO {
+            plan: user_plan.plan,
+            status: user_plan
+                .subscription
+                .map(|subscription| subscription.status),
+            features: user_plan.features,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Queryable)]
 pub struct CardMetadataWithCount {
     pub id: uuid::Uuid,
@@ -243,9 +521,22 @@ pub struct CardMetadataWithCount {
     pub oc_file_path: Option<String>,
     pub card_html: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
     pub count: i64,
 }
 
+// Where a card's evidence was published, kept apart from the freeform link
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CardCitation {
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Selectable, Clone)]
 #[diesel(table_name = card_metadata)]
 pub struct CardMetadata {
@@ -259,6 +550,12 @@ pub struct CardMetadata {
     pub oc_file_path: Option<String>,
     pub card_html: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
+    pub approved: bool,
+    pub language: String,
 }
 
 impl CardMetadata {
@@ -282,6 +579,12 @@ impl CardMetadata {
             updated_at: chrono::Local::now().naive_local(),
             oc_file_path: oc_file_path.clone(),
             private,
+            source_author: None,
+            source_title: None,
+            source_date: None,
+            source_url: None,
+            approved: true,
+            language: "english".to_string(),
         }
     }
 }
@@ -309,6 +612,41 @@ impl CardMetadata {
             updated_at: chrono::Local::now().naive_local(),
             oc_file_path: oc_file_path.clone(),
             private,
+            source_author: None,
+            source_title: None,
+            source_date: None,
+            source_url: None,
+            approved: true,
+            language: "english".to_string(),
+        }
+    }
+}
+
+impl CardMetadata {
+    pub fn with_citation(self, citation: CardCitation) -> Self {
+        CardMetadata {
+            source_author: citation.source_author,
+            source_title: citation.source_title,
+            source_date: citation.source_date,
+            source_url: citation.source_url,
+            ..self
+        }
+    }
+
+    pub fn with_approval(self, approved: bool) -> Self {
+        CardMetadata { approved, ..self }
+    }
+
+    pub fn with_language(self, language: String) -> Self {
+        CardMetadata { language, ..self }
+    }
+
+    pub fn citation(&self) -> CardCitation {
+        CardCitation {
+            source_author: self.source_author.clone(),
+            source_title: self.source_title.clone(),
+            source_date: self.source_date,
+            source_url: self.source_url.clone(),
         }
     }
 }
@@ -335,6 +673,33 @@ impl CardCollisions {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[diesel(table_name = card_idempotency_keys)]
+pub struct CardIdempotencyKey {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub idempotency_key: String,
+    // None while the request that reserved the key is still creating its card
+    pub card_metadata_id: Option<uuid::Uuid>,
+    pub duplicate: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl CardIdempotencyKey {
+    pub fn reserve(user_id: uuid::Uuid, idempotency_key: String) -> Self {
+        CardIdempotencyKey {
+            id: uuid::Uuid::new_v4(),
+            user_id,
+            idempotency_key,
+            card_metadata_id: None,
+            duplicate: false,
+            created_at: chrono::Local::now().naive_local(),
+            updated_at: chrono::Local::now().naive_local(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Queryable, Insertable, ValidGrouping)]
 #[diesel(table_name = card_votes)]
 pub struct CardVote {
@@ -380,6 +745,10 @@ pub struct CardMetadataWithVotes {
     pub updated_at: chrono::NaiveDateTime,
     pub oc_file_path: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
     pub score: Option<f64>,
 }
 
@@ -400,6 +769,10 @@ pub struct CardMetadataWithVotesWithoutScore {
     pub file_id: Option<uuid::Uuid>,
     pub file_name: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
     pub verification_score: Option<i64>,
 }
 
@@ -424,6 +797,13 @@ impl From<User> for SlimUser {
     }
 }
 
+// Only what an @-mention needs, never the email
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct UserMentionDTO {
+    pub id: uuid::Uuid,
+    pub username: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserDTO {
     pub id: uuid::Uuid,
@@ -553,6 +933,17 @@ pub struct UserDTOWithVotesAndCards {
     pub total_votes_cast: i32,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserStats {
+    pub total_cards_created: i64,
+    pub total_upvotes_received: i32,
+    pub total_downvotes_received: i32,
+    pub total_votes_cast: i32,
+    pub total_collections: i64,
+    pub total_topics: i64,
+    pub tokens_used_this_month: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Queryable)]
 pub struct UserDTOWithScore {
     pub id: uuid::Uuid,
@@ -582,6 +973,10 @@ pub struct FullTextSearchResult {
     pub oc_file_path: Option<String>,
     pub card_html: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
     pub score: Option<f64>,
     pub count: i64,
 }
@@ -600,6 +995,10 @@ impl From<CardMetadata> for FullTextSearchResult {
             card_html: cards.card_html,
             score: None,
             private: cards.private,
+            source_author: cards.source_author,
+            source_title: cards.source_title,
+            source_date: cards.source_date,
+            source_url: cards.source_url,
             count: 0,
         }
     }
@@ -619,6 +1018,10 @@ impl From<CardMetadataWithCount> for FullTextSearchResult {
             card_html: cards.card_html,
             score: None,
             private: cards.private,
+            source_author: cards.source_author,
+            source_title: cards.source_title,
+            source_date: cards.source_date,
+            source_url: cards.source_url,
             count: cards.count,
         }
     }
@@ -642,6 +1045,10 @@ impl From<CardMetadataWithVotesAndFiles> for CardMetadataWithVotesWithoutScore {
             file_id: cards.file_id,
             file_name: cards.file_name,
             private: cards.private,
+            source_author: cards.source_author,
+            source_title: cards.source_title,
+            source_date: cards.source_date,
+            source_url: cards.source_url,
             verification_score: cards.verification_score,
         }
     }
@@ -754,6 +1161,10 @@ pub struct CardMetadataWithVotesAndFiles {
     pub updated_at: chrono::NaiveDateTime,
     pub oc_file_path: Option<String>,
     pub private: bool,
+    pub source_author: Option<String>,
+    pub source_title: Option<String>,
+    pub source_date: Option<chrono::NaiveDate>,
+    pub source_url: Option<String>,
     pub score: Option<f64>,
     pub file_id: Option<uuid::Uuid>,
     pub file_name: Option<String>,
@@ -776,6 +1187,10 @@ impl From<CardMetadataWithVotes> for CardMetadataWithVotesAndFiles {
             updated_at: card.updated_at,
             oc_file_path: card.oc_file_path,
             private: card.private,
+            source_author: card.source_author,
+            source_title: card.source_title,
+            source_date: card.source_date,
+            source_url: card.source_url,
             score: card.score,
             file_id: None,
             file_name: None,
@@ -826,3 +1241,82 @@ impl VerificationNotification {
         }
     }
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Queryable, Insertable)]
+#[diesel(table_name = search_queries)]
+pub struct SearchQuery {
+    pub id: uuid::Uuid,
+    pub query: String,
+    pub search_type: String,
+    pub user_id: Option<uuid::Uuid>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl SearchQuery {
+    pub fn from_details(query: &str, search_type: &str, user_id: Option<uuid::Uuid>) -> Self {
+        SearchQuery {
+            id: uuid::Uuid::new_v4(),
+            query: query.trim().to_lowercase(),
+            search_type: search_type.to_string(),
+            user_id,
+            created_at: chrono::Local::now().naive_local(),
+            updated_at: chrono::Local::now().naive_local(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrendingSearchQuery {
+    pub query: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PageInfo {
+    pub page: i64,
+    pub per_page: i64,
+    pub total_items: i64,
+    pub total_pages: i64,
+    pub has_next: bool,
+    pub has_prev: bool,
+}
+
+impl PageInfo {
+    // Rows to skip for a page, saturating so an absurd page number can't overflow
+    pub fn offset(page: i64, per_page: i64) -> i64 {
+        (page.max(1) - 1).saturating_mul(per_page.max(0))
+    }
+
+    pub fn new(page: i64, per_page: i64, total_items: i64) -> Self {
+        let per_page = per_page.max(1);
+        let total_pages = (total_items as f64 / per_page as f64).ceil() as i64;
+
+        PageInfo {
+            page,
+            per_page,
+            total_items,
+            total_pages,
+            has_next: page < total_pages,
+            has_prev: page > 1,
+        }
+    }
+
+    // For lists that are returned whole
+    pub fn single_page(total_items: usize) -> Self {
+        PageInfo::new(1, total_items as i64, total_items as i64)
+    }
+}
+
+// The shape every list endpoint responds with, the items themselves are always under data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginatedResponse<T> {
+    pub data: T,
+    pub page_info: PageInfo,
+}
+
+impl<T> PaginatedResponse<T> {
+    pub fn new(data: T, page_info: PageInfo) -> Self {
+        PaginatedResponse { data, page_info }
+    }
+}
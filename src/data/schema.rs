@@ -48,6 +48,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    card_idempotency_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        idempotency_key -> Text,
+        card_metadata_id -> Nullable<Uuid>,
+        duplicate -> Bool,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::Tsvector;
@@ -64,6 +76,13 @@ diesel::table! {
         card_html -> Nullable<Text>,
         private -> Bool,
         card_metadata_tsvector -> Nullable<Tsvector>,
+        language -> Text,
+        view_count -> Int8,
+        source_author -> Nullable<Text>,
+        source_title -> Nullable<Text>,
+        source_date -> Nullable<Date>,
+        source_url -> Nullable<Text>,
+        approved -> Bool,
     }
 }
 
@@ -99,6 +118,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    email_changes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        new_email -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     files (id) {
         id -> Uuid,
@@ -120,6 +150,22 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         referral_tokens -> Nullable<Text>,
+        token -> Text,
+        used -> Bool,
+    }
+}
+
+diesel::table! {
+    message_versions (id) {
+        id -> Uuid,
+        message_id -> Uuid,
+        topic_id -> Uuid,
+        sort_order -> Int4,
+        content -> Text,
+        role -> Varchar,
+        message_created_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -135,6 +181,8 @@ diesel::table! {
         completion_tokens -> Nullable<Int4>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        pinned -> Bool,
+        finish_reason -> Nullable<Text>,
     }
 }
 
@@ -148,6 +196,24 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    qdrant_migrations (name) {
+        name -> Text,
+        completed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    search_queries (id) {
+        id -> Uuid,
+        query -> Text,
+        search_type -> Text,
+        user_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     stripe_customers (id) {
         id -> Uuid,
@@ -168,6 +234,8 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         normal_chat -> Bool,
+        share_token -> Nullable<Text>,
+        listed -> Bool,
     }
 }
 
@@ -183,6 +251,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -214,15 +293,22 @@ diesel::joinable!(card_collection_bookmarks -> card_collection (collection_id));
 diesel::joinable!(card_collection_bookmarks -> card_metadata (card_metadata_id));
 diesel::joinable!(card_files -> card_metadata (card_id));
 diesel::joinable!(card_files -> files (file_id));
+diesel::joinable!(card_idempotency_keys -> card_metadata (card_metadata_id));
+diesel::joinable!(card_idempotency_keys -> users (user_id));
 diesel::joinable!(card_metadata -> users (author_id));
 diesel::joinable!(card_verification -> card_metadata (card_id));
 diesel::joinable!(card_votes -> card_metadata (card_metadata_id));
 diesel::joinable!(card_votes -> users (voted_user_id));
 diesel::joinable!(collections_from_files -> card_collection (collection_id));
 diesel::joinable!(collections_from_files -> files (file_id));
+diesel::joinable!(email_changes -> users (user_id));
 diesel::joinable!(files -> users (user_id));
+diesel::joinable!(message_versions -> messages (message_id));
+diesel::joinable!(message_versions -> topics (topic_id));
 diesel::joinable!(messages -> topics (topic_id));
+diesel::joinable!(search_queries -> users (user_id));
 diesel::joinable!(topics -> users (user_id));
+diesel::joinable!(user_sessions -> users (user_id));
 diesel::joinable!(verification_notifications -> card_metadata (card_uuid));
 diesel::joinable!(verification_notifications -> card_verification (verification_uuid));
 diesel::joinable!(verification_notifications -> users (user_uuid));
@@ -232,17 +318,23 @@ diesel::allow_tables_to_appear_in_same_query!(
     card_collection_bookmarks,
     card_collisions,
     card_files,
+    card_idempotency_keys,
     card_metadata,
     card_verification,
     card_votes,
     collections_from_files,
+    email_changes,
     files,
     invitations,
+    message_versions,
     messages,
     password_resets,
+    qdrant_migrations,
+    search_queries,
     stripe_customers,
     topics,
     user_plans,
+    user_sessions,
     users,
     verification_notifications,
 );
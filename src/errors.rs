@@ -1,17 +1,36 @@
 use std::convert::From;
 
-use actix_web::{error::ResponseError, HttpResponse};
+use actix_web::{error::ResponseError, http::header, HttpResponse};
 use derive_more::Display;
+use diesel::r2d2::PoolError;
 use diesel::result::{DatabaseErrorKind, Error as DBError};
 use serde::{Deserialize, Serialize};
 use uuid::Error as ParseError;
 
-#[derive(Serialize, Deserialize, Debug, Display, derive_more::Error)]
+#[derive(Serialize, Deserialize, Debug, Default, Display, derive_more::Error)]
 #[display(fmt = "{}", message)]
 pub struct DefaultError {
     pub message: &'static str,
+    #[serde(skip)]
+    pub kind: ErrorKind,
 }
 
+// Operators only return DefaultError, so the kind is how handlers tell an unreachable vector
+// store or an exhausted database pool apart from a bad request
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    #[default]
+    BadRequest,
+    VectorStoreUnavailable,
+    DatabaseUnavailable,
+}
+
+pub const VECTOR_STORE_UNAVAILABLE_MESSAGE: &str =
+    "Search is temporarily unavailable, please try again shortly";
+
+pub const DATABASE_UNAVAILABLE_MESSAGE: &str =
+    "The service is temporarily unavailable, please try again shortly";
+
 #[derive(Serialize, Deserialize, Debug, Display)]
 pub struct BadRequestBody {
     pub message: String,
@@ -33,6 +52,15 @@ pub enum ServiceError {
 
     #[display(fmt = "Not Found")]
     NotFound,
+
+    #[display(fmt = "Vector Store Unavailable")]
+    VectorStoreUnavailable,
+
+    #[display(fmt = "Database Unavailable")]
+    DatabaseUnavailable,
+
+    #[display(fmt = "Moderation Unavailable")]
+    ModerationUnavailable,
 }
 
 // impl ResponseError trait allows to convert our errors into http responses with appropriate data
@@ -50,6 +78,21 @@ impl ResponseError for ServiceError {
             ServiceError::Unauthorized => HttpResponse::Unauthorized().json("Unauthorized"),
             ServiceError::Forbidden => HttpResponse::Forbidden().json("Forbidden"),
             ServiceError::NotFound => HttpResponse::NotFound().json("Record not found"),
+            ServiceError::VectorStoreUnavailable => HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, "5"))
+                .json(BadRequestBody {
+                    message: VECTOR_STORE_UNAVAILABLE_MESSAGE.to_string(),
+                }),
+            ServiceError::DatabaseUnavailable => HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, "5"))
+                .json(BadRequestBody {
+                    message: DATABASE_UNAVAILABLE_MESSAGE.to_string(),
+                }),
+            ServiceError::ModerationUnavailable => HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, "5"))
+                .json(BadRequestBody {
+                    message: "Could not check card content, try again later".to_string(),
+                }),
         }
     }
 }
@@ -62,6 +105,32 @@ impl From<ParseError> for ServiceError {
     }
 }
 
+impl From<DefaultError> for ServiceError {
+    fn from(error: DefaultError) -> ServiceError {
+        match error.kind {
+            ErrorKind::VectorStoreUnavailable => ServiceError::VectorStoreUnavailable,
+            ErrorKind::DatabaseUnavailable => ServiceError::DatabaseUnavailable,
+            ErrorKind::BadRequest => ServiceError::BadRequest(error.message.into()),
+        }
+    }
+}
+
+// Lets operators write pool.get()? and have a pool timeout surface as a 503
+impl From<PoolError> for DefaultError {
+    fn from(_: PoolError) -> DefaultError {
+        DefaultError {
+            message: DATABASE_UNAVAILABLE_MESSAGE,
+            kind: ErrorKind::DatabaseUnavailable,
+        }
+    }
+}
+
+impl From<PoolError> for ServiceError {
+    fn from(_: PoolError) -> ServiceError {
+        ServiceError::DatabaseUnavailable
+    }
+}
+
 impl From<DBError> for ServiceError {
     fn from(error: DBError) -> ServiceError {
         // Right now we just care about UniqueViolation from diesel
@@ -0,0 +1,18 @@
+use actix_web::{web, HttpResponse};
+
+use crate::{
+    data::models::Pool, errors::ServiceError, operators::stats_operator::get_corpus_stats_query,
+};
+
+use super::auth_handler::AdminUser;
+
+pub async fn get_corpus_stats(
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let corpus_stats = web::block(move || get_corpus_stats_query(&pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(corpus_stats))
+}
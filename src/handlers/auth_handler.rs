@@ -1,15 +1,18 @@
-use std::future::{ready, Ready};
-
 use actix_identity::Identity;
 use actix_web::{
     dev::Payload, web, Error, FromRequest, HttpMessage as _, HttpRequest, HttpResponse,
 };
 use diesel::prelude::*;
-use serde::Deserialize;
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{Pool, SlimUser, User},
+    data::models::{Pool, SlimUser, User, UserSessionDTO},
     errors::{DefaultError, ServiceError},
+    operators::session_operator::{
+        create_user_session_query, get_active_sessions_query, revoke_all_user_sessions_query,
+        revoke_user_session_query, validate_user_session_query,
+    },
     operators::user_operator::get_user_by_id_query,
 };
 
@@ -21,24 +24,80 @@ pub struct AuthData {
     pub password: String,
 }
 
+// What the identity cookie holds, the session id ties it to a user_sessions row that can be revoked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionIdentity {
+    #[serde(flatten)]
+    pub user: SlimUser,
+    pub session_id: uuid::Uuid,
+}
+
+impl SessionIdentity {
+    pub fn from_request(req: &HttpRequest) -> Option<Self> {
+        let identity = Identity::from_request(req, &mut Payload::None)
+            .into_inner()
+            .ok()?;
+        serde_json::from_str(&identity.id().ok()?).ok()
+    }
+
+    pub fn login(&self, req: &HttpRequest) {
+        let identity_string = serde_json::to_string(self).unwrap();
+        Identity::login(&req.extensions(), identity_string).unwrap();
+    }
+}
+
 // we need the same data
 // simple aliasing makes the intentions clear and its more readable
 pub type LoggedUser = SlimUser;
 
 impl FromRequest for LoggedUser {
     type Error = Error;
-    type Future = Ready<Result<LoggedUser, Error>>;
+    type Future = LocalBoxFuture<'static, Result<LoggedUser, Error>>;
+
+    fn from_request(req: &HttpRequest, _pl: &mut Payload) -> Self::Future {
+        let session_identity = SessionIdentity::from_request(req);
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+
+        Box::pin(async move {
+            // identities from before sessions were tracked have no session id and must log in again
+            let (Some(session_identity), Some(pool)) = (session_identity, pool) else {
+                return Err(ServiceError::Unauthorized.into());
+            };
+
+            let session_id = session_identity.session_id;
+            let user_id = session_identity.user.id;
+            web::block(move || validate_user_session_query(session_id, user_id, &pool))
+                .await?
+                .map_err(|_| ServiceError::Unauthorized)?;
+
+            Ok(session_identity.user)
+        })
+    }
+}
+
+// Admins are the logged in users whose email is listed in ADMIN_EMAILS
+pub struct AdminUser(pub LoggedUser);
+
+impl FromRequest for AdminUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<AdminUser, Error>>;
 
     fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
-        if let Ok(identity) = Identity::from_request(req, pl).into_inner() {
-            if let Ok(user_json) = identity.id() {
-                if let Ok(user) = serde_json::from_str(&user_json) {
-                    return ready(Ok(user));
-                }
+        let logged_user = LoggedUser::from_request(req, pl);
+
+        Box::pin(async move {
+            let user = logged_user.await?;
+
+            let admin_emails = std::env::var("ADMIN_EMAILS").unwrap_or_default();
+            if admin_emails
+                .split(',')
+                .any(|admin_email| admin_email.trim().eq_ignore_ascii_case(&user.email))
+            {
+                return Ok(AdminUser(user));
             }
-        }
 
-        ready(Err(ServiceError::Unauthorized.into()))
+            Err(ServiceError::Forbidden.into())
+        })
     }
 }
 
@@ -55,9 +114,21 @@ pub fn verify(hash: &str, password: &str) -> Result<bool, ServiceError> {
     })
 }
 
-pub async fn logout(id: Identity) -> HttpResponse {
+pub async fn logout(
+    req: HttpRequest,
+    id: Identity,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_identity) = SessionIdentity::from_request(&req) {
+        // the session may already be revoked, logging out still clears the cookie
+        let _ = web::block(move || {
+            revoke_user_session_query(session_identity.session_id, session_identity.user.id, &pool)
+        })
+        .await?;
+    }
+
     id.logout();
-    HttpResponse::NoContent().finish()
+    Ok(HttpResponse::NoContent().finish())
 }
 
 pub async fn login(
@@ -72,16 +143,29 @@ pub async fn login(
     if email.is_empty() || password.is_empty() {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Email or password is empty",
+            ..Default::default()
         }));
     }
 
-    let find_user_result =
-        web::block(move || find_user_match(AuthData { email, password }, pool)).await?;
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| user_agent.chars().take(512).collect::<String>());
+
+    let login_result = web::block(move || {
+        let user = find_user_match(AuthData { email, password }, pool.clone())?;
+        let session = create_user_session_query(user.id, user_agent, &pool)?;
+        Ok::<_, DefaultError>(SessionIdentity {
+            user,
+            session_id: session.id,
+        })
+    })
+    .await?;
 
-    match find_user_result {
-        Ok(user) => {
-            let user_string = serde_json::to_string(&user).unwrap();
-            Identity::login(&req.extensions(), user_string).unwrap();
+    match login_result {
+        Ok(session_identity) => {
+            session_identity.login(&req);
 
             Ok(HttpResponse::NoContent().finish())
         }
@@ -103,10 +187,72 @@ pub async fn get_me(
     }
 }
 
+pub async fn get_sessions(
+    req: HttpRequest,
+    logged_user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id =
+        SessionIdentity::from_request(&req).map(|session_identity| session_identity.session_id);
+
+    let sessions = web::block(move || get_active_sessions_query(logged_user.id, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| UserSessionDTO {
+            id: session.id,
+            user_agent: session.user_agent,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            current: Some(session.id) == current_session_id,
+        })
+        .collect::<Vec<UserSessionDTO>>();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+pub async fn revoke_session(
+    req: HttpRequest,
+    id: Identity,
+    session_id: web::Path<uuid::Uuid>,
+    logged_user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = session_id.into_inner();
+
+    web::block(move || revoke_user_session_query(session_id, logged_user.id, &pool))
+        .await?
+        .map_err(|_| ServiceError::NotFound)?;
+
+    if SessionIdentity::from_request(&req)
+        .is_some_and(|session_identity| session_identity.session_id == session_id)
+    {
+        id.logout();
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Log out everywhere, including the device making the request
+pub async fn revoke_all_sessions(
+    id: Identity,
+    logged_user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    web::block(move || revoke_all_user_sessions_query(logged_user.id, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    id.logout();
+    Ok(HttpResponse::NoContent().finish())
+}
+
 fn find_user_match(auth_data: AuthData, pool: web::Data<Pool>) -> Result<SlimUser, DefaultError> {
     use crate::data::schema::users::dsl::{email, users};
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let mut items = users
         .filter(email.eq(&auth_data.email))
@@ -122,5 +268,6 @@ fn find_user_match(auth_data: AuthData, pool: web::Data<Pool>) -> Result<SlimUse
     }
     Err(DefaultError {
         message: "Incorrect email or password",
+        ..Default::default()
     })
 }
@@ -1,23 +1,32 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::data::models::{
-    CardMetadata, CardMetadataWithVotesAndFiles, CardMetadataWithVotesWithoutScore, Pool,
+    CardCitation, CardIdempotencyKey, CardMetadata, CardMetadataWithVotesAndFiles,
+    CardMetadataWithVotesWithoutScore, PageInfo, PaginatedResponse, Pool, ReadPool, SearchQuery,
 };
-use crate::errors::ServiceError;
+use crate::errors::{DefaultError, ServiceError};
 use crate::operators::card_operator::*;
 use crate::operators::card_operator::{
     get_metadata_from_id_query, get_qdrant_connection, search_card_query,
 };
 use crate::operators::collection_operator::get_collection_by_id_query;
-use actix_web::{web, HttpResponse};
+use crate::operators::search_operator::{
+    get_trending_search_queries_query, insert_search_query_query,
+};
+use crate::operators::vote_operator::get_vote_tallies_query;
+use crate::operators::webhook_operator::dispatch_card_created_webhook;
+use actix_web::{web, HttpRequest, HttpResponse};
 use difference::{Changeset, Difference};
+use once_cell::sync::Lazy;
 use qdrant_client::qdrant::points_selector::PointsSelectorOneOf;
 use qdrant_client::qdrant::{PointStruct, PointsIdsList, PointsSelector};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use soup::Soup;
 
-use super::auth_handler::LoggedUser;
+use super::auth_handler::{AdminUser, LoggedUser};
 
 pub async fn user_owns_card(
     user_id: uuid::Uuid,
@@ -26,7 +35,7 @@ pub async fn user_owns_card(
 ) -> Result<CardMetadata, actix_web::Error> {
     let cards = web::block(move || get_metadata_from_id_query(card_id, pool.lock().unwrap()))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     if cards.author_id != user_id {
         return Err(ServiceError::Forbidden.into());
@@ -42,12 +51,112 @@ pub struct CreateCardData {
     pub oc_file_path: Option<String>,
     pub private: Option<bool>,
     pub file_uuid: Option<uuid::Uuid>,
+    pub language: Option<String>,
+    #[serde(flatten)]
+    pub citation: CardCitation,
+    #[serde(default)]
+    pub duplicate_strategy: DuplicateStrategy,
+}
+
+// What happens to a new card that collides with an existing one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateStrategy {
+    // The card is only kept as a duplicate sharing the existing card's vector, unless the
+    // existing card has no html yet, then it gets the new card's html instead
+    #[default]
+    Reject,
+    // The user's own existing card takes over the new card's content and is re-embedded, cards
+    // owned by someone else are handled like Reject
+    UpdateExisting,
+    // Collisions are not checked, the card gets its own vector like any other
+    Allow,
+}
+
+// A card that owns its point gets a fresh vector and payload. Duplicates pointing at that point
+// were already within the dedup threshold of it, so they keep sharing it.
+async fn replace_existing_card_content(
+    existing_card_id: uuid::Uuid,
+    content: &str,
+    card: &CreateCardData,
+    card_language: String,
+    embedding_vector: Option<Vec<f32>>,
+    pool: Arc<Mutex<web::Data<Pool>>>,
+) -> Result<CardMetadata, actix_web::Error> {
+    let content_1 = content.to_string();
+    let card_html = card.card_html.clone();
+    let link = card.link.clone();
+    let oc_file_path = card.oc_file_path.clone();
+    let file_uuid = card.file_uuid;
+    let card_metadata = web::block(move || {
+        replace_card_content_query(
+            existing_card_id,
+            content_1,
+            card_html,
+            link,
+            oc_file_path,
+            card_language,
+            file_uuid,
+            &pool.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    if let Some(qdrant_point_id) = card_metadata.qdrant_point_id {
+        let embedding_vector = match embedding_vector {
+            Some(embedding_vector) => embedding_vector,
+            None => create_openai_embedding(content).await?,
+        };
+        let payload = get_card_qdrant_payload(
+            card_metadata.author_id,
+            &card_metadata.link,
+            &card_metadata.oc_file_path,
+            card_metadata.private,
+            card_metadata.approved,
+            &card_metadata.citation(),
+        );
+
+        let qdrant = get_qdrant_connection().await.map_err(ServiceError::from)?;
+        let point = PointStruct::new(qdrant_point_id.to_string(), embedding_vector, payload);
+        retry_qdrant_operation(
+            || qdrant.upsert_points_blocking("debate_cards", vec![point.clone()], None),
+            |_err| ServiceError::InternalServerError,
+        )
+        .await?;
+    }
+
+    Ok(card_metadata)
+}
+
+pub fn get_card_content_from_html(card_html: &Option<String>) -> Result<String, &'static str> {
+    let content = Soup::new(card_html.as_ref().unwrap_or(&"".to_string()).as_str())
+        .text()
+        .lines()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim()
+        .to_string();
+
+    if !content.chars().any(|c| c.is_alphanumeric()) {
+        return Err("Card content must contain text");
+    }
+
+    let words_in_content = content.split_whitespace().count();
+    if words_in_content < 70 {
+        return Err("Card content must be at least 70 words long");
+    }
+
+    Ok(content)
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ReturnCreatedCard {
     pub card_metadata: CardMetadata,
     pub duplicate: bool,
+    // Set when the content was too long to embed in full
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
 }
 
 pub async fn create_card(
@@ -55,43 +164,150 @@ pub async fn create_card(
     pool: web::Data<Pool>,
     user: LoggedUser,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let created_card =
+        create_card_with_embedding(card, pool, user, None, CARD_DEDUP_THRESHOLD).await?;
+
+    Ok(HttpResponse::Ok().json(created_card))
+}
+
+// Thresholds are cosine similarities, see normalize_similarity_score. File uploads pass their own
+// threshold, see file_dedup_threshold.
+pub const CARD_DEDUP_THRESHOLD: f32 = 0.95;
+// Short cards are held to a slightly lower threshold than the one passed in
+const SHORT_CARD_DEDUP_THRESHOLD_OFFSET: f32 = 0.03;
+// A full text match scoring at least this is the same card, no embedding needed to tell
+const FULL_TEXT_DEDUP_THRESHOLD: f64 = 0.85;
+
+// The read only half of the text collision check create_card_with_embedding runs, returns the
+// card the content collides with
+pub async fn find_full_text_collision(
+    content: String,
+    language: String,
+    pool: web::Data<Pool>,
+) -> Result<Option<uuid::Uuid>, actix_web::Error> {
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let top_text_result = web::block(move || {
+        global_top_full_text_card_query(content, language, thread_safe_pool.lock().unwrap())
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(top_text_result
+        .filter(|score_card| score_card.score >= Some(FULL_TEXT_DEDUP_THRESHOLD))
+        .map(|score_card| score_card.id))
+}
+
+// The read only half of the semantic collision check, returns the card the content collides with
+pub async fn find_semantic_collision(
+    embedding_vector: Vec<f32>,
+    content: &str,
+    dedup_threshold: f32,
+    user_id: uuid::Uuid,
+    pool: web::Data<Pool>,
+) -> Result<Option<uuid::Uuid>, actix_web::Error> {
+    let top_semantic_result = global_unfiltered_top_match_query(embedding_vector)
+        .await
+        .map_err(ServiceError::from)?;
+
+    let mut similarity_threshold = dedup_threshold;
+    if content.len() < 200 {
+        similarity_threshold = dedup_threshold - SHORT_CARD_DEDUP_THRESHOLD_OFFSET;
+    }
+    if normalize_similarity_score(top_semantic_result.score) < similarity_threshold {
+        return Ok(None);
+    }
+
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let score_cards = web::block(move || {
+        get_metadata_from_point_ids(
+            vec![top_semantic_result.point_id],
+            Some(user_id),
+            thread_safe_pool.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(score_cards.first().map(|score_card| score_card.id))
+}
+
+// Callers creating many cards at once can embed them in a batch up front and pass the vector in
+pub async fn create_card_with_embedding(
+    mut card: web::Json<CreateCardData>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+    mut precomputed_embedding_vector: Option<Vec<f32>>,
+    dedup_threshold: f32,
+) -> Result<ReturnCreatedCard, actix_web::Error> {
     let private = card.private.unwrap_or(false);
+    let duplicate_strategy = card.duplicate_strategy;
     let mut collision: Option<uuid::Uuid> = None;
     let mut embedding_vector: Option<Vec<f32>> = None;
+    let mut embedding_truncated = false;
     let thread_safe_pool = Arc::new(Mutex::new(pool));
 
     let pool1 = thread_safe_pool.clone();
     let pool2 = thread_safe_pool.clone();
     let pool3 = thread_safe_pool.clone();
 
-    let content = Soup::new(card.card_html.as_ref().unwrap_or(&"".to_string()).as_str())
-        .text()
-        .lines()
-        .collect::<Vec<&str>>()
-        .join(" ")
-        .trim_end()
-        .to_string();
+    card.card_html = card.card_html.as_deref().map(sanitize_card_html);
+    let content = match get_card_content_from_html(&card.card_html) {
+        Ok(content) => content,
+        Err(message) => {
+            return Err(ServiceError::BadRequest(message.into()).into());
+        }
+    };
+    screen_card_content(&content).await?;
 
-    let words_in_content = content.split(' ').collect::<Vec<&str>>().len();
-    if words_in_content < 70 {
-        return Ok(HttpResponse::BadRequest().json(json!({
-            "message": "Card content must be at least 70 words long",
-        })));
+    let card_language = card.language.clone().unwrap_or("english".to_string());
+    if !is_supported_full_text_search_language(&card_language) {
+        return Err(ServiceError::BadRequest("Unsupported card language".into()).into());
     }
+    let card_language_1 = card_language.clone();
 
     // // text based similarity check to avoid paying for openai api call if not necessary
     let card_content_1 = content.clone();
-    let first_text_result = web::block(move || {
-        global_top_full_text_card_query(card_content_1, thread_safe_pool.lock().unwrap())
-    })
-    .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    let first_text_result = if duplicate_strategy == DuplicateStrategy::Allow {
+        None
+    } else {
+        web::block(move || {
+            global_top_full_text_card_query(
+                card_content_1,
+                card_language_1,
+                thread_safe_pool.lock().unwrap(),
+            )
+        })
+        .await?
+        .map_err(ServiceError::from)?
+    };
 
     if let Some(score_card) = first_text_result {
-        if score_card.score >= Some(0.85) {
+        if score_card.score >= Some(FULL_TEXT_DEDUP_THRESHOLD) {
             //Sets collision to collided card id
             collision = Some(score_card.qdrant_point_id);
 
+            let owns_score_card = score_card
+                .author
+                .as_ref()
+                .is_some_and(|author| author.id == user.id);
+            if duplicate_strategy == DuplicateStrategy::UpdateExisting && owns_score_card {
+                let card_metadata = replace_existing_card_content(
+                    score_card.id,
+                    &content,
+                    &card,
+                    card_language.clone(),
+                    None,
+                    pool3.clone(),
+                )
+                .await?;
+
+                return Ok(ReturnCreatedCard {
+                    card_metadata,
+                    duplicate: true,
+                    warning: None,
+                });
+            }
+
             if score_card.card_html.is_none() {
                 let score_card_1 = score_card.clone();
                 let card_metadata = CardMetadata::from_details_with_id(
@@ -102,44 +318,54 @@ pub async fn create_card(
                     &card.oc_file_path,
                     score_card_1.author.clone().unwrap().id,
                     Some(score_card_1.qdrant_point_id),
-                    card.private.unwrap_or(score_card_1.private),
+                    score_card_1.private,
                 );
                 let metadata_1 = card_metadata.clone();
                 web::block(move || {
                     update_card_metadata_query(card_metadata, card.file_uuid, pool3.lock().unwrap())
                 })
                 .await?
-                .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+                .map_err(ServiceError::from)?;
 
-                return Ok(HttpResponse::Ok().json(ReturnCreatedCard {
+                return Ok(ReturnCreatedCard {
                     card_metadata: metadata_1,
                     duplicate: true,
-                }));
+                    warning: None,
+                });
             }
         }
     }
 
     // only check for embedding similarity if no text based collision was found
-    if collision.is_none() {
-        let openai_embedding_vector = create_openai_embedding(&content).await?;
+    if collision.is_none() && duplicate_strategy != DuplicateStrategy::Allow {
+        let openai_embedding_vector = match precomputed_embedding_vector.take() {
+            Some(embedding_vector) => embedding_vector,
+            None => {
+                let (embedding_vector, truncated) =
+                    create_openai_embedding_with_truncation(&content).await?;
+                embedding_truncated = truncated;
+                embedding_vector
+            }
+        };
         embedding_vector = Some(openai_embedding_vector.clone());
 
         let first_semantic_result =
             global_unfiltered_top_match_query(openai_embedding_vector.clone())
                 .await
-                .map_err(|err| {
-                    ServiceError::BadRequest(format!(
+                .map_err(|err| match ServiceError::from(err) {
+                    ServiceError::BadRequest(message) => ServiceError::BadRequest(format!(
                         "Could not get semantic similarity for collision check: {}",
-                        err.message
-                    ))
+                        message
+                    )),
+                    err => err,
                 })?;
 
-        let mut similarity_threshold = 0.95;
+        let mut similarity_threshold = dedup_threshold;
         if content.len() < 200 {
-            similarity_threshold = 0.92;
+            similarity_threshold = dedup_threshold - SHORT_CARD_DEDUP_THRESHOLD_OFFSET;
         }
 
-        if first_semantic_result.score >= similarity_threshold {
+        if normalize_similarity_score(first_semantic_result.score) >= similarity_threshold {
             //Sets collision to collided card id
             collision = Some(first_semantic_result.point_id);
 
@@ -177,6 +403,26 @@ pub async fn create_card(
                 }
             };
 
+            if duplicate_strategy == DuplicateStrategy::UpdateExisting
+                && top_score_card_author_id == user.id
+            {
+                let card_metadata = replace_existing_card_content(
+                    top_score_card.id,
+                    &content,
+                    &card,
+                    card_language.clone(),
+                    Some(openai_embedding_vector),
+                    pool3.clone(),
+                )
+                .await?;
+
+                return Ok(ReturnCreatedCard {
+                    card_metadata,
+                    duplicate: true,
+                    warning: None,
+                });
+            }
+
             if top_score_card.card_html.is_none() {
                 let card_metadata = CardMetadata::from_details_with_id(
                     top_score_card.id,
@@ -194,18 +440,20 @@ pub async fn create_card(
                     update_card_metadata_query(card_metadata, card.file_uuid, pool3.lock().unwrap())
                 })
                 .await?
-                .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+                .map_err(ServiceError::from)?;
 
-                return Ok(HttpResponse::Ok().json(ReturnCreatedCard {
+                return Ok(ReturnCreatedCard {
                     card_metadata: metadata_1,
                     duplicate: true,
-                }));
+                    warning: None,
+                });
             }
         }
     }
 
     let mut card_metadata: CardMetadata;
     let mut duplicate: bool = false;
+    let approved = !card_approval_required();
 
     //if collision is not nil, insert card with collision
     if collision.is_some() {
@@ -217,7 +465,10 @@ pub async fn create_card(
             user.id,
             None,
             private,
-        );
+        )
+        .with_citation(card.citation.clone())
+        .with_approval(approved)
+        .with_language(card_language);
         card_metadata = web::block(move || {
             insert_duplicate_card_metadata_query(
                 card_metadata,
@@ -227,30 +478,32 @@ pub async fn create_card(
             )
         })
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
         duplicate = true;
     }
-    //if collision is nil and embedding vector is some, insert card with no collision
+    //if collision is nil, insert card with its own vector
     else {
-        // if this statement is reached, the embedding vector must be some
-        let ensured_embedding_vector = match embedding_vector {
+        // cards allowed to be duplicates skip the similarity check, so they are embedded here
+        let ensured_embedding_vector = match embedding_vector.or(precomputed_embedding_vector) {
             Some(embedding_vector) => embedding_vector,
             None => {
-                return Ok(HttpResponse::BadRequest().json(json!({
-                    "message": "Could not create embedding vector",
-                })))
+                let (embedding_vector, truncated) =
+                    create_openai_embedding_with_truncation(&content).await?;
+                embedding_truncated = truncated;
+                embedding_vector
             }
         };
 
-        let qdrant = get_qdrant_connection()
-            .await
-            .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
-        //if private is true, set payload to private
-        let payload = match private {
-            true => json!({"private": true}).try_into().unwrap(),
-            false => json!({}).try_into().unwrap(),
-        };
+        let qdrant = get_qdrant_connection().await.map_err(ServiceError::from)?;
+        let payload = get_card_qdrant_payload(
+            user.id,
+            &card.link,
+            &card.oc_file_path,
+            private,
+            approved,
+            &card.citation,
+        );
 
         let point_id = uuid::Uuid::new_v4();
         let point = PointStruct::new(
@@ -266,23 +519,140 @@ pub async fn create_card(
             user.id,
             Some(point_id),
             private,
-        );
-        card_metadata = web::block(move || {
+        )
+        .with_citation(card.citation.clone())
+        .with_approval(approved)
+        .with_language(card_language);
+
+        // upsert the vector first so a committed card is never left without one
+        retry_qdrant_operation(
+            || qdrant.upsert_points_blocking("debate_cards", vec![point.clone()], None),
+            |_err| ServiceError::InternalServerError,
+        )
+        .await?;
+
+        let insert_result = web::block(move || {
             insert_card_metadata_query(card_metadata, card.file_uuid, pool1.lock().unwrap())
         })
-        .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .await?;
+
+        card_metadata = match insert_result {
+            Ok(card_metadata) => card_metadata,
+            Err(_err) => {
+                let deleted_values = PointsSelector {
+                    points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                        ids: vec![point_id.to_string().into()],
+                    })),
+                };
+                retry_qdrant_operation(
+                    || qdrant.delete_points_blocking("debate_cards", &deleted_values, None),
+                    |_err| ServiceError::InternalServerError,
+                )
+                .await?;
 
-        qdrant
-            .upsert_points_blocking("debate_cards".to_string(), vec![point], None)
-            .await
-            .map_err(|_err| ServiceError::BadRequest("Failed inserting card to qdrant".into()))?;
+                return Err(ServiceError::InternalServerError.into());
+            }
+        };
     }
 
-    Ok(HttpResponse::Ok().json(ReturnCreatedCard {
+    dispatch_card_created_webhook(&card_metadata);
+
+    Ok(ReturnCreatedCard {
         card_metadata,
         duplicate,
-    }))
+        warning: (embedding_truncated && !duplicate).then(|| {
+            "Card content was too long to embed in full, only its start is searchable by meaning"
+                .to_string()
+        }),
+    })
+}
+
+// Retried requests carrying the same Idempotency-Key get the originally created card back
+pub async fn create_card_handler(
+    req: HttpRequest,
+    card: web::Json<CreateCardData>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let idempotency_key = match req.headers().get("Idempotency-Key") {
+        Some(header) => Some(
+            header
+                .to_str()
+                .map_err(|_err| ServiceError::BadRequest("Invalid Idempotency-Key".into()))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let idempotency_key = match idempotency_key {
+        Some(idempotency_key) => idempotency_key,
+        None => return create_card(card, pool, user).await,
+    };
+
+    let thread_safe_pool = Arc::new(Mutex::new(pool.clone()));
+    let pool1 = thread_safe_pool.clone();
+    let pool2 = thread_safe_pool.clone();
+    let user_id = user.id;
+    let idempotency_key_1 = idempotency_key.clone();
+    let idempotency_key_2 = idempotency_key.clone();
+
+    let reservation = web::block(move || {
+        reserve_card_idempotency_key_query(
+            CardIdempotencyKey::reserve(user_id, idempotency_key_1),
+            thread_safe_pool.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    match reservation {
+        CardIdempotencyKeyReservation::Reserved => {}
+        CardIdempotencyKeyReservation::InProgress => {
+            return Ok(HttpResponse::Conflict().json(json!({
+                "message": "A request with this Idempotency-Key is still in progress",
+            })));
+        }
+        CardIdempotencyKeyReservation::Completed(card_metadata, duplicate) => {
+            return Ok(HttpResponse::Ok().json(ReturnCreatedCard {
+                card_metadata,
+                duplicate,
+                warning: None,
+            }));
+        }
+    }
+
+    let created_card =
+        match create_card_with_embedding(card, pool, user, None, CARD_DEDUP_THRESHOLD).await {
+            Ok(created_card) => created_card,
+            Err(err) => {
+                web::block(move || {
+                    release_card_idempotency_key_query(
+                        user_id,
+                        idempotency_key_2,
+                        pool2.lock().unwrap(),
+                    )
+                })
+                .await?
+                .map_err(ServiceError::from)?;
+
+                return Err(err);
+            }
+        };
+
+    let created_card_id = created_card.card_metadata.id;
+    let duplicate = created_card.duplicate;
+    web::block(move || {
+        complete_card_idempotency_key_query(
+            user_id,
+            idempotency_key,
+            created_card_id,
+            duplicate,
+            pool1.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(created_card))
 }
 
 pub async fn delete_card(
@@ -291,14 +661,63 @@ pub async fn delete_card(
     user: LoggedUser,
 ) -> Result<HttpResponse, actix_web::Error> {
     let thread_safe_pool = Arc::new(Mutex::new(pool));
-    let card_id_inner = card_id.into_inner();
-    let pool1 = thread_safe_pool.clone();
+    let card_metadata =
+        user_owns_card(user.id, card_id.into_inner(), thread_safe_pool.clone()).await?;
+
+    delete_card_and_point(card_metadata, thread_safe_pool).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+const MAX_BULK_DELETE_CARDS: usize = 500;
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkDeleteCardsDTO {
+    pub deleted: usize,
+    pub skipped: usize,
+}
+
+// Ids the caller does not own, or that do not exist, are counted as skipped rather than failing the request
+pub async fn bulk_delete_cards(
+    card_ids: web::Json<Vec<uuid::Uuid>>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut card_ids = card_ids.into_inner();
+    card_ids.sort();
+    card_ids.dedup();
+
+    if card_ids.len() > MAX_BULK_DELETE_CARDS {
+        return Err(ServiceError::BadRequest(format!(
+            "Cannot delete more than {} cards at once",
+            MAX_BULK_DELETE_CARDS
+        ))
+        .into());
+    }
+    let requested_count = card_ids.len();
 
-    let card_metadata = user_owns_card(user.id, card_id_inner, thread_safe_pool).await?;
+    let deleted_cards =
+        web::block(move || delete_owned_cards_metadata_query(card_ids, user.id, &pool))
+            .await?
+            .map_err(ServiceError::from)?;
 
-    let qdrant = get_qdrant_connection()
+    delete_qdrant_points_query(&deleted_cards.orphaned_qdrant_point_ids)
         .await
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(BulkDeleteCardsDTO {
+        deleted: deleted_cards.deleted_count,
+        skipped: requested_count - deleted_cards.deleted_count,
+    }))
+}
+
+pub async fn delete_card_and_point(
+    card_metadata: CardMetadata,
+    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
+) -> Result<(), actix_web::Error> {
+    let card_id = card_metadata.id;
+
+    let qdrant = get_qdrant_connection().await.map_err(ServiceError::from)?;
 
     let deleted_values = PointsSelector {
         points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
@@ -310,18 +729,72 @@ pub async fn delete_card(
         })),
     };
 
-    web::block(move || delete_card_metadata_query(card_id_inner, pool1))
+    web::block(move || delete_card_metadata_query(card_id, pool))
         .await?
         .await
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
+
+    retry_qdrant_operation(
+        || qdrant.delete_points_blocking("debate_cards", &deleted_values, None),
+        |_err| ServiceError::BadRequest("Failed deleting card from qdrant".into()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn reembed_card(
+    card_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let card_metadata = user_owns_card(user.id, card_id.into_inner(), thread_safe_pool).await?;
+
+    // duplicate cards share the vector of the card they collided with
+    let qdrant_point_id = match card_metadata.qdrant_point_id {
+        Some(qdrant_point_id) => qdrant_point_id,
+        None => {
+            return Err(ServiceError::BadRequest("Cannot reembed a duplicate card".into()).into())
+        }
+    };
+
+    let embedding_vector = create_openai_embedding(&card_metadata.content).await?;
+
+    let qdrant = get_qdrant_connection().await.map_err(ServiceError::from)?;
+    let point = PointStruct::new(
+        qdrant_point_id.to_string(),
+        embedding_vector,
+        get_card_qdrant_payload(
+            card_metadata.author_id,
+            &card_metadata.link,
+            &card_metadata.oc_file_path,
+            card_metadata.private,
+            card_metadata.approved,
+            &card_metadata.citation(),
+        ),
+    );
+
+    retry_qdrant_operation(
+        || qdrant.upsert_points_blocking("debate_cards", vec![point.clone()], None),
+        |_err| ServiceError::BadRequest("Failed inserting card to qdrant".into()),
+    )
+    .await?;
 
-    qdrant
-        .delete_points_blocking("debate_cards".to_string(), &deleted_values, None)
-        .await
-        .map_err(|_err| ServiceError::BadRequest("Failed deleting card from qdrant".into()))?;
     Ok(HttpResponse::NoContent().finish())
 }
 
+pub async fn get_cards_missing_vectors(
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let cards = get_cards_missing_vectors_query(user.id, pool)
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(cards))
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct UpdateCardData {
     card_uuid: uuid::Uuid,
@@ -335,7 +808,7 @@ pub struct CardHtmlUpdateError {
     changed_content: String,
 }
 pub async fn update_card(
-    card: web::Json<UpdateCardData>,
+    mut card: web::Json<UpdateCardData>,
     pool: web::Data<Pool>,
     user: LoggedUser,
 ) -> Result<HttpResponse, actix_web::Error> {
@@ -348,13 +821,14 @@ pub async fn update_card(
         .clone()
         .unwrap_or_else(|| card_metadata.link.unwrap_or_default());
 
+    card.card_html = card.card_html.as_deref().map(sanitize_card_html);
     let soup = Soup::new(card.card_html.as_ref().unwrap_or(&"".to_string()).as_str());
     let new_content = soup
         .text()
         .lines()
         .collect::<Vec<&str>>()
         .join(" ")
-        .trim_end()
+        .trim()
         .to_string();
     if new_content != card_metadata.content {
         let soup_text_ref = soup.text();
@@ -380,6 +854,9 @@ pub async fn update_card(
         }));
     }
 
+    // the link can change here, and a card made public should pass the checks as they are now
+    screen_card_content(&format!("{}\n{}", card_metadata.content, link)).await?;
+
     let card_html = match card.card_html.clone() {
         Some(card_html) => Some(card_html),
         None => card_metadata.card_html,
@@ -392,6 +869,15 @@ pub async fn update_card(
         return Err(ServiceError::BadRequest("Cannot make a duplicate card public".into()).into());
     }
     let private = card.private.unwrap_or(card_metadata.private);
+    let qdrant_point_id = card_metadata.qdrant_point_id;
+    let payload = get_card_qdrant_payload(
+        card_metadata.author_id,
+        &Some(link.clone()),
+        &card_metadata.oc_file_path,
+        private,
+        card_metadata.approved,
+        &card_metadata.citation(),
+    );
 
     web::block(move || {
         update_card_metadata_query(
@@ -410,78 +896,659 @@ pub async fn update_card(
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
+
+    if let Some(qdrant_point_id) = qdrant_point_id {
+        update_card_qdrant_payload_query(qdrant_point_id, payload)
+            .await
+            .map_err(ServiceError::from)?;
+    }
 
     Ok(HttpResponse::NoContent().finish())
 }
-#[derive(Serialize, Deserialize)]
-pub struct SearchCardData {
-    content: String,
-    filter_oc_file_path: Option<Vec<String>>,
-    filter_link_url: Option<Vec<String>>,
+
+// With #[serde(default)] a field left out stays None, while an explicit null becomes Some(None)
+fn deserialize_patch_field<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct ScoreCardDTO {
-    metadata: Vec<CardMetadataWithVotesWithoutScore>,
-    score: f64,
+pub struct PatchCardSourceData {
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    link: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    source_author: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    source_title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    source_date: Option<Option<chrono::NaiveDate>>,
+    #[serde(default, deserialize_with = "deserialize_patch_field")]
+    source_url: Option<Option<String>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SearchCardQueryResponseBody {
-    score_cards: Vec<ScoreCardDTO>,
-    total_card_pages: i64,
+fn is_valid_source_url(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .map(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+        .unwrap_or(false)
 }
 
-pub async fn search_card(
-    data: web::Json<SearchCardData>,
-    page: Option<web::Path<u64>>,
-    user: Option<LoggedUser>,
+// Fields left out of the request keep their current values and fields sent as null are cleared
+pub async fn patch_card_source(
+    card_id: web::Path<uuid::Uuid>,
+    data: web::Json<PatchCardSourceData>,
     pool: web::Data<Pool>,
+    user: LoggedUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let current_user_id = user.map(|user| user.id);
-    let thread_safe_pool = Arc::new(Mutex::new(pool));
-    let page = page.map(|page| page.into_inner()).unwrap_or(1);
-    let embedding_vector = create_openai_embedding(&data.content).await?;
-    let pool2 = thread_safe_pool.clone();
-    let pool3 = thread_safe_pool.clone();
-
-    let search_card_query_results = search_card_query(
-        embedding_vector,
-        page,
-        thread_safe_pool,
-        data.filter_oc_file_path.clone(),
-        data.filter_link_url.clone(),
-        current_user_id,
-    )
-    .await
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    let data = data.into_inner();
+    for url in [&data.link, &data.source_url]
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        if !is_valid_source_url(url) {
+            return Err(ServiceError::BadRequest(format!("Invalid url: {}", url)).into());
+        }
+    }
 
-    let point_ids = search_card_query_results
-        .search_results
-        .iter()
-        .map(|point| point.point_id)
-        .collect::<Vec<_>>();
-    let point_ids_1 = point_ids.clone();
+    let thread_safe_pool = Arc::new(Mutex::new(pool.clone()));
+    let card_metadata = user_owns_card(user.id, card_id.into_inner(), thread_safe_pool).await?;
+
+    let link = data.link.unwrap_or(card_metadata.link.clone());
+    let existing_citation = card_metadata.citation();
+    let citation = CardCitation {
+        source_author: data
+            .source_author
+            .unwrap_or(existing_citation.source_author),
+        source_title: data.source_title.unwrap_or(existing_citation.source_title),
+        source_date: data.source_date.unwrap_or(existing_citation.source_date),
+        source_url: data.source_url.unwrap_or(existing_citation.source_url),
+    };
+    let payload = get_card_qdrant_payload(
+        card_metadata.author_id,
+        &link,
+        &card_metadata.oc_file_path,
+        card_metadata.private,
+        card_metadata.approved,
+        &citation,
+    );
+
+    web::block(move || update_card_source_query(card_metadata.id, link, citation, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
 
-    let metadata_cards = web::block(move || {
-        let pool = pool2.lock().unwrap(); // Access the locked pool
-        get_metadata_from_point_ids(point_ids, current_user_id, pool)
-    })
-    .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    if let Some(qdrant_point_id) = card_metadata.qdrant_point_id {
+        update_card_qdrant_payload_query(qdrant_point_id, payload)
+            .await
+            .map_err(ServiceError::from)?;
+    }
 
-    let collided_cards = web::block(move || {
-        let pool = pool3.lock().unwrap(); // Access the locked pool
-        get_collided_cards_query(point_ids_1, current_user_id, pool)
-    })
-    .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    let score_cards: Vec<ScoreCardDTO> = search_card_query_results
-        .search_results
-        .iter()
-        .map(|search_result| {
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SearchCardData {
+    content: String,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    exclude_oc_file_path: Option<Vec<String>>,
+    exclude_link_url: Option<Vec<String>>,
+    only_mine: Option<bool>,
+    language: Option<String>,
+    page_size: Option<u64>,
+    collapse_similar: Option<bool>,
+    include_html: Option<bool>,
+    filter_source_author: Option<String>,
+    filter_source_date_from: Option<chrono::NaiveDate>,
+    filter_source_date_to: Option<chrono::NaiveDate>,
+    explain: Option<bool>,
+}
+
+impl SearchCardData {
+    fn page_size(&self) -> u64 {
+        self.page_size.unwrap_or(25).clamp(1, 100)
+    }
+
+    fn filter_author_id(&self, current_user_id: Option<uuid::Uuid>) -> Option<uuid::Uuid> {
+        if self.only_mine.unwrap_or(false) {
+            current_user_id
+        } else {
+            None
+        }
+    }
+
+    fn citation_filter(&self) -> CitationFilter {
+        CitationFilter {
+            source_author: self.filter_source_author.clone(),
+            source_date_from: self.filter_source_date_from,
+            source_date_to: self.filter_source_date_to,
+        }
+    }
+
+    // Every filter the search applied, whether or not it narrowed this particular result
+    fn applied_filters(&self) -> Vec<String> {
+        let is_set =
+            |values: &Option<Vec<String>>| values.as_ref().is_some_and(|values| !values.is_empty());
+
+        [
+            ("filter_oc_file_path", is_set(&self.filter_oc_file_path)),
+            ("filter_link_url", is_set(&self.filter_link_url)),
+            ("filter_source_author", self.filter_source_author.is_some()),
+            (
+                "filter_source_date",
+                self.filter_source_date_from.is_some() || self.filter_source_date_to.is_some(),
+            ),
+            ("exclude_oc_file_path", is_set(&self.exclude_oc_file_path)),
+            ("exclude_link_url", is_set(&self.exclude_link_url)),
+            ("only_mine", self.only_mine.unwrap_or(false)),
+        ]
+        .into_iter()
+        .filter(|(_, applied)| *applied)
+        .map(|(filter, _)| filter.to_string())
+        .collect()
+    }
+
+    // The filter values this card's own fields satisfy, exclusions have nothing to match
+    fn matched_values(&self, card: Option<&CardMetadataWithVotesWithoutScore>) -> Vec<String> {
+        let mut matched_values = vec![];
+        let card = match card {
+            Some(card) => card,
+            None => return matched_values,
+        };
+        let contains_value = |field: &Option<String>, value: &String| {
+            field
+                .as_ref()
+                .is_some_and(|field| field.contains(value.as_str()))
+        };
+
+        for value in self.filter_oc_file_path.iter().flatten() {
+            if contains_value(&card.oc_file_path, value) {
+                matched_values.push(format!("filter_oc_file_path={}", value));
+            }
+        }
+        for value in self.filter_link_url.iter().flatten() {
+            if contains_value(&card.link, value) {
+                matched_values.push(format!("filter_link_url={}", value));
+            }
+        }
+        if let Some(source_author) = &self.filter_source_author {
+            // matched case insensitively like the ilike in full text search
+            if card
+                .source_author
+                .as_ref()
+                .is_some_and(|card_source_author| {
+                    card_source_author
+                        .to_lowercase()
+                        .contains(&source_author.to_lowercase())
+                })
+            {
+                matched_values.push(format!("filter_source_author={}", source_author));
+            }
+        }
+        if self.filter_source_date_from.is_some() || self.filter_source_date_to.is_some() {
+            if let Some(source_date) = card.source_date.filter(|source_date| {
+                self.filter_source_date_from
+                    .map_or(true, |date_from| *source_date >= date_from)
+                    && self
+                        .filter_source_date_to
+                        .map_or(true, |date_to| *source_date <= date_to)
+            }) {
+                matched_values.push(format!("filter_source_date={}", source_date));
+            }
+        }
+
+        matched_values
+    }
+
+    fn explain_score_cards(&self, score_cards: &mut [ScoreCardDTO], semantic_scores: bool) {
+        if !self.explain.unwrap_or(false) {
+            return;
+        }
+
+        for score_card in score_cards.iter_mut() {
+            score_card.explain = Some(SearchResultExplanation {
+                raw_score: score_card.score,
+                normalized_score: semantic_scores
+                    .then(|| normalize_similarity_score(score_card.score as f32) as f64),
+                applied_filters: self.applied_filters(),
+                matched_values: self.matched_values(score_card.metadata.first()),
+            });
+        }
+    }
+
+    fn exclusion_filter(&self) -> ExclusionFilter {
+        ExclusionFilter {
+            oc_file_path: self.exclude_oc_file_path.clone().unwrap_or_default(),
+            link_url: self.exclude_link_url.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Deep offsets are expensive for both qdrant and postgres, so every search stops at MAX_SEARCH_PAGE
+fn check_search_page_depth(page: u64) -> Result<(), ServiceError> {
+    let max_page: u64 = std::env::var("MAX_SEARCH_PAGE")
+        .ok()
+        .and_then(|max_page| max_page.parse().ok())
+        .unwrap_or(100);
+    if page > max_page {
+        return Err(ServiceError::BadRequest(format!(
+            "Search results stop at page {}, refine the search or browse with the card feed",
+            max_page
+        )));
+    }
+
+    Ok(())
+}
+
+static ANONYMOUS_SEARCH_REQUESTS: Lazy<Mutex<HashMap<String, (Instant, u64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Unauthenticated searches are limited per IP and to the first few pages so the corpus can't be
+// scraped through pagination
+// Forwarded headers are set by the client unless a proxy overwrote them, so they are only trusted
+// when the connection comes from one of the comma separated TRUSTED_PROXIES
+fn get_client_ip(req: &HttpRequest) -> String {
+    let peer_ip = match req.peer_addr() {
+        Some(peer_addr) => peer_addr.ip().to_string(),
+        None => return "unknown".to_string(),
+    };
+    let trusted_proxies = std::env::var("TRUSTED_PROXIES").unwrap_or_default();
+    if !trusted_proxies
+        .split(',')
+        .any(|trusted_proxy| trusted_proxy.trim() == peer_ip)
+    {
+        return peer_ip;
+    }
+
+    req.connection_info()
+        .realip_remote_addr()
+        .map(|realip| realip.to_string())
+        .unwrap_or(peer_ip)
+}
+
+fn check_anonymous_search_limits(
+    req: &HttpRequest,
+    user: &Option<LoggedUser>,
+    page: u64,
+) -> Option<HttpResponse> {
+    if user.is_some() {
+        return None;
+    }
+
+    let max_page: u64 = std::env::var("ANONYMOUS_SEARCH_MAX_PAGE")
+        .ok()
+        .and_then(|max_page| max_page.parse().ok())
+        .unwrap_or(5);
+    if page > max_page {
+        return Some(HttpResponse::BadRequest().json(json!({
+            "message": format!("Log in to search past page {}", max_page),
+        })));
+    }
+
+    let requests_per_minute: u64 = std::env::var("ANONYMOUS_SEARCH_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(20);
+    let ip = get_client_ip(req);
+
+    let now = Instant::now();
+    let mut anonymous_search_requests = ANONYMOUS_SEARCH_REQUESTS.lock().unwrap();
+    anonymous_search_requests
+        .retain(|_, (window_start, _)| now.duration_since(*window_start) < Duration::from_secs(60));
+    let (_, request_count) = anonymous_search_requests.entry(ip).or_insert((now, 0));
+    *request_count += 1;
+
+    if *request_count > requests_per_minute {
+        return Some(HttpResponse::TooManyRequests().json(json!({
+            "message": "Too many searches, log in or try again later",
+        })));
+    }
+
+    None
+}
+
+static EMBEDDING_REQUESTS: Lazy<Mutex<HashMap<uuid::Uuid, (Instant, u64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Counts a request against the user's one minute window and reports whether it went over
+fn exceeds_user_rate_limit(
+    requests: &Mutex<HashMap<uuid::Uuid, (Instant, u64)>>,
+    user_id: uuid::Uuid,
+    requests_per_minute: u64,
+) -> bool {
+    let now = Instant::now();
+    let mut requests = requests.lock().unwrap();
+    requests
+        .retain(|_, (window_start, _)| now.duration_since(*window_start) < Duration::from_secs(60));
+    let (_, request_count) = requests.entry(user_id).or_insert((now, 0));
+    *request_count += 1;
+
+    *request_count > requests_per_minute
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateEmbeddingData {
+    text: String,
+}
+
+pub async fn create_embedding(
+    data: web::Json<CreateEmbeddingData>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    if data.text.trim().is_empty() {
+        return Err(ServiceError::BadRequest("Text must not be empty".into()).into());
+    }
+
+    let requests_per_minute: u64 = std::env::var("EMBEDDING_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(30);
+    if exceeds_user_rate_limit(&EMBEDDING_REQUESTS, user.id, requests_per_minute) {
+        return Ok(HttpResponse::TooManyRequests().json(json!({
+            "message": "Too many embedding requests, try again later",
+        })));
+    }
+
+    let embedding_vector = create_openai_embedding(&data.text).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "model": "text-embedding-ada-002",
+        "dimension": embedding_vector.len(),
+        "embedding": embedding_vector,
+    })))
+}
+
+static VECTOR_SEARCH_REQUESTS: Lazy<Mutex<HashMap<uuid::Uuid, (Instant, u64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchByVectorData {
+    vector: Vec<f32>,
+    limit: Option<u64>,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    only_mine: Option<bool>,
+    include_html: Option<bool>,
+}
+
+// For clients with their own embeddings (or ones from /embedding), so nothing is sent to OpenAI
+pub async fn search_card_by_vector(
+    data: web::Json<SearchByVectorData>,
+    user: LoggedUser,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if data.vector.len() as u64 != OPENAI_EMBEDDING_DIMENSION {
+        return Err(ServiceError::BadRequest(format!(
+            "Vector must have {} dimensions",
+            OPENAI_EMBEDDING_DIMENSION
+        ))
+        .into());
+    }
+    if data.vector.iter().any(|value| !value.is_finite()) {
+        return Err(
+            ServiceError::BadRequest("Vector must only contain finite values".into()).into(),
+        );
+    }
+
+    let requests_per_minute: u64 = std::env::var("VECTOR_SEARCH_RATE_LIMIT_PER_MINUTE")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(30);
+    if exceeds_user_rate_limit(&VECTOR_SEARCH_REQUESTS, user.id, requests_per_minute) {
+        return Ok(HttpResponse::TooManyRequests().json(json!({
+            "message": "Too many vector searches, try again later",
+        })));
+    }
+
+    let limit = data.limit.unwrap_or(10).clamp(1, 100);
+    let current_user_id = Some(user.id);
+    let filter_author_id = match data.only_mine.unwrap_or(false) {
+        true => current_user_id,
+        false => None,
+    };
+    let thread_safe_read_pool = Arc::new(Mutex::new(read_pool.0.clone()));
+
+    let search_card_query_results = search_card_query(
+        data.vector.clone(),
+        1,
+        limit,
+        thread_safe_read_pool.clone(),
+        data.filter_oc_file_path.clone(),
+        data.filter_link_url.clone(),
+        filter_author_id,
+        CitationFilter::default(),
+        ExclusionFilter::default(),
+        current_user_id,
+    )
+    .await
+    .map_err(ServiceError::from)?;
+
+    let mut score_cards = score_cards_from_search_results(
+        &search_card_query_results.search_results,
+        current_user_id,
+        thread_safe_read_pool,
+    )
+    .await?;
+    prepare_score_cards_html(&mut score_cards, data.include_html.unwrap_or(false));
+
+    Ok(HttpResponse::Ok().json(score_cards))
+}
+
+// Queries are logged anonymously unless LOG_SEARCH_QUERY_USERS is enabled, and not at all
+// when LOG_SEARCH_QUERIES is set to false
+async fn log_search_query(
+    content: &str,
+    search_type: &str,
+    current_user_id: Option<uuid::Uuid>,
+    pool: Arc<Mutex<web::Data<Pool>>>,
+) {
+    if std::env::var("LOG_SEARCH_QUERIES").unwrap_or_default() == "false" {
+        return;
+    }
+
+    let user_id = match std::env::var("LOG_SEARCH_QUERY_USERS").unwrap_or_default() == "true" {
+        true => current_user_id,
+        false => None,
+    };
+    let search_query = SearchQuery::from_details(content, search_type, user_id);
+
+    match web::block(move || insert_search_query_query(search_query, pool.lock().unwrap())).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::error!("{}", err.message),
+        Err(err) => log::error!("Failed to log search query: {}", err),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrendingSearchParams {
+    days: Option<i64>,
+    limit: Option<i64>,
+}
+
+pub async fn get_trending_searches(
+    params: web::Query<TrendingSearchParams>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let days = params.days.unwrap_or(7).clamp(1, 365);
+    let limit = params.limit.unwrap_or(10).clamp(1, 100);
+
+    let trending_queries = web::block(move || get_trending_search_queries_query(days, limit, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(trending_queries))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CardFeedParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+pub async fn get_card_feed(
+    params: web::Query<CardFeedParams>,
+    user: Option<LoggedUser>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let current_user_id = user.map(|user| user.id);
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
+    let cursor = params
+        .cursor
+        .as_deref()
+        .map(decode_card_feed_cursor)
+        .transpose()
+        .map_err(ServiceError::from)?;
+
+    let card_feed = web::block(move || get_card_feed_query(cursor, limit, current_user_id, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(card_feed))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CardChangesParams {
+    since: Option<chrono::NaiveDateTime>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+// Clients start from `since` and then follow next_cursor, which takes precedence when both are set
+pub async fn get_card_changes(
+    params: web::Query<CardChangesParams>,
+    user: Option<LoggedUser>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let current_user_id = user.map(|user| user.id);
+    let limit = params.limit.unwrap_or(100).clamp(1, 500);
+    let cursor = match (params.cursor.as_deref(), params.since) {
+        (Some(cursor), _) => decode_card_feed_cursor(cursor).map_err(ServiceError::from)?,
+        (None, Some(since)) => (since, uuid::Uuid::from_u128(u128::MAX)),
+        (None, None) => {
+            return Err(ServiceError::BadRequest("since or cursor is required".into()).into())
+        }
+    };
+
+    let card_changes =
+        web::block(move || get_card_changes_query(cursor, limit, current_user_id, pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(card_changes))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SearchResultExplanation {
+    raw_score: f64,
+    // semantic scores only, see normalize_similarity_score
+    normalized_score: Option<f64>,
+    applied_filters: Vec<String>,
+    matched_values: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScoreCardDTO {
+    metadata: Vec<CardMetadataWithVotesWithoutScore>,
+    score: f64,
+    duplicates: Vec<uuid::Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explain: Option<SearchResultExplanation>,
+}
+
+// Cards above this cosine similarity to a higher scored result are folded into it
+const COLLAPSE_SIMILARITY_THRESHOLD: f32 = 0.95;
+
+// score_cards must be in the same order as the point_ids they were built from
+async fn collapse_similar_score_cards(
+    point_ids: Vec<uuid::Uuid>,
+    score_cards: Vec<ScoreCardDTO>,
+) -> Result<Vec<ScoreCardDTO>, DefaultError> {
+    let point_vectors = get_point_vectors_query(point_ids.clone()).await?;
+
+    let mut collapsed_cards: Vec<(Option<&Vec<f32>>, ScoreCardDTO)> = vec![];
+    for (point_id, score_card) in point_ids.iter().zip(score_cards) {
+        let vector = point_vectors.get(point_id);
+        let representative = vector.and_then(|vector| {
+            collapsed_cards.iter_mut().find(|(kept_vector, _)| {
+                kept_vector.is_some_and(|kept_vector| {
+                    cosine_similarity(kept_vector, vector) >= COLLAPSE_SIMILARITY_THRESHOLD
+                })
+            })
+        });
+
+        match representative {
+            Some((_, kept_card)) => kept_card
+                .duplicates
+                .extend(score_card.metadata.iter().map(|card| card.id)),
+            None => collapsed_cards.push((vector, score_card)),
+        }
+    }
+
+    Ok(collapsed_cards
+        .into_iter()
+        .map(|(_, score_card)| score_card)
+        .collect())
+}
+
+// Html is left out unless asked for to keep payloads small, and sanitized when it is returned
+fn prepare_score_cards_html(score_cards: &mut [ScoreCardDTO], include_html: bool) {
+    for card in score_cards
+        .iter_mut()
+        .flat_map(|score_card| score_card.metadata.iter_mut())
+    {
+        card.card_html = if include_html {
+            card.card_html.as_deref().map(sanitize_card_html)
+        } else {
+            None
+        };
+    }
+}
+
+fn record_score_card_views(score_cards: &[ScoreCardDTO]) {
+    record_card_views(
+        &score_cards
+            .iter()
+            .filter_map(|score_card| score_card.metadata.first().map(|card| card.id))
+            .collect::<Vec<uuid::Uuid>>(),
+    );
+}
+
+// Pairs each qdrant hit with its card and the duplicates that collided into it
+async fn score_cards_from_search_results(
+    search_results: &[SearchResult],
+    current_user_id: Option<uuid::Uuid>,
+    pool: Arc<Mutex<web::Data<Pool>>>,
+) -> Result<Vec<ScoreCardDTO>, actix_web::Error> {
+    let pool1 = pool.clone();
+    let point_ids = search_results
+        .iter()
+        .map(|point| point.point_id)
+        .collect::<Vec<_>>();
+    let point_ids_1 = point_ids.clone();
+
+    let metadata_cards = web::block(move || {
+        let pool = pool1.lock().unwrap(); // Access the locked pool
+        get_metadata_from_point_ids(point_ids, current_user_id, pool)
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    let collided_cards = web::block(move || {
+        let pool = pool.lock().unwrap(); // Access the locked pool
+        get_collided_cards_query(point_ids_1, current_user_id, pool)
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(search_results
+        .iter()
+        .map(|search_result| {
             let card: CardMetadataWithVotesWithoutScore = <CardMetadataWithVotesAndFiles as Into<
                 CardMetadataWithVotesWithoutScore,
             >>::into(
@@ -510,39 +1577,183 @@ pub async fn search_card(
             ScoreCardDTO {
                 metadata: collided_cards,
                 score: search_result.score.into(),
+                duplicates: vec![],
+                explain: None,
             }
         })
-        .collect();
+        .collect())
+}
 
-    Ok(HttpResponse::Ok().json(SearchCardQueryResponseBody {
+pub async fn search_card(
+    req: HttpRequest,
+    data: web::Json<SearchCardData>,
+    page: Option<web::Path<u64>>,
+    user: Option<LoggedUser>,
+    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = page.map(|page| page.into_inner()).unwrap_or(1);
+    check_search_page_depth(page)?;
+    if let Some(response) = check_anonymous_search_limits(&req, &user, page) {
+        return Ok(response);
+    }
+    let current_user_id = user.map(|user| user.id);
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let thread_safe_read_pool = Arc::new(Mutex::new(read_pool.0.clone()));
+    let embedding_vector = create_openai_embedding(&data.content).await?;
+    let pool2 = thread_safe_read_pool.clone();
+
+    log_search_query(
+        &data.content,
+        "semantic",
+        current_user_id,
+        thread_safe_pool.clone(),
+    )
+    .await;
+
+    let search_card_query_results = search_card_query(
+        embedding_vector,
+        page,
+        data.page_size(),
+        thread_safe_read_pool,
+        data.filter_oc_file_path.clone(),
+        data.filter_link_url.clone(),
+        data.filter_author_id(current_user_id),
+        data.citation_filter(),
+        data.exclusion_filter(),
+        current_user_id,
+    )
+    .await
+    .map_err(ServiceError::from)?;
+
+    let score_cards = score_cards_from_search_results(
+        &search_card_query_results.search_results,
+        current_user_id,
+        pool2,
+    )
+    .await?;
+
+    let mut score_cards = if data.collapse_similar.unwrap_or(false) {
+        collapse_similar_score_cards(
+            search_card_query_results
+                .search_results
+                .iter()
+                .map(|search_result| search_result.point_id)
+                .collect(),
+            score_cards,
+        )
+        .await
+        .map_err(ServiceError::from)?
+    } else {
+        score_cards
+    };
+    data.explain_score_cards(&mut score_cards, true);
+    prepare_score_cards_html(&mut score_cards, data.include_html.unwrap_or(false));
+
+    record_score_card_views(&score_cards);
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
         score_cards,
-        total_card_pages: search_card_query_results.total_card_pages,
-    }))
+        PageInfo::new(
+            page as i64,
+            data.page_size() as i64,
+            search_card_query_results.total_cards,
+        ),
+    )))
+}
+
+// Just the number of matches, for result badges or deciding whether to run the search at all
+pub async fn count_full_text_cards(
+    req: HttpRequest,
+    data: web::Json<SearchCardData>,
+    user: Option<LoggedUser>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(response) = check_anonymous_search_limits(&req, &user, 1) {
+        return Ok(response);
+    }
+    if let Some(language) = &data.language {
+        if !is_supported_full_text_search_language(language) {
+            return Err(ServiceError::BadRequest("Unsupported search language".into()).into());
+        }
+    }
+
+    let thread_safe_read_pool = Arc::new(Mutex::new(read_pool.0.clone()));
+    let current_user_id = user.map(|user| user.id);
+
+    let count = web::block(move || {
+        count_full_text_cards_query(
+            data.content.clone(),
+            thread_safe_read_pool.lock().unwrap(),
+            current_user_id,
+            data.filter_oc_file_path.clone(),
+            data.filter_link_url.clone(),
+            data.filter_author_id(current_user_id),
+            data.citation_filter(),
+            data.exclusion_filter(),
+            data.language.clone(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "count": count })))
 }
 
 pub async fn search_full_text_card(
+    req: HttpRequest,
     data: web::Json<SearchCardData>,
     page: Option<web::Path<u64>>,
     user: Option<LoggedUser>,
     pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let page = page.map(|page| page.into_inner()).unwrap_or(1);
+    check_search_page_depth(page)?;
+    if let Some(response) = check_anonymous_search_limits(&req, &user, page) {
+        return Ok(response);
+    }
+    if let Some(language) = &data.language {
+        if !is_supported_full_text_search_language(language) {
+            return Err(ServiceError::BadRequest("Unsupported search language".into()).into());
+        }
+    }
+
     //search over the links as well
     let thread_safe_pool = Arc::new(Mutex::new(pool));
-    let page = page.map(|page| page.into_inner()).unwrap_or(1);
+    let thread_safe_read_pool = Arc::new(Mutex::new(read_pool.0.clone()));
     let current_user_id = user.map(|user| user.id);
-    let pool2 = thread_safe_pool.clone();
+    let pool2 = thread_safe_read_pool.clone();
+    let collapse_similar = data.collapse_similar.unwrap_or(false);
+    let include_html = data.include_html.unwrap_or(false);
+    let page_size = data.page_size();
+    let explain_data = data.clone();
+
+    log_search_query(
+        &data.content,
+        "full_text",
+        current_user_id,
+        thread_safe_pool.clone(),
+    )
+    .await;
+
     let search_card_query_results = web::block(move || {
         search_full_text_card_query(
             data.content.clone(),
             page,
-            thread_safe_pool.lock().unwrap(),
+            page_size,
+            thread_safe_read_pool.lock().unwrap(),
             current_user_id,
             data.filter_oc_file_path.clone(),
             data.filter_link_url.clone(),
+            data.filter_author_id(current_user_id),
+            data.citation_filter(),
+            data.exclusion_filter(),
+            data.language.clone(),
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let point_ids = search_card_query_results
         .search_results
@@ -554,7 +1765,7 @@ pub async fn search_full_text_card(
         get_collided_cards_query(point_ids, current_user_id, pool2.lock().unwrap())
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let full_text_cards: Vec<ScoreCardDTO> = search_card_query_results
         .search_results
@@ -571,14 +1782,39 @@ pub async fn search_full_text_card(
             ScoreCardDTO {
                 metadata: collided_cards,
                 score: search_result.score.unwrap_or(0.0),
+                duplicates: vec![],
+                explain: None,
             }
         })
         .collect();
 
-    Ok(HttpResponse::Ok().json(SearchCardQueryResponseBody {
-        score_cards: full_text_cards,
-        total_card_pages: search_card_query_results.total_card_pages,
-    }))
+    let mut full_text_cards = if collapse_similar {
+        collapse_similar_score_cards(
+            search_card_query_results
+                .search_results
+                .iter()
+                .map(|search_result| search_result.qdrant_point_id)
+                .collect(),
+            full_text_cards,
+        )
+        .await
+        .map_err(ServiceError::from)?
+    } else {
+        full_text_cards
+    };
+    explain_data.explain_score_cards(&mut full_text_cards, false);
+    prepare_score_cards_html(&mut full_text_cards, include_html);
+
+    record_score_card_views(&full_text_cards);
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        full_text_cards,
+        PageInfo::new(
+            page as i64,
+            page_size as i64,
+            search_card_query_results.total_cards,
+        ),
+    )))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -587,17 +1823,23 @@ pub struct SearchCollectionsData {
     filter_oc_file_path: Option<Vec<String>>,
     filter_link_url: Option<Vec<String>>,
     collection_id: uuid::Uuid,
+    include_html: Option<bool>,
 }
 
 pub async fn search_collections(
+    req: HttpRequest,
     data: web::Json<SearchCollectionsData>,
     page: Option<web::Path<u64>>,
     user: Option<LoggedUser>,
-    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    //search over the links as well
-    let thread_safe_pool = Arc::new(Mutex::new(pool));
     let page = page.map(|page| page.into_inner()).unwrap_or(1);
+    check_search_page_depth(page)?;
+    if let Some(response) = check_anonymous_search_limits(&req, &user, page) {
+        return Ok(response);
+    }
+    //search over the links as well
+    let thread_safe_pool = Arc::new(Mutex::new(read_pool.0.clone()));
     let embedding_vector = create_openai_embedding(&data.content).await?;
     let collection_id = data.collection_id;
     let pool2 = thread_safe_pool.clone();
@@ -610,7 +1852,7 @@ pub async fn search_collections(
     })
     .await
     .map_err(|err| ServiceError::BadRequest(err.to_string()))?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     if !collection.is_public && current_user_id.is_none() {
         return Err(ServiceError::Unauthorized.into());
@@ -629,7 +1871,7 @@ pub async fn search_collections(
         data.collection_id,
     )
     .await
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let point_ids = search_card_query_results
         .search_results
@@ -643,15 +1885,15 @@ pub async fn search_collections(
         get_metadata_from_point_ids(point_ids, current_user_id, pool3.lock().unwrap())
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let collided_cards = web::block(move || {
         get_collided_cards_query(point_ids_1, current_user_id, pool4.lock().unwrap())
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
-    let score_cards: Vec<ScoreCardDTO> = search_card_query_results
+    let mut score_cards: Vec<ScoreCardDTO> = search_card_query_results
         .search_results
         .iter()
         .map(|search_result| {
@@ -676,14 +1918,19 @@ pub async fn search_collections(
             ScoreCardDTO {
                 metadata: collided_cards,
                 score: search_result.score.into(),
+                duplicates: vec![],
+                explain: None,
             }
         })
         .collect();
 
-    Ok(HttpResponse::Ok().json(SearchCardQueryResponseBody {
+    prepare_score_cards_html(&mut score_cards, data.include_html.unwrap_or(false));
+    record_score_card_views(&score_cards);
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
         score_cards,
-        total_card_pages: search_card_query_results.total_card_pages,
-    }))
+        PageInfo::new(page as i64, 25, search_card_query_results.total_cards),
+    )))
 }
 
 pub async fn get_card_by_id(
@@ -696,20 +1943,289 @@ pub async fn get_card_by_id(
         get_metadata_and_votes_from_id_query(card_id.into_inner(), current_user_id, pool)
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
     if card.private && current_user_id.is_none() {
         return Err(ServiceError::Unauthorized.into());
     }
     if card.private && Some(card.clone().author.unwrap().id) != current_user_id {
         return Err(ServiceError::Forbidden.into());
     }
+    record_card_views(&[card.id]);
     Ok(HttpResponse::Ok().json(card))
 }
 
-pub async fn get_total_card_count(pool: web::Data<Pool>) -> Result<HttpResponse, actix_web::Error> {
+const MAX_CARDS_BY_IDS: usize = 100;
+
+pub async fn get_cards_by_ids(
+    card_ids: web::Json<Vec<uuid::Uuid>>,
+    user: Option<LoggedUser>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_ids = card_ids.into_inner();
+    if card_ids.len() > MAX_CARDS_BY_IDS {
+        return Err(ServiceError::BadRequest(format!(
+            "Cannot fetch more than {} cards at once",
+            MAX_CARDS_BY_IDS
+        ))
+        .into());
+    }
+    let current_user_id = user.map(|user| user.id);
+
+    let cards = web::block(move || get_metadata_from_ids_query(card_ids, current_user_id, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(cards))
+}
+
+#[derive(Deserialize)]
+pub struct SimilarCardsParams {
+    limit: Option<u64>,
+    include_html: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CardEmbeddingDebugDTO {
+    pub card_id: uuid::Uuid,
+    pub qdrant_point_id: Option<uuid::Uuid>,
+    pub card_html: Option<String>,
+    // the cleaned up content is exactly what gets sent to the embedding model
+    pub embedded_text: String,
+    pub model: String,
+    pub expected_dimension: u64,
+    pub vector_dimension: Option<usize>,
+    pub vector_norm: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferCardData {
+    pub author_id: uuid::Uuid,
+}
+
+// Leaderboards and profiles count cards by author, so they follow the card to its new author
+pub async fn transfer_card(
+    card_id: web::Path<uuid::Uuid>,
+    data: web::Json<TransferCardData>,
+    pool: web::Data<Pool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let new_author_id = data.author_id;
+
+    let (card_metadata, previous_author_id) =
+        web::block(move || transfer_card_ownership_query(card_id, new_author_id, &pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    log::info!(
+        "Admin {} transferred card {} from {} to {}",
+        admin.0.id,
+        card_id,
+        previous_author_id,
+        new_author_id
+    );
+
+    // the author filter in search reads the author from the qdrant payload
+    sync_card_qdrant_payload_query(&card_metadata)
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(card_metadata))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MergeCardsData {
+    pub keep_id: uuid::Uuid,
+    pub merge_id: uuid::Uuid,
+}
+
+// For near duplicates that slipped past the collision check, the keeper ends up with both cards'
+// votes and collections
+pub async fn merge_cards(
+    data: web::Json<MergeCardsData>,
+    pool: web::Data<Pool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let MergeCardsData { keep_id, merge_id } = data.into_inner();
+    let merge_pool = pool.clone();
+
+    let orphaned_qdrant_point_id =
+        web::block(move || merge_cards_query(keep_id, merge_id, &merge_pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    log::info!(
+        "Admin {} merged card {} into {}",
+        admin.0.id,
+        merge_id,
+        keep_id
+    );
+
+    delete_qdrant_points_query(&Vec::from_iter(orphaned_qdrant_point_id))
+        .await
+        .map_err(ServiceError::from)?;
+
+    let mut vote_tallies = web::block(move || get_vote_tallies_query(vec![keep_id], None, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(vote_tallies.remove(&keep_id).unwrap_or_default()))
+}
+
+pub async fn get_card_embedding_debug(
+    card_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+    _admin: AdminUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+
+    let card_metadata =
+        web::block(move || get_metadata_from_id_query(card_id, thread_safe_pool.lock().unwrap()))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    // duplicates have no point of their own, the vector lives on the card they collided with
+    let vector = match card_metadata.qdrant_point_id {
+        Some(qdrant_point_id) => get_point_vectors_query(vec![qdrant_point_id])
+            .await
+            .map_err(ServiceError::from)?
+            .remove(&qdrant_point_id),
+        None => None,
+    };
+
+    Ok(HttpResponse::Ok().json(CardEmbeddingDebugDTO {
+        card_id: card_metadata.id,
+        qdrant_point_id: card_metadata.qdrant_point_id,
+        card_html: card_metadata.card_html,
+        embedded_text: card_metadata.content,
+        model: OPENAI_EMBEDDING_MODEL.to_string(),
+        expected_dimension: OPENAI_EMBEDDING_DIMENSION,
+        vector_dimension: vector.as_ref().map(|vector| vector.len()),
+        vector_norm: vector.map(|vector| vector.iter().map(|x| x * x).sum::<f32>().sqrt()),
+    }))
+}
+
+pub async fn get_similar_cards(
+    card_id: web::Path<uuid::Uuid>,
+    params: web::Query<SimilarCardsParams>,
+    user: Option<LoggedUser>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let current_user_id = user.map(|user| user.id);
+    let limit = params.limit.unwrap_or(10).clamp(1, 50);
+    let pool1 = pool.clone();
+    let pool2 = pool.clone();
+    let pool3 = pool.clone();
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let pool4 = thread_safe_pool.clone();
+
+    let card =
+        web::block(move || get_metadata_and_votes_from_id_query(card_id, current_user_id, pool1))
+            .await?
+            .map_err(ServiceError::from)?;
+    if card.private && current_user_id.is_none() {
+        return Err(ServiceError::Unauthorized.into());
+    }
+    if card.private && Some(card.clone().author.unwrap().id) != current_user_id {
+        return Err(ServiceError::Forbidden.into());
+    }
+
+    let qdrant_point_id =
+        web::block(move || get_qdrant_point_id_from_card_id_query(card_id, pool2))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    let search_results = search_similar_cards_query(qdrant_point_id, limit, pool3, current_user_id)
+        .await
+        .map_err(ServiceError::from)?;
+
+    let point_ids = search_results
+        .iter()
+        .map(|point| point.point_id)
+        .collect::<Vec<_>>();
+    let point_ids_1 = point_ids.clone();
+
+    let metadata_cards = web::block(move || {
+        get_metadata_from_point_ids(point_ids, current_user_id, thread_safe_pool.lock().unwrap())
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    let collided_cards = web::block(move || {
+        get_collided_cards_query(point_ids_1, current_user_id, pool4.lock().unwrap())
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    let mut score_cards: Vec<ScoreCardDTO> = search_results
+        .iter()
+        .filter_map(|search_result| {
+            let mut collided_cards: Vec<CardMetadataWithVotesWithoutScore> = collided_cards
+                .iter()
+                .filter(|card| card.1 == search_result.point_id)
+                .map(|card| card.0.clone().into())
+                .collect();
+
+            if let Some(card) = metadata_cards
+                .iter()
+                .find(|metadata_card| metadata_card.qdrant_point_id == search_result.point_id)
+            {
+                let card: CardMetadataWithVotesWithoutScore = card.clone().into();
+                if !card.private
+                    || card
+                        .clone()
+                        .author
+                        .is_some_and(|author| Some(author.id) == current_user_id)
+                {
+                    collided_cards.insert(0, card);
+                }
+            }
+
+            if collided_cards.is_empty() {
+                return None;
+            }
+
+            Some(ScoreCardDTO {
+                metadata: collided_cards,
+                score: search_result.score.into(),
+                duplicates: vec![],
+                explain: None,
+            })
+        })
+        .collect();
+
+    prepare_score_cards_html(&mut score_cards, params.include_html.unwrap_or(false));
+
+    Ok(HttpResponse::Ok().json(score_cards))
+}
+
+pub async fn get_total_card_count(
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
     let total_count = web::block(move || get_card_count_query(pool))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::Ok().json(json!({ "total_count": total_count })))
 }
+
+#[derive(Deserialize)]
+pub struct GroupedCardCountParams {
+    group_by: CardCountGroupBy,
+}
+
+pub async fn get_grouped_card_count(
+    params: web::Query<GroupedCardCountParams>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let group_by = params.group_by;
+    let buckets = web::block(move || get_grouped_card_count_query(group_by, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(buckets))
+}
@@ -5,10 +5,14 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     data::models::{
-        CardCollection, CardCollectionBookmark, CardMetadataWithVotesWithoutScore, Pool,
+        CardCollection, CardCollectionBookmark, CardMetadataWithVotesWithoutScore, PageInfo,
+        PaginatedResponse, Pool, ReadPool,
+    },
+    errors::{ErrorKind, ServiceError},
+    operators::{
+        card_operator::{get_collided_cards_query, get_metadata_from_id_query},
+        collection_operator::*,
     },
-    errors::ServiceError,
-    operators::{card_operator::get_collided_cards_query, collection_operator::*},
 };
 
 use super::auth_handler::LoggedUser;
@@ -22,7 +26,7 @@ pub async fn user_owns_collection(
     let collection =
         web::block(move || get_collection_by_id_query(collection_id, pool.lock().unwrap()))
             .await?
-            .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+            .map_err(ServiceError::from)?;
 
     if collection.author_id != user_id {
         return Err(ServiceError::Forbidden.into());
@@ -54,7 +58,7 @@ pub async fn create_card_collection(
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -62,15 +66,16 @@ pub async fn create_card_collection(
 pub async fn get_specific_user_card_collections(
     user: Option<LoggedUser>,
     user_id: web::Path<uuid::Uuid>,
-    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
     let accessing_user_id = user.map(|user| user.id);
     let user_id = user_id.into_inner();
     let collections = web::block(move || {
         get_collections_for_specifc_user_query(user_id, accessing_user_id, pool)
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::Ok().json(collections))
 }
@@ -81,7 +86,7 @@ pub async fn get_logged_in_user_card_collections(
 ) -> Result<HttpResponse, actix_web::Error> {
     let collections = web::block(move || get_collections_for_logged_in_user_query(user.id, pool))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::Ok().json(collections))
 }
@@ -104,7 +109,7 @@ pub async fn delete_card_collection(
 
     web::block(move || delete_collection_by_id_query(collection_id, pool_two.lock().unwrap()))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -142,7 +147,7 @@ pub async fn update_card_collection(
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -172,7 +177,7 @@ pub async fn add_bookmark(
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -196,12 +201,12 @@ pub struct BookmarkCards {
 
 pub async fn get_all_bookmarks(
     path_data: web::Path<GetAllBookmarksData>,
-    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
     user: Option<LoggedUser>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let collection_id = path_data.collection_id;
     let page = path_data.page.unwrap_or(1);
-    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let thread_safe_pool = Arc::new(Mutex::new(read_pool.0.clone()));
     let pool_two = thread_safe_pool.clone();
     let pool_three = thread_safe_pool.clone();
     let current_user_id = user.map(|user| user.id);
@@ -210,7 +215,7 @@ pub async fn get_all_bookmarks(
         get_collection_by_id_query(collection_id, thread_safe_pool.lock().unwrap())
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
     if !collection.is_public && current_user_id.is_none() {
         return Err(ServiceError::Unauthorized.into());
     }
@@ -227,7 +232,7 @@ pub async fn get_all_bookmarks(
         )
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let point_ids = bookmarks
         .metadata
@@ -239,7 +244,7 @@ pub async fn get_all_bookmarks(
         get_collided_cards_query(point_ids, current_user_id, pool_three.lock().unwrap())
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     let collection_cards = bookmarks
         .metadata
@@ -276,6 +281,32 @@ pub async fn get_all_bookmarks(
     }))
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserBookmarksParams {
+    pub page: Option<i64>,
+}
+
+// Cards the user saved rather than wrote, gathered from all of their collections
+pub async fn get_user_bookmarks(
+    params: web::Query<UserBookmarksParams>,
+    user: LoggedUser,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = 25;
+
+    let (bookmarks, total_bookmarks) =
+        web::block(move || get_user_bookmarks_query(user.id, page, per_page, &pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        bookmarks,
+        PageInfo::new(page, per_page, total_bookmarks),
+    )))
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct GetCollectionBookmarkData {
     pub collection_ids: String,
@@ -301,11 +332,39 @@ pub async fn get_collections_card_is_in(
         get_collections_for_bookmark_query(collection_ids, current_user_id, pool)
     })
     .await?
-    .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+    .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::Ok().json(collections))
 }
 
+pub async fn get_card_containers(
+    card_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+    user: Option<LoggedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let current_user_id = user.map(|user| user.id);
+    let card_pool = Arc::new(Mutex::new(pool.clone()));
+
+    // A private card's containers are only listed for its author
+    let card = web::block(move || get_metadata_from_id_query(card_id, card_pool.lock().unwrap()))
+        .await?
+        .map_err(|err| match err.kind {
+            ErrorKind::BadRequest => ServiceError::NotFound,
+            _ => ServiceError::from(err),
+        })?;
+    if card.private && Some(card.author_id) != current_user_id {
+        return Err(ServiceError::Forbidden.into());
+    }
+
+    let card_containers =
+        web::block(move || get_containers_for_card_query(card_id, current_user_id, pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(card_containers))
+}
+
 #[derive(Deserialize)]
 pub struct RemoveBookmarkData {
     pub card_metadata_id: uuid::Uuid,
@@ -327,7 +386,50 @@ pub async fn delete_bookmark(
 
     web::block(move || delete_bookmark_query(bookmark_id, collection_id, pool_two.lock().unwrap()))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     Ok(HttpResponse::NoContent().finish())
 }
+
+#[derive(Deserialize)]
+pub struct MoveBookmarksData {
+    pub card_ids: Vec<uuid::Uuid>,
+    pub from_collection: uuid::Uuid,
+    pub to_collection: uuid::Uuid,
+}
+
+pub async fn move_bookmarks(
+    body: web::Json<MoveBookmarksData>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = body.into_inner();
+    let from_collection = body.from_collection;
+    let to_collection = body.to_collection;
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let pool_two = thread_safe_pool.clone();
+    let pool_three = thread_safe_pool.clone();
+
+    if from_collection == to_collection {
+        return Err(ServiceError::BadRequest(
+            "Cannot move cards to the collection they are already in".into(),
+        )
+        .into());
+    }
+
+    user_owns_collection(user.id, from_collection, thread_safe_pool).await?;
+    user_owns_collection(user.id, to_collection, pool_two).await?;
+
+    let move_result = web::block(move || {
+        move_bookmarks_query(
+            body.card_ids,
+            from_collection,
+            to_collection,
+            pool_three.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(move_result))
+}
@@ -0,0 +1,100 @@
+use crate::data::models::{Pool, SlimUser};
+use crate::data::validators::email_regex;
+use crate::errors::DefaultError;
+use crate::operators::email_change_operator::{
+    confirm_email_change_query, send_email_change_verification_query,
+};
+use crate::operators::stripe_customer_operator::update_stripe_customer_email_operation;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use super::auth_handler::{LoggedUser, SessionIdentity};
+
+#[derive(Debug, Deserialize)]
+pub struct EmailChangeData {
+    pub new_email: String,
+}
+
+pub async fn send_email_change_verification_handler(
+    request: HttpRequest,
+    email_change_data: web::Json<EmailChangeData>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let new_email = email_change_data.into_inner().new_email;
+    if !email_regex().is_match(&new_email) {
+        return Ok(HttpResponse::BadRequest().json(DefaultError {
+            message: "Invalid email",
+            ..Default::default()
+        }));
+    }
+    if new_email == user.email {
+        return Ok(HttpResponse::BadRequest().json(DefaultError {
+            message: "That is already your email",
+            ..Default::default()
+        }));
+    }
+
+    // get the host from the request
+    let app_url = request
+        .headers()
+        .get("Origin")
+        .and_then(|origin| origin.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let send_verification_result = web::block(move || {
+        send_email_change_verification_query(app_url, user.id, new_email, &pool)
+    })
+    .await?;
+
+    match send_verification_result {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+pub async fn confirm_email_change_handler(
+    request: HttpRequest,
+    email_change_id: web::Path<uuid::Uuid>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email_change_id = email_change_id.into_inner();
+
+    let confirm_result =
+        web::block(move || confirm_email_change_query(user.id, email_change_id, &pool)).await?;
+
+    let confirmed_email_change = match confirm_result {
+        Ok(confirmed_email_change) => confirmed_email_change,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    // the local change is already committed, so a Stripe failure is logged rather than undone
+    if let Some(stripe_customer) = confirmed_email_change.stripe_customer {
+        if let Err(e) = update_stripe_customer_email_operation(
+            &stripe_customer.stripe_id,
+            &confirmed_email_change.user.email,
+        )
+        .await
+        {
+            log::error!(
+                "Failed to sync email of stripe customer {}: {}",
+                stripe_customer.stripe_id,
+                e.message
+            );
+        }
+    }
+
+    // the session stores the email, so it has to be refreshed
+    let slim_user = SlimUser::from(confirmed_email_change.user);
+    if let Some(session_identity) = SessionIdentity::from_request(&request) {
+        SessionIdentity {
+            user: slim_user.clone(),
+            session_id: session_identity.session_id,
+        }
+        .login(&request);
+    }
+
+    Ok(HttpResponse::Ok().json(slim_user))
+}
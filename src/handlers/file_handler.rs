@@ -1,20 +1,32 @@
 use std::sync::{Arc, Mutex};
 
 use crate::{
-    data::models::{File, Pool},
+    data::models::{File, PageInfo, PaginatedResponse, Pool},
     errors::ServiceError,
+    operators::card_operator::reembed_cards_query,
     operators::file_operator::{
-        convert_docx_to_html_query, delete_file_query, get_file_query, get_user_file_query,
-        get_user_id_of_file_query, update_file_query, CoreCard,
+        allowed_upload_mime_types, convert_docx_to_html_query, delete_file_query,
+        get_file_cards_query, get_file_data_query, get_file_query, get_user_file_query,
+        get_user_file_usage_query, get_user_id_of_file_query, preview_card_split,
+        update_file_query, validate_docx_query, CoreCard,
     },
+    operators::stripe_customer_operator::get_user_plan_query,
+    operators::upload_progress_operator::{
+        subscribe_upload_progress, UploadProgress, UploadProgressEvent,
+    },
+};
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Bytes},
+    HttpResponse,
 };
-use actix_web::{web, HttpResponse};
 use base64::{
     alphabet,
     engine::{self, general_purpose},
     Engine as _,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use super::auth_handler::LoggedUser;
 pub async fn user_owns_file(
@@ -24,7 +36,7 @@ pub async fn user_owns_file(
 ) -> Result<(), actix_web::Error> {
     let author_id = web::block(move || get_user_id_of_file_query(file_id, pool.lock().unwrap()))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     if author_id != user_id {
         return Err(ServiceError::Forbidden.into());
@@ -37,14 +49,48 @@ pub struct UploadFileData {
     pub file_name: String,
     pub file_mime_type: String,
     pub private: bool,
+    pub dry_run: Option<bool>,
+    pub collection_strategy: Option<FileCollectionStrategy>,
+    // Lets the client follow parsing progress at /file/upload/{upload_job_id}/progress
+    pub upload_job_id: Option<uuid::Uuid>,
+}
+
+// What to do when the user already has a collection from a file with the same name
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCollectionStrategy {
+    Reuse,
+    #[default]
+    Version,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCollectionBehavior {
+    Created,
+    Reused,
+    Versioned,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DuplicateCoreCard {
+    #[serde(flatten)]
+    pub card: CoreCard,
+    // The existing card this one would be saved as a duplicate of
+    pub duplicate_of: uuid::Uuid,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UploadFileResult {
     pub file_metadata: File,
-    pub collection_id: uuid::Uuid,
+    // None for a dry run, which creates no collection
+    pub collection_id: Option<uuid::Uuid>,
+    pub collection_behavior: Option<FileCollectionBehavior>,
     pub created_cards: Vec<CoreCard>,
     pub rejected_cards: Vec<CoreCard>,
+    // Only reported by dry runs, these are also among created_cards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_cards: Option<Vec<DuplicateCoreCard>>,
 }
 
 pub async fn upload_file_handler(
@@ -61,16 +107,54 @@ pub async fn upload_file_handler(
         .decode(upload_file_data.base64_docx_file)
         .map_err(|_e| ServiceError::BadRequest("Could not decode base64 file".to_string()))?;
     let private = upload_file_data.private;
+    let dry_run = upload_file_data.dry_run.unwrap_or(false);
+    let collection_strategy = upload_file_data.collection_strategy.unwrap_or_default();
 
-    let file_mime = match upload_file_data.file_mime_type.as_str() {
-        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
-            upload_file_data.file_mime_type
+    // Dry runs store nothing, so only real uploads count against the plan's file quota
+    if !dry_run {
+        let user_email = user.email.clone();
+        let plan_pool = pool.clone();
+        let user_plan = web::block(move || get_user_plan_query(user_email, &plan_pool))
+            .await?
+            .map_err(ServiceError::from)?;
+        let user_id = user.id;
+        let usage_pool = pool.clone();
+        let file_usage = web::block(move || get_user_file_usage_query(user_id, &usage_pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+        if let Some(max_files) = user_plan.features.max_files {
+            if file_usage.file_count >= max_files {
+                return Ok(HttpResponse::Forbidden().json(json!({
+                    "message": "File limit reached for your plan",
+                    "limit": max_files,
+                })));
+            }
         }
-        _ => {
-            return Err(ServiceError::BadRequest(
-                "Must upload a docx file".to_string(),
-            ))?;
+        if let Some(max_file_storage_bytes) = user_plan.features.max_file_storage_bytes {
+            if file_usage.total_bytes + decoded_file_data.len() as i64 > max_file_storage_bytes {
+                return Ok(HttpResponse::Forbidden().json(json!({
+                    "message": "File storage limit reached for your plan",
+                    "limit": max_file_storage_bytes,
+                })));
+            }
         }
+    }
+
+    let allowed_mime_types = allowed_upload_mime_types();
+    if !allowed_mime_types.contains(&upload_file_data.file_mime_type.as_str()) {
+        return Ok(HttpResponse::UnsupportedMediaType().json(json!({
+            "message": "File type is not allowed",
+            "allowed_mime_types": allowed_mime_types,
+        })));
+    }
+    let file_mime = upload_file_data.file_mime_type;
+
+    let progress = match upload_file_data.upload_job_id {
+        Some(upload_job_id) => {
+            UploadProgress::track(upload_job_id, user.id).map_err(ServiceError::from)?
+        }
+        None => UploadProgress::untracked(),
     };
 
     let conversion_result = convert_docx_to_html_query(
@@ -78,13 +162,101 @@ pub async fn upload_file_handler(
         decoded_file_data,
         file_mime,
         private,
+        dry_run,
+        collection_strategy,
         user,
+        progress.clone(),
         pool_inner,
     )
-    .await
-    .map_err(|e| ServiceError::BadRequest(e.message.to_string()))?;
+    .await;
+
+    match conversion_result {
+        Ok(conversion_result) => {
+            progress.report(UploadProgressEvent::Completed {
+                result: Box::new(conversion_result.clone()),
+            });
+            Ok(HttpResponse::Ok().json(conversion_result))
+        }
+        Err(e) => {
+            progress.report(UploadProgressEvent::Failed {
+                message: e.message.to_string(),
+            });
+            Err(ServiceError::BadRequest(e.message.to_string()).into())
+        }
+    }
+}
+
+// Server-sent events for an upload started with the same upload_job_id, ending with the
+// completed or failed event
+pub async fn get_upload_progress_handler(
+    upload_job_id: web::Path<uuid::Uuid>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut progress_receiver = subscribe_upload_progress(upload_job_id.into_inner(), user.id)
+        .map_err(ServiceError::from)?;
+
+    let progress_stream = async_stream::stream! {
+        loop {
+            let event = progress_receiver.borrow_and_update().clone();
+            let is_final = event.is_final();
+            let event_json = serde_json::to_string(&event).unwrap_or_default();
+            yield Ok::<_, actix_web::Error>(Bytes::from(format!("data: {}\n\n", event_json)));
+
+            if is_final || progress_receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(progress_stream))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ValidateFileData {
+    pub base64_docx_file: String,
+    pub file_mime_type: String,
+}
+
+// Nothing is persisted, failures carry a code so clients can tell a corrupt file from an
+// unsupported or empty one
+pub async fn validate_file_handler(
+    data: web::Json<ValidateFileData>,
+    _user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let validate_file_data = data.into_inner();
+
+    let base64_engine = engine::GeneralPurpose::new(&alphabet::URL_SAFE, general_purpose::NO_PAD);
+    let decoded_file_data = base64_engine
+        .decode(validate_file_data.base64_docx_file)
+        .map_err(|_e| ServiceError::BadRequest("Could not decode base64 file".to_string()))?;
+
+    let validation_result = web::block(move || {
+        validate_docx_query(&decoded_file_data, &validate_file_data.file_mime_type)
+    })
+    .await?;
+
+    match validation_result {
+        Ok(docx_validation) => Ok(HttpResponse::Ok().json(docx_validation)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
 
-    Ok(HttpResponse::Ok().json(conversion_result))
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CardSplitPreviewData {
+    pub text: String,
+}
+
+// Nothing is persisted, this only shows how pasted text would be split into cards
+pub async fn preview_card_split_handler(
+    data: web::Json<CardSplitPreviewData>,
+    _user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_split_preview = web::block(move || preview_card_split(&data.text)).await?;
+
+    Ok(HttpResponse::Ok().json(card_split_preview))
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -122,6 +294,25 @@ pub async fn get_file_handler(
     Ok(HttpResponse::Ok().json(file))
 }
 
+pub async fn download_file_handler(
+    file_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+    user: Option<LoggedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user.map(|user| user.id);
+
+    let (file_metadata, file_data) =
+        get_file_data_query(file_id.into_inner(), user_id, pool).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(file_metadata.mime_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_metadata.file_name)],
+        })
+        .body(file_data))
+}
+
 pub async fn get_user_files_handler(
     user_id: web::Path<uuid::Uuid>,
     pool: web::Data<Pool>,
@@ -131,8 +322,33 @@ pub async fn get_user_files_handler(
     let user_id = user_id.into_inner();
 
     let files = get_user_file_query(user_id, accessing_user_id, pool).await?;
+    let page_info = PageInfo::single_page(files.len());
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(files, page_info)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReembedFileDTO {
+    pub updated: usize,
+}
+
+// Refreshes the vectors of every card from the file, for after edits or an embedding model change
+pub async fn reembed_file_handler(
+    file_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+    user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let file_id = file_id.into_inner();
+    let thread_safe_pool = Arc::new(Mutex::new(pool.clone()));
+    user_owns_file(user.id, file_id, thread_safe_pool).await?;
+
+    let file_cards = web::block(move || get_file_cards_query(file_id, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    let updated = reembed_cards_query(file_cards).await?;
 
-    Ok(HttpResponse::Ok().json(files))
+    Ok(HttpResponse::Ok().json(ReembedFileDTO { updated }))
 }
 
 pub async fn delete_file_handler(
@@ -1,6 +1,6 @@
 use actix_web::{web, HttpRequest, HttpResponse};
 use diesel::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 
 use crate::{
@@ -30,6 +30,7 @@ pub async fn post_invitation(
         return Ok(
             HttpResponse::BadRequest().json(crate::errors::DefaultError {
                 message: "Invalid email",
+                ..Default::default()
             }),
         );
     }
@@ -51,6 +52,49 @@ pub async fn post_invitation(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvitationStatus {
+    Valid,
+    Expired,
+    Consumed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationStatusDTO {
+    pub status: InvitationStatus,
+    pub email: String,
+}
+
+// Only the invitation itself decides the status so this can't be used to check for accounts
+pub async fn get_invitation_status(
+    invitation_token: web::Path<String>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let invitation_token = invitation_token.into_inner();
+
+    let invitation =
+        web::block(move || get_invitation_by_token_query(invitation_token, pool)).await?;
+
+    let invitation = match invitation {
+        Ok(invitation) => invitation,
+        Err(e) => return Ok(HttpResponse::NotFound().json(e)),
+    };
+
+    let status = if invitation.used {
+        InvitationStatus::Consumed
+    } else if invitation.expires_at <= chrono::Local::now().naive_local() {
+        InvitationStatus::Expired
+    } else {
+        InvitationStatus::Valid
+    };
+
+    Ok(HttpResponse::Ok().json(InvitationStatusDTO {
+        status,
+        email: invitation.email,
+    }))
+}
+
 pub fn create_invitation(
     app_url: String,
     email: String,
@@ -73,10 +117,11 @@ fn create_invitation_query(
     if user_exists {
         return Err(DefaultError {
             message: "An account with this email already exists.",
+            ..Default::default()
         });
     }
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let mut new_invitation = Invitation::from(email);
     new_invitation.referral_tokens = Some(invitation_referral_tokens);
@@ -86,7 +131,41 @@ fn create_invitation_query(
         .get_result(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error inserting invitation.",
+            ..Default::default()
         })?;
 
     Ok(inserted_invitation)
 }
+
+fn get_invitation_by_token_query(
+    invitation_token: String,
+    pool: web::Data<Pool>,
+) -> Result<Invitation, DefaultError> {
+    use crate::data::schema::invitations::dsl::{invitations, token};
+
+    let mut conn = pool.get()?;
+
+    invitations
+        .filter(token.eq(invitation_token))
+        .first::<Invitation>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Invalid Invitation",
+            ..Default::default()
+        })
+}
+
+pub fn delete_expired_invitations_query(pool: &Pool) -> Result<usize, DefaultError> {
+    use crate::data::schema::invitations::dsl as invitations_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::delete(
+        invitations_columns::invitations
+            .filter(invitations_columns::expires_at.lt(chrono::Local::now().naive_local())),
+    )
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to delete expired invitations",
+        ..Default::default()
+    })
+}
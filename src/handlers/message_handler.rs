@@ -1,38 +1,196 @@
 use crate::{
     data::models,
-    data::models::Pool,
+    data::models::{PageInfo, PaginatedResponse, Pool},
     errors::{DefaultError, ServiceError},
     operators::message_operator::{
         create_message_query, create_topic_message_query, delete_message_query,
-        get_message_by_sort_for_topic_query, get_messages_for_topic_query, get_topic_messages,
-        user_owns_topic_query,
+        get_message_by_sort_for_topic_query, get_message_count_for_topic_query,
+        get_message_history_query, get_messages_for_topic_query,
+        get_paginated_messages_for_topic_query, get_pinned_messages_for_topic_query,
+        get_prompt_token_count, get_token_count, get_topic_messages,
+        get_topic_messages_after_query, search_user_messages_query, set_message_pinned_query,
+        update_streamed_message_query, user_owns_topic_query,
     },
+    operators::streaming_message_operator::{get_streaming_message, StreamingMessageTracker},
+    operators::stripe_customer_operator::get_user_plan_query,
 };
 use actix::Arbiter;
 use actix_web::{
     web::{self, Bytes},
     HttpResponse,
 };
-use crossbeam_channel::unbounded;
+use once_cell::sync::Lazy;
 use openai_dive::v1::{
     api::Client,
     resources::chat_completion::{ChatCompletionParameters, ChatMessage},
 };
 use serde::{Deserialize, Serialize};
-use tokio_stream::StreamExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
 
 use super::auth_handler::LoggedUser;
 
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CompletionOptions {
+    pub max_tokens: Option<u32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub stop: Option<Vec<String>>,
+    // Plain text clients get only the completion, the usage frame is sent when asked for
+    pub include_usage: Option<bool>,
+}
+
+// OpenAI accepts at most this many stop sequences per completion
+const MAX_STOP_SEQUENCES: usize = 4;
+
+impl CompletionOptions {
+    // OpenAI rejects penalties outside of -2.0 to 2.0 and more than 4 stop sequences
+    fn validate(&self) -> Result<(), DefaultError> {
+        for (penalty, message) in [
+            (
+                self.presence_penalty,
+                "presence_penalty must be between -2.0 and 2.0",
+            ),
+            (
+                self.frequency_penalty,
+                "frequency_penalty must be between -2.0 and 2.0",
+            ),
+        ] {
+            if penalty.is_some_and(|penalty| !(-2.0..=2.0).contains(&penalty)) {
+                return Err(DefaultError {
+                    message,
+                    ..Default::default()
+                });
+            }
+        }
+
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(DefaultError {
+                    message: "stop can have at most 4 sequences",
+                    ..Default::default()
+                });
+            }
+            if stop.iter().any(|stop_sequence| stop_sequence.is_empty()) {
+                return Err(DefaultError {
+                    message: "stop sequences must not be empty",
+                    ..Default::default()
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateMessageData {
     pub new_message_content: String,
     pub topic_id: uuid::Uuid,
+    #[serde(flatten)]
+    pub completion_options: CompletionOptions,
+}
+
+static COMPLETION_REQUESTS: Lazy<Mutex<HashMap<uuid::Uuid, (Instant, u64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Each completion counts against the per minute limit of the user's plan
+async fn check_completion_rate_limit(
+    user: &LoggedUser,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    let user_email = user.email.clone();
+    let plan_pool = pool.clone();
+    let user_plan = web::block(move || get_user_plan_query(user_email, &plan_pool))
+        .await
+        .map_err(|_err| DefaultError {
+            message: "Error finding user plan, try again",
+            ..Default::default()
+        })??;
+
+    let now = Instant::now();
+    let mut completion_requests = COMPLETION_REQUESTS.lock().unwrap();
+    completion_requests
+        .retain(|_, (window_start, _)| now.duration_since(*window_start) < Duration::from_secs(60));
+    let (_, request_count) = completion_requests.entry(user.id).or_insert((now, 0));
+    *request_count += 1;
+
+    if *request_count > user_plan.features.completions_per_minute {
+        return Err(DefaultError {
+            message: "Too many completion requests for your plan, try again later",
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
+
+// Each completion adds the user's message and the reply, both have to fit under the plan's cap
+async fn check_topic_message_limit(
+    user: &LoggedUser,
+    topic_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Option<HttpResponse>, DefaultError> {
+    let user_email = user.email.clone();
+    let plan_pool = pool.clone();
+    let count_pool = pool.clone();
+    let user_plan = web::block(move || get_user_plan_query(user_email, &plan_pool))
+        .await
+        .map_err(|_err| DefaultError {
+            message: "Error finding user plan, try again",
+            ..Default::default()
+        })??;
+    let max_messages_per_topic = match user_plan.features.max_messages_per_topic {
+        Some(max_messages_per_topic) => max_messages_per_topic,
+        None => return Ok(None),
+    };
+
+    let message_count =
+        web::block(move || get_message_count_for_topic_query(topic_id, &count_pool))
+            .await
+            .map_err(|_err| DefaultError {
+                message: "Error counting topic messages",
+                ..Default::default()
+            })??;
+    if message_count + 2 > max_messages_per_topic {
+        return Ok(Some(HttpResponse::Forbidden().json(json!({
+            "message": "This topic has reached the message limit for your plan, fork it or start a new topic to keep going",
+            "limit": max_messages_per_topic,
+            "fork_url": format!("/api/topic/{}/fork", topic_id),
+        }))));
+    }
+
+    Ok(None)
 }
 
 pub async fn create_message_completion_handler(
     data: web::Json<CreateMessageData>,
     user: LoggedUser,
     pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(e) = data.completion_options.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+    match check_topic_message_limit(&user, data.topic_id, &pool).await {
+        Ok(Some(response)) => return Ok(response),
+        Ok(None) => {}
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    }
+    if let Err(e) = check_completion_rate_limit(&user, &pool).await {
+        return Ok(HttpResponse::TooManyRequests().json(e));
+    }
+
+    create_message_completion(data, user, pool).await
+}
+
+async fn create_message_completion(
+    data: web::Json<CreateMessageData>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let create_message_data = data.into_inner();
     let new_message = models::Message::from_details(
@@ -44,6 +202,7 @@ pub async fn create_message_completion_handler(
         None,
     );
     let topic_id = create_message_data.topic_id;
+    let completion_options = create_message_data.completion_options;
     let second_pool = pool.clone();
     let third_pool = pool.clone();
     let fourth_pool = pool.clone();
@@ -56,7 +215,7 @@ pub async fn create_message_completion_handler(
     // get the previous messages
     let previous_messages = web::block(move || get_topic_messages(topic_id, &second_pool))
         .await?
-        .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+        .map_err(ServiceError::from)?;
 
     // call create_topic_message_query with the new_message and previous_messages
     let previous_messages_result = web::block(move || {
@@ -70,17 +229,35 @@ pub async fn create_message_completion_handler(
         }
     };
 
-    stream_response(previous_messages, user.id, topic_id, fourth_pool).await
+    stream_response(
+        previous_messages,
+        user.id,
+        topic_id,
+        completion_options,
+        fourth_pool,
+        StreamingMessageTracker::start(topic_id, user.id),
+    )
+    .await
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TopicMessagesParams {
+    page: Option<i64>,
+    per_page: Option<i64>,
 }
 
+// Deep enough for any real conversation, past it the offset only costs the database
+const MAX_TOPIC_MESSAGES_PAGE: i64 = 1000;
+
 // get_all_topic_messages_handler
 // verify that the user owns the topic for the topic_id they are requesting
-// get all the messages for the topic_id
 // filter out deleted messages
-// return the messages
+// without paging params return every message as before, otherwise a page of the messages, most
+// recent first, with the total count
 pub async fn get_all_topic_messages(
     user: LoggedUser,
     messages_topic_id: web::Path<uuid::Uuid>,
+    params: web::Query<TopicMessagesParams>,
     pool: web::Data<Pool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let second_pool = pool.clone();
@@ -92,10 +269,184 @@ pub async fn get_all_topic_messages(
         return Ok(HttpResponse::Unauthorized().json("Unauthorized"));
     }
 
-    let messages = web::block(move || get_messages_for_topic_query(topic_id, &pool)).await?;
+    if params.page.is_none() && params.per_page.is_none() {
+        let messages = web::block(move || get_messages_for_topic_query(topic_id, &pool)).await?;
+
+        return match messages {
+            Ok(messages) => Ok(HttpResponse::Ok().json(messages)),
+            Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+        };
+    }
+
+    let page = params.page.unwrap_or(1).clamp(1, MAX_TOPIC_MESSAGES_PAGE);
+    let per_page = params.per_page.unwrap_or(50).clamp(1, 200);
+
+    let messages =
+        web::block(move || get_paginated_messages_for_topic_query(topic_id, page, per_page, &pool))
+            .await?;
 
     match messages {
-        Ok(messages) => Ok(HttpResponse::Ok().json(messages)),
+        Ok((messages, total_messages)) => Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+            messages,
+            PageInfo::new(page, per_page, total_messages),
+        ))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TopicMessagesAfterParams {
+    after_sort_order: i32,
+}
+
+// Lets a polling or reconnecting client fetch only the messages it hasn't seen yet
+pub async fn get_topic_messages_after(
+    user: LoggedUser,
+    topic_id: web::Path<uuid::Uuid>,
+    params: web::Query<TopicMessagesAfterParams>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = topic_id.into_inner();
+    let after_sort_order = params.after_sort_order;
+    let second_pool = pool.clone();
+
+    let user_owns_topic =
+        web::block(move || user_owns_topic_query(user.id, topic_id, &second_pool));
+    if let Ok(false) = user_owns_topic.await {
+        return Ok(HttpResponse::Unauthorized().json("Unauthorized"));
+    }
+
+    let messages =
+        web::block(move || get_topic_messages_after_query(topic_id, after_sort_order, &pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(messages))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SearchMessagesData {
+    pub query: String,
+    pub page: Option<i64>,
+}
+
+pub async fn search_messages(
+    user: LoggedUser,
+    data: web::Json<SearchMessagesData>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = data.query.trim().to_string();
+    if query.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "message": "Search query must not be empty",
+        })));
+    }
+    let page = data.page.unwrap_or(1).max(1);
+    let per_page = 10;
+
+    let search_results =
+        web::block(move || search_user_messages_query(user.id, query, page, per_page, &pool))
+            .await?;
+
+    match search_results {
+        Ok((search_results, total_count)) => Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+            search_results,
+            PageInfo::new(page, per_page, total_count),
+        ))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+pub async fn get_message_history(
+    user: LoggedUser,
+    message_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let message_id = message_id.into_inner();
+
+    let history = web::block(move || get_message_history_query(user.id, message_id, &pool)).await?;
+
+    match history {
+        Ok(history) => Ok(HttpResponse::Ok().json(history)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+async fn set_message_pinned(
+    user: LoggedUser,
+    message_id: uuid::Uuid,
+    pinned: bool,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let message =
+        web::block(move || set_message_pinned_query(user.id, message_id, pinned, &pool)).await?;
+
+    match message {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+pub async fn pin_message(
+    user: LoggedUser,
+    message_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_message_pinned(user, message_id.into_inner(), true, pool).await
+}
+
+pub async fn unpin_message(
+    user: LoggedUser,
+    message_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_message_pinned(user, message_id.into_inner(), false, pool).await
+}
+
+pub async fn get_pinned_messages(
+    user: LoggedUser,
+    topic_id: web::Path<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = topic_id.into_inner();
+
+    let pinned_messages =
+        web::block(move || get_pinned_messages_for_topic_query(user.id, topic_id, &pool)).await?;
+
+    match pinned_messages {
+        Ok(pinned_messages) => Ok(HttpResponse::Ok().json(pinned_messages)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+// Lets a client that lost its connection mid completion pick the partial reply back up, once
+// active is false the reply has been saved to the topic
+pub async fn get_topic_streaming_message(
+    user: LoggedUser,
+    topic_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let streaming_message =
+        get_streaming_message(topic_id.into_inner(), user.id).ok_or(ServiceError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(streaming_message))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TokenizeData {
+    text: String,
+    model: Option<String>,
+}
+
+pub async fn tokenize_handler(
+    data: web::Json<TokenizeData>,
+    _user: LoggedUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let model = data.model.clone().unwrap_or("gpt-3.5-turbo".to_string());
+
+    let token_count = web::block(move || get_token_count(&data.text, &model)).await?;
+
+    match token_count {
+        Ok(token_count) => Ok(HttpResponse::Ok().json(json!({ "token_count": token_count }))),
         Err(e) => Ok(HttpResponse::BadRequest().json(e)),
     }
 }
@@ -103,6 +454,8 @@ pub async fn get_all_topic_messages(
 #[derive(Deserialize, Serialize, Debug)]
 pub struct RegenerateMessageData {
     topic_id: uuid::Uuid,
+    #[serde(flatten)]
+    completion_options: CompletionOptions,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -110,6 +463,8 @@ pub struct EditMessageData {
     topic_id: uuid::Uuid,
     message_sort_order: i32,
     new_message_content: String,
+    #[serde(flatten)]
+    completion_options: CompletionOptions,
 }
 
 pub async fn edit_message_handler(
@@ -123,6 +478,13 @@ pub async fn edit_message_handler(
     let second_pool = pool.clone();
     let third_pool = pool.clone();
 
+    if let Err(e) = data.completion_options.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+    if let Err(e) = check_completion_rate_limit(&user, &pool).await {
+        return Ok(HttpResponse::TooManyRequests().json(e));
+    }
+
     let message_from_sort_order_result = web::block(move || {
         get_message_by_sort_for_topic_query(topic_id, message_sort_order, &pool)
     })
@@ -138,10 +500,11 @@ pub async fn edit_message_handler(
     let _ = web::block(move || delete_message_query(&user.id, message_id, topic_id, &second_pool))
         .await?;
 
-    create_message_completion_handler(
+    create_message_completion(
         actix_web::web::Json(CreateMessageData {
             new_message_content: new_message_content.to_string(),
             topic_id,
+            completion_options: data.completion_options.clone(),
         }),
         user,
         third_pool,
@@ -155,9 +518,17 @@ pub async fn regenerate_message_handler(
     pool: web::Data<Pool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let topic_id = data.topic_id;
+    let completion_options = data.completion_options.clone();
     let second_pool = pool.clone();
     let third_pool = pool.clone();
 
+    if let Err(e) = completion_options.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+    if let Err(e) = check_completion_rate_limit(&user, &pool).await {
+        return Ok(HttpResponse::TooManyRequests().json(e));
+    }
+
     let previous_messages_result =
         web::block(move || get_topic_messages(topic_id, &second_pool)).await?;
     let previous_messages = match previous_messages_result {
@@ -170,10 +541,19 @@ pub async fn regenerate_message_handler(
     if previous_messages.len() < 3 {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Not enough messages to regenerate",
+            ..Default::default()
         }));
     }
     if previous_messages.len() == 3 {
-        return stream_response(previous_messages, user.id, topic_id, third_pool).await;
+        return stream_response(
+            previous_messages,
+            user.id,
+            topic_id,
+            completion_options,
+            third_pool,
+            StreamingMessageTracker::start(topic_id, user.id),
+        )
+        .await;
     }
 
     let mut message_to_regenerate = None;
@@ -189,6 +569,7 @@ pub async fn regenerate_message_handler(
         None => {
             return Ok(HttpResponse::BadRequest().json(DefaultError {
                 message: "No message to regenerate",
+                ..Default::default()
             }));
         }
     };
@@ -207,16 +588,143 @@ pub async fn regenerate_message_handler(
         previous_messages_to_regenerate,
         user.id,
         topic_id,
+        completion_options,
+        third_pool,
+        StreamingMessageTracker::start(topic_id, user.id),
+    )
+    .await
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ResumeMessageData {
+    topic_id: uuid::Uuid,
+    #[serde(flatten)]
+    completion_options: CompletionOptions,
+}
+
+// A topic left ending on the user's message, e.g. after a crash mid completion, gets its missing
+// reply generated here instead of making the user send the message again
+pub async fn resume_message_handler(
+    data: web::Json<ResumeMessageData>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = data.topic_id;
+    let completion_options = data.completion_options.clone();
+    let second_pool = pool.clone();
+    let third_pool = pool.clone();
+
+    if let Err(e) = completion_options.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let user_owns_topic = web::block(move || user_owns_topic_query(user.id, topic_id, &pool));
+    if let Ok(false) = user_owns_topic.await {
+        return Ok(HttpResponse::Unauthorized().json("Unauthorized"));
+    }
+
+    // Reserved before the topic is read so a second resume can't also find the user's message
+    // unanswered and generate another reply for it
+    let streaming_message = match StreamingMessageTracker::try_start(topic_id, user.id) {
+        Some(streaming_message) => streaming_message,
+        None => {
+            return Ok(HttpResponse::Conflict().json(DefaultError {
+                message: "A reply is already being generated for this topic",
+                ..Default::default()
+            }));
+        }
+    };
+
+    let previous_messages =
+        match web::block(move || get_topic_messages(topic_id, &second_pool)).await? {
+            Ok(previous_messages) => previous_messages,
+            Err(err) => {
+                streaming_message.cancel();
+                return Err(ServiceError::BadRequest(err.message.into()).into());
+            }
+        };
+    if previous_messages
+        .last()
+        .map(|message| message.role.as_str())
+        != Some("user")
+    {
+        streaming_message.cancel();
+        return Ok(HttpResponse::BadRequest().json(DefaultError {
+            message: "Topic has no unanswered message to resume",
+            ..Default::default()
+        }));
+    }
+
+    // The message limit already made room for this reply when the user's message was sent
+    if let Err(e) = check_completion_rate_limit(&user, &third_pool).await {
+        streaming_message.cancel();
+        return Ok(HttpResponse::TooManyRequests().json(e));
+    }
+
+    stream_response(
+        previous_messages,
+        user.id,
+        topic_id,
+        completion_options,
         third_pool,
+        streaming_message,
     )
     .await
 }
 
+// Completions whose assistant message hasn't been persisted yet
+pub static IN_FLIGHT_COMPLETIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Completions run on their own arbiter and outlive the server, so wait for their replies to be
+// persisted before the process exits
+pub async fn wait_for_in_flight_completions(grace_period: Duration) {
+    let deadline = Instant::now() + grace_period;
+
+    while IN_FLIGHT_COMPLETIONS.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        actix::clock::sleep(Duration::from_millis(100)).await;
+    }
+
+    let remaining = IN_FLIGHT_COMPLETIONS.load(Ordering::SeqCst);
+    if remaining > 0 {
+        log::warn!("Shutting down with {} completions not persisted", remaining);
+    }
+}
+
+// Server side cap on completion length so a runaway generation can't use the whole model limit,
+// applied even when the client doesn't ask for a limit
+fn get_completion_max_tokens(requested_max_tokens: Option<u32>) -> u32 {
+    let server_max_tokens: u32 = std::env::var("COMPLETION_MAX_TOKENS")
+        .ok()
+        .and_then(|max_tokens| max_tokens.parse().ok())
+        .unwrap_or(1024);
+
+    requested_max_tokens
+        .unwrap_or(server_max_tokens)
+        .min(server_max_tokens)
+        .max(1)
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct CompletionUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub finish_reason: Option<String>,
+}
+
+// Separates the streamed completion text from the usage frame sent once it ends, when the client
+// asked for it with include_usage. The record separator never shows up in model output.
+pub const COMPLETION_DONE_DELIMITER: &str = "\u{1e}";
+
+// OpenAI ends a completion with this finish reason when its moderation cut the reply off
+pub const CONTENT_FILTER_FINISH_REASON: &str = "content_filter";
+
 pub async fn stream_response(
     messages: Vec<models::Message>,
     user_id: uuid::Uuid,
     topic_id: uuid::Uuid,
+    completion_options: CompletionOptions,
     pool: web::Data<Pool>,
+    streaming_message: StreamingMessageTracker,
 ) -> Result<HttpResponse, actix_web::Error> {
     let open_ai_messages: Vec<ChatMessage> = messages
         .iter()
@@ -225,57 +733,131 @@ pub async fn stream_response(
 
     let open_ai_api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
     let client = Client::new(open_ai_api_key);
-    let next_message_order = move || {
-        let messages_len = messages.len();
-        if messages_len == 0 {
-            return 3;
-        }
-        messages_len + 1
+    let model = "gpt-3.5-turbo";
+    let mut usage = CompletionUsage {
+        prompt_tokens: get_prompt_token_count(&messages, model).unwrap_or(0),
+        ..Default::default()
+    };
+    let include_usage = completion_options.include_usage.unwrap_or(false);
+    let next_message_order = match messages.len() {
+        0 => 3,
+        messages_len => messages_len + 1,
     };
 
     let parameters = ChatCompletionParameters {
-        model: "gpt-3.5-turbo".into(),
+        model: model.into(),
         messages: open_ai_messages,
         temperature: None,
         top_p: None,
         n: None,
-        stop: None,
-        max_tokens: None,
-        presence_penalty: Some(0.8),
-        frequency_penalty: Some(0.8),
+        stop: completion_options.stop,
+        max_tokens: Some(get_completion_max_tokens(completion_options.max_tokens)),
+        // unset penalties keep the defaults completions have always used
+        presence_penalty: Some(completion_options.presence_penalty.unwrap_or(0.8)),
+        frequency_penalty: Some(completion_options.frequency_penalty.unwrap_or(0.8)),
         logit_bias: None,
         user: None,
     };
 
-    let (s, r) = unbounded::<String>();
-    let stream = client.chat().create_stream(parameters).await.unwrap();
+    let mut stream = match client.chat().create_stream(parameters).await {
+        Ok(stream) => stream,
+        Err(_err) => {
+            streaming_message.cancel();
+            return Err(ServiceError::BadRequest("Failed to start completion".into()).into());
+        }
+    };
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
 
+    // The completion is driven here instead of by the response body, so it keeps going and is
+    // saved to the topic when the client disconnects. The response only reads what is sent.
+    IN_FLIGHT_COMPLETIONS.fetch_add(1, Ordering::SeqCst);
     Arbiter::new().spawn(async move {
-        let chunk_v: Vec<String> = r.iter().collect();
-        let completion = chunk_v.join("");
-
-        let new_message = models::Message::from_details(
-            completion,
+        let mut new_message = models::Message::from_details(
+            String::new(),
             topic_id,
-            next_message_order().try_into().unwrap(),
+            next_message_order.try_into().unwrap(),
             "assistant".to_string(),
             None,
-            Some(chunk_v.len().try_into().unwrap()),
+            Some(0),
         );
+        let mut message_persisted = false;
+        let mut last_persisted_at = Instant::now();
+
+        while let Some(response) = stream.next().await {
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => {
+                    log::error!("Completion stream failed: {:?}", err);
+                    break;
+                }
+            };
 
-        let _ = create_message_query(new_message, user_id, &pool);
-    });
+            let choice = response.choices.first();
+            if let Some(finish_reason) = choice.and_then(|choice| choice.finish_reason.clone()) {
+                usage.finish_reason = Some(finish_reason);
+            }
+            // Role-only, finish and function call deltas carry no text, so they are skipped
+            // rather than treated as content
+            if let Some(chat_content) = choice.and_then(|choice| choice.delta.content.clone()) {
+                new_message.content.push_str(&chat_content);
+                streaming_message.append(&chat_content);
+                let _ = sender.send(Bytes::from(chat_content));
+            }
 
-    Ok(HttpResponse::Ok().streaming(stream.map(
-        move |response| -> Result<Bytes, actix_web::Error> {
-            if let Ok(response) = response {
-                let chat_content = response.choices[0].delta.content.clone();
-                if let Some(message) = chat_content.clone() {
-                    s.send(message).unwrap();
-                }
-                return Ok(Bytes::from(chat_content.unwrap_or("".to_string())));
+            if last_persisted_at.elapsed() >= STREAMED_MESSAGE_PERSIST_INTERVAL {
+                persist_streamed_message(&new_message, &mut message_persisted, user_id, &pool);
+                last_persisted_at = Instant::now();
             }
-            Err(ServiceError::InternalServerError.into())
-        },
-    )))
+        }
+
+        // Deltas don't map one to one onto tokens, so the finished text is tokenized instead
+        usage.completion_tokens = get_token_count(&new_message.content, model).unwrap_or(0);
+        new_message.completion_tokens = usage.completion_tokens.try_into().ok();
+        // Kept on the message so a blocked reply still reads as blocked after a reload
+        new_message.finish_reason = usage.finish_reason.clone();
+
+        persist_streamed_message(&new_message, &mut message_persisted, user_id, &pool);
+        streaming_message.finish();
+        IN_FLIGHT_COMPLETIONS.fetch_sub(1, Ordering::SeqCst);
+
+        if include_usage {
+            let frame = if usage.finish_reason.as_deref() == Some(CONTENT_FILTER_FINISH_REASON) {
+                json!({
+                    "type": "error",
+                    "message": "The response was blocked by the content filter",
+                    "usage": usage,
+                })
+            } else {
+                json!({ "type": "done", "usage": usage })
+            };
+            let _ = sender.send(Bytes::from(format!(
+                "{}{}",
+                COMPLETION_DONE_DELIMITER, frame
+            )));
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .streaming(UnboundedReceiverStream::new(receiver).map(Ok::<Bytes, actix_web::Error>)))
+}
+
+// How often a reply still streaming is written to the topic, so a crash loses at most this much
+const STREAMED_MESSAGE_PERSIST_INTERVAL: Duration = Duration::from_secs(2);
+
+// Inserts the streamed message the first time, later calls overwrite it with the text so far
+fn persist_streamed_message(
+    streamed_message: &models::Message,
+    message_persisted: &mut bool,
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) {
+    let persisted = match *message_persisted {
+        true => update_streamed_message_query(streamed_message, pool),
+        false => create_message_query(streamed_message.clone(), user_id, pool),
+    };
+
+    match persisted {
+        Ok(()) => *message_persisted = true,
+        Err(err) => log::error!("Failed to persist streamed message: {}", err.message),
+    }
 }
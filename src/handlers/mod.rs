@@ -1,9 +1,12 @@
+pub mod admin_handler;
 pub mod auth_handler;
 pub mod card_handler;
 pub mod collection_handler;
+pub mod email_change_handler;
 pub mod file_handler;
 pub mod invitation_handler;
 pub mod message_handler;
+pub mod moderation_handler;
 pub mod notification_handler;
 pub mod password_reset_handler;
 pub mod register_handler;
@@ -0,0 +1,164 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::models::{CardMetadata, PageInfo, PaginatedResponse, Pool},
+    errors::ServiceError,
+    handlers::card_handler::delete_card_and_point,
+    operators::card_operator::{
+        approve_card_query, get_card_qdrant_payload, get_metadata_from_id_query,
+        sync_card_qdrant_payload_query, update_card_metadata_query,
+        update_card_qdrant_payload_query,
+    },
+    operators::moderation_operator::{get_moderation_queue_query, get_pending_cards_query},
+};
+
+use super::auth_handler::AdminUser;
+
+// Past this the queues are better narrowed with the threshold than paged through
+const MAX_MODERATION_PAGE: i64 = 1000;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationQueueParams {
+    pub threshold: Option<i64>,
+    pub page: Option<i64>,
+}
+
+pub async fn get_moderation_queue(
+    params: web::Query<ModerationQueueParams>,
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let threshold = params.threshold.unwrap_or(0);
+    let page = params.page.unwrap_or(1).clamp(1, MAX_MODERATION_PAGE);
+
+    let moderation_queue =
+        web::block(move || get_moderation_queue_query(threshold, page, 10, &pool)).await?;
+
+    match moderation_queue {
+        Ok((cards, total_cards)) => Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+            cards,
+            PageInfo::new(page, 10, total_cards),
+        ))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingCardsParams {
+    pub page: Option<i64>,
+}
+
+pub async fn get_pending_cards(
+    params: web::Query<PendingCardsParams>,
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = params.page.unwrap_or(1).clamp(1, MAX_MODERATION_PAGE);
+
+    let (pending_cards, total_pending_cards) =
+        web::block(move || get_pending_cards_query(page, 10, &pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        pending_cards,
+        PageInfo::new(page, 10, total_pending_cards),
+    )))
+}
+
+pub async fn moderator_delete_card(
+    card_id: web::Path<uuid::Uuid>,
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let pool1 = thread_safe_pool.clone();
+
+    let card_metadata =
+        web::block(move || get_metadata_from_id_query(card_id, pool1.lock().unwrap()))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    delete_card_and_point(card_metadata, thread_safe_pool).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Hiding makes the card private, so it stays with its author but leaves search and the queue
+pub async fn moderator_hide_card(
+    card_id: web::Path<uuid::Uuid>,
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let pool1 = thread_safe_pool.clone();
+    let pool2 = thread_safe_pool.clone();
+
+    let card_metadata =
+        web::block(move || get_metadata_from_id_query(card_id, pool1.lock().unwrap()))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    let qdrant_point_id = card_metadata.qdrant_point_id;
+    let payload = get_card_qdrant_payload(
+        card_metadata.author_id,
+        &card_metadata.link,
+        &card_metadata.oc_file_path,
+        true,
+        card_metadata.approved,
+        &card_metadata.citation(),
+    );
+
+    web::block(move || {
+        update_card_metadata_query(
+            CardMetadata::from_details_with_id(
+                card_metadata.id,
+                &card_metadata.content,
+                &card_metadata.card_html,
+                &card_metadata.link,
+                &card_metadata.oc_file_path,
+                card_metadata.author_id,
+                card_metadata.qdrant_point_id,
+                true,
+            ),
+            None,
+            pool2.lock().unwrap(),
+        )
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    if let Some(qdrant_point_id) = qdrant_point_id {
+        update_card_qdrant_payload_query(qdrant_point_id, payload)
+            .await
+            .map_err(ServiceError::from)?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Approving lets a card created while CARD_APPROVAL_REQUIRED was set into public search
+pub async fn moderator_approve_card(
+    card_id: web::Path<uuid::Uuid>,
+    _admin: AdminUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_id = card_id.into_inner();
+
+    let approved_card = web::block(move || approve_card_query(card_id, None, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    if let Some(approved_card) = approved_card {
+        sync_card_qdrant_payload_query(&approved_card)
+            .await
+            .map_err(ServiceError::from)?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -25,11 +25,13 @@ pub async fn reset_user_password_handler(
     if password_reset_data_inner.password != password_reset_data_inner.password_confirmation {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Passwords do not match",
+            ..Default::default()
         }));
     }
     if password_reset_data_inner.password.len() < 8 {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Password must be at least 8 characters long",
+            ..Default::default()
         }));
     }
 
@@ -57,6 +59,7 @@ pub async fn send_password_reset_email_handler(
     if !email_regex().is_match(&user_email) {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Invalid email",
+            ..Default::default()
         }));
     }
 
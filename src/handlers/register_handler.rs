@@ -30,34 +30,37 @@ pub fn hash_password(password: &str) -> Result<String, DefaultError> {
     argon2::hash_encoded(password.as_bytes(), SALT.as_bytes(), &config).map_err(|_err| {
         DefaultError {
             message: "Error processing password, try again",
+            ..Default::default()
         }
     })
 }
 
 pub async fn register_user(
-    invitation_id: web::Path<String>,
+    invitation_token: web::Path<String>,
     password_data: web::Json<SetPasswordData>,
     pool: web::Data<Pool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let password_data_inner = password_data.into_inner();
     let password = password_data_inner.password;
     let password_confirmation = password_data_inner.password_confirmation;
-    let invitation_id = invitation_id.into_inner();
+    let invitation_token = invitation_token.into_inner();
     let db_pool_two = pool.clone();
 
     if password.len() < 8 {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Password must be at least 8 characters",
+            ..Default::default()
         }));
     }
     if password != password_confirmation {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Passwords do not match",
+            ..Default::default()
         }));
     }
 
     let user =
-        web::block(move || insert_user_from_invitation(invitation_id, password, pool)).await?;
+        web::block(move || insert_user_from_invitation(invitation_token, password, pool)).await?;
 
     match user {
         Ok(user) => {
@@ -71,54 +74,63 @@ pub async fn register_user(
 }
 
 fn insert_user_from_invitation(
-    invitation_id: String,
+    invitation_token: String,
     password: String,
     pool: web::Data<Pool>,
 ) -> Result<SlimUser, DefaultError> {
-    use crate::data::schema::{invitations::dsl::*, users::dsl::*};
+    use crate::data::schema::invitations::dsl as invitations_columns;
+    use crate::data::schema::users::dsl::users;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
-    let invitation_id =
-        uuid::Uuid::try_parse(&invitation_id).map_err(|_uuid_error| DefaultError {
+    let invitation = invitations_columns::invitations
+        .filter(invitations_columns::token.eq(invitation_token))
+        .first::<Invitation>(&mut conn)
+        .map_err(|_db_error| DefaultError {
             message: "Invalid Invitation",
+            ..Default::default()
         })?;
 
-    invitations
-        .filter(crate::data::schema::invitations::columns::id.eq(invitation_id))
-        .load::<Invitation>(&mut conn)
-        .map_err(|_db_error| DefaultError {
-            message: "Invalid Invitation",
-        })
-        .and_then(|mut result| {
-            let invitation = match result.pop() {
-                Some(it) => it,
-                None => {
-                    return Err(DefaultError {
-                        message: "Invalid Invitation",
-                    })
-                }
-            };
-
-            if invitation.expires_at <= chrono::Local::now().naive_local() {
-                return Err(DefaultError {
-                    message: "Invitation Expired",
-                });
-            };
-
-            let password: String =
-                hash_password(&password).map_err(|_hash_error| DefaultError {
-                    message: "Error Processing Password, Try Again",
-                })?;
-
-            let user = User::from_details(invitation.email, password);
-            let inserted_user: User = diesel::insert_into(users)
-                .values(&user)
-                .get_result(&mut conn)
-                .map_err(|_db_error| DefaultError {
-                    message: "Error Inserting User, Try Again",
-                })?;
-
-            Ok(inserted_user.into())
+    if invitation.used {
+        return Err(DefaultError {
+            message: "Invitation has already been used",
+            ..Default::default()
+        });
+    }
+    if invitation.expires_at <= chrono::Local::now().naive_local() {
+        return Err(DefaultError {
+            message: "Invitation Expired",
+            ..Default::default()
+        });
+    };
+
+    let password: String = hash_password(&password).map_err(|_hash_error| DefaultError {
+        message: "Error Processing Password, Try Again",
+        ..Default::default()
+    })?;
+
+    let user = User::from_details(invitation.email, password);
+
+    // the invitation is marked used with the insert so it can't register a second account
+    let inserted_user: User = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let claimed_invitations = diesel::update(
+                invitations_columns::invitations
+                    .find(invitation.id)
+                    .filter(invitations_columns::used.eq(false)),
+            )
+            .set(invitations_columns::used.eq(true))
+            .execute(conn)?;
+            if claimed_invitations == 0 {
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+
+            diesel::insert_into(users).values(&user).get_result(conn)
         })
+        .map_err(|_db_error| DefaultError {
+            message: "Error Inserting User, Try Again",
+            ..Default::default()
+        })?;
+
+    Ok(inserted_user.into())
 }
@@ -2,11 +2,13 @@ use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{Pool, StripeCustomer},
+    data::models::{Pool, StripeCustomer, UserPlanDTO},
+    errors::DefaultError,
     operators::stripe_customer_operator::{
         cancel_stripe_subscription_operation, change_stripe_subscription_operation,
-        create_stripe_checkout_session_operation, get_stripe_customer_query, get_user_plan_query,
-        handle_webhook_query, update_plan_query, update_plan_status_query,
+        construct_webhook_event_query, create_stripe_checkout_session_operation,
+        get_stripe_customer_query, get_user_plan_query, handle_webhook_query, update_plan_query,
+        update_plan_status_query,
     },
 };
 
@@ -60,10 +62,19 @@ pub async fn cancel_subscription(
     let pool_two = pool.clone();
     let plan = web::block(move || get_user_plan_query(user.email, &pool)).await?;
 
-    if let Err(e) = plan {
-        return Ok(HttpResponse::BadRequest().json(e));
-    }
-    let plan = plan.unwrap();
+    let plan = match plan {
+        Ok(UserPlanDTO {
+            subscription: Some(subscription),
+            ..
+        }) => subscription,
+        Ok(_) => {
+            return Ok(HttpResponse::BadRequest().json(DefaultError {
+                message: "No subscription found for user",
+                ..Default::default()
+            }))
+        }
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
 
     let stripe_cancel_result =
         cancel_stripe_subscription_operation(&plan.stripe_subscription_id).await;
@@ -95,10 +106,19 @@ pub async fn change_plan(
     let pool_two = pool.clone();
     let plan = web::block(move || get_user_plan_query(user.email, &pool)).await?;
 
-    if let Err(e) = plan {
-        return Ok(HttpResponse::BadRequest().json(e));
-    }
-    let plan = plan.unwrap();
+    let plan = match plan {
+        Ok(UserPlanDTO {
+            subscription: Some(subscription),
+            ..
+        }) => subscription,
+        Ok(_) => {
+            return Ok(HttpResponse::BadRequest().json(DefaultError {
+                message: "No subscription found for user",
+                ..Default::default()
+            }))
+        }
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
 
     let stripe_resposne =
         change_stripe_subscription_operation(&plan.stripe_subscription_id, plan_id.clone()).await;
@@ -134,7 +154,24 @@ pub async fn stripe_webhook(
         Some(stripe_signature) => stripe_signature,
     };
 
-    let _ = web::block(move || handle_webhook_query(&stripe_signature, payload, &pool))
+    let payload = match String::from_utf8(payload.to_vec()) {
+        Ok(payload) => payload,
+        Err(_) => return Ok(HttpResponse::BadRequest().finish()),
+    };
+
+    // failing verification is answered with a 400 so that Stripe retries the event
+    let event = match construct_webhook_event_query(&stripe_signature, &payload) {
+        Ok(event) => event,
+        Err(err) => {
+            log::error!(
+                "Failed to construct webhook event, ensure your webhook secret is correct: {}",
+                err.message
+            );
+            return Ok(HttpResponse::BadRequest().json(err));
+        }
+    };
+
+    let _ = web::block(move || handle_webhook_query(event, &pool))
         .await
         .map_err(actix_web::error::ErrorInternalServerError)?;
 
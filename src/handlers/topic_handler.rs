@@ -1,10 +1,16 @@
 use crate::{
-    data::models::{Pool, Topic},
+    data::models::{
+        Message, PageInfo, PaginatedResponse, Pool, SharedMessageDTO, SharedTopicDTO, Topic,
+    },
     errors::DefaultError,
     handlers::auth_handler::LoggedUser,
+    operators::message_operator::get_messages_for_topic_query,
+    operators::stripe_customer_operator::get_user_plan_query,
     operators::topic_operator::{
-        create_topic_query, delete_topic_query, get_all_topics_for_user_query,
-        get_topic_for_user_query, update_topic_query,
+        create_topic_query, delete_topic_query, fork_topic_query, get_all_topics_for_user_query,
+        get_shared_topic_query, get_shared_topics_query, get_topic_count_for_user_query,
+        get_topic_for_user_query, merge_topics_query, set_topic_share_token_query,
+        update_topic_query,
     },
 };
 use actix_web::{web, HttpResponse};
@@ -16,6 +22,40 @@ pub struct CreateTopicData {
     pub normal_chat: Option<bool>,
 }
 
+// Topics count against the plan's topic limit whether they are created or forked
+async fn check_topic_limit(user: &LoggedUser, pool: &web::Data<Pool>) -> Result<(), DefaultError> {
+    let user_email = user.email.clone();
+    let user_id = user.id;
+    let plan_pool = pool.clone();
+    let count_pool = pool.clone();
+
+    let user_plan = web::block(move || get_user_plan_query(user_email, &plan_pool))
+        .await
+        .map_err(|_err| DefaultError {
+            message: "Error finding user plan, try again",
+            ..Default::default()
+        })??;
+    let max_topics = match user_plan.features.max_topics {
+        Some(max_topics) => max_topics,
+        None => return Ok(()),
+    };
+
+    let topic_count = web::block(move || get_topic_count_for_user_query(user_id, &count_pool))
+        .await
+        .map_err(|_err| DefaultError {
+            message: "Error counting topics for user",
+            ..Default::default()
+        })??;
+    if topic_count >= max_topics {
+        return Err(DefaultError {
+            message: "Topic limit reached for your plan",
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
+
 pub async fn create_topic(
     data: web::Json<CreateTopicData>,
     user: LoggedUser,
@@ -28,9 +68,14 @@ pub async fn create_topic(
     if resolution.is_empty() {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Resolution must not be empty",
+            ..Default::default()
         }));
     }
 
+    if let Err(e) = check_topic_limit(&user, &pool).await {
+        return Ok(HttpResponse::Forbidden().json(e));
+    }
+
     let new_topic = Topic::from_details(resolution, user.id, normal_chat);
 
     let create_topic_result = web::block(move || create_topic_query(new_topic, &pool)).await?;
@@ -93,6 +138,7 @@ pub async fn update_topic(
     if resolution.is_empty() {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "Resolution must not be empty",
+            ..Default::default()
         }));
     }
 
@@ -120,7 +166,273 @@ pub async fn get_all_topics(
     let topics = web::block(move || get_all_topics_for_user_query(user.id, &pool)).await?;
 
     match topics {
-        Ok(topics) => Ok(HttpResponse::Ok().json(topics)),
+        Ok(topics) => {
+            let page_info = PageInfo::single_page(topics.len());
+            Ok(HttpResponse::Ok().json(PaginatedResponse::new(topics, page_info)))
+        }
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ForkTopicData {
+    pub until_message_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ForkedTopic {
+    pub topic_id: uuid::Uuid,
+    pub messages: Vec<Message>,
+}
+
+pub async fn fork_topic(
+    topic_id: web::Path<uuid::Uuid>,
+    data: Option<web::Json<ForkTopicData>>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = topic_id.into_inner();
+    let until_message_id = data.and_then(|data| data.into_inner().until_message_id);
+
+    if let Err(e) = check_topic_limit(&user, &pool).await {
+        return Ok(HttpResponse::Forbidden().json(e));
+    }
+
+    let fork_topic_result =
+        web::block(move || fork_topic_query(user.id, topic_id, until_message_id, &pool)).await?;
+
+    match fork_topic_result {
+        Ok((topic, messages)) => Ok(HttpResponse::Ok().json(ForkedTopic {
+            topic_id: topic.id,
+            messages,
+        })),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MergeTopicsData {
+    pub source_topic_id: uuid::Uuid,
+    pub target_topic_id: uuid::Uuid,
+}
+
+pub async fn merge_topics(
+    data: web::Json<MergeTopicsData>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let data = data.into_inner();
+    let merge_pool = pool.clone();
+
+    let merge_topics_result = web::block(move || {
+        merge_topics_query(
+            user.id,
+            data.source_topic_id,
+            data.target_topic_id,
+            &merge_pool,
+        )
+    })
+    .await?;
+
+    if let Err(e) = merge_topics_result {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let merged_messages =
+        web::block(move || get_messages_for_topic_query(data.target_topic_id, &pool)).await?;
+
+    match merged_messages {
+        Ok(messages) => Ok(HttpResponse::Ok().json(messages)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicExportFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportTopicParams {
+    pub format: Option<TopicExportFormat>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TopicExport {
+    pub topic: Topic,
+    pub messages: Vec<Message>,
+}
+
+fn topic_export_to_markdown(export: &TopicExport) -> String {
+    let mut markdown = format!(
+        "# {}\n\n_Started {}_\n",
+        export.topic.resolution,
+        export.topic.created_at.format("%Y-%m-%d %H:%M:%S")
+    );
+
+    for message in export.messages.iter() {
+        let mut role = message.role.clone();
+        if let Some(first) = role.get_mut(0..1) {
+            first.make_ascii_uppercase();
+        }
+
+        markdown.push_str(&format!(
+            "\n## {}\n\n_{}_\n\n{}\n",
+            role,
+            message.created_at.format("%Y-%m-%d %H:%M:%S"),
+            message.content
+        ));
+    }
+
+    markdown
+}
+
+pub async fn export_topic(
+    topic_id: web::Path<uuid::Uuid>,
+    params: web::Query<ExportTopicParams>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic_id = topic_id.into_inner();
+
+    let export_result = web::block(move || {
+        let topic = get_topic_for_user_query(user.id, topic_id, &pool)?;
+        let messages = get_messages_for_topic_query(topic_id, &pool)?;
+
+        Ok::<TopicExport, DefaultError>(TopicExport { topic, messages })
+    })
+    .await?;
+
+    let export = match export_result {
+        Ok(export) => export,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    match params
+        .format
+        .as_ref()
+        .unwrap_or(&TopicExportFormat::default())
+    {
+        TopicExportFormat::Markdown => Ok(HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"topic-{}.md\"", topic_id),
+            ))
+            .body(topic_export_to_markdown(&export))),
+        TopicExportFormat::Json => Ok(HttpResponse::Ok()
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"topic-{}.json\"", topic_id),
+            ))
+            .json(export)),
+    }
+}
+
+async fn set_topic_shared(
+    topic_id: uuid::Uuid,
+    user: LoggedUser,
+    shared: bool,
+    listed: bool,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let topic =
+        web::block(move || set_topic_share_token_query(user.id, topic_id, shared, listed, &pool))
+            .await?;
+
+    match topic {
+        Ok(topic) => Ok(HttpResponse::Ok().json(topic)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShareTopicData {
+    pub listed: Option<bool>,
+}
+
+// Listing in the public showcase is opt in, without a body the topic is only reachable by link
+pub async fn share_topic(
+    topic_id: web::Path<uuid::Uuid>,
+    data: Option<web::Json<ShareTopicData>>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let listed = data.and_then(|data| data.listed).unwrap_or(false);
+
+    set_topic_shared(topic_id.into_inner(), user, true, listed, pool).await
+}
+
+pub async fn unshare_topic(
+    topic_id: web::Path<uuid::Uuid>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    set_topic_shared(topic_id.into_inner(), user, false, false, pool).await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SharedTopic {
+    pub topic: SharedTopicDTO,
+    pub messages: Vec<SharedMessageDTO>,
+}
+
+// Read only and open to anyone with the link, nothing here can change the topic
+pub async fn get_shared_topic(
+    share_token: web::Path<String>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let share_token = share_token.into_inner();
+
+    let shared_topic = web::block(move || get_shared_topic_query(share_token, &pool)).await?;
+
+    let (topic, messages) = match shared_topic {
+        Ok(shared_topic) => shared_topic,
+        Err(e) => return Ok(HttpResponse::NotFound().json(e)),
+    };
+    let topic = match SharedTopicDTO::from_topic(topic) {
+        Some(topic) => topic,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    Ok(HttpResponse::Ok().json(SharedTopic {
+        topic,
+        messages: messages
+            .into_iter()
+            .map(|message| SharedMessageDTO {
+                role: message.role,
+                content: message.content,
+                created_at: message.created_at,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SharedTopicsParams {
+    pub page: Option<i64>,
+}
+
+pub async fn get_shared_topics(
+    params: web::Query<SharedTopicsParams>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = 20;
+
+    let shared_topics = web::block(move || get_shared_topics_query(page, per_page, &pool)).await?;
+
+    match shared_topics {
+        Ok((topics, total_shared_topics)) => Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+            topics
+                .into_iter()
+                .filter_map(SharedTopicDTO::from_topic)
+                .collect::<Vec<SharedTopicDTO>>(),
+            PageInfo::new(page, per_page, total_shared_topics),
+        ))),
         Err(e) => Ok(HttpResponse::BadRequest().json(e)),
     }
 }
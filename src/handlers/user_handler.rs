@@ -4,11 +4,19 @@ use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{Pool, UserDTOWithScore},
+    data::models::{
+        CardMetadata, PageInfo, PaginatedResponse, Pool, ReadPool, UserDTOWithScore,
+        UserDTOWithVotesAndCards, UserEntitlementsDTO,
+    },
     errors::{DefaultError, ServiceError},
+    operators::card_operator::{get_cards_for_export_query, sanitize_card_html, CardSortBy},
+    operators::file_operator::{convert_html_to_docx, DOCX_MIME_TYPE},
+    operators::stripe_customer_operator::get_user_plan_query,
     operators::user_operator::{
-        get_top_users_query, get_total_users_query, get_user_with_votes_and_cards_by_id_query,
-        update_user_query,
+        find_user_by_username_query, get_top_users_query, get_total_users_query,
+        get_user_card_activity_query, get_user_stats_query,
+        get_user_with_votes_and_cards_by_id_query, normalize_username,
+        search_users_by_username_query, update_user_query, USER_CARDS_PAGE_SIZE,
     },
 };
 
@@ -27,26 +35,130 @@ pub struct GetUserWithVotesAndCardsData {
     pub page: i64,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserCardsSortParams {
+    pub sort_by: Option<CardSortBy>,
+}
+
+// The profile is the data, its page_info describes the page of cards it carries
+fn paginate_user_cards(
+    user_with_votes_and_cards: UserDTOWithVotesAndCards,
+    page: i64,
+) -> PaginatedResponse<UserDTOWithVotesAndCards> {
+    let page_info = PageInfo::new(
+        page,
+        USER_CARDS_PAGE_SIZE,
+        user_with_votes_and_cards.total_cards_created,
+    );
+
+    PaginatedResponse::new(user_with_votes_and_cards, page_info)
+}
+
 pub async fn get_user_with_votes_and_cards_by_id(
     path_data: web::Path<GetUserWithVotesAndCardsData>,
+    sort_params: web::Query<UserCardsSortParams>,
     user: Option<LoggedUser>,
-    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
     let user_query_id = path_data.user_id;
     let accessing_user_id = user.map(|user| user.id);
     let page = path_data.page;
+    let sort_by = sort_params.sort_by.unwrap_or_default();
 
     let user_result = web::block(move || {
-        get_user_with_votes_and_cards_by_id_query(user_query_id, accessing_user_id, &page, pool)
+        get_user_with_votes_and_cards_by_id_query(
+            user_query_id,
+            accessing_user_id,
+            &page,
+            sort_by,
+            pool,
+        )
     })
     .await?;
 
     match user_result {
-        Ok(user_with_votes_and_cards) => Ok(HttpResponse::Ok().json(user_with_votes_and_cards)),
+        Ok(user_with_votes_and_cards) => {
+            Ok(HttpResponse::Ok().json(paginate_user_cards(user_with_votes_and_cards, page)))
+        }
         Err(e) => Err(ServiceError::BadRequest(e.message.into()).into()),
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserProfileParams {
+    pub page: Option<i64>,
+    pub sort_by: Option<CardSortBy>,
+}
+
+// Unknown usernames are a 404 rather than the bad request the id lookup returns
+pub async fn get_user_by_username(
+    username: web::Path<String>,
+    params: web::Query<UserProfileParams>,
+    user: Option<LoggedUser>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let accessing_user_id = user.map(|user| user.id);
+    let page = params.page.unwrap_or(1).max(1);
+    let sort_by = params.sort_by.unwrap_or_default();
+    let username = username.into_inner();
+
+    let user_result = web::block(move || {
+        let profile_user = match find_user_by_username_query(&username, pool.clone())? {
+            Some(profile_user) => profile_user,
+            None => return Ok(None),
+        };
+
+        get_user_with_votes_and_cards_by_id_query(
+            profile_user.id,
+            accessing_user_id,
+            &page,
+            sort_by,
+            pool,
+        )
+        .map(Some)
+    })
+    .await?
+    .map_err(ServiceError::from)?;
+
+    match user_result {
+        Some(user_with_votes_and_cards) => {
+            Ok(HttpResponse::Ok().json(paginate_user_cards(user_with_votes_and_cards, page)))
+        }
+        None => Err(ServiceError::NotFound.into()),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchUsersParams {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+// Autocomplete for @-mentions and invites, limited to logged in users so names can't be scraped
+pub async fn search_users(
+    params: web::Query<SearchUsersParams>,
+    _user: LoggedUser,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let params = params.into_inner();
+    let user_query = normalize_username(&params.q).to_string();
+    if user_query.is_empty() || user_query.chars().count() > 64 {
+        return Err(
+            ServiceError::BadRequest("q must be between 1 and 64 characters".into()).into(),
+        );
+    }
+    let limit = params.limit.unwrap_or(10).clamp(1, 20);
+    let pool = read_pool.0.clone();
+
+    let users = web::block(move || search_users_by_username_query(&user_query, limit, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
 pub async fn update_user(
     data: web::Json<UpdateUserData>,
     user: LoggedUser,
@@ -54,11 +166,12 @@ pub async fn update_user(
 ) -> Result<HttpResponse, actix_web::Error> {
     let update_user_data = data.into_inner();
 
-    if update_user_data.username.clone().unwrap_or("".to_string()) == ""
+    if normalize_username(update_user_data.username.as_deref().unwrap_or("")).is_empty()
         && !update_user_data.visible_email
     {
         return Ok(HttpResponse::BadRequest().json(DefaultError {
             message: "You must provide a username or make your email visible",
+            ..Default::default()
         }));
     }
 
@@ -78,10 +191,10 @@ pub struct TopUserData {
 }
 pub async fn get_top_users(
     page: web::Path<i64>,
-    pool: web::Data<Pool>,
+    read_pool: web::Data<ReadPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let page = page.into_inner();
-    let thread_safe_pool = Arc::new(Mutex::new(pool));
+    let thread_safe_pool = Arc::new(Mutex::new(read_pool.0.clone()));
 
     let pool2 = thread_safe_pool.clone();
     let users_result =
@@ -99,3 +212,183 @@ pub async fn get_top_users(
         Err(e) => Ok(HttpResponse::BadRequest().json(e)),
     }
 }
+
+pub async fn get_user_stats(
+    user: LoggedUser,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let user_stats = web::block(move || get_user_stats_query(user.id, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(user_stats))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserActivityParams {
+    pub days: Option<i64>,
+}
+
+// Backs the profile heatmap, private cards only count when users look at their own profile
+pub async fn get_user_activity(
+    user_id: web::Path<uuid::Uuid>,
+    params: web::Query<UserActivityParams>,
+    user: Option<LoggedUser>,
+    read_pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let pool = read_pool.0.clone();
+    let user_id = user_id.into_inner();
+    let accessing_user_id = user.map(|user| user.id);
+    let days = params.days.unwrap_or(365).clamp(1, 366);
+
+    let user_activity =
+        web::block(move || get_user_card_activity_query(user_id, accessing_user_id, days, pool))
+            .await?
+            .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(user_activity))
+}
+
+// Users without a subscription resolve to the free plan rather than an error
+pub async fn get_user_plan(
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_plan = web::block(move || get_user_plan_query(user.email, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(UserEntitlementsDTO::from(user_plan)))
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CardExportFormat {
+    #[default]
+    Markdown,
+    Docx,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportCardsParams {
+    pub format: Option<CardExportFormat>,
+    pub collection_id: Option<uuid::Uuid>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Author, title, date and url, whichever of them the card has
+fn card_citation_line(card: &CardMetadata) -> Option<String> {
+    let citation = card.citation();
+    let parts = [
+        citation.source_author,
+        citation.source_title,
+        citation
+            .source_date
+            .map(|source_date| source_date.format("%Y-%m-%d").to_string()),
+        citation.source_url.or(card.link.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+fn card_export_heading(index: usize, card: &CardMetadata) -> String {
+    match &card.source_title {
+        Some(source_title) => format!("{}. {}", index + 1, source_title),
+        None => format!("Card {}", index + 1),
+    }
+}
+
+fn cards_to_markdown(cards: &[CardMetadata]) -> String {
+    let mut markdown = "# Cards\n".to_string();
+
+    for (index, card) in cards.iter().enumerate() {
+        markdown.push_str(&format!("\n## {}\n\n", card_export_heading(index, card)));
+        if let Some(citation_line) = card_citation_line(card) {
+            markdown.push_str(&format!("_{}_\n\n", citation_line));
+        }
+        markdown.push_str(&format!("{}\n", card.content.trim()));
+    }
+
+    markdown
+}
+
+// card_html keeps the formatting from the original upload, so it is preferred over the content
+fn cards_to_html(cards: &[CardMetadata]) -> String {
+    let mut html = concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Cards</title></head>",
+        "<body><h1>Cards</h1>"
+    )
+    .to_string();
+
+    for (index, card) in cards.iter().enumerate() {
+        html.push_str(&format!(
+            "<h2>{}</h2>",
+            escape_html(&card_export_heading(index, card))
+        ));
+        if let Some(citation_line) = card_citation_line(card) {
+            html.push_str(&format!("<p><i>{}</i></p>", escape_html(&citation_line)));
+        }
+        match &card.card_html {
+            Some(card_html) => html.push_str(&sanitize_card_html(card_html)),
+            None => html.push_str(&format!("<p>{}</p>", escape_html(card.content.trim()))),
+        }
+    }
+
+    html.push_str("</body></html>");
+    html
+}
+
+pub async fn export_cards(
+    params: web::Query<ExportCardsParams>,
+    user: LoggedUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let params = params.into_inner();
+    let collection_id = params.collection_id;
+    let file_stem = match collection_id {
+        Some(collection_id) => format!("collection-{}", collection_id),
+        None => "cards".to_string(),
+    };
+
+    let cards = web::block(move || get_cards_for_export_query(user.id, collection_id, &pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    match params.format.unwrap_or_default() {
+        CardExportFormat::Markdown => Ok(HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.md\"", file_stem),
+            ))
+            .body(cards_to_markdown(&cards))),
+        CardExportFormat::Docx => {
+            let html = cards_to_html(&cards);
+            let docx_data = web::block(move || convert_html_to_docx(&html))
+                .await?
+                .map_err(ServiceError::from)?;
+
+            Ok(HttpResponse::Ok()
+                .content_type(DOCX_MIME_TYPE)
+                .insert_header((
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{}.docx\"", file_stem),
+                ))
+                .body(docx_data))
+        }
+    }
+}
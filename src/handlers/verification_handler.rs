@@ -103,7 +103,7 @@ pub async fn verify_card_content(
                 card_op::get_metadata_from_id_query(card_uuid, pool1.lock().unwrap())
             })
             .await?
-            .map_err(|err| ServiceError::BadRequest(err.message.into()))?;
+            .map_err(ServiceError::from)?;
             let link = card
                 .link
                 .ok_or_else(|| ServiceError::BadRequest("No link on this card to verify".into()))?;
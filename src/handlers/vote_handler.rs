@@ -5,9 +5,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     data::models::Pool,
+    errors::ServiceError,
     operators::{
-        card_operator::get_metadata_from_id_query,
-        vote_operator::{create_vote_query, delete_vote_query},
+        card_operator::{
+            approve_card_query, get_card_approval_vote_threshold, get_metadata_from_id_query,
+            sync_card_qdrant_payload_query,
+        },
+        vote_operator::{create_vote_query, delete_vote_query, get_vote_tallies_query},
     },
 };
 
@@ -30,6 +34,7 @@ pub async fn create_vote(
     let card_metadata_id = data_inner.card_metadata_id;
     let vote = data_inner.vote;
     let pool1 = thread_safe_pool.clone();
+    let pool2 = thread_safe_pool.clone();
     let card_data = web::block(move || {
         get_metadata_from_id_query(card_metadata_id, thread_safe_pool.lock().unwrap())
     })
@@ -47,10 +52,44 @@ pub async fn create_vote(
     })
     .await?;
 
-    match create_vote_result {
-        Ok(created_vote) => Ok(HttpResponse::Ok().json(created_vote)),
-        Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+    let created_vote = match create_vote_result {
+        Ok(created_vote) => created_vote,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    // enough upvotes let a card waiting for approval into public search. The vote is already
+    // committed, so a failure here is logged rather than reported as a failed vote
+    if vote {
+        let approved_card = web::block(move || {
+            approve_card_query(
+                card_metadata_id,
+                Some(get_card_approval_vote_threshold()),
+                &pool2.lock().unwrap(),
+            )
+        })
+        .await;
+
+        match approved_card {
+            Ok(Ok(Some(approved_card))) => {
+                if let Err(err) = sync_card_qdrant_payload_query(&approved_card).await {
+                    log::error!(
+                        "Failed to sync payload of approved card {}: {}",
+                        card_metadata_id,
+                        err.message
+                    );
+                }
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(err)) => log::error!(
+                "Failed to approve card {}: {}",
+                card_metadata_id,
+                err.message
+            ),
+            Err(err) => log::error!("Failed to approve card {}: {}", card_metadata_id, err),
+        }
     }
+
+    Ok(HttpResponse::Ok().json(created_vote))
 }
 
 pub async fn delete_vote(
@@ -88,3 +127,23 @@ pub async fn delete_vote(
         Err(e) => Ok(HttpResponse::BadRequest().json(e)),
     }
 }
+
+pub async fn get_vote_tallies(
+    data: web::Json<Vec<uuid::Uuid>>,
+    user: Option<LoggedUser>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let card_ids = data.into_inner();
+    if card_ids.len() > 100 {
+        return Err(
+            ServiceError::BadRequest("Cannot tally votes for more than 100 cards".into()).into(),
+        );
+    }
+    let current_user_id = user.map(|user| user.id);
+
+    let vote_tallies = web::block(move || get_vote_tallies_query(card_ids, current_user_id, pool))
+        .await?
+        .map_err(ServiceError::from)?;
+
+    Ok(HttpResponse::Ok().json(vote_tallies))
+}
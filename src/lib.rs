@@ -4,7 +4,7 @@ extern crate diesel;
 use actix_cors::Cors;
 use actix_identity::IdentityMiddleware;
 use actix_session::{config::PersistentSession, storage::RedisSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, middleware, web, App, HttpServer};
+use actix_web::{cookie::Key, dev::Service, http::header, middleware, web, App, HttpServer};
 use diesel::{prelude::*, r2d2};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use qdrant_client::{
@@ -12,11 +12,16 @@ use qdrant_client::{
     qdrant::{VectorParams, VectorsConfig},
 };
 
-use crate::operators::card_operator::get_qdrant_connection;
+use crate::operators::card_operator::{
+    create_qdrant_payload_indexes_query, flush_card_views_query, get_qdrant_connection,
+    get_qdrant_distance_query, run_qdrant_payload_backfill_query, OPENAI_EMBEDDING_DIMENSION,
+    QDRANT_DISTANCE,
+};
 
 mod data;
 mod errors;
 mod handlers;
+mod logging;
 mod operators;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
@@ -24,24 +29,64 @@ pub const SECONDS_IN_MINUTE: u64 = 60;
 pub const SECONDS_IN_HOUR: u64 = 60 * SECONDS_IN_MINUTE;
 pub const SECONDS_IN_DAY: u64 = 24 * SECONDS_IN_HOUR;
 
+fn delete_expired_tokens(pool: &data::models::Pool) -> Result<usize, errors::DefaultError> {
+    Ok(
+        handlers::invitation_handler::delete_expired_invitations_query(pool)?
+            + operators::password_reset_operator::delete_expired_password_resets_query(pool)?
+            + operators::email_change_operator::delete_expired_email_changes_query(pool)?
+            + operators::session_operator::delete_expired_sessions_query(pool)?,
+    )
+}
+
 fn run_migrations(conn: &mut impl MigrationHarness<diesel::pg::Pg>) {
     conn.run_pending_migrations(MIGRATIONS).unwrap();
 }
 
+// A short connection timeout makes an exhausted pool fail the request instead of hanging it
+fn pool_builder() -> r2d2::Builder<r2d2::ConnectionManager<PgConnection>> {
+    let max_size = std::env::var("DB_POOL_MAX_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(10);
+    let min_idle = std::env::var("DB_POOL_MIN_IDLE")
+        .ok()
+        .and_then(|idle| idle.parse().ok());
+    let connection_timeout = std::time::Duration::from_secs(
+        std::env::var("DB_POOL_CONNECTION_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(3),
+    );
+
+    r2d2::Pool::builder()
+        .max_size(max_size)
+        .min_idle(min_idle)
+        .connection_timeout(connection_timeout)
+}
+
 #[actix_web::main]
 pub async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    logging::init_logger();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
 
     // create db connection pool
     let manager = r2d2::ConnectionManager::<PgConnection>::new(database_url);
-    let pool: data::models::Pool = r2d2::Pool::builder()
+    let pool: data::models::Pool = pool_builder()
         .build(manager)
         .expect("Failed to create pool.");
 
+    let read_pool: data::models::Pool = match std::env::var("READ_DATABASE_URL") {
+        Ok(read_database_url) => pool_builder()
+            .build(r2d2::ConnectionManager::<PgConnection>::new(
+                read_database_url,
+            ))
+            .expect("Failed to create read pool."),
+        Err(_) => pool.clone(),
+    };
+
     let redis_store = RedisSessionStore::new(redis_url.as_str()).await.unwrap();
 
     let qdrant_client = get_qdrant_connection().await.unwrap();
@@ -51,7 +96,7 @@ pub async fn main() -> std::io::Result<()> {
             vectors_config: Some(VectorsConfig {
                 config: Some(qdrant_client::qdrant::vectors_config::Config::Params(
                     VectorParams {
-                        size: 1536,
+                        size: OPENAI_EMBEDDING_DIMENSION,
                         distance: Distance::Cosine.into(),
                         hnsw_config: None,
                         quantization_config: None,
@@ -66,25 +111,97 @@ pub async fn main() -> std::io::Result<()> {
             println!("Failed to create collection: {:?}", err);
         });
 
+    match get_qdrant_distance_query().await {
+        Ok(distance) => {
+            let _ = QDRANT_DISTANCE.set(distance);
+        }
+        Err(err) => log::error!("{}, assuming cosine", err.message),
+    }
+
     run_migrations(&mut pool.get().unwrap());
 
+    match run_qdrant_payload_backfill_query(&pool).await {
+        Ok(Some(count)) => log::info!("Backfilled qdrant payloads for {} cards", count),
+        Ok(None) => {}
+        Err(err) => log::error!("Failed to backfill qdrant payloads: {}", err.message),
+    }
+
+    if let Err(err) = create_qdrant_payload_indexes_query().await {
+        log::error!("{}", err.message);
+    }
+
     let domain: String = std::env::var("DOMAIN").unwrap_or_else(|_| "localhost".to_string());
     let allowed_origin: String =
         std::env::var("ALLOWED_ORIGIN").unwrap_or_else(|_| "http://localhost:3000".to_string());
 
+    let card_view_flush_interval = std::time::Duration::from_secs(
+        std::env::var("CARD_VIEW_FLUSH_INTERVAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(60),
+    );
+    let card_view_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(card_view_flush_interval);
+        loop {
+            interval.tick().await;
+            let pool = card_view_pool.clone();
+            if let Ok(Err(err)) = web::block(move || flush_card_views_query(&pool)).await {
+                log::error!("{}", err.message);
+            }
+        }
+    });
+
+    // Off by default so that only one process of a multi process deploy runs the cleanup
+    if std::env::var("EXPIRED_TOKEN_CLEANUP_ENABLED").unwrap_or_default() == "true" {
+        let expired_token_cleanup_interval = std::time::Duration::from_secs(
+            std::env::var("EXPIRED_TOKEN_CLEANUP_INTERVAL_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(SECONDS_IN_HOUR),
+        );
+        let expired_token_pool = pool.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(expired_token_cleanup_interval);
+            loop {
+                interval.tick().await;
+                let pool = expired_token_pool.clone();
+                match web::block(move || delete_expired_tokens(&pool)).await {
+                    Ok(Ok(deleted_count)) => {
+                        log::info!("Deleted {} expired invitations and tokens", deleted_count)
+                    }
+                    Ok(Err(err)) => log::error!("{}", err.message),
+                    Err(_) => log::error!("Expired token cleanup panicked"),
+                }
+            }
+        });
+    }
+
+    let shutdown_pool = pool.clone();
+
+    let shutdown_grace_period = std::time::Duration::from_secs(
+        std::env::var("SHUTDOWN_GRACE_PERIOD_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(30),
+    );
+
     log::info!("starting HTTP server at http://localhost:8090");
 
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin(&allowed_origin)
             .allowed_origin("https://vault.arguflow.com")
-            .allowed_methods(vec!["GET", "POST", "DELETE", "OPTIONS", "PUT"])
+            .allowed_methods(vec!["GET", "POST", "DELETE", "OPTIONS", "PUT", "PATCH"])
             .allow_any_header()
             .supports_credentials()
             .max_age(3600);
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(data::models::ReadPool(web::Data::new(
+                read_pool.clone(),
+            ))))
             .wrap(
                 IdentityMiddleware::builder()
                     .login_deadline(Some(std::time::Duration::from_secs(SECONDS_IN_DAY)))
@@ -106,8 +223,29 @@ pub async fn main() -> std::io::Result<()> {
                 .cookie_path("/".to_owned())
                 .build(),
             )
+            // tag every request with an id that log lines and the response carry
+            .wrap_fn(|req, srv| {
+                let request_id = logging::request_id_from_header(
+                    req.headers()
+                        .get("X-Request-Id")
+                        .and_then(|header_value| header_value.to_str().ok()),
+                );
+                let fut = logging::REQUEST_ID.scope(request_id.clone(), srv.call(req));
+                async move {
+                    let mut res = fut.await?;
+                    if let Ok(header_value) = header::HeaderValue::from_str(&request_id) {
+                        res.headers_mut().insert(
+                            header::HeaderName::from_static("x-request-id"),
+                            header_value,
+                        );
+                    }
+                    Ok(res)
+                }
+            })
             // enable logger
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#,
+            ))
             // everything under '/api/' route
             .service(
                 web::scope("/api")
@@ -116,7 +254,12 @@ pub async fn main() -> std::io::Result<()> {
                             .route(web::post().to(handlers::invitation_handler::post_invitation)),
                     )
                     .service(
-                        web::resource("/register/{invitation_id}")
+                        web::resource("/invitation/{invitation_token}").route(
+                            web::get().to(handlers::invitation_handler::get_invitation_status),
+                        ),
+                    )
+                    .service(
+                        web::resource("/register/{invitation_token}")
                             .route(web::post().to(handlers::register_handler::register_user)),
                     )
                     .service(
@@ -125,11 +268,29 @@ pub async fn main() -> std::io::Result<()> {
                             .route(web::delete().to(handlers::auth_handler::logout))
                             .route(web::get().to(handlers::auth_handler::get_me)),
                     )
+                    .service(
+                        web::resource("/auth/sessions")
+                            .route(web::get().to(handlers::auth_handler::get_sessions))
+                            .route(web::delete().to(handlers::auth_handler::revoke_all_sessions)),
+                    )
+                    .service(
+                        web::resource("/auth/sessions/{session_id}")
+                            .route(web::delete().to(handlers::auth_handler::revoke_session)),
+                    )
                     .service(web::resource("/password/{email}").route(
                         web::get().to(
                             handlers::password_reset_handler::send_password_reset_email_handler,
                         ),
                     ))
+                    .service(web::resource("/user/email").route(web::post().to(
+                        handlers::email_change_handler::send_email_change_verification_handler,
+                    )))
+                    .service(
+                        web::resource("/user/email/{email_change_id}").route(
+                            web::post()
+                                .to(handlers::email_change_handler::confirm_email_change_handler),
+                        ),
+                    )
                     .service(
                         web::resource("/password").route(
                             web::post()
@@ -143,6 +304,43 @@ pub async fn main() -> std::io::Result<()> {
                             .route(web::put().to(handlers::topic_handler::update_topic))
                             .route(web::get().to(handlers::topic_handler::get_all_topics)),
                     )
+                    .service(
+                        web::resource("/topic/merge")
+                            .route(web::post().to(handlers::topic_handler::merge_topics)),
+                    )
+                    .service(
+                        web::resource("/topic/{topic_id}/export")
+                            .route(web::get().to(handlers::topic_handler::export_topic)),
+                    )
+                    .service(
+                        web::resource("/topic/{topic_id}/pinned")
+                            .route(web::get().to(handlers::message_handler::get_pinned_messages)),
+                    )
+                    .service(
+                        web::resource("/topic/{topic_id}/messages").route(
+                            web::get().to(handlers::message_handler::get_topic_messages_after),
+                        ),
+                    )
+                    .service(web::resource("/topic/{topic_id}/streaming").route(
+                        web::get().to(handlers::message_handler::get_topic_streaming_message),
+                    ))
+                    .service(
+                        web::resource("/topic/{topic_id}/share")
+                            .route(web::post().to(handlers::topic_handler::share_topic))
+                            .route(web::delete().to(handlers::topic_handler::unshare_topic)),
+                    )
+                    .service(
+                        web::resource("/shared/topics")
+                            .route(web::get().to(handlers::topic_handler::get_shared_topics)),
+                    )
+                    .service(
+                        web::resource("/shared/topic/{share_token}")
+                            .route(web::get().to(handlers::topic_handler::get_shared_topic)),
+                    )
+                    .service(
+                        web::resource("/topic/{topic_id}/fork")
+                            .route(web::post().to(handlers::topic_handler::fork_topic)),
+                    )
                     .service(
                         web::resource("/message")
                             .route(
@@ -157,38 +355,137 @@ pub async fn main() -> std::io::Result<()> {
                             ),
                     )
                     .service(
-                        web::resource("/messages/{messages_topic_id}").route(
-                            web::get().to(handlers::message_handler::get_all_topic_messages),
+                        web::resource("/message/resume").route(
+                            web::post().to(handlers::message_handler::resume_message_handler),
                         ),
                     )
+                    .service(
+                        web::resource("/tokenize")
+                            .route(web::post().to(handlers::message_handler::tokenize_handler)),
+                    )
+                    .service(
+                        web::resource("/message/{message_id}/history")
+                            .route(web::get().to(handlers::message_handler::get_message_history)),
+                    )
+                    .service(
+                        web::resource("/message/{message_id}/pin")
+                            .route(web::post().to(handlers::message_handler::pin_message)),
+                    )
+                    .service(
+                        web::resource("/message/{message_id}/unpin")
+                            .route(web::post().to(handlers::message_handler::unpin_message)),
+                    )
+                    // gzip/brotli negotiated from Accept-Encoding, only on routes with large
+                    // payloads so small and streamed responses are sent as is
+                    .service(
+                        web::resource("/message/search")
+                            .route(web::post().to(handlers::message_handler::search_messages))
+                            .wrap(middleware::Compress::default()),
+                    )
+                    .service(
+                        web::resource("/messages/{messages_topic_id}")
+                            .route(web::get().to(handlers::message_handler::get_all_topic_messages))
+                            .wrap(middleware::Compress::default()),
+                    )
                     .service(
                         web::resource("/card")
-                            .route(web::post().to(handlers::card_handler::create_card)),
+                            .route(web::post().to(handlers::card_handler::create_card_handler)),
+                    )
+                    .service(
+                        web::resource("/cards/changes")
+                            .route(web::get().to(handlers::card_handler::get_card_changes)),
+                    )
+                    .service(
+                        web::resource("/cards/delete")
+                            .route(web::post().to(handlers::card_handler::bulk_delete_cards)),
                     )
                     .service(
                         web::resource("/card/update")
                             .route(web::put().to(handlers::card_handler::update_card)),
                     )
+                    .service(
+                        web::resource("/card/split-preview").route(
+                            web::post().to(handlers::file_handler::preview_card_split_handler),
+                        ),
+                    )
                     .service(
                         web::resource("/card/count")
                             .route(web::get().to(handlers::card_handler::get_total_card_count)),
                     )
+                    .service(
+                        web::resource("/card/count/grouped")
+                            .route(web::get().to(handlers::card_handler::get_grouped_card_count)),
+                    )
+                    .service(
+                        web::resource("/card/missing_vectors").route(
+                            web::get().to(handlers::card_handler::get_cards_missing_vectors),
+                        ),
+                    )
+                    .service(
+                        web::resource("/card/fulltext/count")
+                            .route(web::post().to(handlers::card_handler::count_full_text_cards)),
+                    )
+                    .service(
+                        web::resource("/card/by-ids")
+                            .route(web::post().to(handlers::card_handler::get_cards_by_ids)),
+                    )
+                    .service(
+                        web::resource("/card/merge")
+                            .route(web::post().to(handlers::card_handler::merge_cards)),
+                    )
+                    .service(
+                        web::resource("/card/search-by-vector")
+                            .route(web::post().to(handlers::card_handler::search_card_by_vector)),
+                    )
+                    .service(
+                        web::resource("/card/{card_id}/reembed")
+                            .route(web::post().to(handlers::card_handler::reembed_card)),
+                    )
+                    .service(
+                        web::resource("/card/{card_id}/collections").route(
+                            web::get().to(handlers::collection_handler::get_card_containers),
+                        ),
+                    )
+                    .service(
+                        web::resource("/card/{card_id}/transfer")
+                            .route(web::post().to(handlers::card_handler::transfer_card)),
+                    )
+                    .service(
+                        web::resource("/card/{card_id}/debug")
+                            .route(web::get().to(handlers::card_handler::get_card_embedding_debug)),
+                    )
+                    .service(
+                        web::resource("/card/{card_id}/similar")
+                            .route(web::get().to(handlers::card_handler::get_similar_cards)),
+                    )
                     .service(
                         web::resource("/card/{card_id}")
                             .route(web::get().to(handlers::card_handler::get_card_by_id))
-                            .route(web::delete().to(handlers::card_handler::delete_card)),
+                            .route(web::delete().to(handlers::card_handler::delete_card))
+                            .route(web::patch().to(handlers::card_handler::patch_card_source)),
+                    )
+                    .service(
+                        web::resource("/embedding")
+                            .route(web::post().to(handlers::card_handler::create_embedding)),
                     )
                     .service(
                         web::resource("/card/search/")
-                            .route(web::post().to(handlers::card_handler::search_card)),
+                            .route(web::post().to(handlers::card_handler::search_card))
+                            .wrap(middleware::Compress::default()),
                     )
                     .service(
                         web::resource("/card/search/{page}")
-                            .route(web::post().to(handlers::card_handler::search_card)),
+                            .route(web::post().to(handlers::card_handler::search_card))
+                            .wrap(middleware::Compress::default()),
                     )
                     .service(
                         web::resource("/card/fulltextsearch/{page}")
-                            .route(web::post().to(handlers::card_handler::search_full_text_card)),
+                            .route(web::post().to(handlers::card_handler::search_full_text_card))
+                            .wrap(middleware::Compress::default()),
+                    )
+                    .service(
+                        web::resource("/search/trending")
+                            .route(web::get().to(handlers::card_handler::get_trending_searches)),
                     )
                     .service(
                         web::resource("/vote")
@@ -198,6 +495,40 @@ pub async fn main() -> std::io::Result<()> {
                         web::resource("/vote/{card_metadata_id}")
                             .route(web::delete().to(handlers::vote_handler::delete_vote)),
                     )
+                    .service(
+                        web::resource("/feed")
+                            .route(web::get().to(handlers::card_handler::get_card_feed)),
+                    )
+                    .service(
+                        web::resource("/cards/votes")
+                            .route(web::post().to(handlers::vote_handler::get_vote_tallies)),
+                    )
+                    .service(
+                        web::resource("/moderation/cards").route(
+                            web::get().to(handlers::moderation_handler::get_moderation_queue),
+                        ),
+                    )
+                    .service(
+                        web::resource("/moderation/cards/pending")
+                            .route(web::get().to(handlers::moderation_handler::get_pending_cards)),
+                    )
+                    .service(web::resource("/moderation/card/{card_id}").route(
+                        web::delete().to(handlers::moderation_handler::moderator_delete_card),
+                    ))
+                    .service(
+                        web::resource("/moderation/card/{card_id}/hide").route(
+                            web::put().to(handlers::moderation_handler::moderator_hide_card),
+                        ),
+                    )
+                    .service(
+                        web::resource("/moderation/card/{card_id}/approve").route(
+                            web::put().to(handlers::moderation_handler::moderator_approve_card),
+                        ),
+                    )
+                    .service(
+                        web::resource("/admin/stats")
+                            .route(web::get().to(handlers::admin_handler::get_corpus_stats)),
+                    )
                     .service(
                         web::scope("/stripe")
                             .service(
@@ -239,9 +570,44 @@ pub async fn main() -> std::io::Result<()> {
                             ),
                         ),
                     )
-                    .service(web::resource("/user/{user_id}/{page}").route(
-                        web::get().to(handlers::user_handler::get_user_with_votes_and_cards_by_id),
-                    ))
+                    .service(
+                        web::resource("/user/me/stats")
+                            .route(web::get().to(handlers::user_handler::get_user_stats)),
+                    )
+                    .service(
+                        web::resource("/user/me/plan")
+                            .route(web::get().to(handlers::user_handler::get_user_plan)),
+                    )
+                    .service(
+                        web::resource("/user/me/bookmarks")
+                            .route(web::get().to(handlers::collection_handler::get_user_bookmarks)),
+                    )
+                    .service(
+                        web::resource("/user/me/cards/export")
+                            .route(web::get().to(handlers::user_handler::export_cards)),
+                    )
+                    .service(
+                        web::resource("/user/search")
+                            .route(web::get().to(handlers::user_handler::search_users)),
+                    )
+                    .service(
+                        web::resource("/user/by-username/{username}")
+                            .route(web::get().to(handlers::user_handler::get_user_by_username))
+                            .wrap(middleware::Compress::default()),
+                    )
+                    .service(
+                        web::resource("/user/{user_id}/activity")
+                            .route(web::get().to(handlers::user_handler::get_user_activity)),
+                    )
+                    .service(
+                        web::resource("/user/{user_id}/{page}")
+                            .route(
+                                web::get().to(
+                                    handlers::user_handler::get_user_with_votes_and_cards_by_id,
+                                ),
+                            )
+                            .wrap(middleware::Compress::default()),
+                    )
                     .service(
                         web::resource("/user")
                             .route(web::put().to(handlers::user_handler::update_user)),
@@ -265,7 +631,12 @@ pub async fn main() -> std::io::Result<()> {
                     )
                     .service(
                         web::resource("/card_collection/search/{page}")
-                            .route(web::post().to(handlers::card_handler::search_collections)),
+                            .route(web::post().to(handlers::card_handler::search_collections))
+                            .wrap(middleware::Compress::default()),
+                    )
+                    .service(
+                        web::resource("/card_collection/move")
+                            .route(web::post().to(handlers::collection_handler::move_bookmarks)),
                     )
                     .service(web::resource("/card_collection/bookmark").route(
                         web::post().to(handlers::collection_handler::get_collections_card_is_in),
@@ -289,6 +660,23 @@ pub async fn main() -> std::io::Result<()> {
                             .route(web::put().to(handlers::file_handler::update_file_handler))
                             .route(web::post().to(handlers::file_handler::upload_file_handler)),
                     )
+                    .service(
+                        web::resource("/file/validate")
+                            .route(web::post().to(handlers::file_handler::validate_file_handler)),
+                    )
+                    .service(
+                        web::resource("/file/upload/{upload_job_id}/progress").route(
+                            web::get().to(handlers::file_handler::get_upload_progress_handler),
+                        ),
+                    )
+                    .service(
+                        web::resource("/file/{file_id}/reembed")
+                            .route(web::post().to(handlers::file_handler::reembed_file_handler)),
+                    )
+                    .service(
+                        web::resource("/file/{file_id}/download")
+                            .route(web::get().to(handlers::file_handler::download_file_handler)),
+                    )
                     .service(
                         web::resource("/file/{file_id}")
                             .route(web::get().to(handlers::file_handler::get_file_handler))
@@ -313,7 +701,16 @@ pub async fn main() -> std::io::Result<()> {
                     ),
             )
     })
+    .shutdown_timeout(shutdown_grace_period.as_secs())
     .bind(("0.0.0.0", 8090))?
     .run()
-    .await
+    .await?;
+
+    handlers::message_handler::wait_for_in_flight_completions(shutdown_grace_period).await;
+
+    if let Err(err) = flush_card_views_query(&shutdown_pool) {
+        log::error!("{}", err.message);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,54 @@
+use std::io::Write;
+
+use serde_json::json;
+
+tokio::task_local! {
+    // Set for the lifetime of each request by the request id middleware
+    pub static REQUEST_ID: String;
+}
+
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+}
+
+// Clients and proxies may pass their own id, anything unreasonable is replaced with a fresh one
+pub fn request_id_from_header(header_value: Option<&str>) -> String {
+    header_value
+        .filter(|request_id| {
+            !request_id.is_empty()
+                && request_id.len() <= 128
+                && request_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(|request_id| request_id.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+// The level comes from RUST_LOG (info by default). LOG_FORMAT=json writes one JSON object per
+// line for log ingestion, anything else keeps env_logger's text format.
+pub fn init_logger() {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("info"));
+
+    let json_format = std::env::var("LOG_FORMAT")
+        .map(|log_format| log_format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    if json_format {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                    "request_id": current_request_id(),
+                })
+            )
+        });
+    }
+
+    builder.init();
+}
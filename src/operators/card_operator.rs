@@ -1,52 +1,215 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use crate::data::models::{
-    CardCollisions, CardFile, CardFileWithName, CardMetadataWithVotesAndFiles, CardVerifications,
-    CardVote, FullTextSearchResult, User, UserDTO,
+    CardCitation, CardCollection, CardCollisions, CardFile, CardFileWithName, CardIdempotencyKey,
+    CardMetadataWithVotesAndFiles, CardVerifications, CardVote, FullTextSearchResult, User,
+    UserDTO,
 };
 use crate::data::schema;
-use crate::diesel::TextExpressionMethods;
 use crate::diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use crate::diesel::{PgTextExpressionMethods, TextExpressionMethods};
+use crate::operators::vote_operator::CardVoteTally;
 use crate::{
     data::models::{CardMetadata, Pool},
-    errors::DefaultError,
+    errors::{DefaultError, ErrorKind, ServiceError, VECTOR_STORE_UNAVAILABLE_MESSAGE},
 };
 use actix_web::web;
-use diesel::dsl::sql;
+use base64::{engine::general_purpose, Engine as _};
+use diesel::dsl::{count_star, sql};
 use diesel::result::Error::NotFound;
 use diesel::sql_types::Int8;
 use diesel::sql_types::Nullable;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Array, Text};
 use diesel::sql_types::{Bool, Double};
 use diesel::{
-    BoolExpressionMethods, Connection, JoinOnDsl, NullableExpressionMethods, SelectableHelper,
+    BoolExpressionMethods, Connection, JoinOnDsl, NullableExpressionMethods, OptionalExtension,
+    SelectableHelper,
 };
+use once_cell::sync::{Lazy, OnceCell};
 use openai_dive::v1::{api::Client, resources::embedding::EmbeddingParameters};
-use qdrant_client::qdrant::condition::ConditionOneOf::HasId;
+use qdrant_client::qdrant::condition::ConditionOneOf::{self, HasId};
+use qdrant_client::qdrant::points_selector::PointsSelectorOneOf;
+use qdrant_client::qdrant::r#match::MatchValue;
+use qdrant_client::qdrant::vectors::VectorsOptions;
+use qdrant_client::qdrant::vectors_config::Config;
+use qdrant_client::qdrant::Distance;
 use qdrant_client::{
-    prelude::{QdrantClient, QdrantClientConfig},
-    qdrant::{point_id::PointIdOptions, Condition, Filter, HasIdCondition, PointId, SearchPoints},
+    prelude::{Payload, QdrantClient, QdrantClientConfig},
+    qdrant::{
+        point_id::PointIdOptions, Condition, CountPoints, FieldCondition, FieldType, Filter,
+        HasIdCondition, Match, PointId, PointStruct, PointsIdsList, PointsSelector, Range,
+        ScrollPoints, SearchPoints,
+    },
 };
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
+const QDRANT_OPERATION_ATTEMPTS: u32 = 3;
+
+// Creating the client does not touch the network, unreachable Qdrant shows up when an operation
+// runs through retry_qdrant_operation
 pub async fn get_qdrant_connection() -> Result<QdrantClient, DefaultError> {
     let qdrant_url = std::env::var("QDRANT_URL").expect("QDRANT_URL must be set");
+
     QdrantClient::new(Some(QdrantClientConfig::from_url(qdrant_url.as_str()))).map_err(|_err| {
         DefaultError {
-            message: "Failed to connect to Qdrant",
+            message: VECTOR_STORE_UNAVAILABLE_MESSAGE,
+            kind: ErrorKind::VectorStoreUnavailable,
         }
     })
 }
 
+// Qdrant answers over grpc, so an unreachable server surfaces as a transport error or an
+// unavailable status somewhere in the error chain
+fn is_qdrant_transport_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.downcast_ref::<tonic::transport::Error>().is_some() {
+            return true;
+        }
+        if let Some(status) = err.downcast_ref::<tonic::Status>() {
+            return matches!(
+                status.code(),
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+            );
+        }
+        source = err.source();
+    }
+
+    false
+}
+
+// Runs a Qdrant operation, retrying a brief blip with backoff. If Qdrant stays unreachable callers
+// are told to try again later, any other failure is passed to map_err.
+pub async fn retry_qdrant_operation<T, E, R, F, Fut>(
+    operation: F,
+    map_err: impl FnOnce(E) -> R,
+) -> Result<T, R>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: std::ops::Deref<Target = dyn std::error::Error + Send + Sync + 'static> + std::fmt::Display,
+    R: From<DefaultError>,
+{
+    for attempt in 1..=QDRANT_OPERATION_ATTEMPTS {
+        let err = match operation().await {
+            Ok(result) => return Ok(result),
+            Err(err) => err,
+        };
+        if !is_qdrant_transport_error(&*err) {
+            return Err(map_err(err));
+        }
+
+        log::warn!(
+            "Qdrant is unreachable (attempt {}/{}): {}",
+            attempt,
+            QDRANT_OPERATION_ATTEMPTS,
+            err
+        );
+        if attempt < QDRANT_OPERATION_ATTEMPTS {
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(
+                100 * 2u64.pow(attempt - 1),
+            ))
+            .await;
+        }
+    }
+
+    Err(DefaultError {
+        message: VECTOR_STORE_UNAVAILABLE_MESSAGE,
+        kind: ErrorKind::VectorStoreUnavailable,
+    }
+    .into())
+}
+
+pub const OPENAI_EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+pub const OPENAI_EMBEDDING_DIMENSION: u64 = 1536;
+
+// Every embedding request shares these permits, so concurrent uploads queue for OpenAI instead
+// of tripping its rate limit and failing together
+static OPENAI_EMBEDDING_PERMITS: Lazy<Semaphore> = Lazy::new(|| {
+    let permits = std::env::var("OPENAI_EMBEDDING_MAX_CONCURRENCY")
+        .ok()
+        .and_then(|permits| permits.parse().ok())
+        .unwrap_or(8)
+        .max(1);
+    Semaphore::new(permits)
+});
+
+async fn acquire_openai_embedding_permit() -> Result<SemaphorePermit<'static>, actix_web::Error> {
+    let queue_timeout = std::time::Duration::from_secs(
+        std::env::var("OPENAI_EMBEDDING_QUEUE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(60),
+    );
+
+    match actix_web::rt::time::timeout(queue_timeout, OPENAI_EMBEDDING_PERMITS.acquire()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        Ok(Err(_closed)) => Err(actix_web::error::ErrorInternalServerError(
+            "Embedding queue is unavailable",
+        )),
+        Err(_elapsed) => Err(actix_web::error::ErrorServiceUnavailable(
+            "Too many embedding requests in progress, try again later",
+        )),
+    }
+}
+
+// ada-002 uses the cl100k_base encoding
+static OPENAI_EMBEDDING_BPE: Lazy<tiktoken_rs::CoreBPE> =
+    Lazy::new(|| tiktoken_rs::cl100k_base().expect("cl100k_base tokenizer must load"));
+
+// OpenAI rejects embedding inputs longer than this, OPENAI_EMBEDDING_MAX_TOKENS can lower it
+fn openai_embedding_max_tokens() -> usize {
+    std::env::var("OPENAI_EMBEDDING_MAX_TOKENS")
+        .ok()
+        .and_then(|max_tokens| max_tokens.parse().ok())
+        .unwrap_or(8191)
+        .clamp(1, 8191)
+}
+
+// Overlong input is cut to the token limit instead of failing, the flag tells the caller the
+// embedding only covers the start of the text
+pub fn truncate_embedding_input(message: &str) -> (String, bool) {
+    let max_tokens = openai_embedding_max_tokens();
+    let tokens = OPENAI_EMBEDDING_BPE.encode_with_special_tokens(message);
+    if tokens.len() <= max_tokens {
+        return (message.to_string(), false);
+    }
+
+    // A cut can land inside a multi token character, so back off until the prefix decodes
+    let truncated_message = (0..=max_tokens)
+        .rev()
+        .find_map(|end| OPENAI_EMBEDDING_BPE.decode(tokens[..end].to_vec()).ok())
+        .unwrap_or_default();
+    log::warn!(
+        "Truncated embedding input from {} to {} tokens",
+        tokens.len(),
+        max_tokens
+    );
+    (truncated_message, true)
+}
+
 pub async fn create_openai_embedding(message: &str) -> Result<Vec<f32>, actix_web::Error> {
+    create_openai_embedding_with_truncation(message)
+        .await
+        .map(|(vector, _truncated)| vector)
+}
+
+pub async fn create_openai_embedding_with_truncation(
+    message: &str,
+) -> Result<(Vec<f32>, bool), actix_web::Error> {
     let open_ai_api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
     let client = Client::new(open_ai_api_key);
+    let (input, truncated) = truncate_embedding_input(message);
+    let _permit = acquire_openai_embedding_permit().await?;
 
     // Vectorize
     let parameters = EmbeddingParameters {
-        model: "text-embedding-ada-002".to_string(),
-        input: message.to_string(),
+        model: OPENAI_EMBEDDING_MODEL.to_string(),
+        input,
         user: None,
     };
 
@@ -57,304 +220,1039 @@ pub async fn create_openai_embedding(message: &str) -> Result<Vec<f32>, actix_we
         .map_err(actix_web::error::ErrorBadRequest)?;
 
     let vector = embeddings.data.get(0).unwrap().embedding.clone();
-    Ok(vector.iter().map(|&x| x as f32).collect())
+    Ok((vector.iter().map(|&x| x as f32).collect(), truncated))
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SearchResult {
-    pub score: f32,
-    pub point_id: uuid::Uuid,
+#[derive(Serialize)]
+struct OpenAIEmbeddingsBatchRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SearchCardQueryResult {
-    pub search_results: Vec<SearchResult>,
-    pub total_card_pages: i64,
+#[derive(Deserialize)]
+struct OpenAIEmbedding {
+    embedding: Vec<f64>,
+    index: usize,
 }
 
-pub async fn search_card_query(
-    embedding_vector: Vec<f32>,
-    page: u64,
-    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
-    filter_oc_file_path: Option<Vec<String>>,
-    filter_link_url: Option<Vec<String>>,
-    current_user_id: Option<uuid::Uuid>,
-) -> Result<SearchCardQueryResult, DefaultError> {
-    let page = if page == 0 { 1 } else { page };
-    let filter_oc_file_path = filter_oc_file_path.unwrap_or([].to_vec());
-    let filter_link_url = filter_link_url.unwrap_or([].to_vec());
+#[derive(Deserialize)]
+struct OpenAIEmbeddingsBatchResponse {
+    data: Vec<OpenAIEmbedding>,
+}
 
-    let mut conn = pool.lock().unwrap().get().unwrap();
+// The openai_dive embeddings client only takes a single input, so batches go straight to the API.
+// Vectors are returned in the same order as the messages.
+pub async fn create_openai_embeddings_batch(
+    messages: Vec<String>,
+) -> Result<Vec<Vec<f32>>, actix_web::Error> {
+    let open_ai_api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    // OpenAI rejects requests with more than 2048 inputs
+    let batch_size: usize = std::env::var("OPENAI_EMBEDDING_BATCH_SIZE")
+        .ok()
+        .and_then(|batch_size| batch_size.parse().ok())
+        .unwrap_or(100)
+        .clamp(1, 2048);
+    let client = reqwest::Client::new();
+    let messages: Vec<String> = messages
+        .iter()
+        .map(|message| truncate_embedding_input(message).0)
+        .collect();
 
-    // SELECT distinct card_metadata.qdrant_point_id, card_collisions.collision_qdrant_id
-    // FROM card_metadata
-    // left outer JOIN card_collisions ON card_metadata.id = card_collisions.card_id
-    // WHERE card_metadata.private = false OR (card_metadata.private = false and card_metadata.qdrant_point_id is null);
+    let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(messages.len());
+    for messages_chunk in messages.chunks(batch_size) {
+        let request_body = serde_json::to_vec(&OpenAIEmbeddingsBatchRequest {
+            model: OPENAI_EMBEDDING_MODEL,
+            input: messages_chunk,
+        })
+        .map_err(actix_web::error::ErrorBadRequest)?;
 
-    //SELECT DISTINCT "card\_metadata"."qdrant\_point\_id", "card\_collisions"."collision\_qdrant\_id"
-    //FROM ("card\_metadata"
-    //LEFT OUTER JOIN "card\_collisions" ON ("card\_metadata"."id" = "card\_collisions"."card\_id"))
-    //WHERE (("card\_metadata"."private" = $1) OR (("card\_metadata"."private" = $2) AND ("card\_metadata"."qdrant\_point\_id" IS NULL))) -- binds: \[false, false\]
-    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
-    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+        let _permit = acquire_openai_embedding_permit().await?;
+        let response = client
+            .post("https://api.openai.com/v1/embeddings")
+            .bearer_auth(&open_ai_api_key)
+            .header("Content-Type", "application/json")
+            .body(request_body)
+            .send()
+            .await
+            .map_err(actix_web::error::ErrorBadRequest)?
+            .error_for_status()
+            .map_err(actix_web::error::ErrorBadRequest)?
+            .bytes()
+            .await
+            .map_err(actix_web::error::ErrorBadRequest)?;
+
+        let mut embeddings: OpenAIEmbeddingsBatchResponse =
+            serde_json::from_slice(&response).map_err(actix_web::error::ErrorBadRequest)?;
+        if embeddings.data.len() != messages_chunk.len() {
+            return Err(actix_web::error::ErrorBadRequest(
+                "OpenAI returned the wrong number of embeddings",
+            ));
+        }
+        embeddings.data.sort_by_key(|embedding| embedding.index);
 
-    // We only want collisions for which the root CardMetadata is public
-    // Punting on or the current user is the author
+        vectors.extend(
+            embeddings
+                .data
+                .into_iter()
+                .map(|embedding| embedding.embedding.iter().map(|&x| x as f32).collect()),
+        );
+    }
 
-    let mut query = card_metadata_columns::card_metadata
-        .left_outer_join(
-            card_collisions_columns::card_collisions.on(card_metadata_columns::id
-                .eq(card_collisions_columns::card_id)
-                .and(card_metadata_columns::private.eq(false))),
-        )
-        .select((
-            card_metadata_columns::qdrant_point_id,
-            card_collisions_columns::collision_qdrant_id.nullable(),
-        ))
-        .filter(card_metadata_columns::private.eq(false))
-        .or_filter(
-            card_metadata_columns::author_id.eq(current_user_id.unwrap_or(uuid::Uuid::nil())),
-        )
-        .distinct()
-        .into_boxed();
+    Ok(vectors)
+}
 
-    if !filter_oc_file_path.is_empty() {
-        query = query.filter(
-            card_metadata_columns::oc_file_path
-                .like(format!("%{}%", filter_oc_file_path.get(0).unwrap())),
-        );
+const ALLOWED_CARD_HTML_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "big",
+    "blockquote",
+    "br",
+    "center",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "font",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+const ALLOWED_CARD_HTML_ATTRIBUTES: &[&str] = &[
+    "align", "class", "color", "colspan", "dir", "face", "height", "href", "lang", "rowspan",
+    "size", "style", "title", "valign", "width",
+];
+
+// Elements whose content is dropped along with the tags, since it was never meant to be read
+static DROPPED_ELEMENT_REGEXES: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        "script", "style", "iframe", "object", "embed", "template", "noscript", "textarea",
+    ]
+    .iter()
+    .map(|tag| Regex::new(&format!(r"(?is)<{tag}\b[^>]*>.*?</{tag}\s*>")).unwrap())
+    .collect()
+});
+static HTML_COMMENT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<!--.*?(-->|$)|<[!?][^>]*>").unwrap());
+static HTML_TAG_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<(/?)([a-zA-Z][a-zA-Z0-9]*)([^>]*)>").unwrap());
+static HTML_ATTRIBUTE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)(?:\s*=\s*("[^"]*"|'[^']*'|[^\s"'>]+))?"#).unwrap()
+});
+
+static HTML_ENTITY_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"&(#[xX][0-9a-fA-F]+|#[0-9]+|[a-zA-Z][a-zA-Z0-9]*);?").unwrap());
+
+fn decode_named_html_entity(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "amp" | "AMP" => "&",
+        "lt" | "LT" => "<",
+        "gt" | "GT" => ">",
+        "quot" | "QUOT" => "\"",
+        "apos" => "'",
+        "colon" => ":",
+        "semi" => ";",
+        "comma" => ",",
+        "period" => ".",
+        "sol" => "/",
+        "bsol" => "\\",
+        "lpar" => "(",
+        "rpar" => ")",
+        "num" => "#",
+        "excl" => "!",
+        "quest" => "?",
+        "commat" => "@",
+        "Tab" => "\t",
+        "NewLine" => "\n",
+        "nbsp" => "\u{a0}",
+        _ => return None,
+    })
+}
+
+// Attribute values are checked and written back decoded, with every & re-encoded, so entities the
+// decoder doesn't know stay literal text in the browser instead of hiding a scheme or pattern
+fn decode_html_entities(value: &str) -> String {
+    HTML_ENTITY_REGEX
+        .replace_all(value, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let code_point = if let Some(hex) = entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                Some(u32::from_str_radix(hex, 16).unwrap_or(0))
+            } else if let Some(decimal) = entity.strip_prefix('#') {
+                Some(decimal.parse::<u32>().unwrap_or(0))
+            } else {
+                None
+            };
+
+            match code_point {
+                Some(code_point) => char::from_u32(code_point)
+                    .filter(|c| *c != '\0')
+                    .unwrap_or('\u{fffd}')
+                    .to_string(),
+                None => match decode_named_html_entity(entity) {
+                    Some(decoded) => decoded.to_string(),
+                    None => captures[0].to_string(),
+                },
+            }
+        })
+        .into_owned()
+}
+
+fn encode_html_attribute_value(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn is_safe_card_html_attribute(name: &str, value: &str) -> bool {
+    let compact_value = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+
+    match name {
+        "href" => {
+            let scheme_end = compact_value.find(|c| c == ':' || c == '/' || c == '?' || c == '#');
+            match scheme_end {
+                Some(index) if compact_value[index..].starts_with(':') => {
+                    ["http", "https", "mailto"].contains(&&compact_value[..index])
+                }
+                _ => true,
+            }
+        }
+        // css escapes and comments could split the patterns just like entities could
+        "style" => [
+            "expression",
+            "url(",
+            "javascript:",
+            "behavior",
+            "@import",
+            "\\",
+            "/*",
+        ]
+        .iter()
+        .all(|pattern| !compact_value.contains(pattern)),
+        _ => true,
     }
+}
 
-    for file_path in filter_oc_file_path.iter().skip(1) {
-        query =
-            query.or_filter(card_metadata_columns::oc_file_path.like(format!("%{}%", file_path)));
+fn sanitize_card_html_tag(captures: &regex::Captures) -> String {
+    let closing = &captures[1];
+    let tag = captures[2].to_lowercase();
+    if !ALLOWED_CARD_HTML_TAGS.contains(&tag.as_str()) {
+        return String::new();
+    }
+    if !closing.is_empty() {
+        return format!("</{}>", tag);
     }
 
-    if !filter_link_url.is_empty() {
-        query = query.filter(
-            card_metadata_columns::link.like(format!("%{}%", filter_link_url.get(0).unwrap())),
+    let mut sanitized_tag = format!("<{}", tag);
+    for attribute in HTML_ATTRIBUTE_REGEX.captures_iter(&captures[3]) {
+        let name = attribute[1].to_lowercase();
+        let value = decode_html_entities(
+            attribute
+                .get(2)
+                .map(|value| value.as_str().trim_matches(|c| c == '"' || c == '\''))
+                .unwrap_or_default(),
         );
+        if ALLOWED_CARD_HTML_ATTRIBUTES.contains(&name.as_str())
+            && is_safe_card_html_attribute(&name, &value)
+        {
+            sanitized_tag.push_str(&format!(
+                " {}=\"{}\"",
+                name,
+                encode_html_attribute_value(&value)
+            ));
+        }
     }
-    for link_url in filter_link_url.iter().skip(1) {
-        query = query.or_filter(card_metadata_columns::link.like(format!("%{}%", link_url)));
+    sanitized_tag.push('>');
+
+    sanitized_tag
+}
+
+// Card html is rendered by clients, so only tags and attributes used for document formatting are
+// kept. Passes repeat until nothing changes so removed tags can't join fragments into new ones.
+pub fn sanitize_card_html(card_html: &str) -> String {
+    let mut sanitized_html = card_html.to_string();
+
+    loop {
+        let mut next_html = HTML_COMMENT_REGEX
+            .replace_all(&sanitized_html, "")
+            .into_owned();
+        for element_regex in DROPPED_ELEMENT_REGEXES.iter() {
+            next_html = element_regex.replace_all(&next_html, "").into_owned();
+        }
+        next_html = HTML_TAG_REGEX
+            .replace_all(&next_html, sanitize_card_html_tag)
+            .into_owned();
+
+        if next_html == sanitized_html {
+            return sanitized_html;
+        }
+        sanitized_html = next_html;
     }
+}
 
-    let filtered_option_ids: Vec<(Option<uuid::Uuid>, Option<uuid::Uuid>)> =
-        query.load(&mut conn).map_err(|_| DefaultError {
-            message: "Failed to load metadata",
-        })?;
-    let qdrant = get_qdrant_connection().await?;
+#[derive(Serialize)]
+struct OpenAIModerationRequest<'a> {
+    input: &'a str,
+}
 
-    let filtered_point_ids: &Vec<PointId> = &filtered_option_ids
-        .iter()
-        .map(|uuid| {
-            uuid.0
-                .unwrap_or(uuid.1.unwrap_or(uuid::Uuid::nil()))
-                .to_string()
-                .into()
-        })
-        .collect::<Vec<PointId>>();
+#[derive(Deserialize)]
+struct OpenAIModerationResult {
+    flagged: bool,
+    categories: HashMap<String, bool>,
+}
 
-    let mut filter = Filter::default();
-    filter.should.push(Condition {
-        condition_one_of: Some(HasId(HasIdCondition {
-            has_id: (filtered_point_ids).to_vec(),
-        })),
-    });
+#[derive(Deserialize)]
+struct OpenAIModerationResponse {
+    results: Vec<OpenAIModerationResult>,
+}
 
-    let data = qdrant
-        .search_points(&SearchPoints {
-            collection_name: "debate_cards".to_string(),
-            vector: embedding_vector,
-            limit: 25,
-            offset: Some((page - 1) * 25),
-            with_payload: None,
-            filter: Some(filter),
-            ..Default::default()
-        })
+// CARD_BLOCKED_WORDS is a comma separated list matched case insensitively against whole words
+fn get_blocked_card_word(content: &str) -> Option<String> {
+    let blocked_words: HashSet<String> = std::env::var("CARD_BLOCKED_WORDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|blocked_word| blocked_word.trim().to_lowercase())
+        .filter(|blocked_word| !blocked_word.is_empty())
+        .collect();
+    if blocked_words.is_empty() {
+        return None;
+    }
+
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .find(|word| blocked_words.contains(word))
+}
+
+// Returns the categories OpenAI flagged the content for, empty when it passed
+async fn get_openai_moderation_categories(content: &str) -> Result<Vec<String>, actix_web::Error> {
+    let open_ai_api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    let request_body = serde_json::to_vec(&OpenAIModerationRequest { input: content })
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    let response = reqwest::Client::new()
+        .post("https://api.openai.com/v1/moderations")
+        .bearer_auth(&open_ai_api_key)
+        .header("Content-Type", "application/json")
+        .body(request_body)
+        .send()
         .await
-        .map_err(|_e| DefaultError {
-            message: "Failed to search points on Qdrant",
-        })?;
+        .map_err(actix_web::error::ErrorBadRequest)?
+        .error_for_status()
+        .map_err(actix_web::error::ErrorBadRequest)?
+        .bytes()
+        .await
+        .map_err(actix_web::error::ErrorBadRequest)?;
 
-    let point_ids: Vec<SearchResult> = data
-        .result
-        .iter()
-        .filter_map(|point| match point.clone().id?.point_id_options? {
-            PointIdOptions::Uuid(id) => Some(SearchResult {
-                score: point.score,
-                point_id: uuid::Uuid::parse_str(&id).ok()?,
-            }),
-            PointIdOptions::Num(_) => None,
+    let moderation: OpenAIModerationResponse =
+        serde_json::from_slice(&response).map_err(actix_web::error::ErrorBadRequest)?;
+
+    let mut flagged_categories: Vec<String> = moderation
+        .results
+        .into_iter()
+        .filter(|result| result.flagged)
+        .flat_map(|result| {
+            result
+                .categories
+                .into_iter()
+                .filter(|(_, flagged)| *flagged)
+                .map(|(category, _)| category)
         })
         .collect();
-
-    Ok(SearchCardQueryResult {
-        search_results: point_ids,
-        total_card_pages: (filtered_point_ids.len() as f64 / 25.0).ceil() as i64,
-    })
+    flagged_categories.sort();
+    flagged_categories.dedup();
+    Ok(flagged_categories)
 }
 
-pub async fn global_unfiltered_top_match_query(
-    embedding_vector: Vec<f32>,
-) -> Result<SearchResult, DefaultError> {
-    let qdrant = get_qdrant_connection().await?;
+// Both checks are off by default. CARD_MODERATION_ENABLED sends card content to the OpenAI
+// moderation endpoint, if that call fails the card is let through unless
+// CARD_MODERATION_FAIL_CLOSED is true.
+pub async fn screen_card_content(content: &str) -> Result<(), ServiceError> {
+    if let Some(blocked_word) = get_blocked_card_word(content) {
+        return Err(ServiceError::BadRequest(format!(
+            "Card content contains the blocked word \"{}\"",
+            blocked_word
+        )));
+    }
 
-    let data = qdrant
-        .search_points(&SearchPoints {
-            collection_name: "debate_cards".to_string(),
-            vector: embedding_vector,
-            limit: 1,
-            with_payload: None,
-            ..Default::default()
-        })
-        .await
-        .map_err(|_e| DefaultError {
-            message: "Failed to search points on Qdrant",
-        })?;
+    if std::env::var("CARD_MODERATION_ENABLED").unwrap_or_default() != "true" {
+        return Ok(());
+    }
 
-    let top_search_result: SearchResult = match data.result.get(0) {
-        Some(point) => match point.clone().id {
-            Some(point_id) => match point_id.point_id_options {
-                Some(PointIdOptions::Uuid(id)) => SearchResult {
-                    score: point.score,
-                    point_id: uuid::Uuid::parse_str(&id).map_err(|_| DefaultError {
-                        message: "Failed to parse uuid",
-                    })?,
-                },
-                Some(PointIdOptions::Num(_)) => {
-                    return Err(DefaultError {
-                        message: "Failed to parse uuid",
-                    })
-                }
-                None => {
-                    return Err(DefaultError {
-                        message: "Failed to parse uuid",
-                    })
-                }
-            },
-            None => {
-                return Err(DefaultError {
-                    message: "Failed to parse uuid",
-                })
+    match get_openai_moderation_categories(content).await {
+        Ok(flagged_categories) if flagged_categories.is_empty() => Ok(()),
+        Ok(flagged_categories) => Err(ServiceError::BadRequest(format!(
+            "Card content was flagged by moderation for: {}",
+            flagged_categories.join(", ")
+        ))),
+        Err(err) => {
+            log::error!("Failed to moderate card content: {}", err);
+            if std::env::var("CARD_MODERATION_FAIL_CLOSED").unwrap_or_default() == "true" {
+                return Err(ServiceError::ModerationUnavailable);
             }
-        },
-        // This only happens when there are no cards in the database
-        None => SearchResult {
-            score: 0.0,
-            point_id: uuid::Uuid::nil(),
-        },
-    };
+            Ok(())
+        }
+    }
+}
 
-    Ok(top_search_result)
+// With CARD_APPROVAL_REQUIRED set new cards stay out of public search until they are voted in or an
+// admin approves them
+pub fn card_approval_required() -> bool {
+    std::env::var("CARD_APPROVAL_REQUIRED").unwrap_or_default() == "true"
 }
 
-pub async fn search_card_collections_query(
-    embedding_vector: Vec<f32>,
-    page: u64,
-    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
-    filter_oc_file_path: Option<Vec<String>>,
-    filter_link_url: Option<Vec<String>>,
-    collection_id: uuid::Uuid,
-) -> Result<SearchCardQueryResult, DefaultError> {
-    let page = if page == 0 { 1 } else { page };
-    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
-    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+pub fn get_card_approval_vote_threshold() -> i64 {
+    std::env::var("CARD_APPROVAL_VOTE_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(3)
+}
+
+// Returns the card only when this call approved it, so callers know to refresh its qdrant payload.
+// With min_net_votes the card is only approved once its votes reach that threshold.
+pub fn approve_card_query(
+    card_id: uuid::Uuid,
+    min_net_votes: Option<i64>,
+    pool: &web::Data<Pool>,
+) -> Result<Option<CardMetadata>, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.lock().unwrap().get().unwrap();
+    let mut conn = pool.get()?;
+
+    // an admin approval has no vote threshold, which is the same as the lowest possible one
+    let approved_count = diesel::update(
+        card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq(card_id))
+            .filter(card_metadata_columns::approved.eq(false))
+            .filter(
+                sql::<Bool>(
+                    "(SELECT count(*) FILTER (WHERE card_votes.vote) \
+                     - count(*) FILTER (WHERE NOT card_votes.vote) FROM card_votes \
+                     WHERE card_votes.card_metadata_id = card_metadata.id AND NOT card_votes.deleted) >= ",
+                )
+                .bind::<Int8, _>(min_net_votes.unwrap_or(i64::MIN)),
+            ),
+    )
+    .set(card_metadata_columns::approved.eq(true))
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to approve card",
+        ..Default::default()
+    })?;
+    if approved_count == 0 {
+        return Ok(None);
+    }
 
-    let card_ids: Vec<uuid::Uuid> = card_collection_bookmarks_columns::card_collection_bookmarks
-        .select(card_collection_bookmarks_columns::card_metadata_id)
-        .filter(card_collection_bookmarks_columns::collection_id.eq(collection_id))
-        .load::<uuid::Uuid>(&mut conn)
-        .map_err(|_| DefaultError {
+    card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::id.eq(card_id))
+        .select(CardMetadata::as_select())
+        .first::<CardMetadata>(&mut conn)
+        .map(Some)
+        .map_err(|_db_error| DefaultError {
             message: "Failed to load metadata",
-        })?;
+            ..Default::default()
+        })
+}
 
-    let mut query = card_metadata_columns::card_metadata
-        .left_outer_join(
-            card_collisions_columns::card_collisions
-                .on(card_metadata_columns::id.eq(card_collisions_columns::card_id)),
-        )
-        .select((
-            card_metadata_columns::qdrant_point_id,
-            card_collisions_columns::collision_qdrant_id.nullable(),
-        ))
-        .filter(card_metadata_columns::private.eq(false))
-        .or_filter(
-            card_metadata_columns::private
-                .eq(false)
-                .and(card_metadata_columns::qdrant_point_id.is_null()),
+// source_date is stored as a unix timestamp so it can be filtered with a qdrant range
+pub fn get_card_qdrant_payload(
+    author_id: uuid::Uuid,
+    link: &Option<String>,
+    oc_file_path: &Option<String>,
+    private: bool,
+    approved: bool,
+    citation: &CardCitation,
+) -> Payload {
+    json!({
+        "author_id": author_id.to_string(),
+        "link": link,
+        "oc_file_path": oc_file_path,
+        "private": private,
+        "approved": approved,
+        "source_author": citation.source_author,
+        "source_date": citation.source_date.map(get_source_date_timestamp),
+    })
+    .try_into()
+    .unwrap()
+}
+
+fn get_source_date_timestamp(source_date: chrono::NaiveDate) -> i64 {
+    source_date
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .timestamp()
+}
+
+pub async fn update_card_qdrant_payload_query(
+    qdrant_point_id: uuid::Uuid,
+    payload: Payload,
+) -> Result<(), DefaultError> {
+    let qdrant = get_qdrant_connection().await?;
+
+    let points = PointsSelector {
+        points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+            ids: vec![qdrant_point_id.to_string().into()],
+        })),
+    };
+
+    retry_qdrant_operation(
+        || qdrant.set_payload_blocking("debate_cards", &points, payload.clone(), None),
+        |_err| DefaultError {
+            message: "Failed updating card payload on qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Removes all the points in a single request so a large delete is not one round trip per card
+pub async fn delete_qdrant_points_query(
+    qdrant_point_ids: &[uuid::Uuid],
+) -> Result<(), DefaultError> {
+    if qdrant_point_ids.is_empty() {
+        return Ok(());
+    }
+
+    let qdrant = get_qdrant_connection().await?;
+
+    let points = PointsSelector {
+        points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+            ids: qdrant_point_ids
+                .iter()
+                .map(|point_id| point_id.to_string().into())
+                .collect(),
+        })),
+    };
+
+    retry_qdrant_operation(
+        || qdrant.delete_points_blocking("debate_cards", &points, None),
+        |_err| DefaultError {
+            message: "Failed deleting cards from qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+const REEMBED_CARDS_BATCH_SIZE: usize = 100;
+
+// Embeds and upserts the cards a batch at a time so a large file never holds every vector at
+// once, cards without their own point share a vector and are skipped
+pub async fn reembed_cards_query(cards: Vec<CardMetadata>) -> Result<usize, actix_web::Error> {
+    let cards: Vec<(uuid::Uuid, CardMetadata)> = cards
+        .into_iter()
+        .filter_map(|card| card.qdrant_point_id.map(|point_id| (point_id, card)))
+        .collect();
+    if cards.is_empty() {
+        return Ok(0);
+    }
+
+    let qdrant = get_qdrant_connection().await.map_err(ServiceError::from)?;
+
+    for cards_chunk in cards.chunks(REEMBED_CARDS_BATCH_SIZE) {
+        let embedding_vectors = create_openai_embeddings_batch(
+            cards_chunk
+                .iter()
+                .map(|(_, card)| card.content.clone())
+                .collect(),
         )
-        .distinct()
-        .filter(card_metadata_columns::id.eq_any(card_ids))
-        .into_boxed();
+        .await?;
 
-    let filter_oc_file_path = filter_oc_file_path.unwrap_or([].to_vec());
-    let filter_link_url = filter_link_url.unwrap_or([].to_vec());
+        let points = cards_chunk
+            .iter()
+            .zip(embedding_vectors)
+            .map(|((qdrant_point_id, card), embedding_vector)| {
+                PointStruct::new(
+                    qdrant_point_id.to_string(),
+                    embedding_vector,
+                    get_card_qdrant_payload(
+                        card.author_id,
+                        &card.link,
+                        &card.oc_file_path,
+                        card.private,
+                        card.approved,
+                        &card.citation(),
+                    ),
+                )
+            })
+            .collect::<Vec<PointStruct>>();
 
-    if !filter_oc_file_path.is_empty() {
-        query = query.filter(
-            card_metadata_columns::oc_file_path
-                .like(format!("%{}%", filter_oc_file_path.get(0).unwrap())),
-        );
+        retry_qdrant_operation(
+            || qdrant.upsert_points_blocking("debate_cards", points.clone(), None),
+            |_err| ServiceError::BadRequest("Failed inserting cards to qdrant".into()),
+        )
+        .await?;
     }
 
-    for file_path in filter_oc_file_path.iter().skip(1) {
-        query =
-            query.or_filter(card_metadata_columns::oc_file_path.like(format!("%{}%", file_path)));
+    Ok(cards.len())
+}
+
+pub async fn sync_card_qdrant_payload_query(card: &CardMetadata) -> Result<(), DefaultError> {
+    match card.qdrant_point_id {
+        Some(qdrant_point_id) => {
+            update_card_qdrant_payload_query(
+                qdrant_point_id,
+                get_card_qdrant_payload(
+                    card.author_id,
+                    &card.link,
+                    &card.oc_file_path,
+                    card.private,
+                    card.approved,
+                    &card.citation(),
+                ),
+            )
+            .await
+        }
+        None => Ok(()),
     }
+}
 
-    if !filter_link_url.is_empty() {
-        query = query.filter(
-            card_metadata_columns::link.like(format!("%{}%", filter_link_url.get(0).unwrap())),
-        );
+async fn backfill_qdrant_payloads_query(pool: &Pool) -> Result<usize, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let cards: Vec<CardMetadata> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::qdrant_point_id.is_not_null())
+        .select(CardMetadata::as_select())
+        .load(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+
+    for card in cards.iter() {
+        update_card_qdrant_payload_query(
+            card.qdrant_point_id.unwrap_or(uuid::Uuid::nil()),
+            get_card_qdrant_payload(
+                card.author_id,
+                &card.link,
+                &card.oc_file_path,
+                card.private,
+                card.approved,
+                &card.citation(),
+            ),
+        )
+        .await?;
     }
-    for link_url in filter_link_url.iter().skip(1) {
-        query = query.or_filter(card_metadata_columns::link.like(format!("%{}%", link_url)));
+
+    Ok(cards.len())
+}
+
+const QDRANT_PAYLOAD_BACKFILL_MIGRATION: &str = "backfill-card-payloads";
+
+// Points created before payloads were populated can't be filtered on, so the backfill runs once
+// like a migration and later starts skip it. Returns how many cards were backfilled, if any.
+pub async fn run_qdrant_payload_backfill_query(pool: &Pool) -> Result<Option<usize>, DefaultError> {
+    use crate::data::schema::qdrant_migrations::dsl as qdrant_migrations_columns;
+
+    let mut conn = pool.get()?;
+
+    let completed = qdrant_migrations_columns::qdrant_migrations
+        .filter(qdrant_migrations_columns::name.eq(QDRANT_PAYLOAD_BACKFILL_MIGRATION))
+        .select(qdrant_migrations_columns::name)
+        .first::<String>(&mut conn)
+        .optional()
+        .map_err(|_| DefaultError {
+            message: "Failed to load qdrant migrations",
+            ..Default::default()
+        })?
+        .is_some();
+    if completed {
+        return Ok(None);
     }
-    let filtered_option_ids: Vec<(Option<uuid::Uuid>, Option<uuid::Uuid>)> =
-        query.load(&mut conn).map_err(|_| DefaultError {
+
+    let backfilled_count = backfill_qdrant_payloads_query(pool).await?;
+
+    diesel::insert_into(qdrant_migrations_columns::qdrant_migrations)
+        .values(qdrant_migrations_columns::name.eq(QDRANT_PAYLOAD_BACKFILL_MIGRATION))
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to record qdrant migration",
+            ..Default::default()
+        })?;
+
+    Ok(Some(backfilled_count))
+}
+
+// Filters on unindexed payload fields scan every point. The text fields back the MatchValue::Text
+// conditions, and creating an index that already exists does nothing.
+pub async fn create_qdrant_payload_indexes_query() -> Result<(), DefaultError> {
+    let qdrant = get_qdrant_connection().await?;
+
+    let payload_indexes = [
+        ("author_id", FieldType::Keyword),
+        ("source_date", FieldType::Integer),
+        ("link", FieldType::Text),
+        ("oc_file_path", FieldType::Text),
+        ("source_author", FieldType::Text),
+    ];
+    for (field_name, field_type) in payload_indexes {
+        retry_qdrant_operation(
+            || {
+                qdrant.create_field_index_blocking(
+                    "debate_cards",
+                    field_name,
+                    field_type,
+                    None,
+                    None,
+                )
+            },
+            |_err| DefaultError {
+                message: "Failed to create payload index on qdrant",
+                ..Default::default()
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_cards_missing_vectors_query(
+    user_id: uuid::Uuid,
+    pool: web::Data<Pool>,
+) -> Result<Vec<CardMetadata>, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let cards: Vec<CardMetadata> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::author_id.eq(user_id))
+        .filter(card_metadata_columns::qdrant_point_id.is_not_null())
+        .select(CardMetadata::as_select())
+        .load(&mut conn)
+        .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let qdrant = get_qdrant_connection().await?;
 
-    let filtered_point_ids: &Vec<PointId> = &filtered_option_ids
-        .iter()
-        .map(|uuid| {
-            uuid.0
-                .unwrap_or(uuid.1.unwrap_or(uuid::Uuid::nil()))
-                .to_string()
+    let mut found_point_ids: HashSet<uuid::Uuid> = HashSet::new();
+    for cards_chunk in cards.chunks(100) {
+        let mut filter = Filter::default();
+        filter.should.push(Condition {
+            condition_one_of: Some(HasId(HasIdCondition {
+                has_id: cards_chunk
+                    .iter()
+                    .filter_map(|card| card.qdrant_point_id)
+                    .map(|id| id.to_string().into())
+                    .collect::<Vec<PointId>>(),
+            })),
+        });
+
+        let scroll_points = ScrollPoints {
+            collection_name: "debate_cards".to_string(),
+            filter: Some(filter),
+            limit: Some(cards_chunk.len() as u32),
+            ..Default::default()
+        };
+        let data = retry_qdrant_operation(
+            || qdrant.scroll(&scroll_points),
+            |_e| DefaultError {
+                message: "Failed to scroll points on Qdrant",
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        found_point_ids.extend(data.result.iter().filter_map(|point| {
+            match point.clone().id?.point_id_options? {
+                PointIdOptions::Uuid(id) => uuid::Uuid::parse_str(&id).ok(),
+                PointIdOptions::Num(_) => None,
+            }
+        }));
+    }
+
+    Ok(cards
+        .into_iter()
+        .filter(|card| {
+            !found_point_ids.contains(&card.qdrant_point_id.unwrap_or(uuid::Uuid::nil()))
         })
-        // remove duplicates
-        .collect::<HashSet<String>>()
-        .iter()
-        .map(|uuid| (*uuid).clone().into())
-        .collect::<Vec<PointId>>();
+        .collect())
+}
+
+fn get_match_condition(key: &str, match_value: MatchValue) -> Condition {
+    Condition {
+        condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
+            key: key.to_string(),
+            r#match: Some(Match {
+                match_value: Some(match_value),
+            }),
+            ..Default::default()
+        })),
+    }
+}
+
+// Matches when the payload field contains any of the values, mirroring the LIKE '%value%'
+// filters used by full text search
+fn get_any_text_match_condition(key: &str, values: &[String]) -> Condition {
+    Condition {
+        condition_one_of: Some(ConditionOneOf::Filter(Filter {
+            should: values
+                .iter()
+                .map(|value| get_match_condition(key, MatchValue::Text(value.clone())))
+                .collect(),
+            ..Default::default()
+        })),
+    }
+}
+
+// Private and unapproved points are only visible to their author unless a public card collided
+// with them, so those points have to be allowed explicitly
+fn get_visible_points_filter(
+    conn: &mut diesel::PgConnection,
+    current_user_id: Option<uuid::Uuid>,
+) -> Result<Filter, DefaultError> {
+    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let collided_point_ids: Vec<Option<uuid::Uuid>> = card_collisions_columns::card_collisions
+        .inner_join(
+            card_metadata_columns::card_metadata
+                .on(card_metadata_columns::id.eq(card_collisions_columns::card_id)),
+        )
+        .select(card_collisions_columns::collision_qdrant_id)
+        .filter(
+            card_metadata_columns::private
+                .eq(false)
+                .and(card_metadata_columns::approved.eq(true)),
+        )
+        .or_filter(
+            card_metadata_columns::author_id.eq(current_user_id.unwrap_or(uuid::Uuid::nil())),
+        )
+        .distinct()
+        .load(conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
 
     let mut filter = Filter::default();
     filter.should.push(Condition {
-        condition_one_of: Some(HasId(HasIdCondition {
-            has_id: (filtered_point_ids).to_vec(),
+        condition_one_of: Some(ConditionOneOf::Filter(Filter {
+            must_not: vec![
+                get_match_condition("private", MatchValue::Boolean(true)),
+                get_match_condition("approved", MatchValue::Boolean(false)),
+            ],
+            ..Default::default()
         })),
     });
+    if let Some(current_user_id) = current_user_id {
+        filter.should.push(get_match_condition(
+            "author_id",
+            MatchValue::Keyword(current_user_id.to_string()),
+        ));
+    }
+    if !collided_point_ids.is_empty() {
+        filter.should.push(Condition {
+            condition_one_of: Some(HasId(HasIdCondition {
+                has_id: collided_point_ids
+                    .iter()
+                    .flatten()
+                    .map(|id| id.to_string().into())
+                    .collect::<Vec<PointId>>(),
+            })),
+        });
+    }
 
-    let data = qdrant
-        .search_points(&SearchPoints {
-            collection_name: "debate_cards".to_string(),
-            vector: embedding_vector,
-            limit: 25,
-            offset: Some((page - 1) * 25),
-            with_payload: None,
-            filter: Some(filter),
+    Ok(filter)
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CitationFilter {
+    pub source_author: Option<String>,
+    pub source_date_from: Option<chrono::NaiveDate>,
+    pub source_date_to: Option<chrono::NaiveDate>,
+}
+
+fn push_citation_conditions(filter: &mut Filter, citation_filter: CitationFilter) {
+    if let Some(source_author) = citation_filter.source_author {
+        filter.must.push(get_any_text_match_condition(
+            "source_author",
+            &[source_author],
+        ));
+    }
+    if citation_filter.source_date_from.is_some() || citation_filter.source_date_to.is_some() {
+        filter.must.push(Condition {
+            condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
+                key: "source_date".to_string(),
+                range: Some(Range {
+                    gte: citation_filter
+                        .source_date_from
+                        .map(|date| get_source_date_timestamp(date) as f64),
+                    lte: citation_filter
+                        .source_date_to
+                        .map(|date| get_source_date_timestamp(date) as f64),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        });
+    }
+}
+
+// Cards matching any of these are left out of results, cards without the field are kept
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ExclusionFilter {
+    pub oc_file_path: Vec<String>,
+    pub link_url: Vec<String>,
+}
+
+fn push_exclusion_conditions(filter: &mut Filter, exclusion_filter: ExclusionFilter) {
+    if !exclusion_filter.oc_file_path.is_empty() {
+        filter.must_not.push(get_any_text_match_condition(
+            "oc_file_path",
+            &exclusion_filter.oc_file_path,
+        ));
+    }
+    if !exclusion_filter.link_url.is_empty() {
+        filter.must_not.push(get_any_text_match_condition(
+            "link",
+            &exclusion_filter.link_url,
+        ));
+    }
+}
+
+fn get_like_patterns(values: &[String]) -> Vec<String> {
+    values.iter().map(|value| format!("%{}%", value)).collect()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResult {
+    pub score: f32,
+    pub point_id: uuid::Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchCardQueryResult {
+    pub search_results: Vec<SearchResult>,
+    pub total_cards: i64,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn search_card_query(
+    embedding_vector: Vec<f32>,
+    page: u64,
+    page_size: u64,
+    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    filter_author_id: Option<uuid::Uuid>,
+    citation_filter: CitationFilter,
+    exclusion_filter: ExclusionFilter,
+    current_user_id: Option<uuid::Uuid>,
+) -> Result<SearchCardQueryResult, DefaultError> {
+    let page = if page == 0 { 1 } else { page };
+    let filter_oc_file_path = filter_oc_file_path.unwrap_or([].to_vec());
+    let filter_link_url = filter_link_url.unwrap_or([].to_vec());
+
+    let mut conn = pool.lock().unwrap().get()?;
+
+    let mut filter = get_visible_points_filter(&mut conn, current_user_id)?;
+
+    if let Some(author_id) = filter_author_id {
+        filter.must.push(get_match_condition(
+            "author_id",
+            MatchValue::Keyword(author_id.to_string()),
+        ));
+    }
+    if !filter_oc_file_path.is_empty() {
+        filter.must.push(get_any_text_match_condition(
+            "oc_file_path",
+            &filter_oc_file_path,
+        ));
+    }
+    if !filter_link_url.is_empty() {
+        filter
+            .must
+            .push(get_any_text_match_condition("link", &filter_link_url));
+    }
+    push_citation_conditions(&mut filter, citation_filter);
+    push_exclusion_conditions(&mut filter, exclusion_filter);
+
+    let qdrant = get_qdrant_connection().await?;
+
+    let count_points = CountPoints {
+        collection_name: "debate_cards".to_string(),
+        filter: Some(filter.clone()),
+        exact: Some(true),
+    };
+    let filtered_point_count = retry_qdrant_operation(
+        || qdrant.count(&count_points),
+        |_e| DefaultError {
+            message: "Failed to count points on Qdrant",
             ..Default::default()
-        })
-        .await
-        .map_err(|_e| DefaultError {
+        },
+    )
+    .await?
+    .result
+    .map(|count_result| count_result.count)
+    .unwrap_or(0);
+
+    let search_points = SearchPoints {
+        collection_name: "debate_cards".to_string(),
+        vector: embedding_vector,
+        limit: page_size,
+        offset: Some((page - 1) * page_size),
+        with_payload: None,
+        filter: Some(filter),
+        ..Default::default()
+    };
+    let data = retry_qdrant_operation(
+        || qdrant.search_points(&search_points),
+        |_e| DefaultError {
             message: "Failed to search points on Qdrant",
-        })?;
+            ..Default::default()
+        },
+    )
+    .await?;
 
     let point_ids: Vec<SearchResult> = data
         .result
@@ -370,22 +1268,369 @@ pub async fn search_card_collections_query(
 
     Ok(SearchCardQueryResult {
         search_results: point_ids,
-        total_card_pages: (filtered_point_ids.len() as f64 / 25.0).ceil() as i64,
+        total_cards: filtered_point_count as i64,
     })
 }
 
-pub fn get_metadata(
-    card_metadata: Vec<FullTextSearchResult>,
-    current_user_id: Option<uuid::Uuid>,
-    mut conn: r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::PgConnection>>,
-) -> Result<Vec<CardMetadataWithVotesAndFiles>, DefaultError> {
-    use crate::data::schema::card_files::dsl as card_files_columns;
-    use crate::data::schema::card_verification::dsl as card_verification_columns;
-    use crate::data::schema::card_votes::dsl as card_votes_columns;
-    use crate::data::schema::files::dsl as files_columns;
-    use crate::data::schema::users::dsl as user_columns;
+pub async fn get_point_vectors_query(
+    point_ids: Vec<uuid::Uuid>,
+) -> Result<HashMap<uuid::Uuid, Vec<f32>>, DefaultError> {
+    if point_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
 
-    let card_creators: Vec<User> = user_columns::users
+    let qdrant = get_qdrant_connection().await?;
+
+    let qdrant_point_ids = point_ids
+        .iter()
+        .map(|id| id.to_string().into())
+        .collect::<Vec<PointId>>();
+    let points = retry_qdrant_operation(
+        || {
+            qdrant.get_points(
+                "debate_cards",
+                &qdrant_point_ids,
+                Some(true),
+                Some(false),
+                None,
+            )
+        },
+        |_e| DefaultError {
+            message: "Failed to get points from Qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(points
+        .result
+        .into_iter()
+        .filter_map(|point| {
+            let point_id = match point.id?.point_id_options? {
+                PointIdOptions::Uuid(id) => uuid::Uuid::parse_str(&id).ok()?,
+                PointIdOptions::Num(_) => return None,
+            };
+            match point.vectors?.vectors_options? {
+                VectorsOptions::Vector(vector) => Some((point_id, vector.data)),
+                VectorsOptions::Vectors(_) => None,
+            }
+        })
+        .collect())
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+pub async fn search_similar_cards_query(
+    qdrant_point_id: uuid::Uuid,
+    limit: u64,
+    pool: web::Data<Pool>,
+    current_user_id: Option<uuid::Uuid>,
+) -> Result<Vec<SearchResult>, DefaultError> {
+    // Reuse the stored vector instead of embedding the card content again
+    let embedding_vector = get_point_vectors_query(vec![qdrant_point_id])
+        .await?
+        .remove(&qdrant_point_id)
+        .ok_or(DefaultError {
+            message: "Card has no vector on Qdrant",
+            ..Default::default()
+        })?;
+
+    let mut conn = pool.get()?;
+    let mut filter = get_visible_points_filter(&mut conn, current_user_id)?;
+    filter.must_not.push(Condition {
+        condition_one_of: Some(HasId(HasIdCondition {
+            has_id: vec![qdrant_point_id.to_string().into()],
+        })),
+    });
+
+    let qdrant = get_qdrant_connection().await?;
+    let search_points = SearchPoints {
+        collection_name: "debate_cards".to_string(),
+        vector: embedding_vector,
+        limit,
+        with_payload: None,
+        filter: Some(filter),
+        ..Default::default()
+    };
+    let data = retry_qdrant_operation(
+        || qdrant.search_points(&search_points),
+        |_e| DefaultError {
+            message: "Failed to search points on Qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(data
+        .result
+        .iter()
+        .filter_map(|point| match point.clone().id?.point_id_options? {
+            PointIdOptions::Uuid(id) => Some(SearchResult {
+                score: point.score,
+                point_id: uuid::Uuid::parse_str(&id).ok()?,
+            }),
+            PointIdOptions::Num(_) => None,
+        })
+        .collect())
+}
+
+// Distance metric of the debate_cards collection, read from Qdrant at startup
+pub static QDRANT_DISTANCE: OnceCell<Distance> = OnceCell::new();
+
+pub async fn get_qdrant_distance_query() -> Result<Distance, DefaultError> {
+    let qdrant = get_qdrant_connection().await?;
+
+    let collection_info = retry_qdrant_operation(
+        || qdrant.collection_info("debate_cards"),
+        |_e| DefaultError {
+            message: "Failed to get collection info from Qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let distance = collection_info
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.params)
+        .and_then(|params| params.vectors_config)
+        .and_then(|vectors_config| vectors_config.config)
+        .and_then(|config| match config {
+            Config::Params(params) => Distance::from_i32(params.distance),
+            Config::ParamsMap(_) => None,
+        })
+        .ok_or(DefaultError {
+            message: "Failed to read distance metric of collection",
+            ..Default::default()
+        })?;
+
+    Ok(distance)
+}
+
+// The similarity thresholds used for deduplication assume cosine similarity, so scores from
+// other metrics are converted to their cosine equivalent. OpenAI embeddings are unit length,
+// which makes dot product equal to cosine and lets euclidean distance be converted exactly.
+pub fn normalize_similarity_score(score: f32) -> f32 {
+    let normalized_score = match QDRANT_DISTANCE.get().unwrap_or(&Distance::Cosine) {
+        Distance::Euclid => 1.0 - (score * score) / 2.0,
+        _ => score,
+    };
+
+    normalized_score.clamp(0.0, 1.0)
+}
+
+pub async fn global_unfiltered_top_match_query(
+    embedding_vector: Vec<f32>,
+) -> Result<SearchResult, DefaultError> {
+    let qdrant = get_qdrant_connection().await?;
+
+    let search_points = SearchPoints {
+        collection_name: "debate_cards".to_string(),
+        vector: embedding_vector,
+        limit: 1,
+        with_payload: None,
+        ..Default::default()
+    };
+    let data = retry_qdrant_operation(
+        || qdrant.search_points(&search_points),
+        |_e| DefaultError {
+            message: "Failed to search points on Qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let top_search_result: SearchResult = match data.result.get(0) {
+        Some(point) => match point.clone().id {
+            Some(point_id) => match point_id.point_id_options {
+                Some(PointIdOptions::Uuid(id)) => SearchResult {
+                    score: point.score,
+                    point_id: uuid::Uuid::parse_str(&id).map_err(|_| DefaultError {
+                        message: "Failed to parse uuid",
+                        ..Default::default()
+                    })?,
+                },
+                Some(PointIdOptions::Num(_)) => {
+                    return Err(DefaultError {
+                        message: "Failed to parse uuid",
+                        ..Default::default()
+                    })
+                }
+                None => {
+                    return Err(DefaultError {
+                        message: "Failed to parse uuid",
+                        ..Default::default()
+                    })
+                }
+            },
+            None => {
+                return Err(DefaultError {
+                    message: "Failed to parse uuid",
+                    ..Default::default()
+                })
+            }
+        },
+        // This only happens when there are no cards in the database
+        None => SearchResult {
+            score: 0.0,
+            point_id: uuid::Uuid::nil(),
+        },
+    };
+
+    Ok(top_search_result)
+}
+
+pub async fn search_card_collections_query(
+    embedding_vector: Vec<f32>,
+    page: u64,
+    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    collection_id: uuid::Uuid,
+) -> Result<SearchCardQueryResult, DefaultError> {
+    let page = if page == 0 { 1 } else { page };
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.lock().unwrap().get()?;
+
+    let card_ids: Vec<uuid::Uuid> = card_collection_bookmarks_columns::card_collection_bookmarks
+        .select(card_collection_bookmarks_columns::card_metadata_id)
+        .filter(card_collection_bookmarks_columns::collection_id.eq(collection_id))
+        .load::<uuid::Uuid>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+
+    let mut query = card_metadata_columns::card_metadata
+        .left_outer_join(
+            card_collisions_columns::card_collisions
+                .on(card_metadata_columns::id.eq(card_collisions_columns::card_id)),
+        )
+        .select((
+            card_metadata_columns::qdrant_point_id,
+            card_collisions_columns::collision_qdrant_id.nullable(),
+        ))
+        .filter(card_metadata_columns::private.eq(false))
+        .or_filter(
+            card_metadata_columns::private
+                .eq(false)
+                .and(card_metadata_columns::qdrant_point_id.is_null()),
+        )
+        .distinct()
+        .filter(card_metadata_columns::id.eq_any(card_ids))
+        .into_boxed();
+
+    let filter_oc_file_path = filter_oc_file_path.unwrap_or([].to_vec());
+    let filter_link_url = filter_link_url.unwrap_or([].to_vec());
+
+    if !filter_oc_file_path.is_empty() {
+        query = query.filter(
+            card_metadata_columns::oc_file_path
+                .like(format!("%{}%", filter_oc_file_path.get(0).unwrap())),
+        );
+    }
+
+    for file_path in filter_oc_file_path.iter().skip(1) {
+        query =
+            query.or_filter(card_metadata_columns::oc_file_path.like(format!("%{}%", file_path)));
+    }
+
+    if !filter_link_url.is_empty() {
+        query = query.filter(
+            card_metadata_columns::link.like(format!("%{}%", filter_link_url.get(0).unwrap())),
+        );
+    }
+    for link_url in filter_link_url.iter().skip(1) {
+        query = query.or_filter(card_metadata_columns::link.like(format!("%{}%", link_url)));
+    }
+    let filtered_option_ids: Vec<(Option<uuid::Uuid>, Option<uuid::Uuid>)> =
+        query.load(&mut conn).map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+
+    let qdrant = get_qdrant_connection().await?;
+
+    let filtered_point_ids: &Vec<PointId> = &filtered_option_ids
+        .iter()
+        .map(|uuid| {
+            uuid.0
+                .unwrap_or(uuid.1.unwrap_or(uuid::Uuid::nil()))
+                .to_string()
+        })
+        // remove duplicates
+        .collect::<HashSet<String>>()
+        .iter()
+        .map(|uuid| (*uuid).clone().into())
+        .collect::<Vec<PointId>>();
+
+    let mut filter = Filter::default();
+    filter.should.push(Condition {
+        condition_one_of: Some(HasId(HasIdCondition {
+            has_id: (filtered_point_ids).to_vec(),
+        })),
+    });
+
+    let search_points = SearchPoints {
+        collection_name: "debate_cards".to_string(),
+        vector: embedding_vector,
+        limit: 25,
+        offset: Some((page - 1) * 25),
+        with_payload: None,
+        filter: Some(filter),
+        ..Default::default()
+    };
+    let data = retry_qdrant_operation(
+        || qdrant.search_points(&search_points),
+        |_e| DefaultError {
+            message: "Failed to search points on Qdrant",
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let point_ids: Vec<SearchResult> = data
+        .result
+        .iter()
+        .filter_map(|point| match point.clone().id?.point_id_options? {
+            PointIdOptions::Uuid(id) => Some(SearchResult {
+                score: point.score,
+                point_id: uuid::Uuid::parse_str(&id).ok()?,
+            }),
+            PointIdOptions::Num(_) => None,
+        })
+        .collect();
+
+    Ok(SearchCardQueryResult {
+        search_results: point_ids,
+        total_cards: filtered_point_ids.len() as i64,
+    })
+}
+
+pub fn get_metadata(
+    card_metadata: Vec<FullTextSearchResult>,
+    current_user_id: Option<uuid::Uuid>,
+    mut conn: r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::PgConnection>>,
+) -> Result<Vec<CardMetadataWithVotesAndFiles>, DefaultError> {
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_verification::dsl as card_verification_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+    use crate::data::schema::files::dsl as files_columns;
+    use crate::data::schema::users::dsl as user_columns;
+
+    let card_creators: Vec<User> = user_columns::users
         .filter(
             user_columns::id.eq_any(
                 card_metadata
@@ -397,6 +1642,7 @@ pub fn get_metadata(
         .load::<User>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load card creators",
+            ..Default::default()
         })?;
 
     let card_votes: Vec<CardVote> = card_votes_columns::card_votes
@@ -411,6 +1657,7 @@ pub fn get_metadata(
         .load::<CardVote>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load upvotes",
+            ..Default::default()
         })?;
 
     let file_ids: Vec<CardFileWithName> = card_files_columns::card_files
@@ -433,6 +1680,7 @@ pub fn get_metadata(
         .load::<CardFileWithName>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let card_verifications: Vec<CardVerifications> = card_verification_columns::card_verification
@@ -448,24 +1696,18 @@ pub fn get_metadata(
         .load::<CardVerifications>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load verification metadata",
+            ..Default::default()
         })?;
 
     let card_metadata_with_upvotes_and_file_id: Vec<CardMetadataWithVotesAndFiles> = card_metadata
         .into_iter()
         .map(|metadata| {
-            let votes = card_votes
-                .iter()
-                .filter(|upvote| upvote.card_metadata_id == metadata.id)
-                .collect::<Vec<&CardVote>>();
-            let total_upvotes = votes.iter().filter(|upvote| upvote.vote).count() as i64;
-            let total_downvotes = votes.iter().filter(|upvote| !upvote.vote).count() as i64;
-            let vote_by_current_user = match current_user_id {
-                Some(user_id) => votes
+            let vote_tally = CardVoteTally::from_votes(
+                card_votes
                     .iter()
-                    .find(|upvote| upvote.voted_user_id == user_id)
-                    .map(|upvote| upvote.vote),
-                None => None,
-            };
+                    .filter(|card_vote| card_vote.card_metadata_id == metadata.id),
+                current_user_id,
+            );
 
             let author = card_creators
                 .iter()
@@ -497,12 +1739,16 @@ pub fn get_metadata(
                 oc_file_path: metadata.oc_file_path,
                 author,
                 qdrant_point_id: metadata.qdrant_point_id.unwrap_or(uuid::Uuid::nil()),
-                total_upvotes,
-                total_downvotes,
-                vote_by_current_user,
+                total_upvotes: vote_tally.up,
+                total_downvotes: vote_tally.down,
+                vote_by_current_user: vote_tally.my_vote,
                 created_at: metadata.created_at,
                 updated_at: metadata.updated_at,
                 private: metadata.private,
+                source_author: metadata.source_author,
+                source_title: metadata.source_title,
+                source_date: metadata.source_date,
+                source_url: metadata.source_url,
                 score: metadata.score,
                 card_html: metadata.card_html,
                 file_id: card_with_file_name.map(|file| file.file_id),
@@ -517,61 +1763,203 @@ pub fn get_metadata(
 #[derive(Serialize, Deserialize)]
 pub struct FullTextSearchCardQueryResult {
     pub search_results: Vec<CardMetadataWithVotesAndFiles>,
-    pub total_card_pages: i64,
+    pub total_cards: i64,
 }
 
-pub fn search_full_text_card_query(
+// Postgres text search configurations cards can be indexed and searched with
+pub const FULL_TEXT_SEARCH_LANGUAGES: [&str; 16] = [
+    "simple",
+    "danish",
+    "dutch",
+    "english",
+    "finnish",
+    "french",
+    "german",
+    "hungarian",
+    "italian",
+    "norwegian",
+    "portuguese",
+    "romanian",
+    "russian",
+    "spanish",
+    "swedish",
+    "turkish",
+];
+
+pub fn is_supported_full_text_search_language(language: &str) -> bool {
+    FULL_TEXT_SEARCH_LANGUAGES.contains(&language)
+}
+
+// Everything besides paging that narrows a full text search
+struct FullTextCardFilters {
     user_query: String,
-    page: u64,
-    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+    search_language: String,
     current_user_id: Option<uuid::Uuid>,
     filter_oc_file_path: Option<Vec<String>>,
     filter_link_url: Option<Vec<String>>,
-) -> Result<FullTextSearchCardQueryResult, DefaultError> {
-    let page = if page == 0 { 1 } else { page };
-    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
-    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    filter_author_id: Option<uuid::Uuid>,
+    citation_filter: CitationFilter,
+    exclusion_filter: ExclusionFilter,
+    language: Option<String>,
+}
+
+// Builds the boxed query full text search and its count share, so the cards they match can't
+// drift apart. $shape picks the columns of the joined query before it is boxed. A macro because
+// the type of the aliased join can't be written out in a function signature.
+macro_rules! full_text_cards_query {
+    ($second_join:ident, $filters:expr, |$query:ident| $shape:expr) => {{
+        use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+        use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+        let filters: FullTextCardFilters = $filters;
+
+        // SELECT
+        //     card_metadata.qdrant_point_id,
+        //     second_join.qdrant_point_id
+        // FROM
+        //     card_metadata
+        // LEFT OUTER JOIN card_collisions ON
+        //     card_metadata.id = card_collisions.card_id
+        //     AND card_metadata.private = false
+        // LEFT OUTER JOIN card_metadata AS second_join ON
+        //     second_join.qdrant_point_id = card_collisions.collision_qdrant_id
+        //     AND second_join.private = true
+        // WHERE
+        //     card_metadata.private = false
+        //     and card_metadata.approved = true
+        //     and (second_join.qdrant_point_id notnull or card_metadata.qdrant_point_id notnull);
+        let $query = card_metadata_columns::card_metadata
+            .left_outer_join(
+                card_collisions_columns::card_collisions.on(card_metadata_columns::id
+                    .eq(card_collisions_columns::card_id)
+                    .and(card_metadata_columns::private.eq(false))),
+            )
+            .left_outer_join(
+                $second_join.on($second_join
+                    .field(schema::card_metadata::qdrant_point_id)
+                    .eq(card_collisions_columns::collision_qdrant_id)
+                    .and($second_join.field(schema::card_metadata::private).eq(true))),
+            )
+            .filter(
+                card_metadata_columns::private
+                    .eq(false)
+                    .and(card_metadata_columns::approved.eq(true))
+                    .and(
+                        $second_join
+                            .field(schema::card_metadata::qdrant_point_id)
+                            .is_not_null()
+                            .or(card_metadata_columns::qdrant_point_id.is_not_null()),
+                    ),
+            )
+            .or_filter(
+                card_metadata_columns::author_id
+                    .eq(filters.current_user_id.unwrap_or(uuid::Uuid::nil())),
+            );
+        let mut query = $shape.into_boxed();
+
+        query = query.filter(
+            sql::<Bool>("card_metadata.card_metadata_tsvector @@ plainto_tsquery(")
+                .bind::<Text, _>(filters.search_language)
+                .sql("::regconfig, ")
+                .bind::<Text, _>(filters.user_query)
+                .sql(")"),
+        );
+
+        // cards indexed with another configuration won't have comparable lexemes
+        if let Some(language) = filters.language {
+            query = query.filter(card_metadata_columns::language.eq(language));
+        }
+
+        if let Some(author_id) = filters.filter_author_id {
+            query = query.filter(card_metadata_columns::author_id.eq(author_id));
+        }
+
+        if let Some(source_author) = filters.citation_filter.source_author {
+            query = query
+                .filter(card_metadata_columns::source_author.ilike(format!("%{}%", source_author)));
+        }
+        if let Some(source_date_from) = filters.citation_filter.source_date_from {
+            query = query.filter(card_metadata_columns::source_date.ge(source_date_from));
+        }
+        if let Some(source_date_to) = filters.citation_filter.source_date_to {
+            query = query.filter(card_metadata_columns::source_date.le(source_date_to));
+        }
+
+        let filter_oc_file_path = filters.filter_oc_file_path.unwrap_or([].to_vec());
+        let filter_link_url = filters.filter_link_url.unwrap_or([].to_vec());
+
+        // LIKE ANY keeps the alternatives grouped, or_filter would let them bypass the other
+        // filters
+        if !filter_oc_file_path.is_empty() {
+            query = query.filter(
+                sql::<Bool>("card_metadata.oc_file_path LIKE ANY(")
+                    .bind::<Array<Text>, _>(get_like_patterns(&filter_oc_file_path))
+                    .sql(")"),
+            );
+        }
+        if !filter_link_url.is_empty() {
+            query = query.filter(
+                sql::<Bool>("card_metadata.link LIKE ANY(")
+                    .bind::<Array<Text>, _>(get_like_patterns(&filter_link_url))
+                    .sql(")"),
+            );
+        }
+        // cards without the field are never excluded
+        if !filters.exclusion_filter.oc_file_path.is_empty() {
+            query = query.filter(
+                sql::<Bool>("NOT coalesce(card_metadata.oc_file_path LIKE ANY(")
+                    .bind::<Array<Text>, _>(get_like_patterns(
+                        &filters.exclusion_filter.oc_file_path,
+                    ))
+                    .sql("), false)"),
+            );
+        }
+        if !filters.exclusion_filter.link_url.is_empty() {
+            query = query.filter(
+                sql::<Bool>("NOT coalesce(card_metadata.link LIKE ANY(")
+                    .bind::<Array<Text>, _>(get_like_patterns(&filters.exclusion_filter.link_url))
+                    .sql("), false)"),
+            );
+        }
+
+        query
+    }};
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn search_full_text_card_query(
+    user_query: String,
+    page: u64,
+    page_size: u64,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+    current_user_id: Option<uuid::Uuid>,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    filter_author_id: Option<uuid::Uuid>,
+    citation_filter: CitationFilter,
+    exclusion_filter: ExclusionFilter,
+    language: Option<String>,
+) -> Result<FullTextSearchCardQueryResult, DefaultError> {
+    let page = if page == 0 { 1 } else { page };
+    let search_language = language.clone().unwrap_or("english".to_string());
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
     let second_join = diesel::alias!(schema::card_metadata as second_join);
 
-    let mut conn = pool.get().unwrap();
-    // SELECT
-    //     card_metadata.qdrant_point_id,
-    //     second_join.qdrant_point_id
-    // FROM
-    //     card_metadata
-    // LEFT OUTER JOIN card_collisions ON
-    //     card_metadata.id = card_collisions.card_id
-    //     AND card_metadata.private = false
-    // LEFT OUTER JOIN card_metadata AS second_join ON
-    //     second_join.qdrant_point_id = card_collisions.collision_qdrant_id
-    //     AND second_join.private = true
-    // WHERE
-    //     card_metadata.private = false
-    //     and (second_join.qdrant_point_id notnull or card_metadata.qdrant_point_id notnull);
-    let mut query = card_metadata_columns::card_metadata
-        .left_outer_join(
-            card_collisions_columns::card_collisions.on(card_metadata_columns::id
-                .eq(card_collisions_columns::card_id)
-                .and(card_metadata_columns::private.eq(false))),
-        )
-        .left_outer_join(
-            second_join.on(second_join
-                .field(schema::card_metadata::qdrant_point_id)
-                .eq(card_collisions_columns::collision_qdrant_id)
-                .and(second_join.field(schema::card_metadata::private).eq(true))),
-        )
-        .filter(
-            card_metadata_columns::private.eq(false).and(
-                second_join
-                    .field(schema::card_metadata::qdrant_point_id)
-                    .is_not_null()
-                    .or(card_metadata_columns::qdrant_point_id.is_not_null()),
-            ),
-        )
-        .or_filter(
-            card_metadata_columns::author_id.eq(current_user_id.unwrap_or(uuid::Uuid::nil())),
-        )
+    let mut conn = pool.get()?;
+
+    let filters = FullTextCardFilters {
+        user_query: user_query.clone(),
+        search_language: search_language.clone(),
+        current_user_id,
+        filter_oc_file_path,
+        filter_link_url,
+        filter_author_id,
+        citation_filter,
+        exclusion_filter,
+        language,
+    };
+    let mut query = full_text_cards_query!(second_join, filters, |query| query
         .select((
             (
                 card_metadata_columns::id,
@@ -584,10 +1972,16 @@ pub fn search_full_text_card_query(
                 card_metadata_columns::oc_file_path,
                 card_metadata_columns::card_html,
                 card_metadata_columns::private,
+                card_metadata_columns::source_author,
+                card_metadata_columns::source_title,
+                card_metadata_columns::source_date,
+                card_metadata_columns::source_url,
                 sql::<Nullable<Double>>(
-                    "(ts_rank(card_metadata.card_metadata_tsvector, plainto_tsquery('english', ",
+                    "(ts_rank(card_metadata.card_metadata_tsvector, plainto_tsquery(",
                 )
-                .bind::<Text, _>(user_query.clone())
+                .bind::<Text, _>(search_language)
+                .sql("::regconfig, ")
+                .bind::<Text, _>(user_query)
                 .sql(") , 32) * 10) AS rank"),
                 sql::<Int8>("count(*) OVER() AS full_count"),
             ),
@@ -600,38 +1994,7 @@ pub fn search_full_text_card_query(
             second_join
                 .field(schema::card_metadata::qdrant_point_id)
                 .nullable(),
-        ))
-        .into_boxed();
-
-    query = query.filter(
-        sql::<Bool>("card_metadata.card_metadata_tsvector @@ plainto_tsquery('english', ")
-            .bind::<Text, _>(user_query)
-            .sql(")"),
-    );
-
-    let filter_oc_file_path = filter_oc_file_path.unwrap_or([].to_vec());
-    let filter_link_url = filter_link_url.unwrap_or([].to_vec());
-
-    if !filter_oc_file_path.is_empty() {
-        query = query.filter(
-            card_metadata_columns::oc_file_path
-                .like(format!("%{}%", filter_oc_file_path.get(0).unwrap())),
-        );
-    }
-
-    for file_path in filter_oc_file_path.iter().skip(1) {
-        query =
-            query.or_filter(card_metadata_columns::oc_file_path.like(format!("%{}%", file_path)));
-    }
-
-    if !filter_link_url.is_empty() {
-        query = query.filter(
-            card_metadata_columns::link.like(format!("%{}%", filter_link_url.get(0).unwrap())),
-        );
-    }
-    for link_url in filter_link_url.iter().skip(1) {
-        query = query.or_filter(card_metadata_columns::link.like(format!("%{}%", link_url)));
-    }
+        )));
 
     query = query.order((
         card_metadata_columns::qdrant_point_id,
@@ -640,12 +2003,13 @@ pub fn search_full_text_card_query(
     ));
 
     query = query
-        .limit(25)
-        .offset(((page - 1) * 25).try_into().unwrap());
+        .limit(page_size.try_into().unwrap())
+        .offset(((page - 1) * page_size).try_into().unwrap());
 
     let searched_cards: Vec<(FullTextSearchResult, Option<uuid::Uuid>)> =
         query.load(&mut conn).map_err(|_| DefaultError {
             message: "Failed to load trigram searched cards",
+            ..Default::default()
         })?;
 
     let card_metadata_with_upvotes_and_files = get_metadata(
@@ -658,27 +2022,69 @@ pub fn search_full_text_card_query(
     )
     .map_err(|_| DefaultError {
         message: "Failed to load searched cards",
+        ..Default::default()
     })?;
 
-    let total_count = if searched_cards.is_empty() {
-        0
-    } else {
-        (searched_cards.get(0).unwrap().0.count as f64 / 25.0).ceil() as i64
-    };
+    let total_count = searched_cards
+        .get(0)
+        .map(|searched_card| searched_card.0.count)
+        .unwrap_or(0);
 
     Ok(FullTextSearchCardQueryResult {
         search_results: card_metadata_with_upvotes_and_files,
-        total_card_pages: total_count,
+        total_cards: total_count,
+    })
+}
+
+// Same predicate as search_full_text_card_query, counting the distinct results it would page
+// through without ranking or loading them
+#[allow(clippy::too_many_arguments)]
+pub fn count_full_text_cards_query(
+    user_query: String,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+    current_user_id: Option<uuid::Uuid>,
+    filter_oc_file_path: Option<Vec<String>>,
+    filter_link_url: Option<Vec<String>>,
+    filter_author_id: Option<uuid::Uuid>,
+    citation_filter: CitationFilter,
+    exclusion_filter: ExclusionFilter,
+    language: Option<String>,
+) -> Result<i64, DefaultError> {
+    let search_language = language.clone().unwrap_or("english".to_string());
+
+    let second_join = diesel::alias!(schema::card_metadata as second_join);
+
+    let mut conn = pool.get()?;
+
+    let filters = FullTextCardFilters {
+        user_query,
+        search_language,
+        current_user_id,
+        filter_oc_file_path,
+        filter_link_url,
+        filter_author_id,
+        citation_filter,
+        exclusion_filter,
+        language,
+    };
+    let query = full_text_cards_query!(second_join, filters, |query| query.select(sql::<Int8>(
+        "count(DISTINCT (card_metadata.qdrant_point_id, second_join.qdrant_point_id))",
+    )));
+
+    query.first::<i64>(&mut conn).map_err(|_| DefaultError {
+        message: "Failed to count full text searched cards",
+        ..Default::default()
     })
 }
 
 pub fn global_top_full_text_card_query(
     user_query: String,
+    language: String,
     pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
 ) -> Result<Option<CardMetadataWithVotesAndFiles>, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let mut query = card_metadata_columns::card_metadata
         .filter(card_metadata_columns::private.eq(false))
@@ -698,7 +2104,13 @@ pub fn global_top_full_text_card_query(
             card_metadata_columns::oc_file_path,
             card_metadata_columns::card_html,
             card_metadata_columns::private,
-            sql::<Nullable<Double>>("(ts_rank(card_metadata_tsvector, plainto_tsquery('english', ")
+            card_metadata_columns::source_author,
+            card_metadata_columns::source_title,
+            card_metadata_columns::source_date,
+            card_metadata_columns::source_url,
+            sql::<Nullable<Double>>("(ts_rank(card_metadata_tsvector, plainto_tsquery(")
+                .bind::<Text, _>(language.clone())
+                .sql("::regconfig, ")
                 .bind::<Text, _>(user_query.clone())
                 .sql(") , 32) * 10) AS rank"),
             sql::<Int8>("count(*) OVER() AS full_count"),
@@ -707,11 +2119,15 @@ pub fn global_top_full_text_card_query(
         .into_boxed();
 
     query = query.filter(
-        sql::<Bool>("card_metadata.card_metadata_tsvector @@ plainto_tsquery('english', ")
+        sql::<Bool>("card_metadata.card_metadata_tsvector @@ plainto_tsquery(")
+            .bind::<Text, _>(language.clone())
+            .sql("::regconfig, ")
             .bind::<Text, _>(user_query)
             .sql(")"),
     );
 
+    query = query.filter(card_metadata_columns::language.eq(language));
+
     query = query.order((sql::<Text>("rank DESC"),));
 
     let searched_card: FullTextSearchResult = match query.first(&mut conn) {
@@ -722,6 +2138,7 @@ pub fn global_top_full_text_card_query(
             },
             _ => Err(DefaultError {
                 message: "Failed to load top trigram searched card",
+                ..Default::default()
             })
         }
     }?;
@@ -729,6 +2146,7 @@ pub fn global_top_full_text_card_query(
     let card_metadata_with_upvotes_and_files = get_metadata(vec![searched_card], None, conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata for top trigram searched card",
+            ..Default::default()
         })?;
 
     Ok(card_metadata_with_upvotes_and_files.get(0).cloned())
@@ -747,7 +2165,7 @@ pub fn get_metadata_from_point_ids(
 ) -> Result<Vec<CardMetadataWithVotesAndFiles>, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let card_metadata: Vec<CardMetadata> = card_metadata_columns::card_metadata
         .filter(card_metadata_columns::qdrant_point_id.eq_any(&point_ids))
@@ -762,10 +2180,17 @@ pub fn get_metadata_from_point_ids(
             card_metadata_columns::oc_file_path,
             card_metadata_columns::card_html,
             card_metadata_columns::private,
+            card_metadata_columns::source_author,
+            card_metadata_columns::source_title,
+            card_metadata_columns::source_date,
+            card_metadata_columns::source_url,
+            card_metadata_columns::approved,
+            card_metadata_columns::language,
         ))
         .load::<CardMetadata>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let converted_cards: Vec<FullTextSearchResult> = card_metadata
@@ -776,6 +2201,7 @@ pub fn get_metadata_from_point_ids(
     let card_metadata_with_upvotes_and_file_id =
         get_metadata(converted_cards, current_user_id, conn).map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     //combine card_metadata_with vote with the file_ids that was loaded
@@ -783,6 +2209,49 @@ pub fn get_metadata_from_point_ids(
     Ok(card_metadata_with_upvotes_and_file_id)
 }
 
+// Cards come back in the order the ids were asked for so citation numbering holds, ids that are
+// missing or not visible to the user are left out
+pub fn get_metadata_from_ids_query(
+    card_ids: Vec<uuid::Uuid>,
+    current_user_id: Option<uuid::Uuid>,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<CardMetadataWithVotesAndFiles>, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let card_metadata: Vec<CardMetadata> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::id.eq_any(&card_ids))
+        // same visibility as search, cards waiting for approval are only shown to their author
+        .filter(
+            card_metadata_columns::private
+                .eq(false)
+                .and(card_metadata_columns::approved.eq(true))
+                .or(card_metadata_columns::author_id
+                    .eq(current_user_id.unwrap_or(uuid::Uuid::nil()))),
+        )
+        .select(CardMetadata::as_select())
+        .load::<CardMetadata>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+
+    let mut cards_by_id: HashMap<uuid::Uuid, CardMetadataWithVotesAndFiles> = get_metadata(
+        card_metadata.into_iter().map(|card| card.into()).collect(),
+        current_user_id,
+        conn,
+    )?
+    .into_iter()
+    .map(|card| (card.id, card))
+    .collect();
+
+    Ok(card_ids
+        .iter()
+        .filter_map(|card_id| cards_by_id.remove(card_id))
+        .collect())
+}
+
 pub fn get_collided_cards_query(
     point_ids: Vec<uuid::Uuid>,
     current_user_id: Option<uuid::Uuid>,
@@ -791,7 +2260,7 @@ pub fn get_collided_cards_query(
     use crate::data::schema::card_collisions::dsl as card_collisions_columns;
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let card_metadata: Vec<(CardMetadata, uuid::Uuid)> = card_collisions_columns::card_collisions
         .filter(card_collisions_columns::collision_qdrant_id.eq_any(point_ids))
@@ -811,6 +2280,12 @@ pub fn get_collided_cards_query(
                 card_metadata_columns::oc_file_path,
                 card_metadata_columns::card_html,
                 card_metadata_columns::private,
+                card_metadata_columns::source_author,
+                card_metadata_columns::source_title,
+                card_metadata_columns::source_date,
+                card_metadata_columns::source_url,
+                card_metadata_columns::approved,
+                card_metadata_columns::language,
             ),
             (card_collisions_columns::collision_qdrant_id.assume_not_null()),
         ))
@@ -821,6 +2296,7 @@ pub fn get_collided_cards_query(
         .load::<(CardMetadata, uuid::Uuid)>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let collided_qdrant_ids = card_metadata
@@ -836,6 +2312,7 @@ pub fn get_collided_cards_query(
     let card_metadata_with_upvotes_and_file_id =
         get_metadata(converted_cards, current_user_id, conn).map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let card_metadatas_with_collided_qdrant_ids = card_metadata_with_upvotes_and_file_id
@@ -848,13 +2325,52 @@ pub fn get_collided_cards_query(
 
     Ok(card_metadatas_with_collided_qdrant_ids)
 }
+// Cards that collided with an existing card have no point of their own, so they share the
+// point of the card they collided with
+pub fn get_qdrant_point_id_from_card_id_query(
+    card_id: uuid::Uuid,
+    pool: web::Data<Pool>,
+) -> Result<uuid::Uuid, DefaultError> {
+    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let qdrant_point_id: Option<uuid::Uuid> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::id.eq(card_id))
+        .select(card_metadata_columns::qdrant_point_id)
+        .first(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+    if let Some(qdrant_point_id) = qdrant_point_id {
+        return Ok(qdrant_point_id);
+    }
+
+    card_collisions_columns::card_collisions
+        .filter(card_collisions_columns::card_id.eq(card_id))
+        .select(card_collisions_columns::collision_qdrant_id)
+        .first::<Option<uuid::Uuid>>(&mut conn)
+        .optional()
+        .map_err(|_| DefaultError {
+            message: "Failed to load card collision",
+            ..Default::default()
+        })?
+        .flatten()
+        .ok_or(DefaultError {
+            message: "Card has no vector on Qdrant",
+            ..Default::default()
+        })
+}
+
 pub fn get_metadata_from_id_query(
     card_id: uuid::Uuid,
     pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
 ) -> Result<CardMetadata, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     card_metadata_columns::card_metadata
         .filter(card_metadata_columns::id.eq(card_id))
@@ -869,10 +2385,17 @@ pub fn get_metadata_from_id_query(
             card_metadata_columns::oc_file_path,
             card_metadata_columns::card_html,
             card_metadata_columns::private,
+            card_metadata_columns::source_author,
+            card_metadata_columns::source_title,
+            card_metadata_columns::source_date,
+            card_metadata_columns::source_url,
+            card_metadata_columns::approved,
+            card_metadata_columns::language,
         ))
         .first::<CardMetadata>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })
 }
 
@@ -883,7 +2406,7 @@ pub fn get_metadata_and_votes_from_id_query(
 ) -> Result<CardMetadataWithVotesAndFiles, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let card_metadata = card_metadata_columns::card_metadata
         .filter(card_metadata_columns::id.eq(card_id))
@@ -898,10 +2421,17 @@ pub fn get_metadata_and_votes_from_id_query(
             card_metadata_columns::oc_file_path,
             card_metadata_columns::card_html,
             card_metadata_columns::private,
+            card_metadata_columns::source_author,
+            card_metadata_columns::source_title,
+            card_metadata_columns::source_date,
+            card_metadata_columns::source_url,
+            card_metadata_columns::approved,
+            card_metadata_columns::language,
         ))
         .first::<CardMetadata>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
     let converted_card: FullTextSearchResult =
         <CardMetadata as Into<FullTextSearchResult>>::into(card_metadata);
@@ -909,6 +2439,7 @@ pub fn get_metadata_and_votes_from_id_query(
     let card_metadata_with_upvotes_and_file_id =
         get_metadata(vec![converted_card], current_user_id, conn).map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
     Ok(card_metadata_with_upvotes_and_file_id
         .first()
@@ -916,290 +2447,934 @@ pub fn get_metadata_and_votes_from_id_query(
         .clone())
 }
 
-pub fn insert_card_metadata_query(
-    card_data: CardMetadata,
-    file_uuid: Option<uuid::Uuid>,
-    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
-) -> Result<CardMetadata, DefaultError> {
-    use crate::data::schema::card_files::dsl as card_files_columns;
-    use crate::data::schema::card_metadata::dsl::*;
+pub fn insert_card_metadata_query(
+    card_data: CardMetadata,
+    file_uuid: Option<uuid::Uuid>,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<CardMetadata, DefaultError> {
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(card_metadata)
+            .values(&card_data)
+            .execute(conn)?;
+
+        if file_uuid.is_some() {
+            diesel::insert_into(card_files_columns::card_files)
+                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    });
+
+    match transaction_result {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(DefaultError {
+                message: "Failed to insert card metadata",
+                ..Default::default()
+            })
+        }
+    };
+    Ok(card_data)
+}
+
+pub enum CardIdempotencyKeyReservation {
+    Reserved,
+    InProgress,
+    Completed(CardMetadata, bool),
+}
+
+// The unique constraint on (user_id, idempotency_key) decides which of several concurrent
+// requests gets to create the card, the others see the reservation or its finished card
+pub fn reserve_card_idempotency_key_query(
+    card_idempotency_key: CardIdempotencyKey,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<CardIdempotencyKeyReservation, DefaultError> {
+    use crate::data::schema::card_idempotency_keys::dsl as card_idempotency_keys_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let ttl_secs: i64 = std::env::var("CARD_IDEMPOTENCY_KEY_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(24 * 60 * 60);
+    let valid_since = chrono::Local::now().naive_local() - chrono::Duration::seconds(ttl_secs);
+
+    // an expired key may still be stored and would conflict with its reuse
+    diesel::delete(
+        card_idempotency_keys_columns::card_idempotency_keys
+            .filter(card_idempotency_keys_columns::user_id.eq(card_idempotency_key.user_id))
+            .filter(
+                card_idempotency_keys_columns::idempotency_key
+                    .eq(&card_idempotency_key.idempotency_key),
+            )
+            .filter(card_idempotency_keys_columns::created_at.lt(valid_since)),
+    )
+    .execute(&mut conn)
+    .map_err(|_| DefaultError {
+        message: "Failed to store idempotency key",
+        ..Default::default()
+    })?;
+
+    match diesel::insert_into(card_idempotency_keys_columns::card_idempotency_keys)
+        .values(&card_idempotency_key)
+        .execute(&mut conn)
+    {
+        Ok(_) => return Ok(CardIdempotencyKeyReservation::Reserved),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => {}
+        Err(_) => {
+            return Err(DefaultError {
+                message: "Failed to store idempotency key",
+                ..Default::default()
+            })
+        }
+    }
+
+    let (existing_card_id, duplicate) = card_idempotency_keys_columns::card_idempotency_keys
+        .filter(card_idempotency_keys_columns::user_id.eq(card_idempotency_key.user_id))
+        .filter(
+            card_idempotency_keys_columns::idempotency_key
+                .eq(&card_idempotency_key.idempotency_key),
+        )
+        .select((
+            card_idempotency_keys_columns::card_metadata_id,
+            card_idempotency_keys_columns::duplicate,
+        ))
+        .first::<(Option<uuid::Uuid>, bool)>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load card for idempotency key",
+            ..Default::default()
+        })?;
+
+    let existing_card_id = match existing_card_id {
+        Some(existing_card_id) => existing_card_id,
+        None => return Ok(CardIdempotencyKeyReservation::InProgress),
+    };
+
+    card_metadata_columns::card_metadata
+        .find(existing_card_id)
+        .select(CardMetadata::as_select())
+        .first::<CardMetadata>(&mut conn)
+        .map(|card_metadata| CardIdempotencyKeyReservation::Completed(card_metadata, duplicate))
+        .map_err(|_| DefaultError {
+            message: "Failed to load card for idempotency key",
+            ..Default::default()
+        })
+}
+
+pub fn complete_card_idempotency_key_query(
+    user_id: uuid::Uuid,
+    idempotency_key: String,
+    card_metadata_id: uuid::Uuid,
+    duplicate: bool,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::card_idempotency_keys::dsl as card_idempotency_keys_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::update(
+        card_idempotency_keys_columns::card_idempotency_keys
+            .filter(card_idempotency_keys_columns::user_id.eq(user_id))
+            .filter(card_idempotency_keys_columns::idempotency_key.eq(idempotency_key)),
+    )
+    .set((
+        card_idempotency_keys_columns::card_metadata_id.eq(card_metadata_id),
+        card_idempotency_keys_columns::duplicate.eq(duplicate),
+    ))
+    .execute(&mut conn)
+    .map_err(|_| DefaultError {
+        message: "Failed to store idempotency key",
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+// Frees a reservation whose card could not be created so the request can be retried
+pub fn release_card_idempotency_key_query(
+    user_id: uuid::Uuid,
+    idempotency_key: String,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::card_idempotency_keys::dsl as card_idempotency_keys_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::delete(
+        card_idempotency_keys_columns::card_idempotency_keys
+            .filter(card_idempotency_keys_columns::user_id.eq(user_id))
+            .filter(card_idempotency_keys_columns::idempotency_key.eq(idempotency_key))
+            .filter(card_idempotency_keys_columns::card_metadata_id.is_null()),
+    )
+    .execute(&mut conn)
+    .map_err(|_| DefaultError {
+        message: "Failed to release idempotency key",
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+pub fn insert_duplicate_card_metadata_query(
+    card_data: CardMetadata,
+    duplicate_card: uuid::Uuid,
+    file_uuid: Option<uuid::Uuid>,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<CardMetadata, DefaultError> {
+    use crate::data::schema::card_collisions::dsl::*;
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(card_metadata)
+            .values(&card_data)
+            .execute(conn)?;
+
+        //insert duplicate into card_collisions
+        diesel::insert_into(card_collisions)
+            .values(&CardCollisions::from_details(card_data.id, duplicate_card))
+            .execute(conn)?;
+
+        if file_uuid.is_some() {
+            diesel::insert_into(card_files_columns::card_files)
+                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    });
+
+    match transaction_result {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(DefaultError {
+                message: "Failed to insert card metadata",
+                ..Default::default()
+            })
+        }
+    };
+    Ok(card_data)
+}
+
+// Swaps in new content for an existing card, the tsvector follows through its trigger. Visibility
+// and citation are left as they were.
+#[allow(clippy::too_many_arguments)]
+pub fn replace_card_content_query(
+    card_id: uuid::Uuid,
+    content: String,
+    card_html: Option<String>,
+    link: Option<String>,
+    oc_file_path: Option<String>,
+    language: String,
+    file_uuid: Option<uuid::Uuid>,
+    pool: &web::Data<Pool>,
+) -> Result<CardMetadata, DefaultError> {
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let card = diesel::update(card_metadata_columns::card_metadata.find(card_id))
+            .set((
+                card_metadata_columns::content.eq(content),
+                card_metadata_columns::card_html.eq(card_html),
+                card_metadata_columns::link.eq(link),
+                card_metadata_columns::oc_file_path.eq(oc_file_path),
+                card_metadata_columns::language.eq(language),
+            ))
+            .returning(CardMetadata::as_select())
+            .get_result::<CardMetadata>(conn)?;
+
+        if let Some(file_uuid) = file_uuid {
+            diesel::insert_into(card_files_columns::card_files)
+                .values(&CardFile::from_details(card_id, file_uuid))
+                .execute(conn)?;
+        }
+
+        Ok(card)
+    })
+    .map_err(|_| DefaultError {
+        message: "Failed to update card content",
+        ..Default::default()
+    })
+}
+
+pub fn update_card_metadata_query(
+    card_data: CardMetadata,
+    file_uuid: Option<uuid::Uuid>,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
+    let mut conn = pool.get()?;
+
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(
+            card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_data.id)),
+        )
+        .set((
+            card_metadata_columns::link.eq(card_data.link),
+            card_metadata_columns::card_html.eq(card_data.card_html),
+            card_metadata_columns::private.eq(card_data.private),
+        ))
+        .execute(conn)?;
+
+        diesel::update(
+            card_votes_columns::card_votes
+                .filter(card_votes_columns::card_metadata_id.eq(card_data.id)),
+        )
+        .set(card_votes_columns::deleted.eq(card_data.private))
+        .execute(conn)?;
+
+        if file_uuid.is_some() {
+            diesel::insert_into(card_files_columns::card_files)
+                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
+                .execute(conn)?;
+        }
+        Ok(())
+    });
+
+    match transaction_result {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(DefaultError {
+                message: "Failed to update card metadata",
+                ..Default::default()
+            })
+        }
+    };
+
+    Ok(())
+}
+
+// Only the link and citation change, so the card keeps its embedding
+pub fn update_card_source_query(
+    card_id: uuid::Uuid,
+    link: Option<String>,
+    citation: CardCitation,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::update(
+        card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_id)),
+    )
+    .set((
+        card_metadata_columns::link.eq(link),
+        card_metadata_columns::source_author.eq(citation.source_author),
+        card_metadata_columns::source_title.eq(citation.source_title),
+        card_metadata_columns::source_date.eq(citation.source_date),
+        card_metadata_columns::source_url.eq(citation.source_url),
+        card_metadata_columns::updated_at.eq(chrono::Local::now().naive_local()),
+    ))
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to update card source",
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+// Returns the card with its new author and the author it was transferred from
+pub fn transfer_card_ownership_query(
+    card_id: uuid::Uuid,
+    new_author_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<(CardMetadata, uuid::Uuid), DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::users::dsl as users_columns;
+
+    let mut conn = pool.get()?;
+
+    let new_author_exists = diesel::select(diesel::dsl::exists(
+        users_columns::users.filter(users_columns::id.eq(new_author_id)),
+    ))
+    .get_result::<bool>(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to load user",
+        ..Default::default()
+    })?;
+    if !new_author_exists {
+        return Err(DefaultError {
+            message: "New author does not exist",
+            ..Default::default()
+        });
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let previous_author_id = card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq(card_id))
+            .select(card_metadata_columns::author_id)
+            .for_update()
+            .first::<uuid::Uuid>(conn)?;
+
+        diesel::update(
+            card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_id)),
+        )
+        .set((
+            card_metadata_columns::author_id.eq(new_author_id),
+            card_metadata_columns::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+
+        let card = card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq(card_id))
+            .select(CardMetadata::as_select())
+            .first::<CardMetadata>(conn)?;
+
+        Ok((card, previous_author_id))
+    })
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to transfer card",
+        ..Default::default()
+    })
+}
+
+// Folds merge_id into keep_id, votes and bookmarks move over unless the keeper already has one
+// from the same user or collection. Returns merge_id's point if no duplicate took it over.
+pub fn merge_cards_query(
+    keep_id: uuid::Uuid,
+    merge_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Option<uuid::Uuid>, DefaultError> {
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
+    if keep_id == merge_id {
+        return Err(DefaultError {
+            message: "Cannot merge a card into itself",
+            ..Default::default()
+        });
+    }
+
+    let mut conn = pool.get()?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let cards: Vec<(uuid::Uuid, Option<uuid::Uuid>)> = card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq_any(vec![keep_id, merge_id]))
+            .select((
+                card_metadata_columns::id,
+                card_metadata_columns::qdrant_point_id,
+            ))
+            .for_update()
+            .load(conn)?;
+        if cards.len() != 2 {
+            return Err(NotFound);
+        }
+        let merge_qdrant_point_id = cards
+            .iter()
+            .find(|(card_id, _)| *card_id == merge_id)
+            .and_then(|(_, qdrant_point_id)| *qdrant_point_id);
+
+        let keeper_voter_ids: Vec<uuid::Uuid> = card_votes_columns::card_votes
+            .filter(card_votes_columns::card_metadata_id.eq(keep_id))
+            .select(card_votes_columns::voted_user_id)
+            .load(conn)?;
+        diesel::delete(
+            card_votes_columns::card_votes
+                .filter(card_votes_columns::card_metadata_id.eq(merge_id))
+                .filter(card_votes_columns::voted_user_id.eq_any(&keeper_voter_ids)),
+        )
+        .execute(conn)?;
+        diesel::update(
+            card_votes_columns::card_votes
+                .filter(card_votes_columns::card_metadata_id.eq(merge_id)),
+        )
+        .set(card_votes_columns::card_metadata_id.eq(keep_id))
+        .execute(conn)?;
+
+        let keeper_collection_ids: Vec<uuid::Uuid> =
+            card_collection_bookmarks_columns::card_collection_bookmarks
+                .filter(card_collection_bookmarks_columns::card_metadata_id.eq(keep_id))
+                .select(card_collection_bookmarks_columns::collection_id)
+                .load(conn)?;
+        diesel::update(
+            card_collection_bookmarks_columns::card_collection_bookmarks
+                .filter(card_collection_bookmarks_columns::card_metadata_id.eq(merge_id))
+                .filter(
+                    card_collection_bookmarks_columns::collection_id.ne_all(&keeper_collection_ids),
+                ),
+        )
+        .set(card_collection_bookmarks_columns::card_metadata_id.eq(keep_id))
+        .execute(conn)?;
+
+        // the bookmarks left behind are in collections the keeper is already in
+        match delete_card_metadata_in_transaction(merge_id, conn)? {
+            TransactionResult::CardCollisionNotDetected => Ok(merge_qdrant_point_id),
+            TransactionResult::CardCollisionDetected => Ok(None),
+        }
+    })
+    .map_err(|db_error| match db_error {
+        NotFound => DefaultError {
+            message: "Card not found",
+            ..Default::default()
+        },
+        _ => DefaultError {
+            message: "Failed to merge cards",
+            ..Default::default()
+        },
+    })
+}
+
+enum TransactionResult {
+    CardCollisionDetected,
+    CardCollisionNotDetected,
+}
+
+// Removes a card's rows, handing its qdrant point to the oldest collision if it has any
+fn delete_card_metadata_in_transaction(
+    card_uuid: uuid::Uuid,
+    conn: &mut diesel::PgConnection,
+) -> Result<TransactionResult, diesel::result::Error> {
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    diesel::delete(
+        card_files_columns::card_files.filter(card_files_columns::card_id.eq(card_uuid)),
+    )
+    .execute(conn)?;
+
+    diesel::delete(
+        card_collection_bookmarks_columns::card_collection_bookmarks
+            .filter(card_collection_bookmarks_columns::card_metadata_id.eq(card_uuid)),
+    )
+    .execute(conn)?;
+
+    let deleted_card_collision_count = diesel::delete(
+        card_collisions_columns::card_collisions
+            .filter(card_collisions_columns::card_id.eq(card_uuid)),
+    )
+    .execute(conn)?;
+
+    if deleted_card_collision_count > 0 {
+        // there cannot be collisions for a collision, just delete the card_metadata without issue
+        diesel::delete(
+            card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_uuid)),
+        )
+        .execute(conn)?;
+
+        return Ok(TransactionResult::CardCollisionNotDetected);
+    }
+
+    let card_collisions: Vec<(CardCollisions, bool)> = card_collisions_columns::card_collisions
+        .inner_join(card_metadata_columns::card_metadata.on(
+            card_metadata_columns::qdrant_point_id.eq(card_collisions_columns::collision_qdrant_id),
+        ))
+        .filter(card_metadata_columns::id.eq(card_uuid))
+        .select((CardCollisions::as_select(), card_metadata_columns::private))
+        .order_by(card_collisions_columns::created_at.asc())
+        .load::<(CardCollisions, bool)>(conn)?;
+
+    if !card_collisions.is_empty() {
+        // get the first collision that is public or the first collision if all are private
+        let latest_collision = match card_collisions.iter().find(|x| !x.1) {
+            Some(x) => x.0.clone(),
+            None => card_collisions[0].0.clone(),
+        };
+
+        // update all collisions except latest_collision to point to a qdrant_id of None
+        diesel::update(
+            card_collisions_columns::card_collisions.filter(
+                card_collisions_columns::id.eq_any(
+                    card_collisions
+                        .iter()
+                        .filter(|x| x.0.id != latest_collision.id)
+                        .map(|x| x.0.id)
+                        .collect::<Vec<uuid::Uuid>>(),
+                ),
+            ),
+        )
+        .set(card_collisions_columns::collision_qdrant_id.eq::<Option<uuid::Uuid>>(None))
+        .execute(conn)?;
+
+        // delete latest_collision from card_collisions
+        diesel::delete(
+            card_collisions_columns::card_collisions
+                .filter(card_collisions_columns::id.eq(latest_collision.id)),
+        )
+        .execute(conn)?;
+
+        // delete the original card_metadata
+        diesel::delete(
+            card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_uuid)),
+        )
+        .execute(conn)?;
+
+        // set the card_metadata of latest_collision to have the qdrant_point_id of the original card_metadata
+        diesel::update(
+            card_metadata_columns::card_metadata
+                .filter(card_metadata_columns::id.eq(latest_collision.card_id)),
+        )
+        .set((card_metadata_columns::qdrant_point_id.eq(latest_collision.collision_qdrant_id),))
+        .execute(conn)?;
+
+        // set the collision_qdrant_id of all other collisions to be the same as they were to begin with
+        diesel::update(
+            card_collisions_columns::card_collisions.filter(
+                card_collisions_columns::id.eq_any(
+                    card_collisions
+                        .iter()
+                        .skip(1)
+                        .map(|x| x.0.id)
+                        .collect::<Vec<uuid::Uuid>>(),
+                ),
+            ),
+        )
+        .set((
+            card_collisions_columns::collision_qdrant_id.eq(latest_collision.collision_qdrant_id),
+        ))
+        .execute(conn)?;
+
+        return Ok(TransactionResult::CardCollisionDetected);
+    }
 
-    let mut conn = pool.get().unwrap();
+    // if there were no collisions, just delete the card_metadata without issue
+    diesel::delete(
+        card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_uuid)),
+    )
+    .execute(conn)?;
 
-    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        diesel::insert_into(card_metadata)
-            .values(&card_data)
-            .execute(conn)?;
+    Ok(TransactionResult::CardCollisionNotDetected)
+}
 
-        if file_uuid.is_some() {
-            diesel::insert_into(card_files_columns::card_files)
-                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
-                .execute(conn)?;
-        }
+pub async fn delete_card_metadata_query(
+    card_uuid: uuid::Uuid,
+    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
+) -> Result<(), DefaultError> {
+    let mut conn = pool.lock().unwrap().get()?;
 
-        Ok(())
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        delete_card_metadata_in_transaction(card_uuid, conn)
     });
 
     match transaction_result {
-        Ok(_) => (),
+        Ok(result) => {
+            if let TransactionResult::CardCollisionNotDetected = result {
+                let qdrant = get_qdrant_connection().await?;
+                let deleted_points =
+                    vec![<String as Into<PointId>>::into(card_uuid.to_string())].into();
+                let _ = retry_qdrant_operation(
+                    || qdrant.delete_points("debate_cards", &deleted_points, None),
+                    |_e| DefaultError {
+                        message: "Failed to delete card from qdrant",
+                        ..Default::default()
+                    },
+                )
+                .await;
+            }
+        }
+
         Err(_) => {
             return Err(DefaultError {
-                message: "Failed to insert card metadata",
+                message: "Failed to delete card data",
+                ..Default::default()
             })
         }
     };
-    Ok(card_data)
+
+    Ok(())
 }
 
-pub fn insert_duplicate_card_metadata_query(
-    card_data: CardMetadata,
-    duplicate_card: uuid::Uuid,
-    file_uuid: Option<uuid::Uuid>,
-    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
-) -> Result<CardMetadata, DefaultError> {
-    use crate::data::schema::card_collisions::dsl::*;
-    use crate::data::schema::card_files::dsl as card_files_columns;
-    use crate::data::schema::card_metadata::dsl::*;
+pub struct DeletedCards {
+    pub deleted_count: usize,
+    pub orphaned_qdrant_point_ids: Vec<uuid::Uuid>,
+}
 
-    let mut conn = pool.get().unwrap();
+// Deletes the cards among card_ids that author_id wrote in one transaction, the caller removes the orphaned points
+pub fn delete_owned_cards_metadata_query(
+    card_ids: Vec<uuid::Uuid>,
+    author_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<DeletedCards, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        diesel::insert_into(card_metadata)
-            .values(&card_data)
-            .execute(conn)?;
+    let mut conn = pool.get()?;
 
-        //insert duplicate into card_collisions
-        diesel::insert_into(card_collisions)
-            .values(&CardCollisions::from_details(card_data.id, duplicate_card))
-            .execute(conn)?;
+    let owned_card_ids: Vec<uuid::Uuid> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::id.eq_any(&card_ids))
+        .filter(card_metadata_columns::author_id.eq(author_id))
+        .select(card_metadata_columns::id)
+        .load::<uuid::Uuid>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to load cards",
+            ..Default::default()
+        })?;
 
-        if file_uuid.is_some() {
-            diesel::insert_into(card_files_columns::card_files)
-                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
-                .execute(conn)?;
-        }
+    let orphaned_qdrant_point_ids = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let mut orphaned_qdrant_point_ids = vec![];
+            for card_id in owned_card_ids.iter() {
+                // read the point inside the loop since an earlier delete may have handed it to this card
+                let qdrant_point_id: Option<uuid::Uuid> = card_metadata_columns::card_metadata
+                    .filter(card_metadata_columns::id.eq(card_id))
+                    .select(card_metadata_columns::qdrant_point_id)
+                    .first(conn)?;
+
+                if let TransactionResult::CardCollisionNotDetected =
+                    delete_card_metadata_in_transaction(*card_id, conn)?
+                {
+                    orphaned_qdrant_point_ids.extend(qdrant_point_id);
+                }
+            }
+            Ok(orphaned_qdrant_point_ids)
+        })
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to delete card data",
+            ..Default::default()
+        })?;
 
-        Ok(())
-    });
+    Ok(DeletedCards {
+        deleted_count: owned_card_ids.len(),
+        orphaned_qdrant_point_ids,
+    })
+}
 
-    match transaction_result {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(DefaultError {
-                message: "Failed to insert card metadata",
-            })
-        }
+#[derive(Serialize, Deserialize)]
+pub struct CardFeedPage {
+    pub cards: Vec<CardMetadataWithVotesAndFiles>,
+    pub next_cursor: Option<String>,
+}
+
+// The cursor is the created_at and id of the last card on the page, opaque to clients
+pub fn encode_card_feed_cursor(created_at: chrono::NaiveDateTime, card_id: uuid::Uuid) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(format!(
+        "{}|{}",
+        created_at.format("%Y-%m-%dT%H:%M:%S%.f"),
+        card_id
+    ))
+}
+
+pub fn decode_card_feed_cursor(
+    cursor: &str,
+) -> Result<(chrono::NaiveDateTime, uuid::Uuid), DefaultError> {
+    let invalid_cursor = || DefaultError {
+        message: "Invalid feed cursor",
+        ..Default::default()
     };
-    Ok(card_data)
+
+    let decoded_cursor = general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|decoded_cursor| String::from_utf8(decoded_cursor).ok())
+        .ok_or_else(invalid_cursor)?;
+    let (created_at, card_id) = decoded_cursor.split_once('|').ok_or_else(invalid_cursor)?;
+
+    Ok((
+        chrono::NaiveDateTime::parse_from_str(created_at, "%Y-%m-%dT%H:%M:%S%.f")
+            .map_err(|_| invalid_cursor())?,
+        uuid::Uuid::parse_str(card_id).map_err(|_| invalid_cursor())?,
+    ))
 }
 
-pub fn update_card_metadata_query(
-    card_data: CardMetadata,
-    file_uuid: Option<uuid::Uuid>,
-    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
-) -> Result<(), DefaultError> {
-    use crate::data::schema::card_files::dsl as card_files_columns;
+// Newest public cards first, leaving out cards that belong to a private file and cards awaiting
+// approval unless the current user wrote them
+pub fn get_card_feed_query(
+    cursor: Option<(chrono::NaiveDateTime, uuid::Uuid)>,
+    limit: i64,
+    current_user_id: Option<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<CardFeedPage, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
-    use crate::data::schema::card_votes::dsl as card_votes_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
-    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        diesel::update(
-            card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_data.id)),
+    let mut query = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::private.eq(false))
+        .filter(
+            card_metadata_columns::approved
+                .eq(true)
+                .or(card_metadata_columns::author_id
+                    .eq(current_user_id.unwrap_or(uuid::Uuid::nil()))),
         )
-        .set((
-            card_metadata_columns::link.eq(card_data.link),
-            card_metadata_columns::card_html.eq(card_data.card_html),
-            card_metadata_columns::private.eq(card_data.private),
+        .filter(sql::<Bool>(
+            "NOT EXISTS (SELECT 1 FROM card_files INNER JOIN files ON files.id = card_files.file_id \
+             WHERE card_files.card_id = card_metadata.id AND files.private)",
         ))
-        .execute(conn)?;
+        .select(CardMetadata::as_select())
+        .into_boxed();
 
-        diesel::update(
-            card_votes_columns::card_votes
-                .filter(card_votes_columns::card_metadata_id.eq(card_data.id)),
-        )
-        .set(card_votes_columns::deleted.eq(card_data.private))
-        .execute(conn)?;
+    if let Some((cursor_created_at, cursor_card_id)) = cursor {
+        query = query.filter(
+            card_metadata_columns::created_at.lt(cursor_created_at).or(
+                card_metadata_columns::created_at
+                    .eq(cursor_created_at)
+                    .and(card_metadata_columns::id.lt(cursor_card_id)),
+            ),
+        );
+    }
 
-        if file_uuid.is_some() {
-            diesel::insert_into(card_files_columns::card_files)
-                .values(&CardFile::from_details(card_data.id, file_uuid.unwrap()))
-                .execute(conn)?;
-        }
-        Ok(())
-    });
+    let mut cards: Vec<CardMetadata> = query
+        .order((
+            card_metadata_columns::created_at.desc(),
+            card_metadata_columns::id.desc(),
+        ))
+        .limit(limit + 1)
+        .load(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load card feed",
+            ..Default::default()
+        })?;
 
-    match transaction_result {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(DefaultError {
-                message: "Failed to update card metadata",
-            })
-        }
+    let next_cursor = if cards.len() as i64 > limit {
+        cards.truncate(limit as usize);
+        cards
+            .last()
+            .map(|card| encode_card_feed_cursor(card.created_at, card.id))
+    } else {
+        None
     };
 
-    Ok(())
+    let cards = get_metadata(
+        cards
+            .into_iter()
+            .map(<CardMetadata as Into<FullTextSearchResult>>::into)
+            .collect(),
+        current_user_id,
+        conn,
+    )?;
+
+    Ok(CardFeedPage { cards, next_cursor })
 }
 
-enum TransactionResult {
-    CardCollisionDetected,
-    CardCollisionNotDetected,
+#[derive(Serialize, Deserialize)]
+pub struct CardChangesPage {
+    pub cards: Vec<CardMetadata>,
+    pub next_cursor: Option<String>,
 }
 
-pub async fn delete_card_metadata_query(
-    card_uuid: uuid::Uuid,
-    pool: Arc<Mutex<web::Data<r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>>>>,
-) -> Result<(), DefaultError> {
-    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
-    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
-    use crate::data::schema::card_files::dsl as card_files_columns;
+// Cards updated after the cursor (an updated_at and id, encoded like the feed cursor), oldest
+// change first so a mirror can apply them in order. Cards are hard deleted, so deletions don't
+// show up here.
+pub fn get_card_changes_query(
+    cursor: (chrono::NaiveDateTime, uuid::Uuid),
+    limit: i64,
+    current_user_id: Option<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<CardChangesPage, DefaultError> {
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
-    let mut conn = pool.lock().unwrap().get().unwrap();
-
-    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        {
-            diesel::delete(
-                card_files_columns::card_files.filter(card_files_columns::card_id.eq(card_uuid)),
-            )
-            .execute(conn)?;
 
-            diesel::delete(
-                card_collection_bookmarks_columns::card_collection_bookmarks
-                    .filter(card_collection_bookmarks_columns::card_metadata_id.eq(card_uuid)),
-            )
-            .execute(conn)?;
+    let mut conn = pool.get()?;
+    let (cursor_updated_at, cursor_card_id) = cursor;
 
-            let deleted_card_collision_count = diesel::delete(
-                card_collisions_columns::card_collisions
-                    .filter(card_collisions_columns::card_id.eq(card_uuid)),
-            )
-            .execute(conn)?;
+    let mut cards: Vec<CardMetadata> = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::private.eq(false))
+        .filter(
+            card_metadata_columns::approved
+                .eq(true)
+                .or(card_metadata_columns::author_id
+                    .eq(current_user_id.unwrap_or(uuid::Uuid::nil()))),
+        )
+        .filter(sql::<Bool>(
+            "NOT EXISTS (SELECT 1 FROM card_files INNER JOIN files ON files.id = card_files.file_id \
+             WHERE card_files.card_id = card_metadata.id AND files.private)",
+        ))
+        .filter(
+            card_metadata_columns::updated_at.gt(cursor_updated_at).or(
+                card_metadata_columns::updated_at
+                    .eq(cursor_updated_at)
+                    .and(card_metadata_columns::id.gt(cursor_card_id)),
+            ),
+        )
+        .select(CardMetadata::as_select())
+        .order((
+            card_metadata_columns::updated_at.asc(),
+            card_metadata_columns::id.asc(),
+        ))
+        .limit(limit + 1)
+        .load(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load card changes",
+            ..Default::default()
+        })?;
 
-            if deleted_card_collision_count > 0 {
-                // there cannot be collisions for a collision, just delete the card_metadata without issue
-                diesel::delete(
-                    card_metadata_columns::card_metadata
-                        .filter(card_metadata_columns::id.eq(card_uuid)),
-                )
-                .execute(conn)?;
+    let next_cursor = if cards.len() as i64 > limit {
+        cards.truncate(limit as usize);
+        cards
+            .last()
+            .map(|card| encode_card_feed_cursor(card.updated_at, card.id))
+    } else {
+        None
+    };
 
-                return Ok(TransactionResult::CardCollisionNotDetected);
-            }
+    Ok(CardChangesPage { cards, next_cursor })
+}
 
-            let card_collisions: Vec<(CardCollisions, bool)> =
-                card_collisions_columns::card_collisions
-                    .inner_join(
-                        card_metadata_columns::card_metadata
-                            .on(card_metadata_columns::qdrant_point_id
-                                .eq(card_collisions_columns::collision_qdrant_id)),
-                    )
-                    .filter(card_metadata_columns::id.eq(card_uuid))
-                    .select((CardCollisions::as_select(), card_metadata_columns::private))
-                    .order_by(card_collisions_columns::created_at.asc())
-                    .load::<(CardCollisions, bool)>(conn)?;
-
-            if !card_collisions.is_empty() {
-                // get the first collision that is public or the first collision if all are private
-                let latest_collision = match card_collisions.iter().find(|x| !x.1) {
-                    Some(x) => x.0.clone(),
-                    None => card_collisions[0].0.clone(),
-                };
-
-                // update all collisions except latest_collision to point to a qdrant_id of None
-                diesel::update(
-                    card_collisions_columns::card_collisions.filter(
-                        card_collisions_columns::id.eq_any(
-                            card_collisions
-                                .iter()
-                                .filter(|x| x.0.id != latest_collision.id)
-                                .map(|x| x.0.id)
-                                .collect::<Vec<uuid::Uuid>>(),
-                        ),
-                    ),
-                )
-                .set(card_collisions_columns::collision_qdrant_id.eq::<Option<uuid::Uuid>>(None))
-                .execute(conn)?;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CardSortBy {
+    #[default]
+    Recent,
+    Popularity,
+}
 
-                // delete latest_collision from card_collisions
-                diesel::delete(
-                    card_collisions_columns::card_collisions
-                        .filter(card_collisions_columns::id.eq(latest_collision.id)),
-                )
-                .execute(conn)?;
+// Views are accumulated in memory and flushed periodically so that searches don't cause a
+// write per returned card
+pub static PENDING_CARD_VIEWS: Lazy<Mutex<HashMap<uuid::Uuid, i64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
-                // delete the original card_metadata
-                diesel::delete(
-                    card_metadata_columns::card_metadata
-                        .filter(card_metadata_columns::id.eq(card_uuid)),
-                )
-                .execute(conn)?;
+pub fn record_card_views(card_ids: &[uuid::Uuid]) {
+    let mut pending_card_views = PENDING_CARD_VIEWS.lock().unwrap();
+    for card_id in card_ids {
+        *pending_card_views.entry(*card_id).or_insert(0) += 1;
+    }
+}
 
-                // set the card_metadata of latest_collision to have the qdrant_point_id of the original card_metadata
-                diesel::update(
-                    card_metadata_columns::card_metadata
-                        .filter(card_metadata_columns::id.eq(latest_collision.card_id)),
-                )
-                .set((
-                    card_metadata_columns::qdrant_point_id.eq(latest_collision.collision_qdrant_id),
-                ))
-                .execute(conn)?;
+pub fn flush_card_views_query(pool: &Pool) -> Result<(), DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
 
-                // set the collision_qdrant_id of all other collisions to be the same as they were to begin with
-                diesel::update(
-                    card_collisions_columns::card_collisions.filter(
-                        card_collisions_columns::id.eq_any(
-                            card_collisions
-                                .iter()
-                                .skip(1)
-                                .map(|x| x.0.id)
-                                .collect::<Vec<uuid::Uuid>>(),
-                        ),
-                    ),
-                )
-                .set((card_collisions_columns::collision_qdrant_id
-                    .eq(latest_collision.collision_qdrant_id),))
-                .execute(conn)?;
+    let card_views: HashMap<uuid::Uuid, i64> =
+        std::mem::take(&mut *PENDING_CARD_VIEWS.lock().unwrap());
+    if card_views.is_empty() {
+        return Ok(());
+    }
 
-                return Ok(TransactionResult::CardCollisionDetected);
-            }
+    let mut conn = pool.get()?;
 
-            // if there were no collisions, just delete the card_metadata without issue
-            diesel::delete(
-                card_metadata_columns::card_metadata
-                    .filter(card_metadata_columns::id.eq(card_uuid)),
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        for (card_id, views) in card_views.iter() {
+            diesel::update(
+                card_metadata_columns::card_metadata.filter(card_metadata_columns::id.eq(card_id)),
             )
+            .set(card_metadata_columns::view_count.eq(card_metadata_columns::view_count + views))
             .execute(conn)?;
-
-            Ok(TransactionResult::CardCollisionNotDetected)
         }
+
+        Ok(())
     });
 
-    match transaction_result {
-        Ok(result) => {
-            if let TransactionResult::CardCollisionNotDetected = result {
-                let qdrant = get_qdrant_connection().await?;
-                let _ = qdrant
-                    .delete_points(
-                        "debate_cards",
-                        &vec![<String as Into<PointId>>::into(card_uuid.to_string())].into(),
-                        None,
-                    )
-                    .await
-                    .map_err(|_e| {
-                        Err::<(), DefaultError>(DefaultError {
-                            message: "Failed to delete card from qdrant",
-                        })
-                    });
-            }
+    if transaction_result.is_err() {
+        // keep the views around for the next flush
+        let mut pending_card_views = PENDING_CARD_VIEWS.lock().unwrap();
+        for (card_id, views) in card_views {
+            *pending_card_views.entry(card_id).or_insert(0) += views;
         }
 
-        Err(_) => {
-            return Err(DefaultError {
-                message: "Failed to delete card data",
-            })
-        }
-    };
+        return Err(DefaultError {
+            message: "Failed to flush card views",
+            ..Default::default()
+        });
+    }
 
     Ok(())
 }
@@ -1207,12 +3382,137 @@ pub async fn delete_card_metadata_query(
 pub fn get_card_count_query(pool: web::Data<Pool>) -> Result<i64, DefaultError> {
     use crate::data::schema::card_metadata::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     card_metadata
         .count()
         .get_result::<i64>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Failed to get card count",
+            ..Default::default()
+        })
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CardCountGroupBy {
+    File,
+    Author,
+    Day,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CardCountBucket {
+    pub key: Option<String>,
+    pub count: i64,
+}
+
+pub fn get_grouped_card_count_query(
+    group_by: CardCountGroupBy,
+    pool: web::Data<Pool>,
+) -> Result<Vec<CardCountBucket>, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    // private cards are left out so their file paths and authors aren't exposed
+    let buckets: Vec<(Option<String>, i64)> = match group_by {
+        CardCountGroupBy::File => card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::private.eq(false))
+            .group_by(card_metadata_columns::oc_file_path)
+            .select((card_metadata_columns::oc_file_path, count_star()))
+            .order(count_star().desc())
+            .load(&mut conn),
+        CardCountGroupBy::Author => card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::private.eq(false))
+            .group_by(card_metadata_columns::author_id)
+            .select((card_metadata_columns::author_id, count_star()))
+            .order(count_star().desc())
+            .load::<(uuid::Uuid, i64)>(&mut conn)
+            .map(|buckets| {
+                buckets
+                    .into_iter()
+                    .map(|(author_id, count)| (Some(author_id.to_string()), count))
+                    .collect()
+            }),
+        CardCountGroupBy::Day => card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::private.eq(false))
+            .group_by(sql::<Text>(
+                "to_char(card_metadata.created_at, 'YYYY-MM-DD')",
+            ))
+            .select((
+                sql::<Nullable<Text>>("to_char(card_metadata.created_at, 'YYYY-MM-DD')"),
+                count_star(),
+            ))
+            .order(sql::<Text>("1 ASC"))
+            .load(&mut conn),
+    }
+    .map_err(|_err| DefaultError {
+        message: "Failed to get grouped card count",
+        ..Default::default()
+    })?;
+
+    Ok(buckets
+        .into_iter()
+        .map(|(key, count)| CardCountBucket { key, count })
+        .collect())
+}
+
+// Exports are the caller's own cards, or a collection they can see without other people's
+// private cards, in the order they were created or bookmarked
+pub fn get_cards_for_export_query(
+    user_id: uuid::Uuid,
+    collection_id: Option<uuid::Uuid>,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<CardMetadata>, DefaultError> {
+    use crate::data::schema::card_collection::dsl as card_collection_columns;
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let collection_id = match collection_id {
+        Some(collection_id) => collection_id,
+        None => {
+            return card_metadata_columns::card_metadata
+                .filter(card_metadata_columns::author_id.eq(user_id))
+                .order(card_metadata_columns::created_at.asc())
+                .select(CardMetadata::as_select())
+                .load::<CardMetadata>(&mut conn)
+                .map_err(|_db_error| DefaultError {
+                    message: "Failed to load cards for export",
+                    ..Default::default()
+                })
+        }
+    };
+
+    let collection = card_collection_columns::card_collection
+        .filter(card_collection_columns::id.eq(collection_id))
+        .first::<CardCollection>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Collection not found",
+            ..Default::default()
+        })?;
+    if !collection.is_public && collection.author_id != user_id {
+        return Err(DefaultError {
+            message: "Collection not found",
+            ..Default::default()
+        });
+    }
+
+    card_collection_bookmarks_columns::card_collection_bookmarks
+        .inner_join(card_metadata_columns::card_metadata)
+        .filter(card_collection_bookmarks_columns::collection_id.eq(collection_id))
+        .filter(
+            card_metadata_columns::private
+                .eq(false)
+                .or(card_metadata_columns::author_id.eq(user_id)),
+        )
+        .order(card_collection_bookmarks_columns::created_at.asc())
+        .select(CardMetadata::as_select())
+        .load::<CardMetadata>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to load cards for export",
+            ..Default::default()
         })
 }
@@ -2,19 +2,27 @@ use std::sync::MutexGuard;
 
 use crate::{
     data::models::{
-        CardCollectionAndFile, CardCollectionBookmark, CardMetadataWithCount,
+        CardCollectionAndFile, CardCollectionBookmark, CardFileWithName, CardMetadataWithCount,
         CardMetadataWithVotesAndFiles, FileCollection, FullTextSearchResult,
     },
-    diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl},
+    diesel::{
+        BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl,
+        SelectableHelper,
+    },
+    handlers::file_handler::{FileCollectionBehavior, FileCollectionStrategy},
     operators::card_operator::get_metadata,
 };
 
 use actix_web::web;
-use diesel::{dsl::sql, sql_types::Int8, JoinOnDsl, NullableExpressionMethods};
+use diesel::{
+    dsl::sql,
+    sql_types::{Int8, Timestamp},
+    JoinOnDsl, NullableExpressionMethods,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{CardCollection, Pool},
+    data::models::{CardCollection, PageInfo, Pool},
     errors::DefaultError,
 };
 
@@ -24,7 +32,7 @@ pub fn create_collection_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::card_collection::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::insert_into(card_collection)
         .values(&new_collection)
@@ -33,62 +41,119 @@ pub fn create_collection_query(
             log::error!("Error creating collection {:}", err);
             DefaultError {
                 message: "Error creating collection",
+                ..Default::default()
             }
         })?;
 
     Ok(())
 }
 
+// "Collection for file x" is version 1, later uploads of x are named "Collection for file x (n)"
+fn get_file_collection_version(collection_name: &str, base_name: &str) -> Option<i64> {
+    if collection_name == base_name {
+        return Some(1);
+    }
+
+    collection_name
+        .strip_prefix(base_name)
+        .and_then(|suffix| suffix.strip_prefix(" ("))
+        .and_then(|suffix| suffix.strip_suffix(')'))
+        .and_then(|version| version.parse().ok())
+}
+
 pub fn create_collection_and_add_bookmarks_query(
-    new_collection: CardCollection,
+    author_id: uuid::Uuid,
+    file_name: String,
+    is_public: bool,
     bookmarks: Vec<uuid::Uuid>,
     created_file_id: uuid::Uuid,
+    strategy: FileCollectionStrategy,
     pool: web::Data<Pool>,
-) -> Result<CardCollection, DefaultError> {
-    use crate::data::schema::card_collection::dsl::*;
+) -> Result<(uuid::Uuid, FileCollectionBehavior), DefaultError> {
+    use crate::data::schema::card_collection::dsl as card_collection_columns;
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::collections_from_files::dsl as collections_from_files_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
+    let base_name = format!("Collection for file {}", file_name);
 
     let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
-        diesel::insert_into(card_collection)
-            .values(&new_collection)
-            .execute(conn)?;
-
-        use crate::data::schema::card_collection_bookmarks::dsl::*;
+        // newest first, so the collection to reuse is the first match
+        let existing_collections: Vec<(uuid::Uuid, i64)> =
+            card_collection_columns::card_collection
+                .inner_join(collections_from_files_columns::collections_from_files.on(
+                    collections_from_files_columns::collection_id.eq(card_collection_columns::id),
+                ))
+                .filter(card_collection_columns::author_id.eq(author_id))
+                .select((card_collection_columns::id, card_collection_columns::name))
+                .order(card_collection_columns::created_at.desc())
+                .load::<(uuid::Uuid, String)>(conn)?
+                .into_iter()
+                .filter_map(|(collection_id, collection_name)| {
+                    get_file_collection_version(&collection_name, &base_name)
+                        .map(|version| (collection_id, version))
+                })
+                .collect();
+
+        let (collection_id, behavior) = match (strategy, existing_collections.first()) {
+            (FileCollectionStrategy::Reuse, Some((collection_id, _))) => {
+                (*collection_id, FileCollectionBehavior::Reused)
+            }
+            (_, latest_collection) => {
+                let (name, behavior) = match latest_collection {
+                    Some(_) => {
+                        let next_version = existing_collections
+                            .iter()
+                            .map(|(_, version)| version)
+                            .max()
+                            .unwrap_or(&1)
+                            + 1;
+                        (
+                            format!("{} ({})", base_name, next_version),
+                            FileCollectionBehavior::Versioned,
+                        )
+                    }
+                    None => (base_name.clone(), FileCollectionBehavior::Created),
+                };
+                let new_collection =
+                    CardCollection::from_details(author_id, name, is_public, "".to_string());
+
+                diesel::insert_into(card_collection_columns::card_collection)
+                    .values(&new_collection)
+                    .execute(conn)?;
+
+                (new_collection.id, behavior)
+            }
+        };
 
-        diesel::insert_into(card_collection_bookmarks)
+        diesel::insert_into(card_collection_bookmarks_columns::card_collection_bookmarks)
             .values(
                 bookmarks
                     .iter()
-                    .map(|bookmark| {
-                        CardCollectionBookmark::from_details(new_collection.id, *bookmark)
-                    })
+                    .map(|bookmark| CardCollectionBookmark::from_details(collection_id, *bookmark))
                     .collect::<Vec<CardCollectionBookmark>>(),
             )
+            // a reused collection may already hold some of the cards
+            .on_conflict_do_nothing()
             .execute(conn)?;
 
-        use crate::data::schema::collections_from_files::dsl::*;
-
-        diesel::insert_into(collections_from_files)
+        diesel::insert_into(collections_from_files_columns::collections_from_files)
             .values(&FileCollection::from_details(
                 created_file_id,
-                new_collection.id,
+                collection_id,
             ))
             .execute(conn)?;
 
-        Ok(())
+        Ok((collection_id, behavior))
     });
 
-    match transaction_result {
-        Ok(_) => (),
-        Err(err) => {
-            log::error!("Error creating collection {:}", err);
-            return Err(DefaultError {
-                message: "Error creating collection",
-            });
+    transaction_result.map_err(|err| {
+        log::error!("Error creating collection {:}", err);
+        DefaultError {
+            message: "Error creating collection",
+            ..Default::default()
         }
-    }
-    Ok(new_collection)
+    })
 }
 
 pub fn get_collections_for_specifc_user_query(
@@ -99,7 +164,7 @@ pub fn get_collections_for_specifc_user_query(
     use crate::data::schema::card_collection::dsl::*;
     use crate::data::schema::collections_from_files::dsl as collections_from_files_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
     let mut collections = card_collection
         .left_outer_join(
             collections_from_files_columns::collections_from_files
@@ -131,6 +196,7 @@ pub fn get_collections_for_specifc_user_query(
         .load::<CardCollectionAndFile>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error getting collections",
+            ..Default::default()
         })?;
 
     Ok(collections)
@@ -143,7 +209,7 @@ pub fn get_collections_for_logged_in_user_query(
     use crate::data::schema::card_collection::dsl::*;
     use crate::data::schema::collections_from_files::dsl as collections_from_files_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let collections = card_collection
         .left_outer_join(
@@ -164,6 +230,7 @@ pub fn get_collections_for_logged_in_user_query(
         .load::<CardCollectionAndFile>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error getting collections",
+            ..Default::default()
         })?;
 
     Ok(collections)
@@ -175,13 +242,14 @@ pub fn get_collection_by_id_query(
 ) -> Result<CardCollection, DefaultError> {
     use crate::data::schema::card_collection::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let collection = card_collection
         .filter(id.eq(collection_id))
         .first::<CardCollection>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Collection not found",
+            ..Default::default()
         })?;
 
     Ok(collection)
@@ -195,7 +263,7 @@ pub fn delete_collection_by_id_query(
     use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
     use crate::data::schema::collections_from_files::dsl as collections_from_files_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::delete(
         collections_from_files_columns::collections_from_files
@@ -204,6 +272,7 @@ pub fn delete_collection_by_id_query(
     .execute(&mut conn)
     .map_err(|_err| DefaultError {
         message: "Error deleting collection",
+        ..Default::default()
     })?;
 
     diesel::delete(
@@ -213,6 +282,7 @@ pub fn delete_collection_by_id_query(
     .execute(&mut conn)
     .map_err(|_err| DefaultError {
         message: "Error deleting collection",
+        ..Default::default()
     })?;
 
     diesel::delete(
@@ -222,6 +292,7 @@ pub fn delete_collection_by_id_query(
     .execute(&mut conn)
     .map_err(|_err| DefaultError {
         message: "Error deleting collection",
+        ..Default::default()
     })?;
 
     Ok(())
@@ -236,7 +307,7 @@ pub fn update_card_collection_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::card_collection::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(card_collection.filter(id.eq(collection.id)))
         .set((
@@ -247,6 +318,7 @@ pub fn update_card_collection_query(
         .execute(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error updating collection",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -258,7 +330,7 @@ pub fn create_card_bookmark_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::card_collection_bookmarks::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::insert_into(card_collection_bookmarks)
         .values(&bookmark)
@@ -267,6 +339,7 @@ pub fn create_card_bookmark_query(
             log::error!("Error creating bookmark {:}", _err);
             DefaultError {
                 message: "Error creating bookmark",
+                ..Default::default()
             }
         })?;
 
@@ -287,13 +360,14 @@ pub fn get_bookmarks_for_collection_query(
     use crate::data::schema::card_metadata::dsl as card_metadata_columns;
     let page = if page == 0 { 1 } else { page };
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let bookmarks = card_collection_bookmarks_columns::card_collection_bookmarks
         .filter(card_collection_bookmarks_columns::collection_id.eq(collection))
         .load::<CardCollectionBookmark>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error getting bookmarks",
+            ..Default::default()
         })?;
 
     let bookmark_metadata: Vec<(CardMetadataWithCount, Option<uuid::Uuid>)> =
@@ -322,6 +396,10 @@ pub fn get_bookmarks_for_collection_query(
                     card_metadata_columns::oc_file_path,
                     card_metadata_columns::card_html,
                     card_metadata_columns::private,
+                    card_metadata_columns::source_author,
+                    card_metadata_columns::source_title,
+                    card_metadata_columns::source_date,
+                    card_metadata_columns::source_url,
                     sql::<Int8>("count(*) OVER() AS full_count"),
                 ),
                 card_collisions_columns::collision_qdrant_id.nullable(),
@@ -331,6 +409,7 @@ pub fn get_bookmarks_for_collection_query(
             .load::<(CardMetadataWithCount, Option<uuid::Uuid>)>(&mut conn)
             .map_err(|_err| DefaultError {
                 message: "Error getting bookmarks",
+                ..Default::default()
             })?;
 
     let converted_cards: Vec<FullTextSearchResult> = bookmark_metadata
@@ -348,6 +427,7 @@ pub fn get_bookmarks_for_collection_query(
     let card_metadata_with_upvotes_and_file_id =
         get_metadata(converted_cards, current_user_id, conn).map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let total_pages = match bookmark_metadata.get(0) {
@@ -360,6 +440,100 @@ pub fn get_bookmarks_for_collection_query(
         total_pages,
     })
 }
+
+// Every card the user saved into any of their collections, most recently bookmarked first. A card
+// bookmarked in several collections shows up once. The user's own cards, which uploads file into
+// collections automatically, and cards that have since been made private are left out.
+pub fn get_user_bookmarks_query(
+    user_id: uuid::Uuid,
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<CardMetadataWithVotesAndFiles>, i64), DefaultError> {
+    use crate::data::schema::card_collection::dsl as card_collection_columns;
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_collisions::dsl as card_collisions_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let bookmarked_card_ids = card_collection_bookmarks_columns::card_collection_bookmarks
+        .inner_join(card_collection_columns::card_collection)
+        .filter(card_collection_columns::author_id.eq(user_id))
+        .select(card_collection_bookmarks_columns::card_metadata_id);
+
+    let bookmark_metadata: Vec<(CardMetadataWithCount, Option<uuid::Uuid>)> =
+        card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq_any(bookmarked_card_ids))
+            .filter(card_metadata_columns::author_id.ne(user_id))
+            .filter(card_metadata_columns::private.eq(false))
+            .left_join(
+                card_collisions_columns::card_collisions
+                    .on(card_metadata_columns::id.eq(card_collisions_columns::card_id)),
+            )
+            .select((
+                (
+                    card_metadata_columns::id,
+                    card_metadata_columns::content,
+                    card_metadata_columns::link,
+                    card_metadata_columns::author_id,
+                    card_metadata_columns::qdrant_point_id,
+                    card_metadata_columns::created_at,
+                    card_metadata_columns::updated_at,
+                    card_metadata_columns::oc_file_path,
+                    card_metadata_columns::card_html,
+                    card_metadata_columns::private,
+                    card_metadata_columns::source_author,
+                    card_metadata_columns::source_title,
+                    card_metadata_columns::source_date,
+                    card_metadata_columns::source_url,
+                    sql::<Int8>("count(*) OVER() AS full_count"),
+                ),
+                card_collisions_columns::collision_qdrant_id.nullable(),
+            ))
+            .order(
+                sql::<Timestamp>(concat!(
+                    "(SELECT max(card_collection_bookmarks.created_at) FROM card_collection_bookmarks",
+                    " INNER JOIN card_collection",
+                    " ON card_collection.id = card_collection_bookmarks.collection_id",
+                    " WHERE card_collection_bookmarks.card_metadata_id = card_metadata.id",
+                    " AND card_collection.author_id = "
+                ))
+                .bind::<diesel::sql_types::Uuid, _>(user_id)
+                .sql(") DESC"),
+            )
+            .limit(per_page)
+            .offset(PageInfo::offset(page, per_page))
+            .load::<(CardMetadataWithCount, Option<uuid::Uuid>)>(&mut conn)
+            .map_err(|_err| DefaultError {
+                message: "Error getting bookmarks",
+                ..Default::default()
+            })?;
+
+    let total_bookmarks = bookmark_metadata
+        .first()
+        .map(|(card, _)| card.count)
+        .unwrap_or(0);
+
+    let converted_cards: Vec<FullTextSearchResult> = bookmark_metadata
+        .into_iter()
+        .map(|(mut card, collided_id)| {
+            if collided_id.is_some() {
+                card.qdrant_point_id = collided_id;
+            }
+            FullTextSearchResult::from(card)
+        })
+        .collect();
+
+    let bookmarks =
+        get_metadata(converted_cards, Some(user_id), conn).map_err(|_| DefaultError {
+            message: "Failed to load metadata",
+            ..Default::default()
+        })?;
+
+    Ok((bookmarks, total_bookmarks))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BookmarkCollectionResult {
     pub card_uuid: uuid::Uuid,
@@ -373,7 +547,7 @@ pub fn get_collections_for_bookmark_query(
     use crate::data::schema::card_collection::dsl as card_collection_columns;
     use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let user_collections: Vec<uuid::Uuid> = card_collection_columns::card_collection
         .filter(card_collection_columns::author_id.eq(current_user_id.unwrap_or_default()))
@@ -381,6 +555,7 @@ pub fn get_collections_for_bookmark_query(
         .load::<uuid::Uuid>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error getting bookmarks",
+            ..Default::default()
         })?;
 
     let collections = card_collection_bookmarks_columns::card_collection_bookmarks
@@ -389,6 +564,7 @@ pub fn get_collections_for_bookmark_query(
         .load::<CardCollectionBookmark>(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error getting bookmarks",
+            ..Default::default()
         })?;
 
     let bookmark_collections: Vec<BookmarkCollectionResult> =
@@ -412,6 +588,66 @@ pub fn get_collections_for_bookmark_query(
 
     Ok(bookmark_collections)
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CardContainers {
+    pub collections: Vec<CardCollection>,
+    pub files: Vec<CardFileWithName>,
+}
+
+// The inverse of the collection listing. Private collections and files only show up for their
+// owner.
+pub fn get_containers_for_card_query(
+    card_id: uuid::Uuid,
+    current_user_id: Option<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<CardContainers, DefaultError> {
+    use crate::data::schema::card_collection::dsl as card_collection_columns;
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::files::dsl as files_columns;
+
+    let mut conn = pool.get()?;
+    let current_user_id = current_user_id.unwrap_or_default();
+
+    let collections = card_collection_bookmarks_columns::card_collection_bookmarks
+        .inner_join(card_collection_columns::card_collection)
+        .filter(card_collection_bookmarks_columns::card_metadata_id.eq(card_id))
+        .filter(
+            card_collection_columns::is_public
+                .eq(true)
+                .or(card_collection_columns::author_id.eq(current_user_id)),
+        )
+        .select(CardCollection::as_select())
+        .order(card_collection_columns::created_at.desc())
+        .load::<CardCollection>(&mut conn)
+        .map_err(|_err| DefaultError {
+            message: "Error getting card collections",
+            ..Default::default()
+        })?;
+
+    let files = card_files_columns::card_files
+        .inner_join(files_columns::files)
+        .filter(card_files_columns::card_id.eq(card_id))
+        .filter(
+            files_columns::private
+                .eq(false)
+                .or(files_columns::user_id.eq(current_user_id)),
+        )
+        .select((
+            card_files_columns::card_id,
+            card_files_columns::file_id,
+            files_columns::file_name,
+        ))
+        .load::<CardFileWithName>(&mut conn)
+        .map_err(|_err| DefaultError {
+            message: "Error getting card files",
+            ..Default::default()
+        })?;
+
+    Ok(CardContainers { collections, files })
+}
+
 pub fn delete_bookmark_query(
     bookmark: uuid::Uuid,
     collection: uuid::Uuid,
@@ -419,7 +655,7 @@ pub fn delete_bookmark_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::card_collection_bookmarks::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::delete(
         card_collection_bookmarks
@@ -431,8 +667,92 @@ pub fn delete_bookmark_query(
         log::error!("Error deleting bookmark {:}", _err);
         DefaultError {
             message: "Error deleting bookmark",
+            ..Default::default()
         }
     })?;
 
     Ok(())
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MoveBookmarksResult {
+    pub moved_card_ids: Vec<uuid::Uuid>,
+    pub skipped_card_ids: Vec<uuid::Uuid>,
+    pub from_collection_count: i64,
+    pub to_collection_count: i64,
+}
+
+pub fn move_bookmarks_query(
+    card_ids: Vec<uuid::Uuid>,
+    from_collection_id: uuid::Uuid,
+    to_collection_id: uuid::Uuid,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<MoveBookmarksResult, DefaultError> {
+    use crate::data::schema::card_collection_bookmarks::dsl as card_collection_bookmarks_columns;
+
+    let mut conn = pool.get()?;
+
+    let transaction_result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let cards_in_from_collection: Vec<uuid::Uuid> =
+            card_collection_bookmarks_columns::card_collection_bookmarks
+                .filter(card_collection_bookmarks_columns::collection_id.eq(from_collection_id))
+                .filter(card_collection_bookmarks_columns::card_metadata_id.eq_any(&card_ids))
+                .select(card_collection_bookmarks_columns::card_metadata_id)
+                .load::<uuid::Uuid>(conn)?;
+
+        let (moved_card_ids, skipped_card_ids): (Vec<uuid::Uuid>, Vec<uuid::Uuid>) = card_ids
+            .into_iter()
+            .partition(|card_id| cards_in_from_collection.contains(card_id));
+
+        diesel::delete(
+            card_collection_bookmarks_columns::card_collection_bookmarks
+                .filter(card_collection_bookmarks_columns::collection_id.eq(from_collection_id))
+                .filter(
+                    card_collection_bookmarks_columns::card_metadata_id.eq_any(&moved_card_ids),
+                ),
+        )
+        .execute(conn)?;
+
+        let cards_in_to_collection: Vec<uuid::Uuid> =
+            card_collection_bookmarks_columns::card_collection_bookmarks
+                .filter(card_collection_bookmarks_columns::collection_id.eq(to_collection_id))
+                .filter(card_collection_bookmarks_columns::card_metadata_id.eq_any(&moved_card_ids))
+                .select(card_collection_bookmarks_columns::card_metadata_id)
+                .load::<uuid::Uuid>(conn)?;
+
+        diesel::insert_into(card_collection_bookmarks_columns::card_collection_bookmarks)
+            .values(
+                moved_card_ids
+                    .iter()
+                    .filter(|card_id| !cards_in_to_collection.contains(card_id))
+                    .map(|card_id| CardCollectionBookmark::from_details(to_collection_id, *card_id))
+                    .collect::<Vec<CardCollectionBookmark>>(),
+            )
+            .execute(conn)?;
+
+        let from_collection_count = card_collection_bookmarks_columns::card_collection_bookmarks
+            .filter(card_collection_bookmarks_columns::collection_id.eq(from_collection_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        let to_collection_count = card_collection_bookmarks_columns::card_collection_bookmarks
+            .filter(card_collection_bookmarks_columns::collection_id.eq(to_collection_id))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(MoveBookmarksResult {
+            moved_card_ids,
+            skipped_card_ids,
+            from_collection_count,
+            to_collection_count,
+        })
+    });
+
+    transaction_result.map_err(|err| {
+        log::error!("Error moving bookmarks {:}", err);
+        DefaultError {
+            message: "Error moving bookmarks",
+            ..Default::default()
+        }
+    })
+}
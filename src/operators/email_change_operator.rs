@@ -0,0 +1,164 @@
+use crate::data::models::{EmailChange, Pool, StripeCustomer, User};
+use crate::diesel::prelude::*;
+use crate::errors::DefaultError;
+use crate::operators::email_operator::send_email_change_verification;
+use actix_web::web;
+
+// An email can't be taken over while it belongs to another account or has an invitation waiting
+// to be accepted
+fn check_email_available_query(
+    new_email: &str,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::invitations::dsl as invitations_columns;
+    use crate::data::schema::users::dsl as users_columns;
+
+    let mut conn = pool.get()?;
+
+    let existing_user: Option<User> = users_columns::users
+        .filter(users_columns::email.eq(new_email))
+        .first::<User>(&mut conn)
+        .optional()
+        .map_err(|_db_error| DefaultError {
+            message: "Error checking email, try again",
+            ..Default::default()
+        })?;
+    if existing_user.is_some() {
+        return Err(DefaultError {
+            message: "That email is already in use",
+            ..Default::default()
+        });
+    }
+
+    let pending_invitations: i64 = invitations_columns::invitations
+        .filter(invitations_columns::email.eq(new_email))
+        .filter(invitations_columns::expires_at.gt(chrono::Local::now().naive_local()))
+        .filter(invitations_columns::used.eq(false))
+        .count()
+        .get_result(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error checking email, try again",
+            ..Default::default()
+        })?;
+    if pending_invitations > 0 {
+        return Err(DefaultError {
+            message: "That email has a pending invitation",
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
+
+pub fn send_email_change_verification_query(
+    app_url: String,
+    user_id: uuid::Uuid,
+    new_email: String,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::email_changes::dsl::email_changes;
+
+    check_email_available_query(&new_email, pool)?;
+
+    let mut conn = pool.get()?;
+
+    let email_change: EmailChange = diesel::insert_into(email_changes)
+        .values(&EmailChange::from_details(user_id, new_email))
+        .get_result(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error inserting new email change request, try again",
+            ..Default::default()
+        })?;
+
+    send_email_change_verification(app_url, &email_change)?;
+
+    Ok(())
+}
+
+pub struct ConfirmedEmailChange {
+    pub user: User,
+    pub stripe_customer: Option<StripeCustomer>,
+}
+
+pub fn confirm_email_change_query(
+    user_id: uuid::Uuid,
+    email_change_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<ConfirmedEmailChange, DefaultError> {
+    use crate::data::schema::email_changes::dsl as email_changes_columns;
+    use crate::data::schema::stripe_customers::dsl as stripe_customers_columns;
+    use crate::data::schema::users::dsl as users_columns;
+
+    let mut conn = pool.get()?;
+
+    let email_change: EmailChange = email_changes_columns::email_changes
+        .filter(email_changes_columns::id.eq(email_change_id))
+        .filter(email_changes_columns::user_id.eq(user_id))
+        .first::<EmailChange>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Invalid email change request",
+            ..Default::default()
+        })?;
+
+    if email_change.expires_at < chrono::Local::now().naive_local() {
+        return Err(DefaultError {
+            message: "Email change request expired",
+            ..Default::default()
+        });
+    }
+
+    // the email could have been claimed since the verification link was sent
+    check_email_available_query(&email_change.new_email, pool)?;
+
+    let old_user: User = users_columns::users
+        .find(user_id)
+        .first::<User>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error finding user",
+            ..Default::default()
+        })?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let user: User = diesel::update(users_columns::users.find(user_id))
+            .set(users_columns::email.eq(&email_change.new_email))
+            .get_result(conn)?;
+
+        let stripe_customer: Option<StripeCustomer> = diesel::update(
+            stripe_customers_columns::stripe_customers
+                .filter(stripe_customers_columns::email.eq(&old_user.email)),
+        )
+        .set(stripe_customers_columns::email.eq(&email_change.new_email))
+        .get_result(conn)
+        .optional()?;
+
+        diesel::delete(
+            email_changes_columns::email_changes.filter(email_changes_columns::user_id.eq(user_id)),
+        )
+        .execute(conn)?;
+
+        Ok(ConfirmedEmailChange {
+            user,
+            stripe_customer,
+        })
+    })
+    .map_err(|_db_error| DefaultError {
+        message: "Error changing email, try again",
+        ..Default::default()
+    })
+}
+
+pub fn delete_expired_email_changes_query(pool: &Pool) -> Result<usize, DefaultError> {
+    use crate::data::schema::email_changes::dsl as email_changes_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::delete(
+        email_changes_columns::email_changes
+            .filter(email_changes_columns::expires_at.lt(chrono::Local::now().naive_local())),
+    )
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to delete expired email changes",
+        ..Default::default()
+    })
+}
@@ -1,5 +1,5 @@
 use crate::{
-    data::models::{Invitation, PasswordReset},
+    data::models::{EmailChange, Invitation, PasswordReset},
     errors::DefaultError,
 };
 use sendgrid::v3::{Content, Email, Message, Personalization, Sender};
@@ -11,7 +11,7 @@ pub fn send_invitation(app_url: String, invitation: &Invitation) -> Result<(), D
          {}/register</a> <br>
          your Invitation expires at <strong>{}</strong>",
         app_url,
-        invitation.id,
+        invitation.token,
         invitation.email,
         app_url,
         invitation.expires_at.format("%I:%M %p %A, %-d %B, %C%y")
@@ -59,6 +59,35 @@ pub fn send_password_reset(
     send_email(sg_email)
 }
 
+pub fn send_email_change_verification(
+    app_url: String,
+    email_change: &EmailChange,
+) -> Result<(), DefaultError> {
+    let sg_email_content = format!(
+        "Please click on the link below to confirm your new email address. <br/>
+         <a href=\"{}/auth/email/{}?email={}\">
+         {}/auth/email</a> <br>
+         your email change link expires at <strong>{}</strong>",
+        app_url,
+        email_change.id,
+        email_change.new_email,
+        app_url,
+        email_change.expires_at.format("%I:%M %p %A, %-d %B, %C%y")
+    );
+    let sg_email_personalization =
+        Personalization::new(Email::new(email_change.new_email.as_str()));
+    let sg_email = Message::new(Email::new("no-reply@arguflow.com"))
+        .set_subject("Confirm your new Arguflow AI email")
+        .add_content(
+            Content::new()
+                .set_content_type("text/html")
+                .set_value(sg_email_content),
+        )
+        .add_personalization(sg_email_personalization);
+
+    send_email(sg_email)
+}
+
 fn send_email(sg_email: Message) -> Result<(), DefaultError> {
     let sg_api_key = std::env::var("SENDGRID_API_KEY").expect("SENDGRID_API_KEY must be set");
     let sg_sender = Sender::new(sg_api_key);
@@ -67,6 +96,7 @@ fn send_email(sg_email: Message) -> Result<(), DefaultError> {
         Ok(_) => Ok(()),
         Err(_e) => Err(DefaultError {
             message: "Error sending email.",
+            ..Default::default()
         }),
     }
 }
@@ -1,11 +1,13 @@
 use crate::diesel::Connection;
-use actix_web::{body::MessageBody, web};
+use actix_web::{http::StatusCode, web};
 use base64::{
     alphabet,
     engine::{self, general_purpose},
     Engine as _,
 };
-use diesel::RunQueryDsl;
+use diesel::dsl::sql;
+use diesel::sql_types::Int8;
+use diesel::{Queryable, RunQueryDsl};
 use log::info;
 use regex::Regex;
 use s3::{creds::Credentials, Bucket, Region};
@@ -13,23 +15,30 @@ use serde::{Deserialize, Serialize};
 use soup::{NodeExt, QueryBuilderExt, Soup};
 use std::{process::Command, sync::MutexGuard};
 
-use crate::{data::models::CardCollection, handlers::card_handler::ReturnCreatedCard};
+use crate::data::models::CardCitation;
 use crate::{
     data::models::FileDTO,
-    diesel::{ExpressionMethods, QueryDsl},
+    diesel::{ExpressionMethods, QueryDsl, SelectableHelper},
     errors::ServiceError,
 };
 use crate::{
-    data::models::{File, Pool},
+    data::models::{CardMetadata, File, Pool},
     errors::DefaultError,
     handlers::{
         auth_handler::LoggedUser,
-        card_handler::{create_card, CreateCardData},
-        file_handler::UploadFileResult,
+        card_handler::{
+            create_card_with_embedding, find_full_text_collision, find_semantic_collision,
+            get_card_content_from_html, CreateCardData, DuplicateStrategy, CARD_DEDUP_THRESHOLD,
+        },
+        file_handler::{DuplicateCoreCard, FileCollectionStrategy, UploadFileResult},
     },
 };
 
+use super::card_operator::{
+    create_openai_embeddings_batch, delete_owned_cards_metadata_query, delete_qdrant_points_query,
+};
 use super::collection_operator::create_collection_and_add_bookmarks_query;
+use super::upload_progress_operator::{UploadProgress, UploadProgressEvent};
 
 pub fn get_aws_bucket() -> Result<Bucket, DefaultError> {
     let s3_access_key = std::env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set");
@@ -53,6 +62,7 @@ pub fn get_aws_bucket() -> Result<Bucket, DefaultError> {
     let aws_bucket = Bucket::new(&s3_bucket_name, aws_region, aws_credentials)
         .map_err(|_| DefaultError {
             message: "Could not create bucket",
+            ..Default::default()
         })?
         .with_path_style();
 
@@ -91,6 +101,7 @@ pub fn create_file_query(
 
     let mut conn = pool.get().map_err(|_| DefaultError {
         message: "Could not get database connection",
+        ..Default::default()
     })?;
 
     let new_file = File::from_details(user_id, file_name, mime_type, private, file_size);
@@ -100,11 +111,42 @@ pub fn create_file_query(
         .get_result(&mut conn)
         .map_err(|_| DefaultError {
             message: "Could not create file, try again",
+            ..Default::default()
         })?;
 
     Ok(created_file)
 }
 
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct UserFileUsage {
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+pub fn get_user_file_usage_query(
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<UserFileUsage, DefaultError> {
+    use crate::data::schema::files::dsl as files_columns;
+
+    let mut conn = pool.get().map_err(|_| DefaultError {
+        message: "Could not get database connection",
+        ..Default::default()
+    })?;
+
+    files_columns::files
+        .filter(files_columns::user_id.eq(user_id))
+        .select((
+            sql::<Int8>("count(*)"),
+            sql::<Int8>("COALESCE(SUM(size), 0)::int8"),
+        ))
+        .first::<UserFileUsage>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Could not get file usage, try again",
+            ..Default::default()
+        })
+}
+
 pub fn get_user_id_of_file_query(
     file_id: uuid::Uuid,
     pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
@@ -112,6 +154,7 @@ pub fn get_user_id_of_file_query(
     use crate::data::schema::files::dsl as files_columns;
     let mut conn = pool.get().map_err(|_| DefaultError {
         message: "Could not get database connection",
+        ..Default::default()
     })?;
     let file: uuid::Uuid = files_columns::files
         .filter(files_columns::id.eq(file_id))
@@ -119,10 +162,34 @@ pub fn get_user_id_of_file_query(
         .first(&mut conn)
         .map_err(|_| DefaultError {
             message: "Could not find file",
+            ..Default::default()
         })?;
     Ok(file)
 }
 
+pub fn get_file_cards_query(
+    file_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<CardMetadata>, DefaultError> {
+    use crate::data::schema::card_files::dsl as card_files_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get().map_err(|_| DefaultError {
+        message: "Could not get database connection",
+        ..Default::default()
+    })?;
+
+    card_files_columns::card_files
+        .inner_join(card_metadata_columns::card_metadata)
+        .filter(card_files_columns::file_id.eq(file_id))
+        .select(CardMetadata::as_select())
+        .load::<CardMetadata>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Could not load cards for file",
+            ..Default::default()
+        })
+}
+
 pub fn update_file_query(
     file_id: uuid::Uuid,
     private: bool,
@@ -131,6 +198,7 @@ pub fn update_file_query(
     use crate::data::schema::files::dsl as files_columns;
     let mut conn = pool.get().map_err(|_| DefaultError {
         message: "Could not get database connection",
+        ..Default::default()
     })?;
 
     diesel::update(files_columns::files.filter(files_columns::id.eq(file_id)))
@@ -138,94 +206,58 @@ pub fn update_file_query(
         .execute(&mut conn)
         .map_err(|_| DefaultError {
             message: "Could not update file, try again",
+            ..Default::default()
         })?;
     Ok(())
 }
 
+fn replace_card_html_emphasis(card_html: &str) -> String {
+    card_html
+        .replace("<em", "<u><b")
+        .replace("</em>", "</b></u>")
+}
+
+fn remove_temp_files(
+    temp_docx_file_path: &str,
+    temp_html_file_path: &std::path::Path,
+) -> Result<(), DefaultError> {
+    std::fs::remove_file(temp_docx_file_path).map_err(|_| DefaultError {
+        message: "Could not remove temp docx file",
+        ..Default::default()
+    })?;
+    std::fs::remove_file(temp_html_file_path).map_err(|_| DefaultError {
+        message: "Could not remove temp html file",
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CoreCard {
     pub card_html: String,
     pub link: String,
 }
 
-pub async fn convert_docx_to_html_query(
-    file_name: String,
-    file_data: Vec<u8>,
-    file_mime: String,
-    private: bool,
-    user: LoggedUser,
-    pool: web::Data<Pool>,
-) -> Result<UploadFileResult, DefaultError> {
-    let temp_docx_file_path = format!("./tmp/{}", file_name);
-    std::fs::write(&temp_docx_file_path, file_data.clone()).map_err(|_| DefaultError {
-        message: "Could not write file to disk",
-    })?;
-
-    let temp_html_file_path_buf = std::path::PathBuf::from(&format!(
-        "./tmp/{}.html",
-        file_name.split_once('.').unwrap_or_default().0
-    ));
-
-    let conversion_command_output =
-        Command::new(std::env::var("LIBREOFFICE_PATH").expect("LIBREOFFICE_PATH must be set"))
-            .arg("--headless")
-            .arg("--convert-to")
-            .arg("html")
-            .arg("--outdir")
-            .arg("./tmp")
-            .arg(&temp_docx_file_path)
-            .output();
+// A card starts at a heading and needs a link before any of its content is kept, text before the
+// next heading is the card's html. Documents only keep a card once the next heading closes it.
+pub fn split_document_into_cards(html: &str) -> Vec<CoreCard> {
+    split_html_into_cards(html, false)
+}
 
-    if conversion_command_output.is_err() {
-        return Err(DefaultError {
-            message: "Could not convert file",
-        });
-    }
+// Pasted text has no closing heading to wait for, so its last card is kept too
+pub fn split_text_into_cards(text: &str) -> Vec<CoreCard> {
+    split_html_into_cards(&convert_text_to_html(text), true)
+}
 
-    let html_string =
-        std::fs::read_to_string(&temp_html_file_path_buf).map_err(|_| DefaultError {
-            message: "Could not read html file",
-        })?;
-    let soup = Soup::new(&html_string);
+fn split_html_into_cards(html: &str, keep_last_card: bool) -> Vec<CoreCard> {
+    let soup = Soup::new(html);
+    // html5ever adds a body even to an empty document, so this is only a guard
     let body_tag = match soup.tag("body").find() {
         Some(body_tag) => body_tag,
-        None => {
-            return Err(DefaultError {
-                message: "Could not find body tag in html file",
-            })
-        }
-    };
-
-    let file_size = match file_data.len().try_into() {
-        Ok(file_size) => file_size,
-        Err(_) => {
-            return Err(DefaultError {
-                message: "Could not convert file size to i64",
-            })
-        }
+        None => return vec![],
     };
 
-    let created_file = create_file_query(
-        user.id,
-        &file_name,
-        &file_mime,
-        file_size,
-        private,
-        pool.clone(),
-    )?;
-
-    let bucket = get_aws_bucket()?;
-    bucket
-        .put_object_with_content_type(
-            created_file.id.to_string(),
-            file_data.as_slice(),
-            &file_mime,
-        )
-        .await
-        .map_err(|_| DefaultError {
-            message: "Could not upload file to S3",
-        })?;
-
     let mut cards: Vec<CoreCard> = vec![];
     let mut is_heading = false;
     let mut is_link = false;
@@ -277,18 +309,400 @@ pub async fn convert_docx_to_html_query(
         }
     }
 
+    if keep_last_card && is_heading && is_link {
+        cards.push(CoreCard {
+            card_html,
+            link: card_link,
+        });
+    }
+
+    cards
+}
+
+// Pasted text has no markup, so lines starting with #s become headings and the rest paragraphs
+pub fn convert_text_to_html(text: &str) -> String {
+    let mut html = String::from("<body>");
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let escaped_line = line
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        let heading_level = escaped_line.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&heading_level) {
+            html.push_str(&format!(
+                "<h{0}>{1}</h{0}>",
+                heading_level,
+                escaped_line[heading_level..].trim()
+            ));
+        } else {
+            html.push_str(&format!("<p>{}</p>", escaped_line));
+        }
+    }
+    html.push_str("</body>");
+
+    html
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CardSplitPreview {
+    pub card_html: String,
+    pub link: String,
+    pub word_count: usize,
+    pub rejection_reason: Option<String>,
+}
+
+pub fn preview_card_split(text: &str) -> Vec<CardSplitPreview> {
+    split_text_into_cards(text)
+        .into_iter()
+        .map(|card| {
+            let card_html = replace_card_html_emphasis(&card.card_html);
+            let word_count = Soup::new(&card_html).text().split_whitespace().count();
+            let rejection_reason = get_card_content_from_html(&Some(card_html))
+                .err()
+                .map(|reason| reason.to_string());
+
+            CardSplitPreview {
+                card_html: card.card_html,
+                link: card.link,
+                word_count,
+                rejection_reason,
+            }
+        })
+        .collect()
+}
+
+pub const DOCX_MIME_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+// Every type the upload pipeline knows how to turn into cards
+pub const SUPPORTED_UPLOAD_MIME_TYPES: &[&str] = &[DOCX_MIME_TYPE];
+
+// ALLOWED_UPLOAD_MIMES narrows the supported types for a deployment and an unset or empty list
+// allows everything supported. Unknown entries are logged and ignored, so a list with none of the
+// supported types turns uploads off rather than silently allowing all of them
+pub fn allowed_upload_mime_types() -> Vec<&'static str> {
+    let allowed_upload_mimes = std::env::var("ALLOWED_UPLOAD_MIMES").unwrap_or_default();
+    let allowed_upload_mimes: Vec<&str> = allowed_upload_mimes
+        .split(',')
+        .map(|allowed_mime| allowed_mime.trim())
+        .filter(|allowed_mime| !allowed_mime.is_empty())
+        .collect();
+
+    if allowed_upload_mimes.is_empty() {
+        return SUPPORTED_UPLOAD_MIME_TYPES.to_vec();
+    }
+
+    for allowed_mime in allowed_upload_mimes.iter() {
+        if !SUPPORTED_UPLOAD_MIME_TYPES
+            .iter()
+            .any(|mime_type| allowed_mime.eq_ignore_ascii_case(mime_type))
+        {
+            log::warn!(
+                "Ignoring unsupported mime type {} in ALLOWED_UPLOAD_MIMES",
+                allowed_mime
+            );
+        }
+    }
+
+    SUPPORTED_UPLOAD_MIME_TYPES
+        .iter()
+        .copied()
+        .filter(|mime_type| {
+            allowed_upload_mimes
+                .iter()
+                .any(|allowed_mime| allowed_mime.eq_ignore_ascii_case(mime_type))
+        })
+        .collect()
+}
+
+// LibreOffice writes the html next to the docx in ./tmp
+fn convert_docx_file_to_html(
+    temp_docx_file_path: &str,
+    temp_html_file_path: &std::path::Path,
+) -> Result<String, DefaultError> {
+    let conversion_command_output =
+        Command::new(std::env::var("LIBREOFFICE_PATH").expect("LIBREOFFICE_PATH must be set"))
+            .arg("--headless")
+            .arg("--convert-to")
+            .arg("html")
+            .arg("--outdir")
+            .arg("./tmp")
+            .arg(temp_docx_file_path)
+            .output();
+
+    if conversion_command_output.is_err() {
+        return Err(DefaultError {
+            message: "Could not convert file",
+            ..Default::default()
+        });
+    }
+
+    std::fs::read_to_string(temp_html_file_path).map_err(|_| DefaultError {
+        message: "Could not read html file",
+        ..Default::default()
+    })
+}
+
+// The reverse of upload, LibreOffice turns an html document into a docx in ./tmp
+pub fn convert_html_to_docx(html: &str) -> Result<Vec<u8>, DefaultError> {
+    let temp_file_stem = format!("export-{}", uuid::Uuid::new_v4());
+    let temp_html_file_path = format!("./tmp/{}.html", temp_file_stem);
+    let temp_docx_file_path = format!("./tmp/{}.docx", temp_file_stem);
+    std::fs::write(&temp_html_file_path, html).map_err(|_| DefaultError {
+        message: "Could not write export file",
+        ..Default::default()
+    })?;
+
+    let conversion_command_output =
+        Command::new(std::env::var("LIBREOFFICE_PATH").expect("LIBREOFFICE_PATH must be set"))
+            .arg("--headless")
+            .arg("--infilter=HTML (StarWriter)")
+            .arg("--convert-to")
+            .arg("docx:MS Word 2007 XML")
+            .arg("--outdir")
+            .arg("./tmp")
+            .arg(&temp_html_file_path)
+            .output();
+    let docx_data = conversion_command_output
+        .ok()
+        .and_then(|_| std::fs::read(&temp_docx_file_path).ok());
+
+    let _ = std::fs::remove_file(&temp_html_file_path);
+    let _ = std::fs::remove_file(&temp_docx_file_path);
+
+    docx_data.ok_or(DefaultError {
+        message: "Could not convert export to docx",
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DocxValidationErrorCode {
+    UnsupportedType,
+    CorruptFile,
+    EmptyDocument,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocxValidationError {
+    pub code: DocxValidationErrorCode,
+    pub message: &'static str,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocxValidation {
+    pub estimated_cards: usize,
+    pub valid_cards: usize,
+}
+
+// Runs the same conversion and split as an upload under a throwaway name, keeping nothing
+pub fn validate_docx_query(
+    file_data: &[u8],
+    file_mime: &str,
+) -> Result<DocxValidation, DocxValidationError> {
+    if file_mime != DOCX_MIME_TYPE {
+        return Err(DocxValidationError {
+            code: DocxValidationErrorCode::UnsupportedType,
+            message: "Must upload a docx file",
+        });
+    }
+    let corrupt_file = DocxValidationError {
+        code: DocxValidationErrorCode::CorruptFile,
+        message: "File is not a readable docx",
+    };
+    // docx files are zip archives
+    if !file_data.starts_with(b"PK\x03\x04") {
+        return Err(corrupt_file);
+    }
+
+    let temp_file_stem = format!("validate-{}", uuid::Uuid::new_v4());
+    let temp_docx_file_path = format!("./tmp/{}.docx", temp_file_stem);
+    let temp_html_file_path_buf =
+        std::path::PathBuf::from(format!("./tmp/{}.html", temp_file_stem));
+    std::fs::write(&temp_docx_file_path, file_data).map_err(|_| corrupt_file.clone())?;
+
+    let html_string = convert_docx_file_to_html(&temp_docx_file_path, &temp_html_file_path_buf);
+    let _ = std::fs::remove_file(&temp_docx_file_path);
+    let _ = std::fs::remove_file(&temp_html_file_path_buf);
+    let html_string = html_string.map_err(|_| corrupt_file)?;
+
+    if !Soup::new(&html_string)
+        .text()
+        .chars()
+        .any(|c| c.is_alphanumeric())
+    {
+        return Err(DocxValidationError {
+            code: DocxValidationErrorCode::EmptyDocument,
+            message: "Document has no text",
+        });
+    }
+
+    let cards = split_document_into_cards(&html_string);
+    let valid_cards = cards
+        .iter()
+        .filter(|card| {
+            get_card_content_from_html(&Some(replace_card_html_emphasis(&card.card_html))).is_ok()
+        })
+        .count();
+
+    Ok(DocxValidation {
+        estimated_cards: cards.len(),
+        valid_cards,
+    })
+}
+
+// Briefs often repeat passages on purpose, so uploads can be made less eager to collapse them
+// into one card than creating a card by hand, which always uses CARD_DEDUP_THRESHOLD. A card that
+// collides still gets saved as a duplicate pointing at the existing card's vector, or fills in
+// the html of a matching card that has none. Short cards use a slightly lower threshold in both
+// cases, and the full text match that runs before the semantic check is not affected.
+fn file_dedup_threshold() -> f32 {
+    std::env::var("FILE_DEDUP_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(CARD_DEDUP_THRESHOLD)
+        .clamp(0.0, 1.0)
+}
+
+pub async fn convert_docx_to_html_query(
+    file_name: String,
+    file_data: Vec<u8>,
+    file_mime: String,
+    private: bool,
+    dry_run: bool,
+    collection_strategy: FileCollectionStrategy,
+    user: LoggedUser,
+    progress: UploadProgress,
+    pool: web::Data<Pool>,
+) -> Result<UploadFileResult, DefaultError> {
+    progress.report(UploadProgressEvent::Converting);
+
+    let temp_docx_file_path = format!("./tmp/{}", file_name);
+    std::fs::write(&temp_docx_file_path, file_data.clone()).map_err(|_| DefaultError {
+        message: "Could not write file to disk",
+        ..Default::default()
+    })?;
+
+    let temp_html_file_path_buf = std::path::PathBuf::from(&format!(
+        "./tmp/{}.html",
+        file_name.split_once('.').unwrap_or_default().0
+    ));
+
+    let html_string = convert_docx_file_to_html(&temp_docx_file_path, &temp_html_file_path_buf)?;
+    let file_size = match file_data.len().try_into() {
+        Ok(file_size) => file_size,
+        Err(_) => {
+            return Err(DefaultError {
+                message: "Could not convert file size to i64",
+                ..Default::default()
+            })
+        }
+    };
+
+    // a dry run only previews how the file splits into cards, so nothing is persisted
+    let created_file = if dry_run {
+        File::from_details(user.id, &file_name, &file_mime, private, file_size)
+    } else {
+        let created_file = create_file_query(
+            user.id,
+            &file_name,
+            &file_mime,
+            file_size,
+            private,
+            pool.clone(),
+        )?;
+
+        let bucket = get_aws_bucket()?;
+        bucket
+            .put_object_with_content_type(
+                created_file.id.to_string(),
+                file_data.as_slice(),
+                &file_mime,
+            )
+            .await
+            .map_err(|_| DefaultError {
+                message: "Could not upload file to S3",
+                ..Default::default()
+            })?;
+
+        created_file
+    };
+
+    let cards = split_document_into_cards(&html_string);
+    let total_cards = cards.len();
+    progress.report(UploadProgressEvent::Parsed { total_cards });
+
+    let dedup_threshold = file_dedup_threshold();
+
+    if dry_run {
+        let (created_cards, rejected_cards, duplicate_cards) =
+            preview_file_cards(cards, dedup_threshold, user.id, pool).await?;
+
+        remove_temp_files(&temp_docx_file_path, &temp_html_file_path_buf)?;
+
+        return Ok(UploadFileResult {
+            file_metadata: created_file,
+            collection_id: None,
+            collection_behavior: None,
+            created_cards,
+            rejected_cards,
+            duplicate_cards: Some(duplicate_cards),
+        });
+    }
+
     let mut created_cards: Vec<CoreCard> = [].to_vec();
     let mut rejected_cards: Vec<CoreCard> = [].to_vec();
-    let mut card_metadata: ReturnCreatedCard;
     let mut card_ids: Vec<uuid::Uuid> = [].to_vec();
 
     let pool1 = pool.clone();
 
-    for card in cards {
-        let replaced_card_html = card
-            .card_html
-            .replace("<em", "<u><b")
-            .replace("</em>", "</b></u>");
+    let mut cards_need_embedding: Vec<bool> = vec![];
+    let mut card_contents_to_embed: Vec<String> = vec![];
+    for card in cards.iter() {
+        let card_content =
+            match get_card_content_from_html(&Some(replace_card_html_emphasis(&card.card_html))) {
+                Ok(card_content) => card_content,
+                Err(_) => {
+                    cards_need_embedding.push(false);
+                    continue;
+                }
+            };
+
+        // a card matching an existing one by text is saved as a duplicate without ever using
+        // its embedding, so it is left out of the batch
+        let text_collision =
+            find_full_text_collision(card_content.clone(), "english".to_string(), pool.clone())
+                .await
+                .map_err(|err| {
+                    info!("Error checking card for duplicates: {:?}", err.to_string());
+
+                    DefaultError {
+                        message: "Could not check file cards for duplicates",
+                        ..Default::default()
+                    }
+                })?;
+        cards_need_embedding.push(text_collision.is_none());
+        if text_collision.is_none() {
+            card_contents_to_embed.push(card_content);
+        }
+    }
+
+    // embed the remaining cards up front in batches instead of one request per card
+    let mut embedding_vectors = create_openai_embeddings_batch(card_contents_to_embed)
+        .await
+        .map_err(file_embeddings_error)?
+        .into_iter();
+
+    for (processed_cards, (card, needs_embedding)) in
+        cards.into_iter().zip(cards_need_embedding).enumerate()
+    {
+        let replaced_card_html = replace_card_html_emphasis(&card.card_html);
+        let embedding_vector = if needs_embedding {
+            embedding_vectors.next()
+        } else {
+            None
+        };
 
         let create_card_data = CreateCardData {
             card_html: Some(replaced_card_html.clone()),
@@ -296,27 +710,24 @@ pub async fn convert_docx_to_html_query(
             oc_file_path: None,
             private: Some(private),
             file_uuid: Some(created_file.id),
+            language: None,
+            citation: CardCitation::default(),
+            duplicate_strategy: DuplicateStrategy::default(),
         };
         let web_json_create_card_data = web::Json(create_card_data);
 
-        match create_card(web_json_create_card_data, pool.clone(), user.clone()).await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    created_cards.push(card);
-                    card_metadata = serde_json::from_slice(
-                        response.into_body().try_into_bytes().unwrap().as_ref(),
-                    )
-                    .map_err(|err| {
-                        info!("Error creating card metadata: {:?}", err.to_string());
-
-                        DefaultError {
-                            message: "Error creating card metadata's for file",
-                        }
-                    })?;
-                    card_ids.push(card_metadata.card_metadata.id);
-                } else {
-                    rejected_cards.push(card);
-                }
+        match create_card_with_embedding(
+            web_json_create_card_data,
+            pool.clone(),
+            user.clone(),
+            embedding_vector,
+            dedup_threshold,
+        )
+        .await
+        {
+            Ok(created_card) => {
+                created_cards.push(card);
+                card_ids.push(created_card.card_metadata.id);
             }
             Err(error) => {
                 info!("Error creating card: {:?}", error.to_string());
@@ -324,55 +735,138 @@ pub async fn convert_docx_to_html_query(
                 rejected_cards.push(card)
             }
         }
+
+        progress.report(UploadProgressEvent::CardsProcessed {
+            processed: processed_cards + 1,
+            total: total_cards,
+        });
     }
 
-    let collection_id: uuid::Uuid;
-    match web::block(move || {
+    let (collection_id, collection_behavior) = web::block(move || {
         create_collection_and_add_bookmarks_query(
-            CardCollection::from_details(
-                user.id,
-                format!("Collection for file {}", file_name),
-                !private,
-                "".to_string(),
-            ),
+            user.id,
+            file_name,
+            !private,
             card_ids,
             created_file.id,
+            collection_strategy,
             pool1,
         )
     })
     .await
-    {
-        Ok(response) => match response {
-            Ok(collection) => collection_id = collection.id,
-            Err(err) => return Err(err),
-        },
-        Err(_) => {
-            return Err(DefaultError {
-                message: "Error creating collection",
-            })
-        }
-    }
+    .map_err(|_| DefaultError {
+        message: "Error creating collection",
+        ..Default::default()
+    })??;
 
-    std::fs::remove_file(&temp_docx_file_path).map_err(|_| DefaultError {
-        message: "Could not remove temp docx file",
-    })?;
-    std::fs::remove_file(&temp_html_file_path_buf).map_err(|_| DefaultError {
-        message: "Could not remove temp html file",
-    })?;
+    remove_temp_files(&temp_docx_file_path, &temp_html_file_path_buf)?;
 
     Ok(UploadFileResult {
         file_metadata: created_file,
-        collection_id,
+        collection_id: Some(collection_id),
+        collection_behavior: Some(collection_behavior),
         created_cards,
         rejected_cards,
+        duplicate_cards: None,
     })
 }
 
-pub async fn get_file_query(
+fn file_embeddings_error(err: actix_web::Error) -> DefaultError {
+    info!("Error creating card embeddings: {:?}", err.to_string());
+
+    if err.as_response_error().status_code() == StatusCode::SERVICE_UNAVAILABLE {
+        return DefaultError {
+            message: "Too many files are being processed, try again later",
+            ..Default::default()
+        };
+    }
+    DefaultError {
+        message: "Could not create embeddings for file",
+        ..Default::default()
+    }
+}
+
+// Runs the same text and semantic collision checks an upload would, without writing anything,
+// so a dry run shows which cards would be saved as duplicates
+async fn preview_file_cards(
+    cards: Vec<CoreCard>,
+    dedup_threshold: f32,
+    user_id: uuid::Uuid,
+    pool: web::Data<Pool>,
+) -> Result<(Vec<CoreCard>, Vec<CoreCard>, Vec<DuplicateCoreCard>), DefaultError> {
+    let duplicate_check_error = |err: actix_web::Error| {
+        info!("Error checking card for duplicates: {:?}", err.to_string());
+
+        DefaultError {
+            message: "Could not check file cards for duplicates",
+            ..Default::default()
+        }
+    };
+
+    let mut created_cards: Vec<CoreCard> = vec![];
+    let mut rejected_cards: Vec<CoreCard> = vec![];
+    let mut duplicate_cards: Vec<DuplicateCoreCard> = vec![];
+    let mut unmatched_cards: Vec<(CoreCard, String)> = vec![];
+
+    for card in cards {
+        let card_content =
+            match get_card_content_from_html(&Some(replace_card_html_emphasis(&card.card_html))) {
+                Ok(card_content) => card_content,
+                Err(_) => {
+                    rejected_cards.push(card);
+                    continue;
+                }
+            };
+
+        match find_full_text_collision(card_content.clone(), "english".to_string(), pool.clone())
+            .await
+            .map_err(duplicate_check_error)?
+        {
+            Some(duplicate_of) => duplicate_cards.push(DuplicateCoreCard {
+                card: card.clone(),
+                duplicate_of,
+            }),
+            None => unmatched_cards.push((card.clone(), card_content)),
+        }
+        created_cards.push(card);
+    }
+
+    // only cards without a text match need an embedding, same as an upload
+    let embedding_vectors = create_openai_embeddings_batch(
+        unmatched_cards
+            .iter()
+            .map(|(_, card_content)| card_content.clone())
+            .collect(),
+    )
+    .await
+    .map_err(file_embeddings_error)?;
+
+    for ((card, card_content), embedding_vector) in
+        unmatched_cards.into_iter().zip(embedding_vectors)
+    {
+        if let Some(duplicate_of) = find_semantic_collision(
+            embedding_vector,
+            &card_content,
+            dedup_threshold,
+            user_id,
+            pool.clone(),
+        )
+        .await
+        .map_err(duplicate_check_error)?
+        {
+            duplicate_cards.push(DuplicateCoreCard { card, duplicate_of });
+        }
+    }
+
+    Ok((created_cards, rejected_cards, duplicate_cards))
+}
+
+// Applies the file's privacy rules before fetching the original upload from S3
+pub async fn get_file_data_query(
     file_uuid: uuid::Uuid,
     user_uuid: Option<uuid::Uuid>,
     pool: web::Data<Pool>,
-) -> Result<FileDTO, actix_web::Error> {
+) -> Result<(File, Vec<u8>), actix_web::Error> {
     use crate::data::schema::files::dsl as files_columns;
 
     let mut conn = pool
@@ -399,6 +893,16 @@ pub async fn get_file_query(
         .map_err(|_| ServiceError::BadRequest("Could not get file from S3".to_string()))?
         .to_vec();
 
+    Ok((file_metadata, file_data))
+}
+
+pub async fn get_file_query(
+    file_uuid: uuid::Uuid,
+    user_uuid: Option<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<FileDTO, actix_web::Error> {
+    let (file_metadata, file_data) = get_file_data_query(file_uuid, user_uuid, pool).await?;
+
     let base64_engine = engine::GeneralPurpose::new(&alphabet::URL_SAFE, general_purpose::NO_PAD);
     let base64_file_data = base64_engine.encode(file_data);
 
@@ -459,10 +963,16 @@ pub async fn delete_file_query(
         .get_result(&mut conn)
         .map_err(|_| ServiceError::NotFound)?;
 
-    if file_metadata.private && user_uuid != file_metadata.user_id {
+    if user_uuid != file_metadata.user_id {
         return Err(ServiceError::Forbidden.into());
     }
 
+    let file_card_ids: Vec<uuid::Uuid> = card_files_columns::card_files
+        .filter(card_files_columns::file_id.eq(file_uuid))
+        .select(card_files_columns::card_id)
+        .load(&mut conn)
+        .map_err(|_| ServiceError::BadRequest("Could not load cards for file".to_string()))?;
+
     let bucket = get_aws_bucket().map_err(|e| ServiceError::BadRequest(e.message.to_string()))?;
     bucket
         .delete_object(file_metadata.id.to_string())
@@ -486,5 +996,106 @@ pub async fn delete_file_query(
         Err(_) => return Err(ServiceError::BadRequest("Could not delete file".to_string()).into()),
     }
 
+    // The file's cards go with it, otherwise their points keep surfacing in search
+    let deleted_cards = delete_owned_cards_metadata_query(file_card_ids, user_uuid, &pool)
+        .map_err(ServiceError::from)?;
+
+    delete_qdrant_points_query(&deleted_cards.orphaned_qdrant_point_ids)
+        .await
+        .map_err(ServiceError::from)?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed from what LibreOffice writes for a debate file, highlighting comes out as background
+    // spans and each tag is a heading followed by its cite
+    const HIGHLIGHTED_DOCUMENT: &str = r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.0 Transitional//EN">
+<html>
+<head><meta http-equiv="content-type" content="text/html; charset=utf-8"/></head>
+<body lang="en-US" dir="ltr">
+<h1 class="western">1AC</h1>
+<h4 class="western">Warming is real and anthropogenic</h4>
+<p style="margin-bottom: 0in"><b>Hausfather 21</b> (Zeke, climate scientist) https://www.carbonbrief.org/state-of-the-climate, </p>
+<p style="margin-bottom: 0in">The <span style="background: #ffff00"><u><b>planet has warmed</b></u></span> by about <em>1.2C</em> since pre-industrial times.</p>
+<h4 class="western">Emissions keep rising</h4>
+<p style="margin-bottom: 0in"><b>IEA 22</b> https://www.iea.org/reports/co2-emissions-in-2022</p>
+<p style="margin-bottom: 0in"><span style="background: #00ffff">Global emissions grew</span> again last year.</p>
+<h4 class="western">Last card has nothing after it to close it</h4>
+<p style="margin-bottom: 0in">https://example.com/unclosed</p>
+<p style="margin-bottom: 0in">Dropped content.</p>
+</body>
+</html>"#;
+
+    const TABLE_DOCUMENT: &str = r#"<html>
+<body>
+<h4>Costs are declining</h4>
+<a href="https://www.irena.org/publications/2022/Jul/Renewable-Power-Generation-Costs-in-2021">IRENA 22</a>
+<p>Prices fell across every technology.</p>
+<table width="100%" cellpadding="4" cellspacing="0">
+<tr><td><p>Solar PV</p></td><td><p>-88%</p></td></tr>
+<tr><td><p>Onshore wind</p></td><td><p>-68%</p></td></tr>
+</table>
+<h4>Next tag</h4>
+</body>
+</html>"#;
+
+    #[test]
+    fn splits_highlighted_cards_at_each_heading() {
+        let cards = split_document_into_cards(HIGHLIGHTED_DOCUMENT);
+
+        assert_eq!(cards.len(), 2);
+        assert_eq!(
+            cards[0].link,
+            "https://www.carbonbrief.org/state-of-the-climate"
+        );
+        assert!(cards[0].card_html.contains(
+            r#"<span style="background: #ffff00"><u><b>planet has warmed</b></u></span>"#
+        ));
+        assert!(cards[0].card_html.contains("<em>1.2C</em>"));
+        assert_eq!(
+            cards[1].link,
+            "https://www.iea.org/reports/co2-emissions-in-2022"
+        );
+        assert!(cards[1].card_html.contains("Global emissions grew"));
+    }
+
+    #[test]
+    fn leaves_headings_and_cites_out_of_card_html() {
+        let cards = split_document_into_cards(HIGHLIGHTED_DOCUMENT);
+
+        for card in &cards {
+            assert!(!card.card_html.contains("<h1"));
+            assert!(!card.card_html.contains("<h4"));
+            assert!(!card.card_html.contains("https://"));
+        }
+        assert!(!cards
+            .iter()
+            .any(|card| card.card_html.contains("Dropped content")));
+    }
+
+    #[test]
+    fn keeps_tables_inside_a_card() {
+        let cards = split_document_into_cards(TABLE_DOCUMENT);
+
+        assert_eq!(cards.len(), 1);
+        assert_eq!(
+            cards[0].link,
+            "https://www.irena.org/publications/2022/Jul/Renewable-Power-Generation-Costs-in-2021"
+        );
+        assert!(cards[0].card_html.contains("<table"));
+        assert!(cards[0].card_html.contains("Onshore wind"));
+    }
+
+    #[test]
+    fn pasted_text_keeps_its_last_card() {
+        let cards = split_text_into_cards("# Tag\nhttps://example.com/source\nThe evidence.");
+
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].link, "https://example.com/source");
+        assert_eq!(cards[0].card_html, "<p>The evidence.</p>");
+    }
+}
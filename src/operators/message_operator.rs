@@ -1,10 +1,12 @@
 use crate::diesel::prelude::*;
 use crate::operators::topic_operator::get_topic_query;
 use crate::{
-    data::models::{Message, Pool},
+    data::models::{Message, MessageVersion, PageInfo, Pool},
     errors::DefaultError,
 };
 use actix_web::web;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Double, Int8, Text};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,13 +15,41 @@ pub struct ChatCompletionDTO {
     pub completion_tokens: i32,
 }
 
+pub fn get_token_count(text: &str, model: &str) -> Result<usize, DefaultError> {
+    let bpe = tiktoken_rs::get_bpe_from_model(model).map_err(|_err| DefaultError {
+        message: "Unsupported model for tokenization",
+        ..Default::default()
+    })?;
+
+    Ok(bpe.encode_with_special_tokens(text).len())
+}
+
+// Follows OpenAI's counting for chat models, every message carries a few tokens of framing and
+// the reply is primed with a few more
+pub fn get_prompt_token_count(messages: &[Message], model: &str) -> Result<usize, DefaultError> {
+    let bpe = tiktoken_rs::get_bpe_from_model(model).map_err(|_err| DefaultError {
+        message: "Unsupported model for tokenization",
+        ..Default::default()
+    })?;
+
+    let message_tokens: usize = messages
+        .iter()
+        .map(|message| {
+            3 + bpe.encode_with_special_tokens(&message.role).len()
+                + bpe.encode_with_special_tokens(&message.content).len()
+        })
+        .sum();
+
+    Ok(message_tokens + 3)
+}
+
 pub fn get_topic_messages(
     messages_topic_id: uuid::Uuid,
     pool: &web::Data<Pool>,
 ) -> Result<Vec<Message>, DefaultError> {
     use crate::data::schema::messages::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let topic_messages = messages
         .filter(topic_id.eq(messages_topic_id))
@@ -28,11 +58,56 @@ pub fn get_topic_messages(
         .load::<Message>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error getting topic messages",
+            ..Default::default()
         })?;
 
     Ok(topic_messages)
 }
 
+// A regenerated reply takes over the sort order of the one it replaced, so clients polling for a
+// regeneration ask from the sort order before that reply
+pub fn get_topic_messages_after_query(
+    messages_topic_id: uuid::Uuid,
+    after_sort_order: i32,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<Message>, DefaultError> {
+    use crate::data::schema::messages::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    messages
+        .filter(topic_id.eq(messages_topic_id))
+        .filter(deleted.eq(false))
+        .filter(sort_order.gt(after_sort_order))
+        .order(sort_order.asc())
+        .load::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting topic messages",
+            ..Default::default()
+        })
+}
+
+// System messages are not written by the user, so they don't count towards the plan limit
+pub fn get_message_count_for_topic_query(
+    messages_topic_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<i64, DefaultError> {
+    use crate::data::schema::messages::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    messages
+        .filter(topic_id.eq(messages_topic_id))
+        .filter(deleted.eq(false))
+        .filter(role.ne("system"))
+        .count()
+        .get_result(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error counting topic messages",
+            ..Default::default()
+        })
+}
+
 pub fn user_owns_topic_query(
     user_given_id: uuid::Uuid,
     topic_id: uuid::Uuid,
@@ -60,12 +135,13 @@ pub fn create_message_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::messages::dsl::messages;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     match get_topic_query(new_message.topic_id, pool) {
         Ok(topic) if topic.user_id != given_user_id => {
             return Err(DefaultError {
                 message: "Unauthorized",
+                ..Default::default()
             })
         }
         Ok(_topic) => {}
@@ -77,6 +153,31 @@ pub fn create_message_query(
         .execute(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error creating message, try again",
+            ..Default::default()
+        })?;
+
+    Ok(())
+}
+
+// Overwrites a streamed assistant message with the text generated so far
+pub fn update_streamed_message_query(
+    streamed_message: &Message,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::messages::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    diesel::update(messages.filter(id.eq(streamed_message.id)))
+        .set((
+            content.eq(&streamed_message.content),
+            completion_tokens.eq(streamed_message.completion_tokens),
+            finish_reason.eq(&streamed_message.finish_reason),
+        ))
+        .execute(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error updating message, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -161,7 +262,7 @@ pub fn get_message_by_sort_for_topic_query(
 ) -> Result<Message, DefaultError> {
     use crate::data::schema::messages::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     messages
         .filter(deleted.eq(false))
@@ -170,6 +271,7 @@ pub fn get_message_by_sort_for_topic_query(
         .first::<Message>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "This message does not exist for the authenticated user",
+            ..Default::default()
         })
 }
 
@@ -179,7 +281,7 @@ pub fn get_messages_for_topic_query(
 ) -> Result<Vec<Message>, DefaultError> {
     use crate::data::schema::messages::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     messages
         .filter(topic_id.eq(message_topic_id))
@@ -188,9 +290,114 @@ pub fn get_messages_for_topic_query(
         .load::<Message>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "This topic does not exist for the authenticated user",
+            ..Default::default()
         })
 }
 
+// Page 1 holds the most recent messages and higher pages go further back in the conversation,
+// each page still ordered oldest to newest
+pub fn get_paginated_messages_for_topic_query(
+    message_topic_id: uuid::Uuid,
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<Message>, i64), DefaultError> {
+    use crate::data::schema::messages::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    let total_message_count: i64 = messages
+        .filter(topic_id.eq(message_topic_id))
+        .filter(deleted.eq(false))
+        .count()
+        .get_result(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error counting topic messages",
+            ..Default::default()
+        })?;
+
+    let mut page_messages = messages
+        .filter(topic_id.eq(message_topic_id))
+        .filter(deleted.eq(false))
+        .order_by(sort_order.desc())
+        .limit(per_page)
+        .offset(PageInfo::offset(page, per_page))
+        .load::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "This topic does not exist for the authenticated user",
+            ..Default::default()
+        })?;
+    page_messages.reverse();
+
+    Ok((page_messages, total_message_count))
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct MessageSearchResult {
+    pub message_id: uuid::Uuid,
+    pub topic_id: uuid::Uuid,
+    pub role: String,
+    pub snippet: String,
+    pub created_at: chrono::NaiveDateTime,
+    #[serde(skip)]
+    pub full_count: i64,
+}
+
+// Only messages in the user's own topics are searched, best matches first
+pub fn search_user_messages_query(
+    given_user_id: uuid::Uuid,
+    user_query: String,
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<MessageSearchResult>, i64), DefaultError> {
+    use crate::data::schema::messages::dsl as messages_columns;
+    use crate::data::schema::topics::dsl as topics_columns;
+
+    let mut conn = pool.get()?;
+
+    let results = messages_columns::messages
+        .inner_join(topics_columns::topics)
+        .filter(topics_columns::user_id.eq(given_user_id))
+        .filter(topics_columns::deleted.eq(false))
+        .filter(messages_columns::deleted.eq(false))
+        .filter(messages_columns::role.ne("system"))
+        .filter(
+            sql::<Bool>("to_tsvector('english', messages.content) @@ plainto_tsquery('english', ")
+                .bind::<Text, _>(user_query.clone())
+                .sql(")"),
+        )
+        .select((
+            messages_columns::id,
+            messages_columns::topic_id,
+            messages_columns::role,
+            sql::<Text>("ts_headline('english', messages.content, plainto_tsquery('english', ")
+                .bind::<Text, _>(user_query.clone())
+                .sql("), 'StartSel=**, StopSel=**, MaxWords=35, MinWords=15')"),
+            messages_columns::created_at,
+            sql::<Int8>("count(*) OVER() AS full_count"),
+        ))
+        .order((
+            sql::<Double>(
+                "ts_rank(to_tsvector('english', messages.content), plainto_tsquery('english', ",
+            )
+            .bind::<Text, _>(user_query)
+            .sql(")) DESC"),
+            messages_columns::created_at.desc(),
+        ))
+        .limit(per_page)
+        .offset(PageInfo::offset(page, per_page))
+        .load::<MessageSearchResult>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error searching messages",
+            ..Default::default()
+        })?;
+
+    let total_count = results.first().map(|result| result.full_count).unwrap_or(0);
+
+    Ok((results, total_count))
+}
+
 pub fn delete_message_query(
     given_user_id: &uuid::Uuid,
     given_message_id: uuid::Uuid,
@@ -199,12 +406,13 @@ pub fn delete_message_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::messages::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     match get_topic_query(given_topic_id, pool) {
         Ok(topic) if topic.user_id != *given_user_id => {
             return Err(DefaultError {
                 message: "Unauthorized",
+                ..Default::default()
             })
         }
         Ok(_topic) => {}
@@ -216,18 +424,142 @@ pub fn delete_message_query(
         .first::<Message>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error finding message",
+            ..Default::default()
         })?;
 
-    diesel::update(
-        messages
-            .filter(topic_id.eq(given_topic_id))
-            .filter(sort_order.ge(target_message.sort_order)),
-    )
-    .set(deleted.eq(true))
-    .execute(&mut conn)
+    // keep the replaced content of every message being removed, so edits and regenerations can
+    // be looked back on
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let deleted_messages = diesel::update(
+            messages
+                .filter(topic_id.eq(given_topic_id))
+                .filter(sort_order.ge(target_message.sort_order))
+                .filter(deleted.eq(false)),
+        )
+        .set(deleted.eq(true))
+        .get_results::<Message>(conn)?;
+
+        let deleted_message_versions: Vec<MessageVersion> = deleted_messages
+            .into_iter()
+            .map(MessageVersion::from)
+            .collect();
+        diesel::insert_into(crate::data::schema::message_versions::dsl::message_versions)
+            .values(&deleted_message_versions)
+            .execute(conn)?;
+
+        Ok(())
+    })
     .map_err(|_| DefaultError {
         message: "Error deleting message",
+        ..Default::default()
     })?;
 
     Ok(())
 }
+
+pub fn set_message_pinned_query(
+    given_user_id: uuid::Uuid,
+    given_message_id: uuid::Uuid,
+    message_pinned: bool,
+    pool: &web::Data<Pool>,
+) -> Result<Message, DefaultError> {
+    use crate::data::schema::messages::dsl as messages_columns;
+
+    let mut conn = pool.get()?;
+
+    let target_message: Message = messages_columns::messages
+        .find(given_message_id)
+        .filter(messages_columns::deleted.eq(false))
+        .first::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error finding message",
+            ..Default::default()
+        })?;
+
+    match get_topic_query(target_message.topic_id, pool) {
+        Ok(topic) if topic.user_id != given_user_id => {
+            return Err(DefaultError {
+                message: "Unauthorized",
+                ..Default::default()
+            })
+        }
+        Ok(_topic) => {}
+        Err(e) => return Err(e),
+    };
+
+    diesel::update(messages_columns::messages.find(given_message_id))
+        .set(messages_columns::pinned.eq(message_pinned))
+        .get_result::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error updating message, try again",
+            ..Default::default()
+        })
+}
+
+pub fn get_pinned_messages_for_topic_query(
+    given_user_id: uuid::Uuid,
+    message_topic_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<Message>, DefaultError> {
+    use crate::data::schema::messages::dsl as messages_columns;
+
+    if !user_owns_topic_query(given_user_id, message_topic_id, pool) {
+        return Err(DefaultError {
+            message: "Unauthorized",
+            ..Default::default()
+        });
+    }
+
+    let mut conn = pool.get()?;
+
+    messages_columns::messages
+        .filter(messages_columns::topic_id.eq(message_topic_id))
+        .filter(messages_columns::pinned.eq(true))
+        .filter(messages_columns::deleted.eq(false))
+        .order(messages_columns::sort_order.asc())
+        .load::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting pinned messages",
+            ..Default::default()
+        })
+}
+
+pub fn get_message_history_query(
+    given_user_id: uuid::Uuid,
+    given_message_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<MessageVersion>, DefaultError> {
+    use crate::data::schema::message_versions::dsl as message_versions_columns;
+    use crate::data::schema::messages::dsl as messages_columns;
+
+    let mut conn = pool.get()?;
+
+    let target_message: Message = messages_columns::messages
+        .find(given_message_id)
+        .first::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error finding message",
+            ..Default::default()
+        })?;
+
+    match get_topic_query(target_message.topic_id, pool) {
+        Ok(topic) if topic.user_id != given_user_id => {
+            return Err(DefaultError {
+                message: "Unauthorized",
+                ..Default::default()
+            })
+        }
+        Ok(_topic) => {}
+        Err(e) => return Err(e),
+    };
+
+    message_versions_columns::message_versions
+        .filter(message_versions_columns::topic_id.eq(target_message.topic_id))
+        .filter(message_versions_columns::sort_order.eq(target_message.sort_order))
+        .order(message_versions_columns::created_at.asc())
+        .load::<MessageVersion>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting message history",
+            ..Default::default()
+        })
+}
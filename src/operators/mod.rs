@@ -1,12 +1,20 @@
 pub mod card_operator;
 pub mod collection_operator;
+pub mod email_change_operator;
 pub mod email_operator;
 pub mod file_operator;
 pub mod message_operator;
+pub mod moderation_operator;
 pub mod notification_operator;
 pub mod password_reset_operator;
+pub mod search_operator;
+pub mod session_operator;
+pub mod stats_operator;
+pub mod streaming_message_operator;
 pub mod stripe_customer_operator;
 pub mod topic_operator;
+pub mod upload_progress_operator;
 pub mod user_operator;
 pub mod verification_operator;
 pub mod vote_operator;
+pub mod webhook_operator;
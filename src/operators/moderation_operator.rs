@@ -0,0 +1,119 @@
+use crate::diesel::prelude::*;
+use crate::{
+    data::models::{PageInfo, Pool},
+    errors::DefaultError,
+};
+use actix_web::web;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Int8};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct ModerationCard {
+    pub id: uuid::Uuid,
+    pub content: String,
+    pub link: Option<String>,
+    pub author_id: uuid::Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub upvotes: i64,
+    pub downvotes: i64,
+    #[serde(skip)]
+    pub full_count: i64,
+}
+
+const NET_VOTES_SQL: &str = concat!(
+    "(count(card_votes.id) FILTER (WHERE card_votes.vote)",
+    " - count(card_votes.id) FILTER (WHERE NOT card_votes.vote))"
+);
+
+// Public cards whose net votes are below the threshold, lowest scoring first
+pub fn get_moderation_queue_query(
+    net_vote_threshold: i64,
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<ModerationCard>, i64), DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
+    let mut conn = pool.get()?;
+
+    let cards = card_metadata_columns::card_metadata
+        .left_outer_join(
+            card_votes_columns::card_votes.on(card_votes_columns::card_metadata_id
+                .eq(card_metadata_columns::id)
+                .and(card_votes_columns::deleted.eq(false))),
+        )
+        .filter(card_metadata_columns::private.eq(false))
+        .group_by(card_metadata_columns::id)
+        .having(sql::<Bool>(&format!("{} < ", NET_VOTES_SQL)).bind::<Int8, _>(net_vote_threshold))
+        .select((
+            card_metadata_columns::id,
+            card_metadata_columns::content,
+            card_metadata_columns::link,
+            card_metadata_columns::author_id,
+            card_metadata_columns::created_at,
+            sql::<Int8>("count(card_votes.id) FILTER (WHERE card_votes.vote)"),
+            sql::<Int8>("count(card_votes.id) FILTER (WHERE NOT card_votes.vote)"),
+            sql::<Int8>("count(*) OVER() AS full_count"),
+        ))
+        .order((
+            sql::<Int8>(&format!("{} ASC", NET_VOTES_SQL)),
+            card_metadata_columns::created_at.desc(),
+        ))
+        .limit(per_page)
+        .offset(PageInfo::offset(page, per_page))
+        .load::<ModerationCard>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to load moderation queue",
+            ..Default::default()
+        })?;
+
+    let total_count = cards.first().map(|card| card.full_count).unwrap_or(0);
+
+    Ok((cards, total_count))
+}
+
+// Cards waiting for approval, oldest first so nothing waits forever
+pub fn get_pending_cards_query(
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<ModerationCard>, i64), DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
+    let mut conn = pool.get()?;
+
+    let cards = card_metadata_columns::card_metadata
+        .left_outer_join(
+            card_votes_columns::card_votes.on(card_votes_columns::card_metadata_id
+                .eq(card_metadata_columns::id)
+                .and(card_votes_columns::deleted.eq(false))),
+        )
+        .filter(card_metadata_columns::approved.eq(false))
+        .filter(card_metadata_columns::private.eq(false))
+        .group_by(card_metadata_columns::id)
+        .select((
+            card_metadata_columns::id,
+            card_metadata_columns::content,
+            card_metadata_columns::link,
+            card_metadata_columns::author_id,
+            card_metadata_columns::created_at,
+            sql::<Int8>("count(card_votes.id) FILTER (WHERE card_votes.vote)"),
+            sql::<Int8>("count(card_votes.id) FILTER (WHERE NOT card_votes.vote)"),
+            sql::<Int8>("count(*) OVER() AS full_count"),
+        ))
+        .order(card_metadata_columns::created_at.asc())
+        .limit(per_page)
+        .offset(PageInfo::offset(page, per_page))
+        .load::<ModerationCard>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to load pending cards",
+            ..Default::default()
+        })?;
+
+    let total_count = cards.first().map(|card| card.full_count).unwrap_or(0);
+
+    Ok((cards, total_count))
+}
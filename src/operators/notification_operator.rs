@@ -14,13 +14,14 @@ pub fn add_verificiation_notification_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::verification_notifications::dsl as verification_notifications_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::insert_into(verification_notifications_columns::verification_notifications)
         .values(&notification)
         .execute(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to create notification",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -32,13 +33,14 @@ pub fn get_notifications_query(
 ) -> Result<NotificationTypes, DefaultError> {
     use crate::data::schema::verification_notifications::dsl as verification_notifications_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let notifications = verification_notifications_columns::verification_notifications
         .filter(verification_notifications_columns::user_uuid.eq(user_id))
         .load::<VerificationNotification>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to get notifications",
+            ..Default::default()
         })?;
 
     Ok(NotificationTypes::Verification(notifications))
@@ -51,7 +53,7 @@ pub fn mark_notification_as_read_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::verification_notifications::dsl as verification_notifications_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(
         verification_notifications_columns::verification_notifications
@@ -62,6 +64,7 @@ pub fn mark_notification_as_read_query(
     .execute(&mut conn)
     .map_err(|_| DefaultError {
         message: "Failed to mark notification as read",
+        ..Default::default()
     })?;
 
     Ok(())
@@ -73,7 +76,7 @@ pub fn mark_all_notifications_as_read_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::verification_notifications::dsl as verification_notifications_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(
         verification_notifications_columns::verification_notifications
@@ -83,6 +86,7 @@ pub fn mark_all_notifications_as_read_query(
     .execute(&mut conn)
     .map_err(|_| DefaultError {
         message: "Failed to mark all notifications as read",
+        ..Default::default()
     })?;
 
     Ok(())
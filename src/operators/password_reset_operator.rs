@@ -16,6 +16,7 @@ pub fn reset_user_password(
     if password_reset.expires_at < chrono::Local::now().naive_local() {
         return Err(DefaultError {
             message: "Password reset request expired",
+            ..Default::default()
         });
     }
 
@@ -41,13 +42,14 @@ pub fn send_password_reset_email(
 pub fn get_user_query(user_email: &String, pool: &web::Data<Pool>) -> Result<User, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let user: User = users
         .filter(email.eq(user_email))
         .first::<User>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "There is no account associated with that email",
+            ..Default::default()
         })?;
 
     Ok(user)
@@ -59,7 +61,7 @@ fn create_password_reset_query(
 ) -> Result<PasswordReset, DefaultError> {
     use crate::data::schema::password_resets::dsl::password_resets;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let new_password_reset = PasswordReset::from(email);
 
@@ -68,6 +70,7 @@ fn create_password_reset_query(
         .get_result(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error inserting new password reset request, try again",
+            ..Default::default()
         })?;
 
     Ok(inserted_password_reset)
@@ -79,11 +82,12 @@ fn get_password_reset_query(
 ) -> Result<PasswordReset, DefaultError> {
     use crate::data::schema::password_resets::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let password_reset_id =
         uuid::Uuid::try_parse(&password_reset_id).map_err(|_uuid_error| DefaultError {
             message: "Invalid password reset id",
+            ..Default::default()
         })?;
 
     let password_reset = password_resets
@@ -91,6 +95,7 @@ fn get_password_reset_query(
         .first(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Invalid password reset invitation",
+            ..Default::default()
         })?;
 
     Ok(password_reset)
@@ -103,7 +108,7 @@ fn reset_user_password_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let password: String = hash_password(&password)?;
 
@@ -112,6 +117,7 @@ fn reset_user_password_query(
         .first::<User>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "There is no account associated with that email",
+            ..Default::default()
         })?;
 
     diesel::update(users.find(user.id))
@@ -119,7 +125,24 @@ fn reset_user_password_query(
         .execute(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error updating user password",
+            ..Default::default()
         })?;
 
     Ok(())
 }
+
+pub fn delete_expired_password_resets_query(pool: &Pool) -> Result<usize, DefaultError> {
+    use crate::data::schema::password_resets::dsl as password_resets_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::delete(
+        password_resets_columns::password_resets
+            .filter(password_resets_columns::expires_at.lt(chrono::Local::now().naive_local())),
+    )
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to delete expired password resets",
+        ..Default::default()
+    })
+}
@@ -0,0 +1,78 @@
+use std::sync::MutexGuard;
+
+use actix_web::web;
+use diesel::dsl::{count_distinct, count_star};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+use crate::{
+    data::models::{Pool, SearchQuery, TrendingSearchQuery},
+    errors::DefaultError,
+};
+
+pub fn insert_search_query_query(
+    search_query: SearchQuery,
+    pool: MutexGuard<'_, actix_web::web::Data<Pool>>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::search_queries::dsl as search_queries_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::insert_into(search_queries_columns::search_queries)
+        .values(&search_query)
+        .execute(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to log search query",
+            ..Default::default()
+        })?;
+
+    Ok(())
+}
+
+// Raw queries can identify whoever typed them, so a query only trends once enough different
+// signed in users have searched for it. Without LOG_SEARCH_QUERY_USERS the rows carry no user,
+// so the threshold counts searches instead
+pub fn get_trending_search_queries_query(
+    days: i64,
+    limit: i64,
+    pool: web::Data<Pool>,
+) -> Result<Vec<TrendingSearchQuery>, DefaultError> {
+    use crate::data::schema::search_queries::dsl as search_queries_columns;
+
+    let mut conn = pool.get()?;
+
+    let window_start = chrono::Local::now().naive_local() - chrono::Duration::days(days);
+    let min_searches: i64 = std::env::var("TRENDING_SEARCH_MIN_USERS")
+        .ok()
+        .and_then(|min_users| min_users.parse().ok())
+        .unwrap_or(5)
+        .max(1);
+    let user_ids_logged = std::env::var("LOG_SEARCH_QUERY_USERS").unwrap_or_default() == "true";
+
+    let trending_queries: Vec<(String, i64)> = match user_ids_logged {
+        true => search_queries_columns::search_queries
+            .filter(search_queries_columns::created_at.ge(window_start))
+            .group_by(search_queries_columns::query)
+            .having(count_distinct(search_queries_columns::user_id).ge(min_searches))
+            .select((search_queries_columns::query, count_star()))
+            .order(count_star().desc())
+            .limit(limit)
+            .load(&mut conn),
+        false => search_queries_columns::search_queries
+            .filter(search_queries_columns::created_at.ge(window_start))
+            .group_by(search_queries_columns::query)
+            .having(count_star().ge(min_searches))
+            .select((search_queries_columns::query, count_star()))
+            .order(count_star().desc())
+            .limit(limit)
+            .load(&mut conn),
+    }
+    .map_err(|_| DefaultError {
+        message: "Failed to load trending search queries",
+        ..Default::default()
+    })?;
+
+    Ok(trending_queries
+        .into_iter()
+        .map(|(query, count)| TrendingSearchQuery { query, count })
+        .collect())
+}
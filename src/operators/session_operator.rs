@@ -0,0 +1,163 @@
+use crate::data::models::{Pool, UserSession};
+use crate::diesel::prelude::*;
+use crate::errors::DefaultError;
+use actix_web::web;
+
+// Matches the identity login deadline, older sessions can no longer be used anyway
+fn session_lifetime() -> chrono::Duration {
+    chrono::Duration::days(1)
+}
+
+// last_seen_at only needs to be roughly right, so most requests skip the write
+fn last_seen_resolution() -> chrono::Duration {
+    chrono::Duration::minutes(5)
+}
+
+pub fn create_user_session_query(
+    user_id: uuid::Uuid,
+    user_agent: Option<String>,
+    pool: &web::Data<Pool>,
+) -> Result<UserSession, DefaultError> {
+    use crate::data::schema::user_sessions::dsl::user_sessions;
+
+    let mut conn = pool.get()?;
+
+    diesel::insert_into(user_sessions)
+        .values(&UserSession::from_details(user_id, user_agent))
+        .get_result(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to create session",
+            ..Default::default()
+        })
+}
+
+// Called on every authenticated request, a revoked or expired session logs the user out
+pub fn validate_user_session_query(
+    session_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::user_sessions::dsl as user_sessions_columns;
+
+    let mut conn = pool.get()?;
+    let now = chrono::Local::now().naive_local();
+
+    let session = user_sessions_columns::user_sessions
+        .filter(user_sessions_columns::id.eq(session_id))
+        .filter(user_sessions_columns::user_id.eq(user_id))
+        .filter(user_sessions_columns::revoked_at.is_null())
+        .filter(user_sessions_columns::created_at.gt(now - session_lifetime()))
+        .first::<UserSession>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Session is no longer active",
+            ..Default::default()
+        })?;
+
+    if session.last_seen_at < now - last_seen_resolution() {
+        diesel::update(user_sessions_columns::user_sessions.find(session_id))
+            .set(user_sessions_columns::last_seen_at.eq(now))
+            .execute(&mut conn)
+            .map_err(|_db_error| DefaultError {
+                message: "Failed to update session",
+                ..Default::default()
+            })?;
+    }
+
+    Ok(())
+}
+
+pub fn get_active_sessions_query(
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<Vec<UserSession>, DefaultError> {
+    use crate::data::schema::user_sessions::dsl as user_sessions_columns;
+
+    let mut conn = pool.get()?;
+
+    user_sessions_columns::user_sessions
+        .filter(user_sessions_columns::user_id.eq(user_id))
+        .filter(user_sessions_columns::revoked_at.is_null())
+        .filter(
+            user_sessions_columns::created_at
+                .gt(chrono::Local::now().naive_local() - session_lifetime()),
+        )
+        .order(user_sessions_columns::last_seen_at.desc())
+        .load::<UserSession>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Failed to load sessions",
+            ..Default::default()
+        })
+}
+
+pub fn revoke_user_session_query(
+    session_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::user_sessions::dsl as user_sessions_columns;
+
+    let mut conn = pool.get()?;
+
+    let revoked = diesel::update(
+        user_sessions_columns::user_sessions
+            .filter(user_sessions_columns::id.eq(session_id))
+            .filter(user_sessions_columns::user_id.eq(user_id))
+            .filter(user_sessions_columns::revoked_at.is_null()),
+    )
+    .set(user_sessions_columns::revoked_at.eq(chrono::Local::now().naive_local()))
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to revoke session",
+        ..Default::default()
+    })?;
+
+    if revoked == 0 {
+        return Err(DefaultError {
+            message: "Session not found",
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
+
+pub fn revoke_all_user_sessions_query(
+    user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<usize, DefaultError> {
+    use crate::data::schema::user_sessions::dsl as user_sessions_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::update(
+        user_sessions_columns::user_sessions
+            .filter(user_sessions_columns::user_id.eq(user_id))
+            .filter(user_sessions_columns::revoked_at.is_null()),
+    )
+    .set(user_sessions_columns::revoked_at.eq(chrono::Local::now().naive_local()))
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to revoke sessions",
+        ..Default::default()
+    })
+}
+
+pub fn delete_expired_sessions_query(pool: &Pool) -> Result<usize, DefaultError> {
+    use crate::data::schema::user_sessions::dsl as user_sessions_columns;
+
+    let mut conn = pool.get()?;
+
+    diesel::delete(
+        user_sessions_columns::user_sessions.filter(
+            user_sessions_columns::revoked_at
+                .is_not_null()
+                .or(user_sessions_columns::created_at
+                    .lt(chrono::Local::now().naive_local() - session_lifetime())),
+        ),
+    )
+    .execute(&mut conn)
+    .map_err(|_db_error| DefaultError {
+        message: "Failed to delete expired sessions",
+        ..Default::default()
+    })
+}
@@ -0,0 +1,81 @@
+use crate::diesel::prelude::*;
+use crate::{data::models::Pool, errors::DefaultError};
+use actix_web::web;
+use diesel::dsl::sql;
+use diesel::sql_types::{Double, Nullable};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorpusStats {
+    pub total_cards: i64,
+    pub total_users: i64,
+    pub total_votes: i64,
+    pub total_files: i64,
+    pub cards_last_day: i64,
+    pub cards_last_week: i64,
+    pub cards_last_month: i64,
+    pub average_card_length: f64,
+}
+
+pub fn get_corpus_stats_query(pool: &web::Data<Pool>) -> Result<CorpusStats, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+    use crate::data::schema::files::dsl as files_columns;
+    use crate::data::schema::users::dsl as users_columns;
+
+    let mut conn = pool.get()?;
+    let stats_error = |_db_error| DefaultError {
+        message: "Failed to load corpus stats",
+        ..Default::default()
+    };
+
+    let total_cards = card_metadata_columns::card_metadata
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(stats_error)?;
+    let total_users = users_columns::users
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(stats_error)?;
+    let total_votes = card_votes_columns::card_votes
+        .filter(card_votes_columns::deleted.eq(false))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(stats_error)?;
+    let total_files = files_columns::files
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(stats_error)?;
+
+    let now = chrono::Local::now().naive_local();
+    let mut cards_since = |days: i64| {
+        card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::created_at.ge(now - chrono::Duration::days(days)))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .map_err(stats_error)
+    };
+    let cards_last_day = cards_since(1)?;
+    let cards_last_week = cards_since(7)?;
+    let cards_last_month = cards_since(30)?;
+
+    // Measured in characters of the plain text content, not the stored html
+    let average_card_length = card_metadata_columns::card_metadata
+        .select(sql::<Nullable<Double>>(
+            "avg(char_length(card_metadata.content))::float8",
+        ))
+        .get_result::<Option<f64>>(&mut conn)
+        .map_err(stats_error)?
+        .unwrap_or(0.0);
+
+    Ok(CorpusStats {
+        total_cards,
+        total_users,
+        total_votes,
+        total_files,
+        cards_last_day,
+        cards_last_week,
+        cards_last_month,
+        average_card_length,
+    })
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StreamingMessageDTO {
+    pub content: String,
+    pub active: bool,
+}
+
+struct StreamingMessage {
+    stream_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    content: String,
+    finished_at: Option<Instant>,
+}
+
+// Finished messages stick around briefly so a client reconnecting right after still sees the end
+const FINISHED_STREAMING_MESSAGE_TTL: Duration = Duration::from_secs(300);
+
+// Keyed by topic, a topic only ever has one assistant message generating at a time
+static STREAMING_MESSAGES: Lazy<Mutex<HashMap<uuid::Uuid, StreamingMessage>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn remove_expired_streaming_messages(
+    streaming_messages: &mut HashMap<uuid::Uuid, StreamingMessage>,
+) {
+    streaming_messages.retain(|_, message| {
+        !message
+            .finished_at
+            .is_some_and(|finished_at| finished_at.elapsed() >= FINISHED_STREAMING_MESSAGE_TTL)
+    });
+}
+
+pub fn get_streaming_message(
+    topic_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+) -> Option<StreamingMessageDTO> {
+    let mut streaming_messages = STREAMING_MESSAGES.lock().unwrap();
+    remove_expired_streaming_messages(&mut streaming_messages);
+
+    streaming_messages
+        .get(&topic_id)
+        .filter(|message| message.user_id == user_id)
+        .map(|message| StreamingMessageDTO {
+            content: message.content.clone(),
+            active: message.finished_at.is_none(),
+        })
+}
+
+// Handed to the completion stream, a newer generation for the same topic replaces the entry and
+// the older tracker stops writing to it
+#[derive(Clone)]
+pub struct StreamingMessageTracker {
+    topic_id: uuid::Uuid,
+    stream_id: uuid::Uuid,
+}
+
+impl StreamingMessageTracker {
+    pub fn start(topic_id: uuid::Uuid, user_id: uuid::Uuid) -> Self {
+        let mut streaming_messages = STREAMING_MESSAGES.lock().unwrap();
+        remove_expired_streaming_messages(&mut streaming_messages);
+
+        Self::insert(&mut streaming_messages, topic_id, user_id)
+    }
+
+    // Reserves the topic only when no reply is generating for it, checked and inserted under the
+    // same lock so two callers can't both get it
+    pub fn try_start(topic_id: uuid::Uuid, user_id: uuid::Uuid) -> Option<Self> {
+        let mut streaming_messages = STREAMING_MESSAGES.lock().unwrap();
+        remove_expired_streaming_messages(&mut streaming_messages);
+        if streaming_messages
+            .get(&topic_id)
+            .is_some_and(|message| message.finished_at.is_none())
+        {
+            return None;
+        }
+
+        Some(Self::insert(&mut streaming_messages, topic_id, user_id))
+    }
+
+    fn insert(
+        streaming_messages: &mut HashMap<uuid::Uuid, StreamingMessage>,
+        topic_id: uuid::Uuid,
+        user_id: uuid::Uuid,
+    ) -> Self {
+        let stream_id = uuid::Uuid::new_v4();
+        streaming_messages.insert(
+            topic_id,
+            StreamingMessage {
+                stream_id,
+                user_id,
+                content: String::new(),
+                finished_at: None,
+            },
+        );
+
+        StreamingMessageTracker {
+            topic_id,
+            stream_id,
+        }
+    }
+
+    fn with_message(&self, update: impl FnOnce(&mut StreamingMessage)) {
+        if let Some(message) = STREAMING_MESSAGES
+            .lock()
+            .unwrap()
+            .get_mut(&self.topic_id)
+            .filter(|message| message.stream_id == self.stream_id)
+        {
+            update(message);
+        }
+    }
+
+    pub fn append(&self, chunk: &str) {
+        self.with_message(|message| message.content.push_str(chunk));
+    }
+
+    pub fn finish(&self) {
+        self.with_message(|message| message.finished_at = Some(Instant::now()));
+    }
+
+    // Drops the entry of a generation that never started, freeing the topic again
+    pub fn cancel(&self) {
+        let mut streaming_messages = STREAMING_MESSAGES.lock().unwrap();
+        if streaming_messages
+            .get(&self.topic_id)
+            .is_some_and(|message| message.stream_id == self.stream_id)
+        {
+            streaming_messages.remove(&self.topic_id);
+        }
+    }
+}
@@ -1,17 +1,18 @@
-use std::borrow::Borrow;
 use std::str::FromStr;
 
 use actix_web::web;
+use openssl::memcmp;
 use stripe::{
     CheckoutSession, CheckoutSessionMode, CreateCheckoutSession, CreateCheckoutSessionLineItems,
-    CreateCustomer, CustomerId, EventObject, EventType, Subscription, SubscriptionId,
-    UpdateSubscription, UpdateSubscriptionItems, Webhook,
+    CreateCustomer, CustomerId, Event, EventObject, EventType, Subscription, SubscriptionId,
+    UpdateCustomer, UpdateSubscription, UpdateSubscriptionItems,
 };
 
-use crate::data::models::{Pool, UserPlan};
+use crate::data::models::{Plan, Pool, UserPlan, UserPlanDTO};
 use crate::diesel::prelude::*;
 use crate::handlers::invitation_handler::create_invitation;
 use crate::operators::password_reset_operator::get_user_query;
+use crate::operators::webhook_operator::hmac_sha256_hex;
 use crate::{data::models::StripeCustomer, errors::DefaultError};
 
 pub async fn create_stripe_checkout_session_operation(
@@ -40,9 +41,11 @@ pub async fn create_stripe_checkout_session_operation(
         .await
         .map_err(|_stripe_error| DefaultError {
             message: "Error creating checkout session, try again",
+            ..Default::default()
         })?;
     let checkout_session_url = checkout_session.url.ok_or(DefaultError {
         message: "Error creating checkout session, try again",
+        ..Default::default()
     })?;
 
     Ok(checkout_session_url)
@@ -61,6 +64,7 @@ pub async fn cancel_stripe_subscription_operation(
 
     response.map_err(|_err| DefaultError {
         message: "Error cancelling subscription, try again",
+        ..Default::default()
     })?;
 
     Ok(())
@@ -77,6 +81,7 @@ pub async fn change_stripe_subscription_operation(
         .await
         .map_err(|_err| DefaultError {
             message: "Error retrieving subscription, try again",
+            ..Default::default()
         })?;
 
     let mut params = UpdateSubscription::new();
@@ -94,6 +99,7 @@ pub async fn change_stripe_subscription_operation(
         log::error!("{:?}", _err);
         DefaultError {
             message: "Error changing subscription price, try again",
+            ..Default::default()
         }
     })?;
 
@@ -107,29 +113,19 @@ pub fn update_plan_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::user_plans::dsl::{plan, status, user_plans};
 
-    let silver_plan_id =
-        std::env::var("STRIPE_SILVER_PLAN_ID").expect("STRIPE_SILVER_PLAN_ID must be set");
-
-    let gold_plan_id =
-        std::env::var("STRIPE_GOLD_PLAN_ID").expect("STRIPE_GOLD_PLAN_ID must be set");
-
-    let new_plan = if new_plan_id == silver_plan_id {
-        "silver".to_string()
-    } else if new_plan_id == gold_plan_id {
-        "gold".to_string()
-    } else {
-        return Err(DefaultError {
-            message: "Invalid plan id",
-        });
-    };
+    let new_plan = Plan::from_stripe_price_id(&new_plan_id).ok_or(DefaultError {
+        message: "Invalid plan id",
+        ..Default::default()
+    })?;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(user_plans.find(user_plan.id))
-        .set((plan.eq(new_plan), status.eq("active")))
+        .set((plan.eq(new_plan.name()), status.eq("active")))
         .execute(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error updating plan status, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -142,13 +138,14 @@ pub fn update_plan_status_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::user_plans::dsl::{status, user_plans};
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(user_plans.find(plan.id))
         .set(status.eq(new_status))
         .execute(&mut conn)
         .map_err(|_err| DefaultError {
             message: "Error updating plan status, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -162,13 +159,14 @@ pub fn get_stripe_customer_query(
         email as stripe_customer_email, stripe_customers,
     };
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let stripe_customer = stripe_customers
         .filter(stripe_customer_email.eq(email))
         .first::<StripeCustomer>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error finding stripe customer, try again",
+            ..Default::default()
         })?;
 
     Ok(stripe_customer)
@@ -189,6 +187,7 @@ pub async fn create_stripe_customer_query(
     .await
     .map_err(|_stripe_error| DefaultError {
         message: "Error creating new stripe customer, try again",
+        ..Default::default()
     })?;
 
     let new_stripe_customer =
@@ -197,18 +196,46 @@ pub async fn create_stripe_customer_query(
     insert_stripe_customer_query(&new_stripe_customer, &pool)
 }
 
+pub async fn update_stripe_customer_email_operation(
+    stripe_customer_id: &str,
+    new_email: &str,
+) -> Result<(), DefaultError> {
+    let stripe_client = get_stripe_client()?;
+    let customer_id = CustomerId::from_str(stripe_customer_id).map_err(|_err| DefaultError {
+        message: "Invalid stripe customer id",
+        ..Default::default()
+    })?;
+
+    stripe::Customer::update(
+        &stripe_client,
+        &customer_id,
+        UpdateCustomer {
+            email: Some(new_email),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|_stripe_error| DefaultError {
+        message: "Error updating stripe customer email, try again",
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
 pub fn insert_stripe_customer_query(
     customer: &StripeCustomer,
     pool: &web::Data<Pool>,
 ) -> Result<StripeCustomer, DefaultError> {
     use crate::data::schema::stripe_customers::dsl::stripe_customers;
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let inserted_stripe_customer = diesel::insert_into(stripe_customers)
         .values(customer)
         .get_result(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error inserting new stripe customer, try again",
+            ..Default::default()
         })?;
 
     Ok(inserted_stripe_customer)
@@ -223,24 +250,28 @@ pub fn get_stripe_client() -> Result<stripe::Client, DefaultError> {
 pub fn get_user_plan_query(
     user_email: String,
     pool: &web::Data<Pool>,
-) -> Result<UserPlan, DefaultError> {
-    use crate::data::schema::user_plans::dsl::{
-        stripe_customer_id as stripe_customer_id_column, user_plans,
-    };
-
-    // get the user's stripe customer id from the stripe_customers table
-    let stripe_customer_id = get_stripe_customer_query(user_email, pool)?.stripe_id;
-
-    let mut conn = pool.get().unwrap();
-
-    let user_plan = user_plans
-        .filter(stripe_customer_id_column.eq(stripe_customer_id))
+) -> Result<UserPlanDTO, DefaultError> {
+    use crate::data::schema::stripe_customers::dsl as stripe_customers_columns;
+    use crate::data::schema::user_plans::dsl as user_plans_columns;
+
+    let mut conn = pool.get()?;
+
+    let user_plan = user_plans_columns::user_plans
+        .inner_join(
+            stripe_customers_columns::stripe_customers
+                .on(stripe_customers_columns::stripe_id.eq(user_plans_columns::stripe_customer_id)),
+        )
+        .filter(stripe_customers_columns::email.eq(user_email))
+        .select(crate::data::schema::user_plans::all_columns)
+        .order(user_plans_columns::created_at.desc())
         .first::<UserPlan>(&mut conn)
+        .optional()
         .map_err(|_db_error| DefaultError {
             message: "Error finding user plan, try again",
+            ..Default::default()
         })?;
 
-    Ok(user_plan)
+    Ok(UserPlanDTO::from(user_plan))
 }
 
 pub fn create_user_plan_query(
@@ -251,7 +282,7 @@ pub fn create_user_plan_query(
 ) -> Result<UserPlan, DefaultError> {
     use crate::data::schema::user_plans::dsl::user_plans;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let new_user_plan =
         UserPlan::from_details(stripe_customer_id, plan_name, subscription_id, None);
@@ -263,95 +294,141 @@ pub fn create_user_plan_query(
             log::error!("db_error: {:?}", _db_error);
             DefaultError {
                 message: "Error inserting new user plan, try again",
+                ..Default::default()
             }
         })?;
 
     Ok(inserted_user_plan)
 }
 
-pub fn handle_webhook_query(
+// Same verification as Webhook::construct_event, which hardcodes a 300 second tolerance for the
+// signature timestamp
+fn construct_webhook_event(
+    payload: &str,
     stripe_signature: &str,
-    payload: web::Bytes,
-    pool: &web::Data<Pool>,
-) -> Result<(), DefaultError> {
+    webhook_secret: &str,
+    tolerance_secs: i64,
+) -> Result<Event, DefaultError> {
+    let bad_signature = || DefaultError {
+        message: "Invalid webhook signature",
+        ..Default::default()
+    };
+
+    let mut timestamp: Option<i64> = None;
+    let mut signatures: Vec<&str> = vec![];
+    for part in stripe_signature.split(',') {
+        match part.split_once('=') {
+            Some(("t", value)) => timestamp = value.parse().ok(),
+            Some(("v1", value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or_else(bad_signature)?;
+
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > tolerance_secs {
+        return Err(DefaultError {
+            message: "Webhook signature timestamp is outside of the tolerance",
+            ..Default::default()
+        });
+    }
+
+    let expected_signature =
+        hmac_sha256_hex(webhook_secret, timestamp, payload).map_err(|_| bad_signature())?;
+
+    if !signatures.iter().any(|signature| {
+        signature.len() == expected_signature.len()
+            && memcmp::eq(signature.as_bytes(), expected_signature.as_bytes())
+    }) {
+        return Err(bad_signature());
+    }
+
+    serde_json::from_str(payload).map_err(|_| DefaultError {
+        message: "Failed to parse webhook event",
+        ..Default::default()
+    })
+}
+
+pub fn construct_webhook_event_query(
+    stripe_signature: &str,
+    payload: &str,
+) -> Result<Event, DefaultError> {
     let webhook_secret =
         std::env::var("WEBHOOK_SIGNING_SECRET").expect("WEBHOOK_SIGNING_SECRET must be set");
+    let tolerance_secs: i64 = std::env::var("WEBHOOK_TOLERANCE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(300);
+
+    construct_webhook_event(payload, stripe_signature, &webhook_secret, tolerance_secs)
+}
 
-    let payload_str = std::str::from_utf8(payload.borrow()).unwrap();
-
-    if let Ok(event) = Webhook::construct_event(payload_str, stripe_signature, &webhook_secret) {
-        match event.type_ {
-            EventType::CheckoutSessionCompleted => {
-                if let EventObject::CheckoutSession(session) = event.data.object {
-                    let stripe_customer = match &session.customer {
-                        Some(customer) => customer,
-                        None => {
-                            let err = DefaultError {
-                                message: "Stripe customer id is none",
-                            };
-                            log::error!("{}", err.message);
-                            return Err(err);
-                        }
-                    };
-
-                    let subscription = &session.subscription.unwrap();
-                    let plan_price = match session.amount_subtotal {
-                        Some(val) if val == 4999 => create_user_plan_query(
-                            stripe_customer.id().to_string(),
-                            "gold".to_owned(),
-                            subscription.id().to_string(),
-                            pool,
-                        ),
-                        Some(val) if val == 999 => create_user_plan_query(
-                            stripe_customer.id().to_string(),
-                            "silver".to_owned(),
-                            subscription.id().to_string(),
-                            pool,
-                        ),
-                        _ => {
-                            let err = DefaultError {
-                                message: "Plan id is not silver or gold",
-                            };
-                            log::error!("{}", err.message);
-                            return Err(err);
-                        }
-                    };
-
-                    if let Err(err) = plan_price {
-                        log::error!("Plan price result {}", err.message);
+pub fn handle_webhook_query(event: Event, pool: &web::Data<Pool>) -> Result<(), DefaultError> {
+    match event.type_ {
+        EventType::CheckoutSessionCompleted => {
+            if let EventObject::CheckoutSession(session) = event.data.object {
+                let stripe_customer = match &session.customer {
+                    Some(customer) => customer,
+                    None => {
+                        let err = DefaultError {
+                            message: "Stripe customer id is none",
+                            ..Default::default()
+                        };
+                        log::error!("{}", err.message);
                         return Err(err);
                     }
+                };
+
+                let subscription = &session.subscription.unwrap();
+                let plan_price = match session.amount_subtotal.and_then(Plan::from_checkout_amount)
+                {
+                    Some(new_plan) => create_user_plan_query(
+                        stripe_customer.id().to_string(),
+                        new_plan.name().to_owned(),
+                        subscription.id().to_string(),
+                        pool,
+                    ),
+                    None => {
+                        let err = DefaultError {
+                            message: "Plan id is not silver or gold",
+                            ..Default::default()
+                        };
+                        log::error!("{}", err.message);
+                        return Err(err);
+                    }
+                };
+
+                if let Err(err) = plan_price {
+                    log::error!("Plan price result {}", err.message);
+                    return Err(err);
                 }
             }
-            EventType::CustomerCreated => {
-                if let EventObject::Customer(customer) = event.data.object {
-                    log::info!("New Customer {:?}", &customer);
-                    if let Some(email) = customer.email {
-                        // If they are not in our db now, send invite
-                        log::info!("Customer email {:?}", email);
-                        let arguflow_user = get_user_query(&email, pool).ok();
-                        if arguflow_user.is_none() {
-                            create_invitation(
-                                "https://arguflow.com".to_string(),
-                                email.clone(),
-                                "".to_owned(),
-                                pool.to_owned(),
-                            )?;
-                        }
-
-                        let new_stripe_customer =
-                            StripeCustomer::from_details(customer.id.to_string(), Some(email));
-
-                        let _ = insert_stripe_customer_query(&new_stripe_customer, pool)?;
+        }
+        EventType::CustomerCreated => {
+            if let EventObject::Customer(customer) = event.data.object {
+                log::info!("New Customer {:?}", &customer);
+                if let Some(email) = customer.email {
+                    // If they are not in our db now, send invite
+                    log::info!("Customer email {:?}", email);
+                    let arguflow_user = get_user_query(&email, pool).ok();
+                    if arguflow_user.is_none() {
+                        create_invitation(
+                            "https://arguflow.com".to_string(),
+                            email.clone(),
+                            "".to_owned(),
+                            pool.to_owned(),
+                        )?;
                     }
+
+                    let new_stripe_customer =
+                        StripeCustomer::from_details(customer.id.to_string(), Some(email));
+
+                    let _ = insert_stripe_customer_query(&new_stripe_customer, pool)?;
                 }
             }
-            _ => {
-                log::error!("Unknown event encountered in webhook: {:?}", event.type_);
-            }
         }
-    } else {
-        log::error!("Failed to construct webhook event, ensure your webhook secret is correct.");
+        _ => {
+            log::error!("Unknown event encountered in webhook: {:?}", event.type_);
+        }
     }
 
     Ok(())
@@ -1,17 +1,18 @@
-use crate::data::models::{Pool, Topic};
+use crate::data::models::{generate_secure_token, Message, PageInfo, Pool, Topic};
 use crate::{diesel::prelude::*, errors::DefaultError};
 use actix_web::web;
 
 pub fn create_topic_query(topic: Topic, pool: &web::Data<Pool>) -> Result<(), DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::insert_into(topics)
         .values(&topic)
         .execute(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error inserting new topic, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -23,13 +24,14 @@ pub fn delete_topic_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(topics.filter(id.eq(topic_id)))
         .set(deleted.eq(true))
         .execute(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error deleting topic, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -43,7 +45,7 @@ pub fn update_topic_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::update(topics.filter(id.eq(topic_id)))
         .set((
@@ -54,6 +56,7 @@ pub fn update_topic_query(
         .execute(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error updating topic, try again",
+            ..Default::default()
         })?;
 
     Ok(())
@@ -65,7 +68,7 @@ pub fn get_topic_query(
 ) -> Result<Topic, DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     topics
         .filter(id.eq(topic_id))
@@ -73,6 +76,7 @@ pub fn get_topic_query(
         .first::<Topic>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "This topic does not exist",
+            ..Default::default()
         })
 }
 
@@ -83,7 +87,7 @@ pub fn get_topic_for_user_query(
 ) -> Result<Topic, DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     topics
         .filter(id.eq(topic_id))
@@ -91,6 +95,26 @@ pub fn get_topic_for_user_query(
         .first::<Topic>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "This topic does not exist for the authenticated user",
+            ..Default::default()
+        })
+}
+
+pub fn get_topic_count_for_user_query(
+    topic_user_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<i64, DefaultError> {
+    use crate::data::schema::topics::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    topics
+        .filter(user_id.eq(topic_user_id))
+        .filter(deleted.eq(false))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error counting topics for user",
+            ..Default::default()
         })
 }
 
@@ -100,7 +124,7 @@ pub fn get_all_topics_for_user_query(
 ) -> Result<Vec<Topic>, DefaultError> {
     use crate::data::schema::topics::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     topics
         .filter(user_id.eq(topic_user_id))
@@ -109,5 +133,331 @@ pub fn get_all_topics_for_user_query(
         .load::<Topic>(&mut conn)
         .map_err(|_db_error| DefaultError {
             message: "Error getting topics for user",
+            ..Default::default()
+        })
+}
+
+// Sharing keeps an existing token so links already handed out stay valid, unsharing revokes it.
+// Only listed topics show up in the public showcase, a plain share is reachable by link alone.
+pub fn set_topic_share_token_query(
+    topic_user_id: uuid::Uuid,
+    topic_id: uuid::Uuid,
+    shared: bool,
+    topic_listed: bool,
+    pool: &web::Data<Pool>,
+) -> Result<Topic, DefaultError> {
+    use crate::data::schema::topics::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    let topic = topics
+        .filter(id.eq(topic_id))
+        .filter(user_id.eq(topic_user_id))
+        .filter(deleted.eq(false))
+        .first::<Topic>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "This topic does not exist for the authenticated user",
+            ..Default::default()
+        })?;
+
+    let new_share_token = match shared {
+        true => Some(topic.share_token.unwrap_or_else(generate_secure_token)),
+        false => None,
+    };
+
+    diesel::update(topics.filter(id.eq(topic_id)))
+        .set((
+            share_token.eq(new_share_token),
+            listed.eq(shared && topic_listed),
+        ))
+        .get_result::<Topic>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error updating topic sharing, try again",
+            ..Default::default()
         })
 }
+
+// System prompts are internal, viewers of a shared topic only see the conversation itself
+pub fn get_shared_topic_query(
+    topic_share_token: String,
+    pool: &web::Data<Pool>,
+) -> Result<(Topic, Vec<Message>), DefaultError> {
+    use crate::data::schema::messages::dsl as messages_columns;
+    use crate::data::schema::topics::dsl as topics_columns;
+
+    let mut conn = pool.get()?;
+
+    let topic = topics_columns::topics
+        .filter(topics_columns::share_token.eq(topic_share_token))
+        .filter(topics_columns::deleted.eq(false))
+        .first::<Topic>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "This shared topic does not exist",
+            ..Default::default()
+        })?;
+
+    let topic_messages = messages_columns::messages
+        .filter(messages_columns::topic_id.eq(topic.id))
+        .filter(messages_columns::deleted.eq(false))
+        .filter(messages_columns::role.ne("system"))
+        .order(messages_columns::sort_order.asc())
+        .load::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting topic messages",
+            ..Default::default()
+        })?;
+
+    Ok((topic, topic_messages))
+}
+
+pub fn get_shared_topics_query(
+    page: i64,
+    per_page: i64,
+    pool: &web::Data<Pool>,
+) -> Result<(Vec<Topic>, i64), DefaultError> {
+    use crate::data::schema::topics::dsl::*;
+
+    let mut conn = pool.get()?;
+
+    let shared_topics = topics
+        .filter(share_token.is_not_null())
+        .filter(listed.eq(true))
+        .filter(deleted.eq(false));
+
+    let total_shared_topics =
+        shared_topics
+            .count()
+            .get_result::<i64>(&mut conn)
+            .map_err(|_db_error| DefaultError {
+                message: "Error counting shared topics",
+                ..Default::default()
+            })?;
+
+    let shared_topics = shared_topics
+        .order(updated_at.desc())
+        .limit(per_page)
+        .offset(PageInfo::offset(page, per_page))
+        .load::<Topic>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting shared topics",
+            ..Default::default()
+        })?;
+
+    Ok((shared_topics, total_shared_topics))
+}
+
+pub fn fork_topic_query(
+    topic_user_id: uuid::Uuid,
+    source_topic_id: uuid::Uuid,
+    until_message_id: Option<uuid::Uuid>,
+    pool: &web::Data<Pool>,
+) -> Result<(Topic, Vec<Message>), DefaultError> {
+    use crate::data::schema::messages::dsl as messages_columns;
+    use crate::data::schema::topics::dsl as topics_columns;
+
+    let mut conn = pool.get()?;
+
+    let source_topic = topics_columns::topics
+        .filter(topics_columns::id.eq(source_topic_id))
+        .filter(topics_columns::user_id.eq(topic_user_id))
+        .filter(topics_columns::deleted.eq(false))
+        .first::<Topic>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "This topic does not exist for the authenticated user",
+            ..Default::default()
+        })?;
+
+    let mut source_messages = messages_columns::messages
+        .filter(messages_columns::topic_id.eq(source_topic_id))
+        .filter(messages_columns::deleted.eq(false))
+        .order(messages_columns::sort_order.asc())
+        .load::<Message>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error getting topic messages",
+            ..Default::default()
+        })?;
+
+    if let Some(until_message_id) = until_message_id {
+        let until_index = source_messages
+            .iter()
+            .position(|message| message.id == until_message_id)
+            .ok_or(DefaultError {
+                message: "Message does not exist in this topic",
+                ..Default::default()
+            })?;
+        source_messages.truncate(until_index + 1);
+    }
+
+    let mut forked_topic = Topic::from_details(
+        source_topic.resolution,
+        topic_user_id,
+        Some(source_topic.normal_chat),
+    );
+    forked_topic.side = source_topic.side;
+
+    let forked_messages: Vec<Message> = source_messages
+        .into_iter()
+        .map(|message| {
+            let mut forked_message = Message::from_details(
+                message.content,
+                forked_topic.id,
+                message.sort_order,
+                message.role,
+                message.prompt_tokens,
+                message.completion_tokens,
+            );
+            forked_message.created_at = message.created_at;
+            forked_message.pinned = message.pinned;
+            forked_message.finish_reason = message.finish_reason;
+            forked_message
+        })
+        .collect();
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(topics_columns::topics)
+            .values(&forked_topic)
+            .execute(conn)?;
+
+        diesel::insert_into(messages_columns::messages)
+            .values(&forked_messages)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_db_error| DefaultError {
+        message: "Error forking topic, try again",
+        ..Default::default()
+    })?;
+
+    Ok((forked_topic, forked_messages))
+}
+
+// Moves the source topic's messages onto the target, interleaved by creation time, and removes
+// the source topic. Only the target's system message is kept so the merged topic has one.
+pub fn merge_topics_query(
+    topic_user_id: uuid::Uuid,
+    source_topic_id: uuid::Uuid,
+    target_topic_id: uuid::Uuid,
+    pool: &web::Data<Pool>,
+) -> Result<(), DefaultError> {
+    use crate::data::schema::message_versions::dsl as message_versions_columns;
+    use crate::data::schema::messages::dsl as messages_columns;
+    use crate::data::schema::topics::dsl as topics_columns;
+
+    if source_topic_id == target_topic_id {
+        return Err(DefaultError {
+            message: "Cannot merge a topic into itself",
+            ..Default::default()
+        });
+    }
+
+    let mut conn = pool.get()?;
+
+    let owned_topic_count = topics_columns::topics
+        .filter(topics_columns::id.eq_any(vec![source_topic_id, target_topic_id]))
+        .filter(topics_columns::user_id.eq(topic_user_id))
+        .filter(topics_columns::deleted.eq(false))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|_db_error| DefaultError {
+            message: "Error finding topics, try again",
+            ..Default::default()
+        })?;
+
+    if owned_topic_count != 2 {
+        return Err(DefaultError {
+            message: "This topic does not exist for the authenticated user",
+            ..Default::default()
+        });
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let topic_messages = messages_columns::messages
+            .filter(messages_columns::topic_id.eq_any(vec![source_topic_id, target_topic_id]))
+            .filter(messages_columns::deleted.eq(false))
+            .load::<Message>(conn)?;
+
+        let (source_system_messages, mut topic_messages): (Vec<Message>, Vec<Message>) =
+            topic_messages.into_iter().partition(|message| {
+                message.topic_id == source_topic_id && message.role == "system"
+            });
+
+        // System messages lead, then everything else by creation time. Ties keep the target's
+        // messages first and each topic's own ordering.
+        topic_messages.sort_by_key(|message| {
+            (
+                message.role != "system",
+                message.created_at,
+                message.topic_id == source_topic_id,
+                message.sort_order,
+            )
+        });
+
+        // Versions are keyed by their message's (topic_id, sort_order) slot, so they are
+        // loaded up front and moved by id to avoid colliding with slots being renumbered.
+        let topic_message_versions = message_versions_columns::message_versions
+            .filter(
+                message_versions_columns::topic_id.eq_any(vec![source_topic_id, target_topic_id]),
+            )
+            .select((
+                message_versions_columns::id,
+                message_versions_columns::topic_id,
+                message_versions_columns::sort_order,
+            ))
+            .load::<(uuid::Uuid, uuid::Uuid, i32)>(conn)?;
+
+        for (merged_sort_order, message) in topic_messages.iter().enumerate() {
+            diesel::update(messages_columns::messages.filter(messages_columns::id.eq(message.id)))
+                .set((
+                    messages_columns::topic_id.eq(target_topic_id),
+                    messages_columns::sort_order.eq(merged_sort_order as i32),
+                ))
+                .execute(conn)?;
+
+            let message_version_ids: Vec<uuid::Uuid> = topic_message_versions
+                .iter()
+                .filter(|(_, version_topic_id, version_sort_order)| {
+                    *version_topic_id == message.topic_id
+                        && *version_sort_order == message.sort_order
+                })
+                .map(|(version_id, _, _)| *version_id)
+                .collect();
+            if !message_version_ids.is_empty() {
+                diesel::update(
+                    message_versions_columns::message_versions
+                        .filter(message_versions_columns::id.eq_any(message_version_ids)),
+                )
+                .set((
+                    message_versions_columns::topic_id.eq(target_topic_id),
+                    message_versions_columns::sort_order.eq(merged_sort_order as i32),
+                ))
+                .execute(conn)?;
+            }
+        }
+
+        let source_system_message_ids: Vec<uuid::Uuid> = source_system_messages
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        diesel::update(
+            messages_columns::messages
+                .filter(messages_columns::id.eq_any(source_system_message_ids)),
+        )
+        .set(messages_columns::deleted.eq(true))
+        .execute(conn)?;
+
+        diesel::update(topics_columns::topics.filter(topics_columns::id.eq(source_topic_id)))
+            .set(topics_columns::deleted.eq(true))
+            .execute(conn)?;
+
+        diesel::update(topics_columns::topics.filter(topics_columns::id.eq(target_topic_id)))
+            .set(topics_columns::updated_at.eq(diesel::dsl::now))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_db_error| DefaultError {
+        message: "Error merging topics, try again",
+        ..Default::default()
+    })
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{errors::DefaultError, handlers::file_handler::UploadFileResult};
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum UploadProgressEvent {
+    Pending,
+    Converting,
+    Parsed { total_cards: usize },
+    CardsProcessed { processed: usize, total: usize },
+    Completed { result: Box<UploadFileResult> },
+    Failed { message: String },
+}
+
+impl UploadProgressEvent {
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            UploadProgressEvent::Completed { .. } | UploadProgressEvent::Failed { .. }
+        )
+    }
+}
+
+struct UploadJob {
+    user_id: uuid::Uuid,
+    sender: watch::Sender<UploadProgressEvent>,
+    created_at: Instant,
+    finished_at: Option<Instant>,
+}
+
+impl UploadJob {
+    fn is_expired(&self) -> bool {
+        match self.finished_at {
+            Some(finished_at) => finished_at.elapsed() >= FINISHED_UPLOAD_JOB_TTL,
+            None => self.created_at.elapsed() >= UNFINISHED_UPLOAD_JOB_TTL,
+        }
+    }
+}
+
+// Finished jobs stick around briefly so a progress stream opened late still gets the result
+const FINISHED_UPLOAD_JOB_TTL: Duration = Duration::from_secs(300);
+// Jobs that never finish, e.g. a progress stream opened for an upload that was never sent, are
+// dropped after this long
+const UNFINISHED_UPLOAD_JOB_TTL: Duration = Duration::from_secs(3600);
+const MAX_UPLOAD_JOBS_PER_USER: usize = 10;
+
+// Jobs are keyed by an id the client picks, so the progress stream can be opened before or after
+// the upload starts. Whichever arrives first registers the job for that user.
+static UPLOAD_JOBS: Lazy<Mutex<HashMap<uuid::Uuid, UploadJob>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn get_or_create_upload_job<T>(
+    job_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    with_job: impl FnOnce(&mut UploadJob) -> T,
+) -> Result<T, DefaultError> {
+    let mut upload_jobs = UPLOAD_JOBS.lock().unwrap();
+    upload_jobs.retain(|_, job| !job.is_expired());
+
+    if !upload_jobs.contains_key(&job_id)
+        && upload_jobs
+            .values()
+            .filter(|job| job.user_id == user_id)
+            .count()
+            >= MAX_UPLOAD_JOBS_PER_USER
+    {
+        return Err(DefaultError {
+            message: "Too many upload jobs, wait for one to finish",
+            ..Default::default()
+        });
+    }
+
+    let job = upload_jobs.entry(job_id).or_insert_with(|| UploadJob {
+        user_id,
+        sender: watch::channel(UploadProgressEvent::Pending).0,
+        created_at: Instant::now(),
+        finished_at: None,
+    });
+    if job.user_id != user_id {
+        return Err(DefaultError {
+            message: "Upload job belongs to another user",
+            ..Default::default()
+        });
+    }
+
+    Ok(with_job(job))
+}
+
+pub fn subscribe_upload_progress(
+    job_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+) -> Result<watch::Receiver<UploadProgressEvent>, DefaultError> {
+    get_or_create_upload_job(job_id, user_id, |job| job.sender.subscribe())
+}
+
+// Handed to the parser so it can report progress without knowing whether anyone is listening
+#[derive(Clone)]
+pub struct UploadProgress {
+    job_id: Option<uuid::Uuid>,
+    sender: Option<watch::Sender<UploadProgressEvent>>,
+}
+
+impl UploadProgress {
+    pub fn untracked() -> Self {
+        UploadProgress {
+            job_id: None,
+            sender: None,
+        }
+    }
+
+    pub fn track(job_id: uuid::Uuid, user_id: uuid::Uuid) -> Result<Self, DefaultError> {
+        let sender = get_or_create_upload_job(job_id, user_id, |job| job.sender.clone())?;
+
+        Ok(UploadProgress {
+            job_id: Some(job_id),
+            sender: Some(sender),
+        })
+    }
+
+    pub fn report(&self, event: UploadProgressEvent) {
+        let (Some(job_id), Some(sender)) = (self.job_id, &self.sender) else {
+            return;
+        };
+
+        let is_final = event.is_final();
+        sender.send_replace(event);
+        if is_final {
+            if let Some(job) = UPLOAD_JOBS.lock().unwrap().get_mut(&job_id) {
+                job.finished_at = Some(Instant::now());
+            }
+        }
+    }
+}
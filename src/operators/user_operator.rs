@@ -2,23 +2,26 @@ use std::sync::MutexGuard;
 
 use crate::data::models::{
     CardFileWithName, CardMetadata, CardMetadataWithVotesAndFiles, CardVerifications, CardVote,
-    SlimUser, UserDTOWithScore, UserDTOWithVotesAndCards, UserScore,
+    SlimUser, UserDTOWithScore, UserDTOWithVotesAndCards, UserMentionDTO, UserScore, UserStats,
 };
 use crate::diesel::prelude::*;
 use crate::handlers::user_handler::UpdateUserData;
+use crate::operators::card_operator::CardSortBy;
 use crate::{
     data::models::{Pool, User},
     errors::DefaultError,
 };
 use actix_web::web;
-use diesel::sql_types::{BigInt, Text};
+use chrono::Datelike;
+use diesel::sql_types::{BigInt, Bool, Date, Nullable, Text};
+use std::collections::BTreeMap;
 pub fn get_user_by_email_query(
     user_email: &String,
     pool: &web::Data<Pool>,
 ) -> Result<User, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let user: Option<User> = users
         .filter(email.eq(user_email))
@@ -26,34 +29,87 @@ pub fn get_user_by_email_query(
         .optional()
         .map_err(|_| DefaultError {
             message: "Error loading user",
+            ..Default::default()
         })?;
     match user {
         Some(user) => Ok(user),
         None => Err(DefaultError {
             message: "User not found",
+            ..Default::default()
         }),
     }
 }
 
-pub fn get_user_by_username_query(
+sql_function!(fn lower(x: Nullable<Text>) -> Nullable<Text>);
+
+// Usernames are stored trimmed and compared case-insensitively, so "Alice" and " alice" are the
+// same name
+pub fn normalize_username(user_name: &str) -> &str {
+    user_name.trim()
+}
+
+pub fn find_user_by_username_query(
     user_name: &String,
     pool: web::Data<Pool>,
-) -> Result<User, DefaultError> {
+) -> Result<Option<User>, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
-    let user: Option<User> = users
-        .filter(username.eq(user_name))
+    users
+        .filter(lower(username).eq(normalize_username(user_name).to_lowercase()))
         .first::<User>(&mut conn)
         .optional()
         .map_err(|_| DefaultError {
             message: "Error loading user",
-        })?;
-    match user {
+            ..Default::default()
+        })
+}
+
+fn escape_like_pattern(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Usernames containing the query, the ones starting with it first
+pub fn search_users_by_username_query(
+    user_query: &str,
+    limit: i64,
+    pool: web::Data<Pool>,
+) -> Result<Vec<UserMentionDTO>, DefaultError> {
+    use crate::data::schema::users::dsl::*;
+
+    let mut conn = pool.get()?;
+    let user_query = escape_like_pattern(&normalize_username(user_query).to_lowercase());
+
+    users
+        .filter(lower(username).like(format!("%{}%", user_query)))
+        .order((
+            diesel::dsl::sql::<Bool>("lower(username) LIKE ")
+                .bind::<Text, _>(format!("{}%", user_query))
+                .sql(" DESC"),
+            username.asc(),
+        ))
+        .select((id, username))
+        .limit(limit)
+        .load::<UserMentionDTO>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error searching users",
+            ..Default::default()
+        })
+}
+
+pub fn get_user_by_username_query(
+    user_name: &String,
+    pool: web::Data<Pool>,
+) -> Result<User, DefaultError> {
+    match find_user_by_username_query(user_name, pool)? {
         Some(user) => Ok(user),
         None => Err(DefaultError {
             message: "User not found",
+            ..Default::default()
         }),
     }
 }
@@ -64,7 +120,7 @@ pub fn get_user_by_id_query(
 ) -> Result<User, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let user: Option<User> = users
         .filter(id.eq(user_id))
@@ -72,19 +128,24 @@ pub fn get_user_by_id_query(
         .optional()
         .map_err(|_| DefaultError {
             message: "Error loading user",
+            ..Default::default()
         })?;
     match user {
         Some(user) => Ok(user),
         None => Err(DefaultError {
             message: "User not found",
+            ..Default::default()
         }),
     }
 }
 
+pub const USER_CARDS_PAGE_SIZE: i64 = 25;
+
 pub fn get_user_with_votes_and_cards_by_id_query(
     user_id: uuid::Uuid,
     accessing_user_id: Option<uuid::Uuid>,
     page: &i64,
+    sort_by: CardSortBy,
     pool: web::Data<Pool>,
 ) -> Result<UserDTOWithVotesAndCards, DefaultError> {
     use crate::data::schema::card_files::dsl as card_files_columns;
@@ -94,7 +155,7 @@ pub fn get_user_with_votes_and_cards_by_id_query(
     use crate::data::schema::files::dsl as files_columns;
     use crate::data::schema::users::dsl as user_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let user_result: Option<User> = user_columns::users
         .filter(user_columns::id.eq(user_id))
@@ -102,11 +163,13 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         .optional()
         .map_err(|_| DefaultError {
             message: "Error loading user",
+            ..Default::default()
         })?;
     let user = match user_result {
         Some(user) => Ok(user),
         None => Err(DefaultError {
             message: "User not found",
+            ..Default::default()
         }),
     }?;
 
@@ -141,10 +204,18 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         .get_result(&mut conn)
         .map_err(|_| DefaultError {
             message: "Error loading user cards",
+            ..Default::default()
         })?;
 
+    user_card_metadatas = match sort_by {
+        CardSortBy::Recent => user_card_metadatas.order(card_metadata_columns::updated_at.desc()),
+        CardSortBy::Popularity => user_card_metadatas.order((
+            card_metadata_columns::view_count.desc(),
+            card_metadata_columns::updated_at.desc(),
+        )),
+    };
+
     let user_card_metadatas = user_card_metadatas
-        .order(card_metadata_columns::updated_at.desc())
         .select((
             card_metadata_columns::id,
             card_metadata_columns::content,
@@ -156,12 +227,19 @@ pub fn get_user_with_votes_and_cards_by_id_query(
             card_metadata_columns::oc_file_path,
             card_metadata_columns::card_html,
             card_metadata_columns::private,
+            card_metadata_columns::source_author,
+            card_metadata_columns::source_title,
+            card_metadata_columns::source_date,
+            card_metadata_columns::source_url,
+            card_metadata_columns::approved,
+            card_metadata_columns::language,
         ))
-        .limit(25)
-        .offset((page - 1) * 25)
+        .limit(USER_CARDS_PAGE_SIZE)
+        .offset((page - 1) * USER_CARDS_PAGE_SIZE)
         .load::<CardMetadata>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Error loading user cards",
+            ..Default::default()
         })?;
 
     let card_votes: Vec<CardVote> = card_votes_columns::card_votes
@@ -176,6 +254,7 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         .load::<CardVote>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load upvotes",
+            ..Default::default()
         })?;
 
     let file_ids: Vec<CardFileWithName> = card_files_columns::card_files
@@ -198,6 +277,7 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         .load::<CardFileWithName>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load metadata",
+            ..Default::default()
         })?;
 
     let card_verifications: Vec<CardVerifications> = card_verification_columns::card_verification
@@ -213,6 +293,7 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         .load::<CardVerifications>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load verification metadata",
+            ..Default::default()
         })?;
 
     let card_metadata_with_upvotes: Vec<CardMetadataWithVotesAndFiles> = (user_card_metadatas)
@@ -248,6 +329,10 @@ pub fn get_user_with_votes_and_cards_by_id_query(
                 updated_at: metadata.updated_at,
                 oc_file_path: metadata.oc_file_path.clone(),
                 private: metadata.private,
+                source_author: metadata.source_author.clone(),
+                source_title: metadata.source_title.clone(),
+                source_date: metadata.source_date,
+                source_url: metadata.source_url.clone(),
                 score: None,
                 file_name: card_with_file_name.map(|file| file.file_name.clone()),
                 file_id: card_with_file_name.map(|file| file.file_id),
@@ -256,6 +341,40 @@ pub fn get_user_with_votes_and_cards_by_id_query(
         })
         .collect();
 
+    let vote_totals = get_user_vote_totals_query(user.id, &mut conn)?;
+
+    Ok(UserDTOWithVotesAndCards {
+        id: user.id,
+        email: if user.visible_email {
+            Some(user.email)
+        } else {
+            None
+        },
+        username: user.username,
+        website: user.website,
+        visible_email: user.visible_email,
+        created_at: user.created_at,
+        total_cards_created: total_cards_created_by_user,
+        cards: card_metadata_with_upvotes,
+        total_upvotes_received: vote_totals.total_upvotes_received,
+        total_downvotes_received: vote_totals.total_downvotes_received,
+        total_votes_cast: vote_totals.total_votes_cast,
+    })
+}
+
+pub struct UserVoteTotals {
+    pub total_upvotes_received: i32,
+    pub total_downvotes_received: i32,
+    pub total_votes_cast: i32,
+}
+
+pub fn get_user_vote_totals_query(
+    user_id: uuid::Uuid,
+    conn: &mut PgConnection,
+) -> Result<UserVoteTotals, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
     let user_card_votes = card_votes_columns::card_votes
         .inner_join(
             card_metadata_columns::card_metadata
@@ -270,10 +389,11 @@ pub fn get_user_with_votes_and_cards_by_id_query(
             card_votes_columns::updated_at,
             card_votes_columns::deleted,
         ))
-        .filter(card_metadata_columns::author_id.eq(user.id))
-        .load::<CardVote>(&mut conn)
+        .filter(card_metadata_columns::author_id.eq(user_id))
+        .load::<CardVote>(conn)
         .map_err(|_| DefaultError {
             message: "Failed to load upvotes",
+            ..Default::default()
         })?;
     let total_upvotes_received = user_card_votes
         .iter()
@@ -282,32 +402,130 @@ pub fn get_user_with_votes_and_cards_by_id_query(
     let total_downvotes_received = user_card_votes.len() as i32 - total_upvotes_received;
 
     let total_votes_cast = card_votes_columns::card_votes
-        .filter(card_votes_columns::voted_user_id.eq(user.id))
+        .filter(card_votes_columns::voted_user_id.eq(user_id))
         .count()
-        .get_result::<i64>(&mut conn)
+        .get_result::<i64>(conn)
         .map_err(|_| DefaultError {
             message: "Failed to load total votes cast",
+            ..Default::default()
         })? as i32;
 
-    Ok(UserDTOWithVotesAndCards {
-        id: user.id,
-        email: if user.visible_email {
-            Some(user.email)
-        } else {
-            None
-        },
-        username: user.username,
-        website: user.website,
-        visible_email: user.visible_email,
-        created_at: user.created_at,
-        total_cards_created: total_cards_created_by_user,
-        cards: card_metadata_with_upvotes,
+    Ok(UserVoteTotals {
         total_upvotes_received,
         total_downvotes_received,
         total_votes_cast,
     })
 }
 
+pub fn get_user_stats_query(
+    user_id: uuid::Uuid,
+    pool: web::Data<Pool>,
+) -> Result<UserStats, DefaultError> {
+    use crate::data::schema::card_collection::dsl as card_collection_columns;
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::messages::dsl as messages_columns;
+    use crate::data::schema::topics::dsl as topics_columns;
+
+    let mut conn = pool.get()?;
+
+    let total_cards_created = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::author_id.eq(user_id))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error loading user cards",
+            ..Default::default()
+        })?;
+
+    let vote_totals = get_user_vote_totals_query(user_id, &mut conn)?;
+
+    let total_collections = card_collection_columns::card_collection
+        .filter(card_collection_columns::author_id.eq(user_id))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error loading user collections",
+            ..Default::default()
+        })?;
+
+    let total_topics = topics_columns::topics
+        .filter(topics_columns::user_id.eq(user_id))
+        .filter(topics_columns::deleted.eq(false))
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error loading user topics",
+            ..Default::default()
+        })?;
+
+    let now = chrono::Local::now().naive_local();
+    let start_of_month = chrono::NaiveDate::from_ymd_opt(now.year(), now.month(), 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .unwrap_or(now);
+
+    let tokens_used_this_month = messages_columns::messages
+        .inner_join(topics_columns::topics)
+        .filter(topics_columns::user_id.eq(user_id))
+        .filter(messages_columns::created_at.ge(start_of_month))
+        .select(diesel::dsl::sql::<BigInt>(
+            "COALESCE(SUM(COALESCE(messages.prompt_tokens, 0) + COALESCE(messages.completion_tokens, 0)), 0)",
+        ))
+        .get_result::<i64>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error loading user token usage",
+            ..Default::default()
+        })?;
+
+    Ok(UserStats {
+        total_cards_created,
+        total_upvotes_received: vote_totals.total_upvotes_received,
+        total_downvotes_received: vote_totals.total_downvotes_received,
+        total_votes_cast: vote_totals.total_votes_cast,
+        total_collections,
+        total_topics,
+        tokens_used_this_month,
+    })
+}
+
+// Cards created per day over the last days, keyed by YYYY-MM-DD. Days without cards are left out.
+pub fn get_user_card_activity_query(
+    user_id: uuid::Uuid,
+    accessing_user_id: Option<uuid::Uuid>,
+    days: i64,
+    pool: web::Data<Pool>,
+) -> Result<BTreeMap<String, i64>, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+
+    let mut conn = pool.get()?;
+
+    let start_date = chrono::Local::now().date_naive() - chrono::Duration::days(days - 1);
+    let mut activity_query = card_metadata_columns::card_metadata
+        .filter(card_metadata_columns::author_id.eq(user_id))
+        .filter(card_metadata_columns::created_at.ge(start_date.and_hms_opt(0, 0, 0).unwrap()))
+        .into_boxed();
+
+    if accessing_user_id != Some(user_id) {
+        activity_query = activity_query.filter(card_metadata_columns::private.eq(false));
+    }
+
+    let card_activity = activity_query
+        .group_by(diesel::dsl::sql::<Date>("date(card_metadata.created_at)"))
+        .select((
+            diesel::dsl::sql::<Date>("date(card_metadata.created_at)"),
+            diesel::dsl::sql::<BigInt>("count(*)"),
+        ))
+        .load::<(chrono::NaiveDate, i64)>(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Error loading user activity",
+            ..Default::default()
+        })?;
+
+    Ok(card_activity
+        .into_iter()
+        .map(|(day, card_count)| (day.format("%Y-%m-%d").to_string(), card_count))
+        .collect())
+}
+
 pub fn update_user_query(
     user_id: &uuid::Uuid,
     new_user: &UpdateUserData,
@@ -315,27 +533,27 @@ pub fn update_user_query(
 ) -> Result<SlimUser, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
-    if new_user.username.clone().unwrap_or("".to_string()) != "" {
-        let user_by_username =
-            get_user_by_username_query(&new_user.username.clone().unwrap(), pool);
-
-        if let Ok(old_user) = user_by_username {
-            if !(old_user.username.is_some()
-                && old_user.username.unwrap() == new_user.username.clone().unwrap())
-            {
-                return Err(DefaultError {
-                    message: "That username is already taken",
-                });
+    let new_user_name: Option<String> = new_user
+        .username
+        .as_deref()
+        .map(normalize_username)
+        .filter(|user_name| !user_name.is_empty())
+        .map(|user_name| user_name.to_string());
+    let username_taken = || DefaultError {
+        message: "That username is already taken",
+        ..Default::default()
+    };
+
+    if let Some(new_user_name) = &new_user_name {
+        if let Some(old_user) = find_user_by_username_query(new_user_name, pool)? {
+            if old_user.id != *user_id {
+                return Err(username_taken());
             }
         }
     }
 
-    let new_user_name: Option<String> = new_user
-        .username
-        .clone()
-        .filter(|user_name| !user_name.is_empty());
     let new_user_website: Option<String> = new_user
         .website
         .clone()
@@ -348,8 +566,16 @@ pub fn update_user_query(
             visible_email.eq(&new_user.visible_email),
         ))
         .get_result(&mut conn)
-        .map_err(|_| DefaultError {
-            message: "Error updating user",
+        .map_err(|err| match err {
+            // Another user claimed a case variant of the name since the check above
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => username_taken(),
+            _ => DefaultError {
+                message: "Error updating user",
+                ..Default::default()
+            },
         })?;
 
     Ok(SlimUser::from(user))
@@ -363,7 +589,7 @@ pub fn get_top_users_query(
     use crate::data::schema::card_votes::dsl as card_votes_columns;
     use crate::data::schema::users::dsl as users_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let query = card_metadata_columns::card_metadata
         .inner_join(
@@ -377,7 +603,11 @@ pub fn get_top_users_query(
         .group_by((
             card_metadata_columns::author_id,
         ))
-        .order(diesel::dsl::sql::<Text>("score desc"))
+        // ties are broken by author so equal scores keep their place between pages
+        .order((
+            diesel::dsl::sql::<Text>("score desc"),
+            card_metadata_columns::author_id.asc(),
+        ))
         .limit(25)
         .offset((page - 1) * 25);
 
@@ -386,6 +616,7 @@ pub fn get_top_users_query(
             .load::<UserScore>(&mut conn)
             .map_err(|_| DefaultError {
                 message: "Failed to load top users",
+                ..Default::default()
             })?;
 
     let users_with_scores = users_columns::users
@@ -400,17 +631,18 @@ pub fn get_top_users_query(
         .load::<User>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load top users",
+            ..Default::default()
         })?;
 
+    // a user deleted between the two queries is left out rather than failing the page
     let user_scores_with_users = user_scores
         .iter()
-        .map(|user_score| {
+        .filter_map(|user_score| {
             let user = users_with_scores
                 .iter()
-                .find(|user| user.id == user_score.author_id)
-                .unwrap();
+                .find(|user| user.id == user_score.author_id)?;
 
-            UserDTOWithScore {
+            Some(UserDTOWithScore {
                 id: user_score.author_id,
                 email: if user.visible_email {
                     Some(user.email.clone())
@@ -422,7 +654,7 @@ pub fn get_top_users_query(
                 visible_email: user.visible_email,
                 created_at: user.created_at,
                 score: user_score.score,
-            }
+            })
         })
         .collect::<Vec<UserDTOWithScore>>();
 
@@ -434,13 +666,14 @@ pub fn get_total_users_query(
 ) -> Result<i64, DefaultError> {
     use crate::data::schema::users::dsl::*;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let total_users = users
         .count()
         .get_result::<i64>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to load total users",
+            ..Default::default()
         })?;
 
     Ok(total_users)
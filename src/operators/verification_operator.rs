@@ -14,10 +14,12 @@ use crate::{
 pub async fn get_webpage_text_headless(url: &str) -> Result<String, DefaultError> {
     let browser = Browser::default().map_err(|_e| DefaultError {
         message: "Could not create browser",
+        ..Default::default()
     })?;
 
     let tab = browser.new_tab().map_err(|_e| DefaultError {
         message: "Could not create tab",
+        ..Default::default()
     })?;
 
     tab.set_user_agent(
@@ -27,22 +29,27 @@ pub async fn get_webpage_text_headless(url: &str) -> Result<String, DefaultError
             Some("Windows NT 10.0; Win64; x64"),
         ).map_err(|_e| DefaultError {
             message: "Could not set user agent",
+            ..Default::default()
         })?;
 
     tab.enable_stealth_mode().map_err(|_e| DefaultError {
         message: "Could not enable stealth mode",
+        ..Default::default()
     })?;
 
     tab.navigate_to(url).map_err(|_e| DefaultError {
         message: "Could not navigate to url",
+        ..Default::default()
     })?;
 
     let body_tag = tab.wait_for_element("body").map_err(|_e| DefaultError {
         message: "Could not wait for body",
+        ..Default::default()
     })?;
 
     let body_tag_inner_html = body_tag.get_inner_text().map_err(|_e| DefaultError {
         message: "Could not get inner html",
+        ..Default::default()
     })?;
 
     let re = Regex::new(r"\s+").unwrap();
@@ -56,17 +63,20 @@ pub async fn get_webpage_text_fetch(url: &str) -> Result<String, DefaultError> {
         .await
         .map_err(|_| DefaultError {
             message: "Could not fetch page",
+            ..Default::default()
         })?
         .text()
         .await
         .map_err(|_| DefaultError {
             message: "Could not parse text",
+            ..Default::default()
         })?;
 
     let soup = soup::Soup::new(&html);
 
     let body = soup.tag("body").find().ok_or(DefaultError {
         message: "Could not find body tag",
+        ..Default::default()
     })?;
 
     // Replace multiple whitesapces chars with a single space
@@ -84,7 +94,7 @@ pub fn upsert_card_verification_query(
 ) -> Result<CardVerifications, DefaultError> {
     use crate::data::schema::card_verification::dsl::*;
 
-    let mut conn = pool.lock().unwrap().get().unwrap();
+    let mut conn = pool.lock().unwrap().get()?;
 
     let new_id = uuid::Uuid::new_v4();
 
@@ -100,6 +110,7 @@ pub fn upsert_card_verification_query(
         .get_result(&mut conn)
         .map_err(|_| DefaultError {
             message: "Could not upsert card verification",
+            ..Default::default()
         })?;
 
     Ok(created_verification)
@@ -1,6 +1,11 @@
+use std::collections::HashMap;
 use std::sync::MutexGuard;
 
-use crate::diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use crate::diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use actix_web::web;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Int8, Nullable};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     data::models::{CardVote, Pool},
@@ -17,7 +22,7 @@ pub fn create_vote_query(
 
     let _ = delete_vote_query(voted_user_id, card_metadata_id, &pool);
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     let new_vote = CardVote::from_details(voted_user_id, card_metadata_id, vote);
 
@@ -26,6 +31,7 @@ pub fn create_vote_query(
         .get_result::<CardVote>(&mut conn)
         .map_err(|_| DefaultError {
             message: "Failed to create vote",
+            ..Default::default()
         })?;
 
     Ok(created_vote)
@@ -38,7 +44,7 @@ pub fn delete_vote_query(
 ) -> Result<(), DefaultError> {
     use crate::data::schema::card_votes::dsl as card_votes_columns;
 
-    let mut conn = pool.get().unwrap();
+    let mut conn = pool.get()?;
 
     diesel::delete(
         card_votes_columns::card_votes
@@ -48,7 +54,96 @@ pub fn delete_vote_query(
     .execute(&mut conn)
     .map_err(|_| DefaultError {
         message: "Failed to delete vote",
+        ..Default::default()
     })?;
 
     Ok(())
 }
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CardVoteTally {
+    pub up: i64,
+    pub down: i64,
+    pub my_vote: Option<bool>,
+}
+
+impl CardVoteTally {
+    // my_vote stays None for anonymous callers even if the nil user id matched a vote
+    pub fn from_votes<'a>(
+        votes: impl IntoIterator<Item = &'a CardVote>,
+        current_user_id: Option<uuid::Uuid>,
+    ) -> Self {
+        votes
+            .into_iter()
+            .fold(CardVoteTally::default(), |mut tally, card_vote| {
+                if card_vote.vote {
+                    tally.up += 1;
+                } else {
+                    tally.down += 1;
+                }
+                if Some(card_vote.voted_user_id) == current_user_id {
+                    tally.my_vote = Some(card_vote.vote);
+                }
+                tally
+            })
+    }
+}
+
+// Private cards are only tallied for their author, other ids are left out as if they didn't exist
+pub fn get_vote_tallies_query(
+    card_ids: Vec<uuid::Uuid>,
+    current_user_id: Option<uuid::Uuid>,
+    pool: web::Data<Pool>,
+) -> Result<HashMap<uuid::Uuid, CardVoteTally>, DefaultError> {
+    use crate::data::schema::card_metadata::dsl as card_metadata_columns;
+    use crate::data::schema::card_votes::dsl as card_votes_columns;
+
+    let mut conn = pool.get()?;
+
+    let card_ids: Vec<uuid::Uuid> =
+        card_metadata_columns::card_metadata
+            .filter(card_metadata_columns::id.eq_any(&card_ids))
+            .filter(card_metadata_columns::private.eq(false).or(
+                card_metadata_columns::author_id.eq(current_user_id.unwrap_or(uuid::Uuid::nil())),
+            ))
+            .select(card_metadata_columns::id)
+            .load(&mut conn)
+            .map_err(|_| DefaultError {
+                message: "Failed to load vote tallies",
+                ..Default::default()
+            })?;
+
+    let tallies: Vec<(uuid::Uuid, i64, i64, Option<bool>)> = card_votes_columns::card_votes
+        .filter(card_votes_columns::card_metadata_id.eq_any(&card_ids))
+        .group_by(card_votes_columns::card_metadata_id)
+        .select((
+            card_votes_columns::card_metadata_id,
+            sql::<Int8>("count(*) FILTER (WHERE vote)"),
+            sql::<Int8>("count(*) FILTER (WHERE NOT vote)"),
+            sql::<Nullable<Bool>>("bool_or(vote) FILTER (WHERE voted_user_id = ")
+                .bind::<diesel::sql_types::Uuid, _>(current_user_id.unwrap_or(uuid::Uuid::nil()))
+                .sql(")"),
+        ))
+        .load(&mut conn)
+        .map_err(|_| DefaultError {
+            message: "Failed to load vote tallies",
+            ..Default::default()
+        })?;
+
+    let mut vote_tallies: HashMap<uuid::Uuid, CardVoteTally> = card_ids
+        .into_iter()
+        .map(|card_id| (card_id, CardVoteTally::default()))
+        .collect();
+    for (card_id, up, down, my_vote) in tallies {
+        vote_tallies.insert(
+            card_id,
+            CardVoteTally {
+                up,
+                down,
+                my_vote: current_user_id.and(my_vote),
+            },
+        );
+    }
+
+    Ok(vote_tallies)
+}
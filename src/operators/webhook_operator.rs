@@ -0,0 +1,130 @@
+use log::info;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::Serialize;
+
+use crate::{data::models::CardMetadata, errors::DefaultError};
+
+const CARD_WEBHOOK_MAX_ATTEMPTS: u32 = 3;
+const CARD_WEBHOOK_CONTENT_PREVIEW_CHARS: usize = 280;
+
+#[derive(Debug, Serialize)]
+pub struct CardCreatedWebhookPayload {
+    pub event: &'static str,
+    pub card_id: uuid::Uuid,
+    pub author_id: uuid::Uuid,
+    pub content_preview: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl From<&CardMetadata> for CardCreatedWebhookPayload {
+    fn from(card: &CardMetadata) -> Self {
+        CardCreatedWebhookPayload {
+            event: "card.created",
+            card_id: card.id,
+            author_id: card.author_id,
+            content_preview: card
+                .content
+                .chars()
+                .take(CARD_WEBHOOK_CONTENT_PREVIEW_CHARS)
+                .collect(),
+            created_at: card.created_at,
+            updated_at: card.updated_at,
+        }
+    }
+}
+
+// Both the Stripe-Signature header we verify and the signature on our own webhooks are an
+// HMAC-SHA256 over "{timestamp}.{payload}", hex encoded
+pub fn hmac_sha256_hex(
+    secret: &str,
+    timestamp: i64,
+    payload: &str,
+) -> Result<String, openssl::error::ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(format!("{}.{}", timestamp, payload).as_bytes())?;
+
+    Ok(signer
+        .sign_to_vec()?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>())
+}
+
+fn sign_webhook_payload(
+    payload: &str,
+    timestamp: i64,
+    webhook_secret: &str,
+) -> Result<String, DefaultError> {
+    let signature =
+        hmac_sha256_hex(webhook_secret, timestamp, payload).map_err(|_| DefaultError {
+            message: "Could not sign webhook payload",
+            ..Default::default()
+        })?;
+
+    Ok(format!("t={},v1={}", timestamp, signature))
+}
+
+async fn send_card_webhook(
+    webhook_url: &str,
+    webhook_secret: &str,
+    payload: &str,
+) -> Result<(), DefaultError> {
+    let signature = sign_webhook_payload(payload, chrono::Utc::now().timestamp(), webhook_secret)?;
+
+    reqwest::Client::new()
+        .post(webhook_url)
+        .header("Content-Type", "application/json")
+        .header("X-Arguflow-Signature", signature)
+        .timeout(std::time::Duration::from_secs(10))
+        .body(payload.to_string())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_err| DefaultError {
+            message: "Card webhook request failed",
+            ..Default::default()
+        })?;
+
+    Ok(())
+}
+
+// Fire and forget, the create path never waits on the receiver. Does nothing unless
+// CARD_WEBHOOK_URL is set, and private cards are never sent out.
+pub fn dispatch_card_created_webhook(card: &CardMetadata) {
+    let webhook_url = match std::env::var("CARD_WEBHOOK_URL") {
+        Ok(webhook_url) if !webhook_url.is_empty() => webhook_url,
+        _ => return,
+    };
+    if card.private {
+        return;
+    }
+    let webhook_secret = match std::env::var("CARD_WEBHOOK_SECRET") {
+        Ok(webhook_secret) => webhook_secret,
+        Err(_) => {
+            info!("CARD_WEBHOOK_URL is set without CARD_WEBHOOK_SECRET, skipping card webhook");
+            return;
+        }
+    };
+    let payload = match serde_json::to_string(&CardCreatedWebhookPayload::from(card)) {
+        Ok(payload) => payload,
+        Err(_) => return,
+    };
+
+    actix_web::rt::spawn(async move {
+        for attempt in 1..=CARD_WEBHOOK_MAX_ATTEMPTS {
+            match send_card_webhook(&webhook_url, &webhook_secret, &payload).await {
+                Ok(()) => return,
+                Err(err) => info!(
+                    "Card webhook attempt {} of {} failed: {}",
+                    attempt, CARD_WEBHOOK_MAX_ATTEMPTS, err.message
+                ),
+            }
+
+            if attempt < CARD_WEBHOOK_MAX_ATTEMPTS {
+                actix_web::rt::time::sleep(std::time::Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+    });
+}